@@ -25,7 +25,7 @@ use super::layout::{
     PCI_HIGH_BASE, PCI_MMIO_CONFIG_SIZE_PER_SEGMENT,
 };
 use vm_fdt::{FdtWriter, FdtWriterResult};
-use vm_memory::{Address, Bytes, GuestMemory, GuestMemoryError, GuestMemoryRegion};
+use vm_memory::{Address, Bytes, GuestMemory, GuestMemoryError};
 
 // This is a value for uniquely identifying the FDT node declaring the interrupt controller.
 const GIC_PHANDLE: u32 = 1;
@@ -97,6 +97,7 @@ pub fn create_fdt<T: DeviceInfoForFdt + Clone + Debug, S: ::std::hash::BuildHash
     numa_nodes: &NumaNodes,
     virtio_iommu_bdf: Option<u32>,
     pmu_supported: bool,
+    reserved_memory: &[(u64, u64)],
 ) -> FdtWriterResult<Vec<u8>> {
     // Allocate stuff necessary for the holding the blob.
     let mut fdt = FdtWriter::new().unwrap();
@@ -117,6 +118,9 @@ pub fn create_fdt<T: DeviceInfoForFdt + Clone + Debug, S: ::std::hash::BuildHash
     fdt.property_u32("interrupt-parent", GIC_PHANDLE)?;
     create_cpu_nodes(&mut fdt, &vcpu_mpidr, vcpu_topology, numa_nodes)?;
     create_memory_node(&mut fdt, guest_mem, numa_nodes)?;
+    if !reserved_memory.is_empty() {
+        create_reserved_memory_node(&mut fdt, reserved_memory)?;
+    }
     create_chosen_node(&mut fdt, cmdline, initrd)?;
     create_gic_node(&mut fdt, gic_device)?;
     create_timer_node(&mut fdt)?;
@@ -139,10 +143,10 @@ pub fn create_fdt<T: DeviceInfoForFdt + Clone + Debug, S: ::std::hash::BuildHash
     Ok(fdt_final)
 }
 
-pub fn write_fdt_to_memory(fdt_final: Vec<u8>, guest_mem: &GuestMemoryMmap) -> Result<()> {
+pub fn write_fdt_to_memory(fdt_final: &[u8], guest_mem: &GuestMemoryMmap) -> Result<()> {
     // Write FDT to memory.
     guest_mem
-        .write_slice(fdt_final.as_slice(), super::layout::FDT_START)
+        .write_slice(fdt_final, super::layout::FDT_START)
         .map_err(Error::WriteFdtToMemory)?;
     Ok(())
 }
@@ -230,65 +234,49 @@ fn create_memory_node(
     guest_mem: &GuestMemoryMmap,
     numa_nodes: &NumaNodes,
 ) -> FdtWriterResult<()> {
+    // Reserved ranges remain part of the memory nodes, they are described
+    // separately under the reserved-memory node.
+    let memory_map = super::guest_memory_map(guest_mem, numa_nodes, &[]);
+
     // See https://github.com/torvalds/linux/blob/58ae0b51506802713aa0e9956d1853ba4c722c98/Documentation/devicetree/bindings/numa.txt
     // for NUMA setting in memory node.
     if numa_nodes.len() > 1 {
-        for numa_node_idx in 0..numa_nodes.len() {
-            let numa_node = numa_nodes.get(&(numa_node_idx as u32));
+        for numa_node_idx in 0..numa_nodes.len() as u32 {
             let mut mem_reg_prop: Vec<u64> = Vec::new();
             let mut node_memory_addr: u64 = 0;
             // Each memory zone of numa will have its own memory node, but
             // different numa nodes should not share same memory zones.
-            for memory_region in numa_node.unwrap().memory_regions.iter() {
-                let memory_region_start_addr: u64 = memory_region.start_addr().raw_value();
-                let memory_region_size: u64 = memory_region.size() as u64;
-                mem_reg_prop.push(memory_region_start_addr);
-                mem_reg_prop.push(memory_region_size);
+            for entry in memory_map
+                .iter()
+                .filter(|e| e.numa_node == Some(numa_node_idx))
+            {
+                mem_reg_prop.push(entry.start);
+                mem_reg_prop.push(entry.size);
                 // Set the node address the first non-zero regison address
                 if node_memory_addr == 0 {
-                    node_memory_addr = memory_region_start_addr;
+                    node_memory_addr = entry.start;
                 }
             }
             let memory_node_name = format!("memory@{:x}", node_memory_addr);
             let memory_node = fdt.begin_node(&memory_node_name)?;
             fdt.property_string("device_type", "memory")?;
             fdt.property_array_u64("reg", &mem_reg_prop)?;
-            fdt.property_u32("numa-node-id", numa_node_idx as u32)?;
+            fdt.property_u32("numa-node-id", numa_node_idx)?;
             fdt.end_node(memory_node)?;
         }
+    } else if let [entry] = memory_map.as_slice() {
+        // All RAM is under the hole
+        let memory_node = fdt.begin_node("memory")?;
+        fdt.property_string("device_type", "memory")?;
+        fdt.property_array_u64("reg", &[entry.start, entry.size])?;
+        fdt.end_node(memory_node)?;
     } else {
-        let last_addr = guest_mem.last_addr().raw_value();
-        if last_addr < super::layout::MEM_32BIT_RESERVED_START.raw_value() {
-            // Case 1: all RAM is under the hole
-            let mem_size = last_addr - super::layout::RAM_START.raw_value() + 1;
-            let mem_reg_prop = [super::layout::RAM_START.raw_value() as u64, mem_size as u64];
-            let memory_node = fdt.begin_node("memory")?;
-            fdt.property_string("device_type", "memory")?;
-            fdt.property_array_u64("reg", &mem_reg_prop)?;
-            fdt.end_node(memory_node)?;
-        } else {
-            // Case 2: RAM is split by the hole
-            // Region 1: RAM before the hole
-            let mem_size = super::layout::MEM_32BIT_RESERVED_START.raw_value()
-                - super::layout::RAM_START.raw_value();
-            let mem_reg_prop = [super::layout::RAM_START.raw_value() as u64, mem_size as u64];
-            let memory_node_name = format!("memory@{:x}", super::layout::RAM_START.raw_value());
+        // RAM is split by the hole, one node for each side
+        for entry in memory_map.iter() {
+            let memory_node_name = format!("memory@{:x}", entry.start);
             let memory_node = fdt.begin_node(&memory_node_name)?;
             fdt.property_string("device_type", "memory")?;
-            fdt.property_array_u64("reg", &mem_reg_prop)?;
-            fdt.end_node(memory_node)?;
-
-            // Region 2: RAM after the hole
-            let mem_size = last_addr - super::layout::RAM_64BIT_START.raw_value() + 1;
-            let mem_reg_prop = [
-                super::layout::RAM_64BIT_START.raw_value() as u64,
-                mem_size as u64,
-            ];
-            let memory_node_name =
-                format!("memory@{:x}", super::layout::RAM_64BIT_START.raw_value());
-            let memory_node = fdt.begin_node(&memory_node_name)?;
-            fdt.property_string("device_type", "memory")?;
-            fdt.property_array_u64("reg", &mem_reg_prop)?;
+            fdt.property_array_u64("reg", &[entry.start, entry.size])?;
             fdt.end_node(memory_node)?;
         }
     }
@@ -296,6 +284,28 @@ fn create_memory_node(
     Ok(())
 }
 
+// See https://github.com/torvalds/linux/blob/master/Documentation/devicetree/bindings/reserved-memory/reserved-memory.yaml
+fn create_reserved_memory_node(
+    fdt: &mut FdtWriter,
+    reserved_memory: &[(u64, u64)],
+) -> FdtWriterResult<()> {
+    let reserved_memory_node = fdt.begin_node("reserved-memory")?;
+    fdt.property_u32("#address-cells", ADDRESS_CELLS)?;
+    fdt.property_u32("#size-cells", SIZE_CELLS)?;
+    fdt.property_null("ranges")?;
+
+    for (start, size) in reserved_memory.iter() {
+        let node = fdt.begin_node(&format!("reserved@{:x}", start))?;
+        fdt.property_array_u64("reg", &[*start, *size])?;
+        fdt.property_null("no-map")?;
+        fdt.end_node(node)?;
+    }
+
+    fdt.end_node(reserved_memory_node)?;
+
+    Ok(())
+}
+
 fn create_chosen_node(
     fdt: &mut FdtWriter,
     cmdline: &str,
@@ -10,14 +10,17 @@ pub mod layout;
 pub mod uefi;
 
 pub use self::fdt::DeviceInfoForFdt;
-use crate::{DeviceType, GuestMemoryMmap, NumaNodes, PciSpaceInfo, RegionType};
+use crate::{
+    DeviceType, GuestMemoryMmap, MemoryMapEntry, MemoryMapEntryType, NumaNodes, PciSpaceInfo,
+    RegionType,
+};
 use hypervisor::arch::aarch64::gic::Vgic;
 use log::{log_enabled, Level};
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Debug;
 use std::sync::{Arc, Mutex};
-use vm_memory::{Address, GuestAddress, GuestMemory, GuestUsize};
+use vm_memory::{Address, GuestAddress, GuestMemory, GuestMemoryRegion, GuestUsize};
 
 /// Errors thrown while configuring aarch64 system.
 #[derive(Debug)]
@@ -133,6 +136,7 @@ pub fn arch_memory_regions(size: GuestUsize) -> Vec<(GuestAddress, usize, Region
 }
 
 /// Configures the system and should be called once per vm before starting vcpu threads.
+/// Returns the device tree written to the guest memory.
 #[allow(clippy::too_many_arguments)]
 pub fn configure_system<T: DeviceInfoForFdt + Clone + Debug, S: ::std::hash::BuildHasher>(
     guest_mem: &GuestMemoryMmap,
@@ -146,7 +150,8 @@ pub fn configure_system<T: DeviceInfoForFdt + Clone + Debug, S: ::std::hash::Bui
     gic_device: &Arc<Mutex<dyn Vgic>>,
     numa_nodes: &NumaNodes,
     pmu_supported: bool,
-) -> super::Result<()> {
+    reserved_memory: &[(u64, u64)],
+) -> super::Result<Vec<u8>> {
     let fdt_final = fdt::create_fdt(
         guest_mem,
         cmdline,
@@ -159,6 +164,7 @@ pub fn configure_system<T: DeviceInfoForFdt + Clone + Debug, S: ::std::hash::Bui
         numa_nodes,
         virtio_iommu_bdf,
         pmu_supported,
+        reserved_memory,
     )
     .map_err(|_| Error::SetupFdt)?;
 
@@ -166,9 +172,67 @@ pub fn configure_system<T: DeviceInfoForFdt + Clone + Debug, S: ::std::hash::Bui
         fdt::print_fdt(&fdt_final);
     }
 
-    fdt::write_fdt_to_memory(fdt_final, guest_mem).map_err(Error::WriteFdtToMemory)?;
+    fdt::write_fdt_to_memory(&fdt_final, guest_mem).map_err(Error::WriteFdtToMemory)?;
 
-    Ok(())
+    Ok(fdt_final)
+}
+
+/// Returns the RAM ranges described to the guest booting with `guest_mem`
+/// through the device tree memory nodes, along with their NUMA node when
+/// the guest has several. The `reserved_memory` ranges, described through
+/// the reserved-memory node, are reported as reserved.
+pub fn guest_memory_map(
+    guest_mem: &GuestMemoryMmap,
+    numa_nodes: &NumaNodes,
+    reserved_memory: &[(u64, u64)],
+) -> Vec<MemoryMapEntry> {
+    let ram = |start: u64, size: u64, numa_node: Option<u32>| MemoryMapEntry {
+        start,
+        size,
+        entry_type: MemoryMapEntryType::Ram,
+        numa_node,
+    };
+
+    let memory_map = if numa_nodes.len() > 1 {
+        numa_nodes
+            .iter()
+            .flat_map(|(numa_node_id, numa_node)| {
+                numa_node.memory_regions.iter().map(move |memory_region| {
+                    ram(
+                        memory_region.start_addr().raw_value(),
+                        memory_region.size() as u64,
+                        Some(*numa_node_id),
+                    )
+                })
+            })
+            .collect()
+    } else {
+        let last_addr = guest_mem.last_addr().raw_value();
+        if last_addr < layout::MEM_32BIT_RESERVED_START.raw_value() {
+            // All RAM is under the hole
+            vec![ram(
+                layout::RAM_START.raw_value(),
+                last_addr - layout::RAM_START.raw_value() + 1,
+                None,
+            )]
+        } else {
+            // RAM is split by the hole
+            vec![
+                ram(
+                    layout::RAM_START.raw_value(),
+                    layout::MEM_32BIT_RESERVED_START.raw_value() - layout::RAM_START.raw_value(),
+                    None,
+                ),
+                ram(
+                    layout::RAM_64BIT_START.raw_value(),
+                    last_addr - layout::RAM_64BIT_START.raw_value() + 1,
+                    None,
+                ),
+            ]
+        }
+    };
+
+    crate::reserve_memory_ranges(memory_map, reserved_memory)
 }
 
 /// Returns the memory address where the initramfs could be loaded.
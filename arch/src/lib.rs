@@ -81,8 +81,8 @@ pub mod aarch64;
 #[cfg(target_arch = "aarch64")]
 pub use aarch64::{
     arch_memory_regions, configure_system, configure_vcpu, fdt::DeviceInfoForFdt,
-    get_host_cpu_phys_bits, initramfs_load_addr, layout, layout::CMDLINE_MAX_SIZE,
-    layout::IRQ_BASE, uefi, EntryPoint,
+    get_host_cpu_phys_bits, guest_memory_map, initramfs_load_addr, layout,
+    layout::CMDLINE_MAX_SIZE, layout::IRQ_BASE, uefi, EntryPoint,
 };
 
 #[cfg(target_arch = "x86_64")]
@@ -91,8 +91,9 @@ pub mod x86_64;
 #[cfg(target_arch = "x86_64")]
 pub use x86_64::{
     arch_memory_regions, configure_system, configure_vcpu, generate_common_cpuid,
-    get_host_cpu_phys_bits, initramfs_load_addr, layout, layout::CMDLINE_MAX_SIZE,
-    layout::CMDLINE_START, regs, CpuidFeatureEntry, EntryPoint,
+    get_host_cpu_phys_bits, guest_memory_map, initramfs_load_addr, layout,
+    layout::CMDLINE_MAX_SIZE, layout::CMDLINE_START, regs, update_cpuid_tsc_deadline_timer,
+    CpuidFeatureEntry, EntryPoint,
 };
 
 /// Safe wrapper for `sysconf(_SC_PAGESIZE)`.
@@ -116,6 +117,74 @@ pub struct NumaNode {
 
 pub type NumaNodes = BTreeMap<u32, NumaNode>;
 
+/// Type of a guest physical address range from the memory map presented to
+/// the guest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemoryMapEntryType {
+    /// Usable RAM.
+    Ram,
+    /// Range the guest must leave alone, such as the PCI MMCONFIG area.
+    Reserved,
+}
+
+/// Guest physical address range from the memory map presented to the guest,
+/// through the e820 table on x86_64 or the device tree memory nodes on
+/// aarch64.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryMapEntry {
+    pub start: u64,
+    pub size: u64,
+    pub entry_type: MemoryMapEntryType,
+    /// NUMA node of the range, when described along with the memory map.
+    pub numa_node: Option<u32>,
+}
+
+/// Marks the guest physical address ranges from `reserved`, given as
+/// (start, size) pairs, as reserved in `memory_map`. The RAM entries they
+/// fall into are split around them, keeping their NUMA node.
+pub fn reserve_memory_ranges(
+    memory_map: Vec<MemoryMapEntry>,
+    reserved: &[(u64, u64)],
+) -> Vec<MemoryMapEntry> {
+    let mut reserved = reserved.to_vec();
+    reserved.sort_unstable();
+
+    let mut result = Vec::new();
+    for entry in memory_map {
+        if entry.entry_type != MemoryMapEntryType::Ram {
+            result.push(entry);
+            continue;
+        }
+
+        let end = entry.start + entry.size;
+        let mut push = |start: u64, end: u64, entry_type: MemoryMapEntryType| {
+            if start < end {
+                result.push(MemoryMapEntry {
+                    start,
+                    size: end - start,
+                    entry_type,
+                    numa_node: entry.numa_node,
+                });
+            }
+        };
+
+        let mut start = entry.start;
+        for (reserved_start, reserved_size) in reserved.iter() {
+            let reserved_end = std::cmp::min(reserved_start + reserved_size, end);
+            let reserved_start = std::cmp::max(*reserved_start, start);
+            if reserved_start >= reserved_end {
+                continue;
+            }
+            push(start, reserved_start, MemoryMapEntryType::Ram);
+            push(reserved_start, reserved_end, MemoryMapEntryType::Reserved);
+            start = reserved_end;
+        }
+        push(start, end, MemoryMapEntryType::Ram);
+    }
+
+    result
+}
+
 /// Type for passing information about the initramfs in the guest memory.
 pub struct InitramfsConfig {
     /// Load address of initramfs in guest memory
@@ -15,6 +15,7 @@ pub mod regs;
 use crate::GuestMemoryMmap;
 use crate::InitramfsConfig;
 use crate::RegionType;
+use crate::{MemoryMapEntry, MemoryMapEntryType};
 use hypervisor::x86_64::{CpuId, CpuIdEntry, CPUID_FLAG_VALID_INDEX};
 use hypervisor::HypervisorError;
 use linux_loader::loader::bootparam::boot_params;
@@ -831,6 +832,7 @@ pub fn arch_memory_regions(size: GuestUsize) -> Vec<(GuestAddress, usize, Region
 /// * `cmdline_addr` - Address in `guest_mem` where the kernel command line was loaded.
 /// * `cmdline_size` - Size of the kernel command line in bytes including the null terminator.
 /// * `num_cpus` - Number of virtual CPUs the guest will have.
+/// * `reserved_memory` - RAM ranges, as (start, size) pairs, reported as reserved to the guest.
 #[allow(clippy::too_many_arguments)]
 pub fn configure_system(
     guest_mem: &GuestMemoryMmap,
@@ -840,13 +842,17 @@ pub fn configure_system(
     rsdp_addr: Option<GuestAddress>,
     sgx_epc_region: Option<SgxEpcRegion>,
     serial_number: Option<&str>,
+    uuid: Option<[u8; 16]>,
+    oem_strings: Option<&[&str]>,
+    reserved_memory: &[(u64, u64)],
 ) -> super::Result<()> {
     // Write EBDA address to location where ACPICA expects to find it
     guest_mem
         .write_obj((layout::EBDA_START.0 >> 4) as u16, layout::EBDA_POINTER)
         .map_err(Error::EbdaSetup)?;
 
-    let size = smbios::setup_smbios(guest_mem, serial_number).map_err(Error::SmbiosSetup)?;
+    let size = smbios::setup_smbios(guest_mem, serial_number, uuid, oem_strings)
+        .map_err(Error::SmbiosSetup)?;
 
     // Place the MP table after the SMIOS table aligned to 16 bytes
     let offset = GuestAddress(layout::SMBIOS_START).unchecked_add(size);
@@ -866,6 +872,7 @@ pub fn configure_system(
         initramfs,
         rsdp_addr,
         sgx_epc_region,
+        reserved_memory,
     )
 }
 
@@ -875,6 +882,7 @@ fn configure_pvh(
     initramfs: &Option<InitramfsConfig>,
     rsdp_addr: Option<GuestAddress>,
     sgx_epc_region: Option<SgxEpcRegion>,
+    reserved_memory: &[(u64, u64)],
 ) -> super::Result<()> {
     const XEN_HVM_START_MAGIC_VALUE: u32 = 0x336ec578;
 
@@ -913,48 +921,12 @@ fn configure_pvh(
     let mut memmap: Vec<hvm_memmap_table_entry> = Vec::new();
 
     // Create the memory map entries.
-    add_memmap_entry(&mut memmap, 0, layout::EBDA_START.raw_value(), E820_RAM);
-
-    let mem_end = guest_mem.last_addr();
-
-    if mem_end < layout::MEM_32BIT_RESERVED_START {
-        add_memmap_entry(
-            &mut memmap,
-            layout::HIGH_RAM_START.raw_value(),
-            mem_end.unchecked_offset_from(layout::HIGH_RAM_START) + 1,
-            E820_RAM,
-        );
-    } else {
-        add_memmap_entry(
-            &mut memmap,
-            layout::HIGH_RAM_START.raw_value(),
-            layout::MEM_32BIT_RESERVED_START.unchecked_offset_from(layout::HIGH_RAM_START),
-            E820_RAM,
-        );
-        if mem_end > layout::RAM_64BIT_START {
-            add_memmap_entry(
-                &mut memmap,
-                layout::RAM_64BIT_START.raw_value(),
-                mem_end.unchecked_offset_from(layout::RAM_64BIT_START) + 1,
-                E820_RAM,
-            );
-        }
-    }
-
-    add_memmap_entry(
-        &mut memmap,
-        layout::PCI_MMCONFIG_START.0,
-        layout::PCI_MMCONFIG_SIZE,
-        E820_RESERVED,
-    );
-
-    if let Some(sgx_epc_region) = sgx_epc_region {
-        add_memmap_entry(
-            &mut memmap,
-            sgx_epc_region.start().raw_value(),
-            sgx_epc_region.size() as u64,
-            E820_RESERVED,
-        );
+    for entry in guest_memory_map(guest_mem, sgx_epc_region.as_ref(), reserved_memory) {
+        let mem_type = match entry.entry_type {
+            MemoryMapEntryType::Ram => E820_RAM,
+            MemoryMapEntryType::Reserved => E820_RESERVED,
+        };
+        add_memmap_entry(&mut memmap, entry.start, entry.size, mem_type);
     }
 
     start_info.0.memmap_entries = memmap.len() as u32;
@@ -999,6 +971,65 @@ fn configure_pvh(
     Ok(())
 }
 
+/// Returns the e820 memory map presented to the guest booting with
+/// `guest_mem`, where the `reserved_memory` ranges are carved out of the RAM.
+pub fn guest_memory_map(
+    guest_mem: &GuestMemoryMmap,
+    sgx_epc_region: Option<&SgxEpcRegion>,
+    reserved_memory: &[(u64, u64)],
+) -> Vec<MemoryMapEntry> {
+    let mut memory_map = Vec::new();
+    let mut add_entry = |start: u64, size: u64, entry_type: MemoryMapEntryType| {
+        memory_map.push(MemoryMapEntry {
+            start,
+            size,
+            entry_type,
+            numa_node: None,
+        })
+    };
+
+    add_entry(0, layout::EBDA_START.raw_value(), MemoryMapEntryType::Ram);
+
+    let mem_end = guest_mem.last_addr();
+
+    if mem_end < layout::MEM_32BIT_RESERVED_START {
+        add_entry(
+            layout::HIGH_RAM_START.raw_value(),
+            mem_end.unchecked_offset_from(layout::HIGH_RAM_START) + 1,
+            MemoryMapEntryType::Ram,
+        );
+    } else {
+        add_entry(
+            layout::HIGH_RAM_START.raw_value(),
+            layout::MEM_32BIT_RESERVED_START.unchecked_offset_from(layout::HIGH_RAM_START),
+            MemoryMapEntryType::Ram,
+        );
+        if mem_end > layout::RAM_64BIT_START {
+            add_entry(
+                layout::RAM_64BIT_START.raw_value(),
+                mem_end.unchecked_offset_from(layout::RAM_64BIT_START) + 1,
+                MemoryMapEntryType::Ram,
+            );
+        }
+    }
+
+    add_entry(
+        layout::PCI_MMCONFIG_START.0,
+        layout::PCI_MMCONFIG_SIZE,
+        MemoryMapEntryType::Reserved,
+    );
+
+    if let Some(sgx_epc_region) = sgx_epc_region {
+        add_entry(
+            sgx_epc_region.start().raw_value(),
+            sgx_epc_region.size() as u64,
+            MemoryMapEntryType::Reserved,
+        );
+    }
+
+    super::reserve_memory_ranges(memory_map, reserved_memory)
+}
+
 fn add_memmap_entry(memmap: &mut Vec<hvm_memmap_table_entry>, addr: u64, size: u64, mem_type: u32) {
     // Add the table entry to the vector
     memmap.push(hvm_memmap_table_entry {
@@ -1055,6 +1086,20 @@ pub fn get_host_cpu_phys_bits() -> u8 {
     }
 }
 
+/// Present or hide the TSC-deadline mode of the local APIC timer to the
+/// guest, through CPUID leaf 1.
+pub fn update_cpuid_tsc_deadline_timer(cpuid: &mut CpuId, enabled: bool) {
+    for entry in cpuid.as_mut_slice().iter_mut() {
+        if entry.function == 1 && entry.index == 0 {
+            if enabled {
+                entry.ecx |= 1 << TSC_DEADLINE_TIMER_ECX_BIT;
+            } else {
+                entry.ecx &= !(1 << TSC_DEADLINE_TIMER_ECX_BIT);
+            }
+        }
+    }
+}
+
 fn update_cpuid_topology(
     cpuid: &mut CpuId,
     threads_per_core: u8,
@@ -1196,6 +1241,9 @@ mod tests {
             Some(layout::RSDP_POINTER),
             None,
             None,
+            None,
+            None,
+            &[],
         );
         assert!(config_err.is_err());
 
@@ -1209,7 +1257,19 @@ mod tests {
             .collect();
         let gm = GuestMemoryMmap::from_ranges(&ram_regions).unwrap();
 
-        configure_system(&gm, GuestAddress(0), &None, no_vcpus, None, None, None).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            &None,
+            no_vcpus,
+            None,
+            None,
+            None,
+            None,
+            None,
+            &[],
+        )
+        .unwrap();
 
         // Now assigning some memory that is equal to the start of the 32bit memory hole.
         let mem_size = 3328 << 20;
@@ -1220,9 +1280,33 @@ mod tests {
             .map(|r| (r.0, r.1))
             .collect();
         let gm = GuestMemoryMmap::from_ranges(&ram_regions).unwrap();
-        configure_system(&gm, GuestAddress(0), &None, no_vcpus, None, None, None).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            &None,
+            no_vcpus,
+            None,
+            None,
+            None,
+            None,
+            None,
+            &[],
+        )
+        .unwrap();
 
-        configure_system(&gm, GuestAddress(0), &None, no_vcpus, None, None, None).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            &None,
+            no_vcpus,
+            None,
+            None,
+            None,
+            None,
+            None,
+            &[],
+        )
+        .unwrap();
 
         // Now assigning some memory that falls after the 32bit memory hole.
         let mem_size = 3330 << 20;
@@ -1233,9 +1317,33 @@ mod tests {
             .map(|r| (r.0, r.1))
             .collect();
         let gm = GuestMemoryMmap::from_ranges(&ram_regions).unwrap();
-        configure_system(&gm, GuestAddress(0), &None, no_vcpus, None, None, None).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            &None,
+            no_vcpus,
+            None,
+            None,
+            None,
+            None,
+            None,
+            &[],
+        )
+        .unwrap();
 
-        configure_system(&gm, GuestAddress(0), &None, no_vcpus, None, None, None).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            &None,
+            no_vcpus,
+            None,
+            None,
+            None,
+            None,
+            None,
+            &[],
+        )
+        .unwrap();
     }
 
     #[test]
@@ -1262,4 +1370,92 @@ mod tests {
 
         assert_eq!(format!("{:?}", memmap), format!("{:?}", expected_memmap));
     }
+
+    #[test]
+    fn test_guest_memory_map() {
+        let mem_size = 128 << 20;
+        let ram_regions: Vec<(GuestAddress, usize)> = arch_memory_regions(mem_size)
+            .iter()
+            .filter(|r| r.2 == RegionType::Ram)
+            .map(|r| (r.0, r.1))
+            .collect();
+        let gm = GuestMemoryMmap::from_ranges(&ram_regions).unwrap();
+
+        let memory_map = guest_memory_map(&gm, None, &[]);
+        assert_eq!(memory_map.len(), 3);
+        assert_eq!(memory_map[0].start, 0);
+        assert_eq!(memory_map[0].size, layout::EBDA_START.raw_value());
+        assert_eq!(memory_map[1].start, layout::HIGH_RAM_START.raw_value());
+        assert_eq!(
+            memory_map[1].start + memory_map[1].size,
+            gm.last_addr().raw_value() + 1
+        );
+        assert_eq!(memory_map[2].start, layout::PCI_MMCONFIG_START.0);
+        assert_eq!(memory_map[2].entry_type, MemoryMapEntryType::Reserved);
+        assert!(memory_map[..2]
+            .iter()
+            .all(|e| e.entry_type == MemoryMapEntryType::Ram && e.numa_node.is_none()));
+    }
+
+    #[test]
+    fn test_guest_memory_map_reserved() {
+        let mem_size = 128 << 20;
+        let ram_regions: Vec<(GuestAddress, usize)> = arch_memory_regions(mem_size)
+            .iter()
+            .filter(|r| r.2 == RegionType::Ram)
+            .map(|r| (r.0, r.1))
+            .collect();
+        let gm = GuestMemoryMmap::from_ranges(&ram_regions).unwrap();
+
+        let memory_map = guest_memory_map(&gm, None, &[(64 << 20, 2 << 20), (96 << 20, 4 << 20)]);
+        let entries: Vec<(u64, u64, MemoryMapEntryType)> = memory_map
+            .iter()
+            .map(|e| (e.start, e.size, e.entry_type))
+            .collect();
+        let high_ram_start = layout::HIGH_RAM_START.raw_value();
+        assert_eq!(
+            entries,
+            vec![
+                (0, layout::EBDA_START.raw_value(), MemoryMapEntryType::Ram),
+                (
+                    high_ram_start,
+                    (64 << 20) - high_ram_start,
+                    MemoryMapEntryType::Ram
+                ),
+                (64 << 20, 2 << 20, MemoryMapEntryType::Reserved),
+                (66 << 20, 30 << 20, MemoryMapEntryType::Ram),
+                (96 << 20, 4 << 20, MemoryMapEntryType::Reserved),
+                (100 << 20, 28 << 20, MemoryMapEntryType::Ram),
+                (
+                    layout::PCI_MMCONFIG_START.0,
+                    layout::PCI_MMCONFIG_SIZE,
+                    MemoryMapEntryType::Reserved
+                ),
+            ]
+        );
+
+        // The e820 table written for the guest reports the range as reserved.
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            &None,
+            1,
+            None,
+            None,
+            None,
+            None,
+            None,
+            &[(64 << 20, 2 << 20)],
+        )
+        .unwrap();
+        let entry: MemmapTableEntryWrapper = gm
+            .read_obj(
+                layout::MEMMAP_START
+                    .unchecked_add(2 * mem::size_of::<hvm_memmap_table_entry>() as u64),
+            )
+            .unwrap();
+        assert_eq!(entry.0.addr, 64 << 20);
+        assert_eq!(entry.0.size, 2 << 20);
+        assert_eq!(entry.0.type_, E820_RESERVED);
+    }
 }
@@ -54,6 +54,7 @@ pub type Result<T> = result::Result<T, Error>;
 const SM3_MAGIC_IDENT: &[u8; 5usize] = b"_SM3_";
 const BIOS_INFORMATION: u8 = 0;
 const SYSTEM_INFORMATION: u8 = 1;
+const OEM_STRINGS: u8 = 11;
 const END_OF_TABLE: u8 = 127;
 const PCI_SUPPORTED: u64 = 1 << 7;
 const IS_VIRTUAL_MACHINE: u8 = 1 << 4;
@@ -133,10 +134,26 @@ impl Clone for SmbiosSysInfo {
     }
 }
 
+#[repr(packed)]
+#[derive(Default, Copy)]
+pub struct SmbiosOemStrings {
+    pub typ: u8,
+    pub length: u8,
+    pub handle: u16,
+    pub count: u8,
+}
+
+impl Clone for SmbiosOemStrings {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
 // SAFETY: These data structures only contain a series of integers
 unsafe impl ByteValued for Smbios30Entrypoint {}
 unsafe impl ByteValued for SmbiosBiosInfo {}
 unsafe impl ByteValued for SmbiosSysInfo {}
+unsafe impl ByteValued for SmbiosOemStrings {}
 
 fn write_and_incr<T: ByteValued>(
     mem: &GuestMemoryMmap,
@@ -162,7 +179,14 @@ fn write_string(
     Ok(curptr)
 }
 
-pub fn setup_smbios(mem: &GuestMemoryMmap, serial_number: Option<&str>) -> Result<u64> {
+/// Write the SMBIOS tables. `uuid` is expected in the SMBIOS wire format,
+/// meaning with its first three fields encoded as little endian.
+pub fn setup_smbios(
+    mem: &GuestMemoryMmap,
+    serial_number: Option<&str>,
+    uuid: Option<[u8; 16]>,
+    oem_strings: Option<&[&str]>,
+) -> Result<u64> {
     let physptr = GuestAddress(SMBIOS_START)
         .checked_add(mem::size_of::<Smbios30Entrypoint>() as u64)
         .ok_or(Error::NotEnoughMemory)?;
@@ -196,6 +220,7 @@ pub fn setup_smbios(mem: &GuestMemoryMmap, serial_number: Option<&str>) -> Resul
             manufacturer: 1, // First string written in this section
             product_name: 2, // Second string written in this section
             serial_number: serial_number.map(|_| 3).unwrap_or_default(), // 3rd string
+            uuid: uuid.unwrap_or_default(),
             ..Default::default()
         };
         curptr = write_and_incr(mem, smbios_sysinfo, curptr)?;
@@ -207,6 +232,21 @@ pub fn setup_smbios(mem: &GuestMemoryMmap, serial_number: Option<&str>) -> Resul
         curptr = write_and_incr(mem, 0u8, curptr)?;
     }
 
+    if let Some(oem_strings) = oem_strings.filter(|s| !s.is_empty()) {
+        handle += 1;
+        let smbios_oemstrings = SmbiosOemStrings {
+            typ: OEM_STRINGS,
+            length: mem::size_of::<SmbiosOemStrings>() as u8,
+            handle,
+            count: oem_strings.len() as u8,
+        };
+        curptr = write_and_incr(mem, smbios_oemstrings, curptr)?;
+        for s in oem_strings {
+            curptr = write_string(mem, s, curptr)?;
+        }
+        curptr = write_and_incr(mem, 0u8, curptr)?;
+    }
+
     {
         handle += 1;
         let smbios_sysinfo = SmbiosSysInfo {
@@ -261,16 +301,61 @@ mod tests {
             0x1busize,
             concat!("Size of: ", stringify!(SmbiosSysInfo))
         );
+        assert_eq!(
+            mem::size_of::<SmbiosOemStrings>(),
+            0x5usize,
+            concat!("Size of: ", stringify!(SmbiosOemStrings))
+        );
     }
 
     #[test]
     fn entrypoint_checksum() {
         let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(SMBIOS_START), 4096)]).unwrap();
 
-        setup_smbios(&mem, None).unwrap();
+        setup_smbios(&mem, None, None, None).unwrap();
 
         let smbios_ep: Smbios30Entrypoint = mem.read_obj(GuestAddress(SMBIOS_START)).unwrap();
 
         assert_eq!(compute_checksum(&smbios_ep), 0);
     }
+
+    #[test]
+    fn oem_strings_table() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(SMBIOS_START), 4096)]).unwrap();
+        let oem_strings = ["foo", "bar"];
+
+        let size = setup_smbios(&mem, None, None, Some(&oem_strings)).unwrap();
+
+        // Walk the tables up to the OEM strings one, each of them being
+        // followed by its strings and an extra NUL byte.
+        let mut addr = GuestAddress(SMBIOS_START + mem::size_of::<Smbios30Entrypoint>() as u64);
+        let end = addr.unchecked_add(size);
+        let mut oem_table = None;
+        while addr < end {
+            let typ: u8 = mem.read_obj(addr).unwrap();
+            let length: u8 = mem.read_obj(addr.unchecked_add(1)).unwrap();
+            if typ == OEM_STRINGS {
+                oem_table = Some(addr);
+                break;
+            }
+            addr = addr.unchecked_add(length as u64);
+            while mem.read_obj::<u16>(addr).unwrap() != 0 {
+                addr = addr.unchecked_add(1);
+            }
+            addr = addr.unchecked_add(2);
+        }
+
+        let addr = oem_table.expect("no OEM strings table");
+        let table: SmbiosOemStrings = mem.read_obj(addr).unwrap();
+        assert_eq!(table.length as usize, mem::size_of::<SmbiosOemStrings>());
+        assert_eq!(table.count, 2);
+
+        let mut strings = vec![0u8; 9];
+        mem.read_slice(
+            &mut strings,
+            addr.unchecked_add(mem::size_of::<SmbiosOemStrings>() as u64),
+        )
+        .unwrap();
+        assert_eq!(&strings, b"foo\0bar\0\0");
+    }
 }
@@ -450,6 +450,14 @@ impl Request {
         Ok(true)
     }
 
+    /// Total length of the data descriptors of the request.
+    pub fn data_len(&self) -> u64 {
+        self.data_descriptors
+            .iter()
+            .map(|(_, len)| u64::from(*len))
+            .sum()
+    }
+
     pub fn complete_async(&mut self) -> result::Result<(), Error> {
         for aligned_operation in self.aligned_operations.drain(..) {
             // We need to perform the copy after the data has been read inside
@@ -13,6 +13,8 @@ mod fwdebug;
 #[cfg(target_arch = "aarch64")]
 mod gpio_pl061;
 mod i8042;
+#[cfg(target_arch = "x86_64")]
+mod pvpanic;
 #[cfg(target_arch = "aarch64")]
 mod rtc_pl031;
 mod serial;
@@ -25,6 +27,8 @@ pub use self::debug_port::DebugPort;
 #[cfg(feature = "fwdebug")]
 pub use self::fwdebug::FwDebugDevice;
 pub use self::i8042::I8042Device;
+#[cfg(target_arch = "x86_64")]
+pub use self::pvpanic::{PvPanic, PvPanicEvent, PVPANIC_CRASH_LOADED, PVPANIC_PANICKED};
 pub use self::serial::Serial;
 
 #[cfg(target_arch = "aarch64")]
@@ -0,0 +1,114 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use std::sync::{Arc, Barrier};
+use std::time::{Duration, Instant};
+use vm_device::BusDevice;
+use vmm_sys_util::eventfd::EventFd;
+
+/// The guest kernel panicked.
+pub const PVPANIC_PANICKED: u8 = 1 << 0;
+/// The guest kernel panicked and a crash kernel has been loaded to collect
+/// a crash dump.
+pub const PVPANIC_CRASH_LOADED: u8 = 1 << 1;
+
+const PVPANIC_SUPPORTED_EVENTS: u8 = PVPANIC_PANICKED | PVPANIC_CRASH_LOADED;
+
+/// Last event reported by the guest through the pvpanic device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PvPanicEvent {
+    /// Raw event flags as written by the guest.
+    pub flags: u8,
+    /// Time elapsed between the VMM start and the notification.
+    pub timestamp: Duration,
+}
+
+/// ISA pvpanic device, compatible with the one provided by QEMU. Reading the
+/// port returns the events the device supports, writing to it notifies the
+/// VMM of a guest panic through `panic_evt`.
+pub struct PvPanic {
+    timestamp: Instant,
+    panic_evt: EventFd,
+    last_event: Option<PvPanicEvent>,
+}
+
+impl PvPanic {
+    pub fn new(timestamp: Instant, panic_evt: EventFd) -> Self {
+        Self {
+            timestamp,
+            panic_evt,
+            last_event: None,
+        }
+    }
+
+    pub fn last_event(&self) -> Option<PvPanicEvent> {
+        self.last_event
+    }
+
+    pub fn clear_last_event(&mut self) -> Option<PvPanicEvent> {
+        self.last_event.take()
+    }
+}
+
+impl BusDevice for PvPanic {
+    fn read(&mut self, _base: u64, _offset: u64, data: &mut [u8]) {
+        if data.len() == 1 {
+            data[0] = PVPANIC_SUPPORTED_EVENTS;
+        }
+    }
+
+    fn write(&mut self, _base: u64, _offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+        if data.len() != 1 {
+            return None;
+        }
+
+        let flags = data[0] & PVPANIC_SUPPORTED_EVENTS;
+        if flags == 0 {
+            return None;
+        }
+
+        if flags & PVPANIC_CRASH_LOADED != 0 {
+            warn!("Guest panicked, crash kernel loaded");
+        } else {
+            warn!("Guest panicked");
+        }
+
+        self.last_event = Some(PvPanicEvent {
+            flags,
+            timestamp: self.timestamp.elapsed(),
+        });
+        if let Err(e) = self.panic_evt.write(1) {
+            error!("Error triggering guest panic event: {}", e);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pvpanic() {
+        let panic_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut pvpanic = PvPanic::new(Instant::now(), panic_evt.try_clone().unwrap());
+
+        let mut data = [0u8];
+        pvpanic.read(0, 0, &mut data);
+        assert_eq!(data[0], PVPANIC_PANICKED | PVPANIC_CRASH_LOADED);
+
+        // Unsupported events and accesses are ignored.
+        pvpanic.write(0, 0, &[1 << 4]);
+        pvpanic.write(0, 0, &[PVPANIC_PANICKED, 0]);
+        assert!(pvpanic.last_event().is_none());
+        assert!(panic_evt.read().is_err());
+
+        pvpanic.write(0, 0, &[PVPANIC_CRASH_LOADED | 1 << 4]);
+        assert_eq!(pvpanic.last_event().unwrap().flags, PVPANIC_CRASH_LOADED);
+        assert_eq!(panic_evt.read().unwrap(), 1);
+
+        assert!(pvpanic.clear_last_event().is_some());
+        assert!(pvpanic.last_event().is_none());
+    }
+}
@@ -102,6 +102,7 @@ fuzz_target!(|bytes| {
         256,
         SeccompAction::Allow,
         None,
+        u64::MAX,
         EventFd::new(EFD_NONBLOCK).unwrap(),
     )
     .unwrap();
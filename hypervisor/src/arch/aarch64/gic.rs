@@ -10,6 +10,9 @@ use std::sync::Arc;
 pub enum Error {
     /// Error while calling KVM ioctl for setting up the global interrupt controller.
     CreateGic(HypervisorVmError),
+    /// Error while creating the ITS, likely because the host GIC doesn't
+    /// provide one.
+    CreateIts(HypervisorVmError),
     /// Error while setting device attributes for the GIC.
     SetDeviceAttribute(HypervisorDeviceError),
     /// Error while getting device attributes for the GIC.
@@ -263,6 +263,8 @@ pub enum VmExit<'a> {
     Ignore,
     Reset,
     Shutdown,
+    #[cfg(target_arch = "x86_64")]
+    TripleFault,
     Hyperv,
     #[cfg(feature = "tdx")]
     Tdx,
@@ -167,9 +167,11 @@ impl KvmGicV3Its {
             flags: 0,
         };
 
+        // The ITS is needed for the guest to use MSIs, which is the only way
+        // PCI devices can signal interrupts since no INTx mapping is exposed.
         let its_fd = vm
             .create_device(&mut its_device)
-            .map_err(Error::CreateGic)?;
+            .map_err(Error::CreateIts)?;
 
         Self::set_device_attribute(
             &its_fd,
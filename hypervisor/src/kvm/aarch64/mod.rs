@@ -111,6 +111,29 @@ macro_rules! arm64_sys_reg {
 // https://elixir.bootlin.com/linux/v4.20.17/source/arch/arm64/include/asm/sysreg.h#L135
 arm64_sys_reg!(MPIDR_EL1, 3, 0, 0, 0, 5);
 
+// PMU registers, from the same file.
+arm64_sys_reg!(PMCR_EL0, 3, 3, 9, 12, 0);
+arm64_sys_reg!(PMCNTENSET_EL0, 3, 3, 9, 12, 1);
+arm64_sys_reg!(PMCCNTR_EL0, 3, 3, 9, 13, 0);
+arm64_sys_reg!(PMEVCNTR0_EL0, 3, 3, 14, 8, 0);
+arm64_sys_reg!(PMEVTYPER0_EL0, 3, 3, 14, 12, 0);
+
+// Event counter registers are indexed through CRm[1:0] and op2.
+fn pmu_counter_reg(reg0: u64, n: u64) -> u64 {
+    reg0 | (((n >> 3) << KVM_REG_ARM64_SYSREG_CRM_SHIFT) & KVM_REG_ARM64_SYSREG_CRM_MASK as u64)
+        | (((n & 0x7) << KVM_REG_ARM64_SYSREG_OP2_SHIFT) & KVM_REG_ARM64_SYSREG_OP2_MASK as u64)
+}
+
+/// ID of the PMEVCNTR<n>_EL0 register, the value of event counter `n`.
+pub fn pmevcntr_el0(n: u64) -> u64 {
+    pmu_counter_reg(PMEVCNTR0_EL0, n)
+}
+
+/// ID of the PMEVTYPER<n>_EL0 register, the event counted by counter `n`.
+pub fn pmevtyper_el0(n: u64) -> u64 {
+    pmu_counter_reg(PMEVTYPER0_EL0, n)
+}
+
 /// Specifies whether a particular register is a system register or not.
 /// The kernel splits the registers on aarch64 in core registers and system registers.
 /// So, below we get the system registers by checking that they are not core registers.
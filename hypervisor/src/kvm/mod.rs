@@ -26,7 +26,7 @@ use crate::vm::{self, InterruptSourceConfig, VmOps};
 use crate::{arm64_core_reg_id, offset__of};
 use kvm_ioctls::{NoDatamatch, VcpuFd, VmFd};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 #[cfg(target_arch = "aarch64")]
 use std::convert::TryInto;
 #[cfg(target_arch = "x86_64")]
@@ -34,10 +34,9 @@ use std::fs::File;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::result;
 #[cfg(target_arch = "x86_64")]
-use std::sync::atomic::{AtomicBool, Ordering};
-#[cfg(target_arch = "aarch64")]
-use std::sync::Mutex;
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use vmm_sys_util::eventfd::EventFd;
 // x86_64 dependencies
 #[cfg(target_arch = "x86_64")]
@@ -48,8 +47,8 @@ use crate::arch::x86::NUM_IOAPIC_PINS;
 use aarch64::{RegList, Register, StandardRegisters};
 #[cfg(target_arch = "x86_64")]
 use kvm_bindings::{
-    kvm_enable_cap, kvm_guest_debug, kvm_msr_entry, MsrList, KVM_CAP_HYPERV_SYNIC,
-    KVM_CAP_SPLIT_IRQCHIP, KVM_GUESTDBG_ENABLE, KVM_GUESTDBG_SINGLESTEP, KVM_GUESTDBG_USE_HW_BP,
+    kvm_guest_debug, kvm_msr_entry, MsrList, KVM_CAP_HYPERV_SYNIC, KVM_CAP_SPLIT_IRQCHIP,
+    KVM_GUESTDBG_ENABLE, KVM_GUESTDBG_SINGLESTEP, KVM_GUESTDBG_USE_HW_BP,
 };
 #[cfg(target_arch = "x86_64")]
 use x86_64::{check_required_kvm_extensions, FpuState, SpecialRegisters, StandardRegisters};
@@ -62,13 +61,12 @@ pub use x86_64::{
 #[cfg(target_arch = "aarch64")]
 pub mod aarch64;
 pub use kvm_bindings;
-#[cfg(feature = "tdx")]
-use kvm_bindings::KVMIO;
 pub use kvm_bindings::{
     kvm_create_device, kvm_device_type_KVM_DEV_TYPE_VFIO, kvm_irq_routing, kvm_irq_routing_entry,
     kvm_userspace_memory_region, KVM_IRQ_ROUTING_IRQCHIP, KVM_IRQ_ROUTING_MSI,
     KVM_MEM_LOG_DIRTY_PAGES, KVM_MEM_READONLY, KVM_MSI_VALID_DEVID,
 };
+use kvm_bindings::{kvm_enable_cap, KVMIO};
 #[cfg(target_arch = "aarch64")]
 use kvm_bindings::{
     kvm_regs, user_fpsimd_state, user_pt_regs, KVM_NR_SPSR, KVM_REG_ARM64, KVM_REG_ARM_CORE,
@@ -76,11 +74,14 @@ use kvm_bindings::{
 };
 pub use kvm_ioctls;
 pub use kvm_ioctls::{Cap, Kvm};
-#[cfg(target_arch = "aarch64")]
 use std::mem;
 use thiserror::Error;
 #[cfg(feature = "tdx")]
-use vmm_sys_util::{ioctl::ioctl_with_val, ioctl_expr, ioctl_ioc_nr, ioctl_iowr_nr};
+use vmm_sys_util::ioctl_iowr_nr;
+use vmm_sys_util::{
+    ioctl::{ioctl, ioctl_with_val},
+    ioctl_expr, ioctl_io_nr, ioctl_ioc_nr,
+};
 ///
 /// Export generically-named wrappers of kvm-bindings for Unix-based platforms
 ///
@@ -95,6 +96,17 @@ pub use {
 
 #[cfg(target_arch = "x86_64")]
 const KVM_CAP_SGX_ATTRIBUTE: u32 = 196;
+const KVM_CAP_HALT_POLL: u32 = 182;
+#[cfg(target_arch = "x86_64")]
+const KVM_CAP_DIRTY_LOG_RING: u32 = 192;
+const KVM_CAP_DIRTY_LOG_RING_ACQ_REL: u32 = 223;
+const KVM_EXIT_DIRTY_RING_FULL: u32 = 31;
+// Offset, in pages, of the dirty ring in the vCPU file mapping.
+const KVM_DIRTY_LOG_PAGE_OFFSET: i64 = 64;
+const KVM_DIRTY_GFN_F_DIRTY: u32 = 1 << 0;
+const KVM_DIRTY_GFN_F_RESET: u32 = 1 << 1;
+// Size of the guest pages tracked by the dirty log.
+const DIRTY_LOG_PAGE_SIZE: u64 = 4096;
 
 #[cfg(feature = "tdx")]
 const KVM_EXIT_TDX: u32 = 35;
@@ -110,6 +122,9 @@ const TDG_VP_VMCALL_INVALID_OPERAND: u64 = 0x8000000000000000;
 #[cfg(feature = "tdx")]
 ioctl_iowr_nr!(KVM_MEMORY_ENCRYPT_OP, KVMIO, 0xba, std::os::raw::c_ulong);
 
+ioctl_io_nr!(KVM_CHECK_EXTENSION, KVMIO, 0x03);
+ioctl_io_nr!(KVM_RESET_DIRTY_RINGS, KVMIO, 0xc7);
+
 #[cfg(feature = "tdx")]
 #[repr(u32)]
 enum TdxCommand {
@@ -172,6 +187,145 @@ struct KvmDirtyLogSlot {
     userspace_addr: u64,
 }
 
+// Entry of a vCPU dirty ring, as defined by KVM.
+#[repr(C)]
+struct KvmDirtyGfn {
+    flags: u32,
+    slot: u32,
+    offset: u64,
+}
+
+// Dirty ring of a vCPU, shared with KVM through the vCPU file mapping.
+struct KvmDirtyRing {
+    gfns: *mut KvmDirtyGfn,
+    entries: u32,
+    // Index of the next entry to collect.
+    next: u32,
+}
+
+// SAFETY: The ring is only accessed through the mutex of KvmDirtyRings.
+unsafe impl Send for KvmDirtyRing {}
+
+impl KvmDirtyRing {
+    fn new(vcpu_fd: &VcpuFd, entries: u32) -> std::io::Result<Self> {
+        // SAFETY: sysconf() has no side effect.
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        // SAFETY: Mapping the dirty ring KVM exposes through the vCPU file,
+        // whose size was given to KVM_CAP_DIRTY_LOG_RING, the result is
+        // checked below.
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                entries as usize * mem::size_of::<KvmDirtyGfn>(),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                vcpu_fd.as_raw_fd(),
+                KVM_DIRTY_LOG_PAGE_OFFSET * page_size,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(KvmDirtyRing {
+            gfns: addr as *mut KvmDirtyGfn,
+            entries,
+            next: 0,
+        })
+    }
+
+    // Collect the entries KVM pushed to the ring into `dirty`, flagging
+    // them to be reset. Returns the number of entries collected.
+    fn collect(&mut self, dirty: &mut HashMap<u32, HashSet<u64>>) -> usize {
+        let mut count = 0;
+        loop {
+            // SAFETY: The index is within the mapped ring.
+            let gfn = unsafe { self.gfns.add((self.next % self.entries) as usize) };
+            // SAFETY: The flags are shared with KVM, hence accessed atomically.
+            let flags = unsafe { &*(std::ptr::addr_of!((*gfn).flags) as *const AtomicU32) };
+            if flags.load(Ordering::Acquire) & KVM_DIRTY_GFN_F_DIRTY == 0 {
+                break;
+            }
+
+            // SAFETY: KVM doesn't touch an entry until it's been reset.
+            let (slot, offset) = unsafe { ((*gfn).slot, (*gfn).offset) };
+            // The upper half of the slot holds the address space id.
+            dirty.entry(slot & 0xffff).or_default().insert(offset);
+            flags.store(KVM_DIRTY_GFN_F_RESET, Ordering::Release);
+
+            self.next = self.next.wrapping_add(1);
+            count += 1;
+        }
+
+        count
+    }
+}
+
+impl Drop for KvmDirtyRing {
+    fn drop(&mut self) {
+        // SAFETY: Unmapping the ring mapped by new().
+        unsafe {
+            libc::munmap(
+                self.gfns as *mut libc::c_void,
+                self.entries as usize * mem::size_of::<KvmDirtyGfn>(),
+            );
+        }
+    }
+}
+
+// Dirty rings of all the vCPUs, along with the pages collected from them
+// which haven't been reported yet, indexed by memory slot.
+struct KvmDirtyRings {
+    vm_fd: Arc<VmFd>,
+    entries: u32,
+    rings: Vec<KvmDirtyRing>,
+    dirty: HashMap<u32, HashSet<u64>>,
+}
+
+impl KvmDirtyRings {
+    // Collect the dirty pages from all the rings, and let KVM reuse the
+    // entries collected.
+    fn collect(&mut self) -> std::io::Result<()> {
+        let mut count = 0;
+        for ring in self.rings.iter_mut() {
+            count += ring.collect(&mut self.dirty);
+        }
+
+        if count > 0 {
+            // SAFETY: The ioctl only resets the entries flagged as such.
+            let ret = unsafe { ioctl(self.vm_fd.as_ref(), KVM_RESET_DIRTY_RINGS()) };
+            if ret < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Capability to enable the dirty rings with, along with the maximum size
+// of a ring in bytes. The rings are always accessed with acquire/release
+// ordering, which is the only flavour KVM offers on weakly ordered
+// architectures. The original capability, which assumes a strongly ordered
+// architecture, is only used on x86_64 kernels older than 6.1.
+fn dirty_ring_capability(vm_fd: &VmFd) -> Option<(u32, u64)> {
+    let caps = [
+        KVM_CAP_DIRTY_LOG_RING_ACQ_REL,
+        #[cfg(target_arch = "x86_64")]
+        KVM_CAP_DIRTY_LOG_RING,
+    ];
+    caps.iter().find_map(|cap| {
+        // SAFETY: The extension check has no side effect.
+        let max_size =
+            unsafe { ioctl_with_val(vm_fd, KVM_CHECK_EXTENSION(), *cap as libc::c_ulong) };
+        if max_size > 0 {
+            Some((*cap, max_size as u64))
+        } else {
+            None
+        }
+    })
+}
+
 /// Wrapper over KVM VM ioctls.
 pub struct KvmVm {
     fd: Arc<VmFd>,
@@ -179,6 +333,8 @@ pub struct KvmVm {
     msrs: MsrEntries,
     state: KvmVmState,
     dirty_log_slots: Arc<RwLock<HashMap<u32, KvmDirtyLogSlot>>>,
+    // Set when the dirty pages are tracked through dirty rings.
+    dirty_rings: Arc<Mutex<Option<KvmDirtyRings>>>,
 }
 
 ///
@@ -246,6 +402,11 @@ impl vm::Vm for KvmVm {
             .fd
             .create_vcpu(id as u64)
             .map_err(|e| vm::HypervisorVmError::CreateVcpu(e.into()))?;
+        if let Some(dirty_rings) = self.dirty_rings.lock().unwrap().as_mut() {
+            let ring = KvmDirtyRing::new(&vc, dirty_rings.entries)
+                .map_err(|e| vm::HypervisorVmError::CreateVcpu(e.into()))?;
+            dirty_rings.rings.push(ring);
+        }
         let vcpu = KvmVcpu {
             fd: vc,
             #[cfg(target_arch = "x86_64")]
@@ -253,6 +414,7 @@ impl vm::Vm for KvmVm {
             vm_ops,
             #[cfg(target_arch = "x86_64")]
             hyperv_synic: AtomicBool::new(false),
+            dirty_rings: self.dirty_rings.clone(),
         };
         Ok(Arc::new(vcpu))
     }
@@ -523,6 +685,62 @@ impl vm::Vm for KvmVm {
             .map_err(|e| vm::HypervisorVmError::EnableSgxAttribute(e.into()))?;
         Ok(())
     }
+    /// Set the maximum halt polling interval of the VM vCPUs, which
+    /// overrides the host wide KVM default. Requires Linux 5.16 or newer.
+    fn set_halt_poll_ns(&self, ns: u32) -> vm::Result<()> {
+        let mut cap = kvm_enable_cap {
+            cap: KVM_CAP_HALT_POLL,
+            ..Default::default()
+        };
+        cap.args[0] = ns as u64;
+        self.fd.enable_cap(&cap).map_err(|e| {
+            if e.errno() == libc::EINVAL {
+                vm::HypervisorVmError::HaltPollUnsupported
+            } else {
+                vm::HypervisorVmError::SetHaltPoll(e.into())
+            }
+        })
+    }
+    /// Track the dirty pages through per vCPU rings of `entries` entries,
+    /// which must be a power of two. Requires Linux 5.11 or newer on
+    /// x86_64, and 6.1 or newer on aarch64.
+    fn enable_dirty_ring(&self, entries: u32) -> vm::Result<()> {
+        let (capability, max_size) = dirty_ring_capability(self.fd.as_ref())
+            .ok_or(vm::HypervisorVmError::DirtyRingUnsupported)?;
+
+        let size = entries as u64 * mem::size_of::<KvmDirtyGfn>() as u64;
+        if !entries.is_power_of_two() || size > max_size {
+            return Err(vm::HypervisorVmError::EnableDirtyRing(anyhow!(
+                "Invalid number of dirty ring entries {}, up to {} supported",
+                entries,
+                max_size / mem::size_of::<KvmDirtyGfn>() as u64
+            )));
+        }
+
+        let mut dirty_rings = self.dirty_rings.lock().unwrap();
+        if dirty_rings.is_some() {
+            return Ok(());
+        }
+
+        // KVM refuses this once vCPUs have been created.
+        let mut cap = kvm_enable_cap {
+            cap: capability,
+            ..Default::default()
+        };
+        cap.args[0] = size;
+        self.fd
+            .enable_cap(&cap)
+            .map_err(|e| vm::HypervisorVmError::EnableDirtyRing(e.into()))?;
+
+        *dirty_rings = Some(KvmDirtyRings {
+            vm_fd: self.fd.clone(),
+            entries,
+            rings: Vec::new(),
+            dirty: HashMap::new(),
+        });
+
+        Ok(())
+    }
     /// Retrieve guest clock.
     #[cfg(target_arch = "x86_64")]
     fn get_clock(&self) -> vm::Result<ClockData> {
@@ -586,6 +804,14 @@ impl vm::Vm for KvmVm {
             }
         }
 
+        // Start over from clean dirty rings.
+        if let Some(dirty_rings) = self.dirty_rings.lock().unwrap().as_mut() {
+            dirty_rings
+                .collect()
+                .map_err(|e| vm::HypervisorVmError::StartDirtyLog(e.into()))?;
+            dirty_rings.dirty.clear();
+        }
+
         Ok(())
     }
 
@@ -617,6 +843,24 @@ impl vm::Vm for KvmVm {
     /// Get dirty pages bitmap (one bit per page)
     ///
     fn get_dirty_log(&self, slot: u32, _base_gpa: u64, memory_size: u64) -> vm::Result<Vec<u64>> {
+        if let Some(dirty_rings) = self.dirty_rings.lock().unwrap().as_mut() {
+            dirty_rings
+                .collect()
+                .map_err(|e| vm::HypervisorVmError::GetDirtyLog(e.into()))?;
+
+            // Report the pages collected from the rings as a bitmap, just
+            // like KVM_GET_DIRTY_LOG does.
+            let pages = memory_size / DIRTY_LOG_PAGE_SIZE;
+            let mut bitmap = vec![0u64; ((pages + 63) / 64) as usize];
+            if let Some(offsets) = dirty_rings.dirty.remove(&slot) {
+                for offset in offsets.into_iter().filter(|o| *o < pages) {
+                    bitmap[(offset / 64) as usize] |= 1 << (offset % 64);
+                }
+            }
+
+            return Ok(bitmap);
+        }
+
         self.fd
             .get_dirty_log(slot, memory_size as usize)
             .map_err(|e| vm::HypervisorVmError::GetDirtyLog(e.into()))
@@ -811,6 +1055,7 @@ impl hypervisor::Hypervisor for KvmHypervisor {
                 msrs,
                 state: VmState {},
                 dirty_log_slots: Arc::new(RwLock::new(HashMap::new())),
+                dirty_rings: Arc::new(Mutex::new(None)),
             }))
         }
 
@@ -820,6 +1065,7 @@ impl hypervisor::Hypervisor for KvmHypervisor {
                 fd: vm_fd,
                 state: VmState {},
                 dirty_log_slots: Arc::new(RwLock::new(HashMap::new())),
+                dirty_rings: Arc::new(Mutex::new(None)),
             }))
         }
     }
@@ -908,6 +1154,7 @@ pub struct KvmVcpu {
     vm_ops: Option<Arc<dyn vm::VmOps>>,
     #[cfg(target_arch = "x86_64")]
     hyperv_synic: AtomicBool,
+    dirty_rings: Arc<Mutex<Option<KvmDirtyRings>>>,
 }
 /// Implementation of Vcpu trait for KVM
 /// Example:
@@ -1168,7 +1415,9 @@ impl cpu::Vcpu for KvmVcpu {
                 #[cfg(target_arch = "x86_64")]
                 VcpuExit::IoapicEoi(vector) => Ok(cpu::VmExit::IoapicEoi(vector)),
                 #[cfg(target_arch = "x86_64")]
-                VcpuExit::Shutdown | VcpuExit::Hlt => Ok(cpu::VmExit::Reset),
+                VcpuExit::Shutdown => Ok(cpu::VmExit::TripleFault),
+                #[cfg(target_arch = "x86_64")]
+                VcpuExit::Hlt => Ok(cpu::VmExit::Reset),
 
                 #[cfg(target_arch = "aarch64")]
                 VcpuExit::SystemEvent(event_type, flags) => {
@@ -1212,6 +1461,16 @@ impl cpu::Vcpu for KvmVcpu {
                 #[cfg(feature = "tdx")]
                 VcpuExit::Unsupported(KVM_EXIT_TDX) => Ok(cpu::VmExit::Tdx),
                 VcpuExit::Debug(_) => Ok(cpu::VmExit::Debug),
+                // The vCPU can't run until its dirty ring has been collected.
+                VcpuExit::Unsupported(KVM_EXIT_DIRTY_RING_FULL) => {
+                    if let Some(dirty_rings) = self.dirty_rings.lock().unwrap().as_mut() {
+                        dirty_rings
+                            .collect()
+                            .map_err(|e| cpu::HypervisorCpuError::RunVcpu(e.into()))?;
+                    }
+
+                    Ok(cpu::VmExit::Ignore)
+                }
 
                 r => Err(cpu::HypervisorCpuError::RunVcpu(anyhow!(
                     "Unexpected exit reason on vcpu run: {:?}",
@@ -1936,3 +2195,131 @@ impl AsRawFd for KvmDevice {
         self.fd.as_raw_fd()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ring backed by anonymous memory, standing for the one KVM shares
+    // through the vCPU file.
+    fn anonymous_ring(entries: u32) -> KvmDirtyRing {
+        // SAFETY: Anonymous mapping, the result is checked below.
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                entries as usize * mem::size_of::<KvmDirtyGfn>(),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(addr, libc::MAP_FAILED);
+
+        KvmDirtyRing {
+            gfns: addr as *mut KvmDirtyGfn,
+            entries,
+            next: 0,
+        }
+    }
+
+    // Push an entry the way KVM does, at the given ring index.
+    fn push(ring: &KvmDirtyRing, index: u32, slot: u32, offset: u64) {
+        // SAFETY: The index is within the mapped ring.
+        unsafe {
+            *ring.gfns.add((index % ring.entries) as usize) = KvmDirtyGfn {
+                flags: KVM_DIRTY_GFN_F_DIRTY,
+                slot,
+                offset,
+            };
+        }
+    }
+
+    fn flags(ring: &KvmDirtyRing, index: u32) -> u32 {
+        // SAFETY: The index is within the mapped ring.
+        unsafe { (*ring.gfns.add((index % ring.entries) as usize)).flags }
+    }
+
+    // What KVM_RESET_DIRTY_RINGS does to the entries flagged for reset.
+    fn reset(ring: &KvmDirtyRing) {
+        for index in 0..ring.entries {
+            if flags(ring, index) == KVM_DIRTY_GFN_F_RESET {
+                // SAFETY: The index is within the mapped ring.
+                unsafe { (*ring.gfns.add(index as usize)).flags = 0 };
+            }
+        }
+    }
+
+    #[test]
+    fn test_dirty_ring_collect() {
+        let mut ring = anonymous_ring(4);
+        let mut dirty: HashMap<u32, HashSet<u64>> = HashMap::new();
+        assert_eq!(ring.collect(&mut dirty), 0);
+        assert!(dirty.is_empty());
+
+        // The address space id in the upper half of the slot is ignored.
+        push(&ring, 0, (1 << 16) | 2, 5);
+        push(&ring, 1, 3, 7);
+        push(&ring, 2, 2, 5);
+        assert_eq!(ring.collect(&mut dirty), 3);
+        assert_eq!(dirty[&2], HashSet::from([5]));
+        assert_eq!(dirty[&3], HashSet::from([7]));
+        for index in 0..3 {
+            assert_eq!(flags(&ring, index), KVM_DIRTY_GFN_F_RESET);
+        }
+        // Nothing new until KVM pushes more entries.
+        assert_eq!(ring.collect(&mut dirty), 0);
+
+        // KVM wraps around once the collected entries have been reset.
+        reset(&ring);
+        push(&ring, 3, 2, 8);
+        push(&ring, 4, 3, 9);
+        assert_eq!(ring.collect(&mut dirty), 2);
+        assert_eq!(ring.next, 5);
+        assert_eq!(dirty[&2], HashSet::from([5, 8]));
+        assert_eq!(dirty[&3], HashSet::from([7, 9]));
+    }
+
+    #[test]
+    fn test_dirty_ring_full() {
+        // The vCPU exits with KVM_EXIT_DIRTY_RING_FULL once all the entries
+        // of its ring are dirty. Collecting them must flag every entry for
+        // reset, and reset the rings so that the vCPU can run again.
+        let kvm = Kvm::new().unwrap();
+        let vm_fd = Arc::new(kvm.create_vm().unwrap());
+        let (capability, _) = match dirty_ring_capability(&vm_fd) {
+            Some(capability) => capability,
+            // Dirty rings not supported by the host kernel.
+            None => return,
+        };
+        let entries = 8;
+        let mut cap = kvm_enable_cap {
+            cap: capability,
+            ..Default::default()
+        };
+        cap.args[0] = entries as u64 * mem::size_of::<KvmDirtyGfn>() as u64;
+        vm_fd.enable_cap(&cap).unwrap();
+
+        let ring = anonymous_ring(entries);
+        for index in 0..entries {
+            push(&ring, index, 0, index as u64);
+        }
+        let mut dirty_rings = KvmDirtyRings {
+            vm_fd,
+            entries,
+            rings: vec![ring],
+            dirty: HashMap::new(),
+        };
+
+        dirty_rings.collect().unwrap();
+        assert_eq!(
+            dirty_rings.dirty[&0],
+            (0..entries as u64).collect::<HashSet<_>>()
+        );
+        let ring = &dirty_rings.rings[0];
+        assert_eq!(ring.next, entries);
+        for index in 0..entries {
+            assert_eq!(flags(ring, index), KVM_DIRTY_GFN_F_RESET);
+        }
+    }
+}
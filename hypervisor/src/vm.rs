@@ -219,6 +219,26 @@ pub enum HypervisorVmError {
     ///
     #[error("Failed to create Vgic: {0}")]
     CreateVgic(#[source] anyhow::Error),
+    ///
+    /// Halt polling control not supported
+    ///
+    #[error("Halt polling control is not supported")]
+    HaltPollUnsupported,
+    ///
+    /// Set halt polling error
+    ///
+    #[error("Failed to set halt polling interval: {0}")]
+    SetHaltPoll(#[source] anyhow::Error),
+    ///
+    /// Dirty ring not supported
+    ///
+    #[error("Dirty ring is not supported")]
+    DirtyRingUnsupported,
+    ///
+    /// Enable dirty ring error
+    ///
+    #[error("Failed to enable the dirty ring: {0}")]
+    EnableDirtyRing(#[source] anyhow::Error),
 }
 ///
 /// Result type for returning from a function
@@ -347,6 +367,15 @@ pub trait Vm: Send + Sync {
     fn stop_dirty_log(&self) -> Result<()>;
     /// Get dirty pages bitmap
     fn get_dirty_log(&self, slot: u32, base_gpa: u64, memory_size: u64) -> Result<Vec<u64>>;
+    /// Set the maximum time the vCPUs poll for a wakeup before halting
+    fn set_halt_poll_ns(&self, _ns: u32) -> Result<()> {
+        Err(HypervisorVmError::HaltPollUnsupported)
+    }
+    /// Track the dirty pages through per vCPU rings of `entries` entries
+    /// rather than bitmaps. Must be called before creating any vCPU.
+    fn enable_dirty_ring(&self, _entries: u32) -> Result<()> {
+        Err(HypervisorVmError::DirtyRingUnsupported)
+    }
     #[cfg(feature = "tdx")]
     /// Initalize TDX on this VM
     fn tdx_init(&self, cpuid: &CpuId, max_vcpus: u32) -> Result<()>;
@@ -257,6 +257,12 @@ impl TupleValue for u64 {
     }
 }
 
+impl TupleValue for ByteSized {
+    fn parse_value(input: &str) -> Result<Self, TupleError> {
+        ByteSized::from_str(input).map_err(|_| TupleError::InvalidValue(input.to_owned()))
+    }
+}
+
 impl TupleValue for Vec<u8> {
     fn parse_value(input: &str) -> Result<Self, TupleError> {
         Ok(IntegerList::from_str(input)
@@ -165,6 +165,11 @@ impl PciBus {
         Err(PciRootError::NoPciDeviceSlotAvailable)
     }
 
+    /// Number of device slots still available on the bus.
+    pub fn free_device_ids(&self) -> usize {
+        self.device_ids.iter().filter(|used| !**used).count()
+    }
+
     pub fn get_device_id(&mut self, id: usize) -> Result<()> {
         if id < NUM_DEVICE_IDS {
             if !self.device_ids[id] {
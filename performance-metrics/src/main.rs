@@ -113,6 +113,7 @@ pub struct PerformanceTestControl {
     net_rx: Option<bool>,
     fio_ops: Option<FioOps>,
     num_boot_vcpus: Option<u8>,
+    memory: Option<&'static str>,
 }
 
 impl fmt::Display for PerformanceTestControl {
@@ -133,6 +134,9 @@ impl fmt::Display for PerformanceTestControl {
         if let Some(o) = &self.fio_ops {
             output = format!("{}, fio_ops = {}", output, o);
         }
+        if let Some(o) = self.memory {
+            output = format!("{}, memory = {}", output, o);
+        }
 
         write!(f, "{}", output)
     }
@@ -148,6 +152,7 @@ impl PerformanceTestControl {
             net_rx: None,
             fio_ops: None,
             num_boot_vcpus: Some(1),
+            memory: None,
         }
     }
 }
@@ -286,6 +291,28 @@ const TEST_LIST: [PerformanceTest; 17] = [
         },
         unit_adjuster: adjuster::s_to_ms,
     },
+    PerformanceTest {
+        name: "boot_time_32g_memory_lazy_ms",
+        func_ptr: performance_boot_time_memory,
+        control: PerformanceTestControl {
+            test_timeout: 2,
+            test_iterations: 10,
+            memory: Some("size=32G"),
+            ..PerformanceTestControl::default()
+        },
+        unit_adjuster: adjuster::s_to_ms,
+    },
+    PerformanceTest {
+        name: "boot_time_32g_memory_prefault_ms",
+        func_ptr: performance_boot_time_memory,
+        control: PerformanceTestControl {
+            test_timeout: 20,
+            test_iterations: 5,
+            memory: Some("size=32G,prefault=on"),
+            ..PerformanceTestControl::default()
+        },
+        unit_adjuster: adjuster::s_to_ms,
+    },
     PerformanceTest {
         name: "virtio_net_latency_us",
         func_ptr: performance_net_latency,
@@ -393,7 +393,9 @@ pub fn performance_net_latency(control: &PerformanceTestControl) -> f64 {
     }
 }
 
-fn parse_boot_time_output(output: &[u8]) -> Result<f64, Error> {
+// Returns the time stamps of the kernel and userspace start, relative to the
+// VMM start.
+fn parse_boot_time_stamps(output: &[u8]) -> Result<(f64, f64), Error> {
     std::panic::catch_unwind(|| {
         let l: Vec<String> = String::from_utf8_lossy(output)
             .lines()
@@ -462,7 +464,7 @@ fn parse_boot_time_output(output: &[u8]) -> Result<f64, Error> {
             t[6].parse::<f64>().unwrap()
         };
 
-        time_stamp_user_start - time_stamp_kernel_start
+        (time_stamp_kernel_start, time_stamp_user_start)
     })
     .map_err(|_| {
         eprintln!(
@@ -473,7 +475,14 @@ fn parse_boot_time_output(output: &[u8]) -> Result<f64, Error> {
     })
 }
 
-fn measure_boot_time(cmd: &mut GuestCommand, test_timeout: u32) -> Result<f64, Error> {
+fn parse_boot_time_output(output: &[u8]) -> Result<f64, Error> {
+    parse_boot_time_stamps(output).map(|(kernel_start, user_start)| user_start - kernel_start)
+}
+
+fn measure_boot_time_stamps(
+    cmd: &mut GuestCommand,
+    test_timeout: u32,
+) -> Result<(f64, f64), Error> {
     let mut child = cmd
         .capture_output()
         .verbosity(VerbosityLevel::Warn)
@@ -485,7 +494,7 @@ fn measure_boot_time(cmd: &mut GuestCommand, test_timeout: u32) -> Result<f64, E
     let _ = child.kill();
     let output = child.wait_with_output().unwrap();
 
-    parse_boot_time_output(&output.stderr).map_err(|e| {
+    parse_boot_time_stamps(&output.stderr).map_err(|e| {
         eprintln!(
             "\n\n==== Start child stdout ====\n\n{}\n\n==== End child stdout ====",
             String::from_utf8_lossy(&output.stdout)
@@ -499,6 +508,11 @@ fn measure_boot_time(cmd: &mut GuestCommand, test_timeout: u32) -> Result<f64, E
     })
 }
 
+fn measure_boot_time(cmd: &mut GuestCommand, test_timeout: u32) -> Result<f64, Error> {
+    measure_boot_time_stamps(cmd, test_timeout)
+        .map(|(kernel_start, user_start)| user_start - kernel_start)
+}
+
 pub fn performance_boot_time(control: &PerformanceTestControl) -> f64 {
     let r = std::panic::catch_unwind(|| {
         let focal = UbuntuDiskConfig::new(FOCAL_IMAGE_NAME.to_string());
@@ -527,6 +541,37 @@ pub fn performance_boot_time(control: &PerformanceTestControl) -> f64 {
     }
 }
 
+// Unlike the other boot time tests, this one includes the time spent by the
+// VMM before starting the kernel, which is dominated by the guest memory
+// initialization for large guests.
+pub fn performance_boot_time_memory(control: &PerformanceTestControl) -> f64 {
+    let r = std::panic::catch_unwind(|| {
+        let focal = UbuntuDiskConfig::new(FOCAL_IMAGE_NAME.to_string());
+        let guest = performance_test_new_guest(Box::new(focal));
+        let mut cmd = GuestCommand::new(&guest);
+
+        let c = cmd
+            .args(&[
+                "--cpus",
+                &format!("boot={}", control.num_boot_vcpus.unwrap_or(1)),
+            ])
+            .args(&["--memory", control.memory.unwrap_or("size=1G")])
+            .args(&["--kernel", direct_kernel_boot_path().to_str().unwrap()])
+            .args(&["--cmdline", DIRECT_KERNEL_BOOT_CMDLINE])
+            .args(&["--console", "off"])
+            .default_disks();
+
+        measure_boot_time_stamps(c, control.test_timeout).unwrap().1
+    });
+
+    match r {
+        Ok(r) => r,
+        Err(_) => {
+            panic!("test failed!");
+        }
+    }
+}
+
 pub fn performance_boot_time_pmem(control: &PerformanceTestControl) -> f64 {
     let r = std::panic::catch_unwind(|| {
         let focal = UbuntuDiskConfig::new(FOCAL_IMAGE_NAME.to_string());
@@ -104,6 +104,25 @@ fn resize_api_command(
     .map_err(Error::ApiClient)
 }
 
+fn reboot_with_kernel_api_command(
+    socket: &mut UnixStream,
+    kernel: &str,
+    cmdline: Option<&str>,
+) -> Result<(), Error> {
+    let reboot_data = vmm::api::VmRebootWithKernelData {
+        kernel: kernel.into(),
+        cmdline: cmdline.map(|c| c.to_owned()),
+    };
+
+    simple_api_command(
+        socket,
+        "PUT",
+        "reboot-with-kernel",
+        Some(&serde_json::to_string(&reboot_data).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
 fn resize_zone_api_command(socket: &mut UnixStream, id: &str, size: &str) -> Result<(), Error> {
     let resize_zone = vmm::api::VmResizeZoneData {
         id: id.to_owned(),
@@ -238,9 +257,14 @@ fn add_vsock_api_command(socket: &mut UnixStream, config: &str) -> Result<(), Er
     .map_err(Error::ApiClient)
 }
 
-fn snapshot_api_command(socket: &mut UnixStream, url: &str) -> Result<(), Error> {
+fn snapshot_api_command(
+    socket: &mut UnixStream,
+    url: &str,
+    memory_only: bool,
+) -> Result<(), Error> {
     let snapshot_config = vmm::api::VmSnapshotConfig {
         destination_url: String::from(url),
+        memory_only,
     };
 
     simple_api_command(
@@ -335,6 +359,18 @@ fn do_command(matches: &ArgMatches) -> Result<(), Error> {
                 .unwrap()
                 .value_of("balloon"),
         ),
+        Some("reboot-with-kernel") => reboot_with_kernel_api_command(
+            &mut socket,
+            matches
+                .subcommand_matches("reboot-with-kernel")
+                .unwrap()
+                .value_of("kernel")
+                .unwrap(),
+            matches
+                .subcommand_matches("reboot-with-kernel")
+                .unwrap()
+                .value_of("cmdline"),
+        ),
         Some("resize-zone") => resize_zone_api_command(
             &mut socket,
             matches
@@ -427,6 +463,10 @@ fn do_command(matches: &ArgMatches) -> Result<(), Error> {
                 .unwrap()
                 .value_of("snapshot_config")
                 .unwrap(),
+            matches
+                .subcommand_matches("snapshot")
+                .unwrap()
+                .is_present("memory_only"),
         ),
         Some("restore") => restore_api_command(
             &mut socket,
@@ -553,6 +593,22 @@ fn main() {
         .subcommand(Command::new("counters").about("Counters from the VM"))
         .subcommand(Command::new("pause").about("Pause the VM"))
         .subcommand(Command::new("reboot").about("Reboot the VM"))
+        .subcommand(
+            Command::new("reboot-with-kernel")
+                .about("Reboot the VM into a different kernel")
+                .arg(
+                    Arg::new("kernel")
+                        .index(1)
+                        .help("Path to the kernel to boot"),
+                )
+                .arg(
+                    Arg::new("cmdline")
+                        .long("cmdline")
+                        .help("New kernel command line")
+                        .takes_value(true)
+                        .number_of_values(1),
+                ),
+        )
         .subcommand(Command::new("power-button").about("Trigger a power button in the VM"))
         .subcommand(
             Command::new("resize")
@@ -606,6 +662,12 @@ fn main() {
                     Arg::new("snapshot_config")
                         .index(1)
                         .help("<destination_url>"),
+                )
+                .arg(
+                    Arg::new("memory_only")
+                        .long("memory-only")
+                        .help("Skip the device state, devices are reset on restore")
+                        .takes_value(false),
                 ),
         )
         .subcommand(
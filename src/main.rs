@@ -152,7 +152,8 @@ fn create_app<'a>(
                     topology=<threads_per_core>:<cores_per_die>:<dies_per_package>:<packages>,\
                     kvm_hyperv=on|off,max_phys_bits=<maximum_number_of_physical_bits>,\
                     affinity=<list_of_vcpus_with_their_associated_cpuset>,\
-                    features=<list_of_features_to_enable>",
+                    features=<list_of_features_to_enable>,tsc_deadline_timer=on|off,\
+                    parallel_creation_threshold=<minimum_number_of_vcpus_created_in_parallel>",
                 )
                 .default_value(default_vcpus)
                 .group("vm-config"),
@@ -161,7 +162,7 @@ fn create_app<'a>(
             Arg::new("platform")
                 .long("platform")
                 .help(
-                    "num_pci_segments=<num pci segments>,iommu_segments=<list_of_segments>,serial_number=<(DMI) device serial number>",
+                    "num_pci_segments=<num pci segments>,iommu_segments=<list_of_segments>,serial_number=<(DMI) device serial number>,uuid=<(DMI) device uuid>,oem_strings=<list of OEM strings>,unregistered_io=warn|fault,unregistered_read_fill=<byte returned by reads to unregistered addresses>",
                 )
                 .takes_value(true)
                 .group("vm-config"),
@@ -176,7 +177,9 @@ fn create_app<'a>(
                      hotplug_method=acpi|virtio-mem,\
                      hotplug_size=<hotpluggable_memory_size>,\
                      hotplugged_size=<hotplugged_memory_size>,\
-                     prefault=on|off\"",
+                     prefault=on|off,wipe_on_shutdown=on|off,\
+                     prefault_timeout=<prefault_time_limit_ms>,mlock=on|off,\
+                     reserved=<list_of_reserved_ranges>\"",
                 )
                 .default_value(default_memory)
                 .group("vm-config"),
@@ -286,6 +289,14 @@ fn create_app<'a>(
                 .default_value("tty")
                 .group("vm-config"),
         )
+        .arg(
+            Arg::new("extra-serial")
+                .long("extra-serial")
+                .help("Additional serial ports (ttyS1 onwards): off|null|pty|file=/path/to/a/file")
+                .takes_value(true)
+                .min_values(1)
+                .group("vm-config"),
+        )
         .arg(
             Arg::new("device")
                 .long("device")
@@ -333,6 +344,34 @@ fn create_app<'a>(
                 .takes_value(false)
                 .group("vm-config"),
         )
+        .arg(
+            Arg::new("stop-on-boot")
+                .long("stop-on-boot")
+                .help("Create the vCPUs but do not start them until the VM is booted again")
+                .takes_value(false)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("break-on-triple-fault")
+                .long("break-on-triple-fault")
+                .help("Stop the VM instead of resetting it when a vCPU triple faults (x86_64 only)")
+                .takes_value(false)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("no-cmdline-console")
+                .long("no-cmdline-console")
+                .help("Don't append the console setup to the kernel command line, leaving it to the user provided one (aarch64 only)")
+                .takes_value(false)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("on-reset")
+                .long("on-reset")
+                .help("Action to take when the guest resets: restart, exit or pause")
+                .takes_value(true)
+                .group("vm-config"),
+        )
         .arg(
             Arg::new("v")
                 .short('v')
@@ -376,7 +415,13 @@ fn create_app<'a>(
             Arg::new("seccomp")
                 .long("seccomp")
                 .takes_value(true)
-                .possible_values(&["true", "false", "log"])
+                .possible_values(&[
+                    "true",
+                    "false",
+                    "log",
+                    #[cfg(feature = "seccomp_trace")]
+                    "trace",
+                ])
                 .default_value("true"),
         );
 
@@ -390,6 +435,15 @@ fn create_app<'a>(
             .group("vm-config"),
     );
 
+    #[cfg(target_arch = "x86_64")]
+    let app = app.arg(
+        Arg::new("guest-panic")
+            .long("guest-panic")
+            .help(config::GuestPanicConfig::SYNTAX)
+            .takes_value(true)
+            .group("vm-config"),
+    );
+
     #[cfg(feature = "gdb")]
     let app = app.arg(
         Arg::new("gdb")
@@ -491,6 +545,8 @@ fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
             "true" => SeccompAction::Trap,
             "false" => SeccompAction::Allow,
             "log" => SeccompAction::Log,
+            #[cfg(feature = "seccomp_trace")]
+            "trace" => SeccompAction::Trap,
             _ => {
                 // The user providing an invalid value will be rejected by clap
                 panic!("Invalid parameter {} for \"--seccomp\" flag", seccomp_value);
@@ -500,7 +556,17 @@ fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
         SeccompAction::Trap
     };
 
-    if seccomp_action == SeccompAction::Trap {
+    #[cfg(feature = "seccomp_trace")]
+    let trace_seccomp = cmd_arguments.value_of("seccomp") == Some("trace");
+    #[cfg(not(feature = "seccomp_trace"))]
+    let trace_seccomp = false;
+
+    if trace_seccomp {
+        #[cfg(feature = "seccomp_trace")]
+        vmm::seccomp_filters::record_seccomp_violations()
+            .map_err(|e| eprintln!("Error adding SIGSYS signal handler: {}", e))
+            .ok();
+    } else if seccomp_action == SeccompAction::Trap {
         // SAFETY: We only using signal_hook for managing signals and only execute signal
         // handler safe functions (writing to stderr) and manipulating signals.
         unsafe {
@@ -633,7 +699,7 @@ mod unit_tests {
     use std::path::PathBuf;
     use vmm::config::{
         CmdlineConfig, ConsoleConfig, ConsoleOutputMode, CpuFeatures, CpusConfig, KernelConfig,
-        MemoryConfig, RngConfig, VmConfig, VmParams,
+        MemoryConfig, ResetAction, RngConfig, VmConfig, VmParams,
     };
 
     fn get_vm_config_from_vec(args: &[&str]) -> VmConfig {
@@ -681,6 +747,9 @@ mod unit_tests {
                 max_phys_bits: 46,
                 affinity: None,
                 features: CpuFeatures::default(),
+                #[cfg(target_arch = "x86_64")]
+                tsc_deadline_timer: true,
+                parallel_creation_threshold: 16,
             },
             memory: MemoryConfig {
                 size: 536_870_912,
@@ -692,14 +761,20 @@ mod unit_tests {
                 hugepages: false,
                 hugepage_size: None,
                 prefault: false,
+                wipe_on_shutdown: false,
+                prefault_timeout: None,
+                mlock: false,
                 zones: None,
+                reserved: None,
             },
             kernel: Some(KernelConfig {
                 path: PathBuf::from("/path/to/kernel"),
+                highmem_start: None,
             }),
             initramfs: None,
             cmdline: CmdlineConfig {
                 args: String::from(""),
+                console_additions: true,
             },
             disks: None,
             net: None,
@@ -720,6 +795,8 @@ mod unit_tests {
                 mode: ConsoleOutputMode::Tty,
                 iommu: false,
             },
+            extra_serial: None,
+            serial_devices: None,
             devices: None,
             user_devices: None,
             vdpa: None,
@@ -734,6 +811,11 @@ mod unit_tests {
             #[cfg(feature = "gdb")]
             gdb: false,
             platform: None,
+            #[cfg(target_arch = "x86_64")]
+            guest_panic: None,
+            stop_on_boot: false,
+            break_on_triple_fault: false,
+            on_reset: ResetAction::Restart,
         };
 
         assert_eq!(expected_vm_config, result_vm_config);
@@ -1654,4 +1736,49 @@ mod unit_tests {
             compare_vm_config_cli_vs_json(cli, openapi, *equal);
         });
     }
+
+    #[test]
+    fn test_valid_vm_config_on_reset() {
+        vec![
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--on-reset",
+                    "exit",
+                ],
+                r#"{
+                    "kernel": {"path": "/path/to/kernel"},
+                    "on_reset": "Exit"
+                }"#,
+                true,
+            ),
+            (
+                vec!["cloud-hypervisor", "--kernel", "/path/to/kernel"],
+                r#"{
+                    "kernel": {"path": "/path/to/kernel"},
+                    "on_reset": "Restart"
+                }"#,
+                true,
+            ),
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--on-reset",
+                    "pause",
+                ],
+                r#"{
+                    "kernel": {"path": "/path/to/kernel"}
+                }"#,
+                false,
+            ),
+        ]
+        .iter()
+        .for_each(|(cli, openapi, equal)| {
+            compare_vm_config_cli_vs_json(cli, openapi, *equal);
+        });
+    }
 }
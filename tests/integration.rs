@@ -2263,6 +2263,49 @@ mod parallel {
         handle_child_output(r, &output);
     }
 
+    #[test]
+    fn test_reserved_memory() {
+        let focal = UbuntuDiskConfig::new(FOCAL_IMAGE_NAME.to_string());
+        let guest = Guest::new(Box::new(focal));
+        let mut cmd = GuestCommand::new(&guest);
+        cmd.args(&["--cpus", "boot=1"])
+            .args(&["--memory", "size=1G,reserved=[512M@16M]"])
+            .args(&["--kernel", direct_kernel_boot_path().to_str().unwrap()])
+            .args(&["--cmdline", DIRECT_KERNEL_BOOT_CMDLINE])
+            .capture_output()
+            .default_disks()
+            .default_net();
+
+        let mut child = cmd.spawn().unwrap();
+
+        guest.wait_vm_boot(None).unwrap();
+
+        #[cfg(target_arch = "x86_64")]
+        let reserved_range = "20000000-20ffffff";
+        #[cfg(target_arch = "aarch64")]
+        let reserved_range = "60000000-60ffffff";
+
+        let r = std::panic::catch_unwind(|| {
+            assert_eq!(
+                guest
+                    .ssh_command(&format!(
+                        "sudo grep -ci '^{} : reserved' /proc/iomem",
+                        reserved_range
+                    ))
+                    .unwrap()
+                    .trim()
+                    .parse::<u32>()
+                    .unwrap_or_default(),
+                1
+            );
+        });
+
+        let _ = child.kill();
+        let output = child.wait_with_output().unwrap();
+
+        handle_child_output(r, &output);
+    }
+
     #[test]
     fn test_power_button() {
         _test_power_button(false);
@@ -4352,6 +4395,71 @@ mod parallel {
         handle_child_output(r, &output);
     }
 
+    #[test]
+    fn test_cpu_hotplug_grow_shrink() {
+        let focal = UbuntuDiskConfig::new(FOCAL_IMAGE_NAME.to_string());
+        let guest = Guest::new(Box::new(focal));
+        let api_socket = temp_api_path(&guest.tmp_dir);
+
+        let kernel_path = direct_kernel_boot_path();
+
+        let mut child = GuestCommand::new(&guest)
+            .args(&["--cpus", "boot=4,max=8"])
+            .args(&["--memory", "size=512M"])
+            .args(&["--kernel", kernel_path.to_str().unwrap()])
+            .args(&["--cmdline", DIRECT_KERNEL_BOOT_CMDLINE])
+            .default_disks()
+            .default_net()
+            .args(&["--api-socket", &api_socket])
+            .capture_output()
+            .spawn()
+            .unwrap();
+
+        let r = std::panic::catch_unwind(|| {
+            guest.wait_vm_boot(None).unwrap();
+
+            assert_eq!(guest.get_cpu_count().unwrap_or_default(), 4);
+
+            // Grow the VM to 8 vCPUs
+            let desired_vcpus = 8;
+            resize_command(&api_socket, Some(desired_vcpus), None, None, None);
+
+            for cpu in 4..8 {
+                guest
+                    .ssh_command(&format!(
+                        "echo 1 | sudo tee /sys/bus/cpu/devices/cpu{}/online",
+                        cpu
+                    ))
+                    .unwrap();
+            }
+            thread::sleep(std::time::Duration::new(10, 0));
+            assert_eq!(
+                guest.get_cpu_count().unwrap_or_default(),
+                u32::from(desired_vcpus)
+            );
+
+            // Shrink it back to 4 vCPUs, the request only returns once the
+            // guest has ejected the extra vCPUs.
+            let desired_vcpus = 4;
+            assert!(resize_command(
+                &api_socket,
+                Some(desired_vcpus),
+                None,
+                None,
+                None
+            ));
+            assert_eq!(
+                guest.get_cpu_count().unwrap_or_default(),
+                u32::from(desired_vcpus)
+            );
+        });
+
+        let _ = child.kill();
+        let output = child.wait_with_output().unwrap();
+
+        handle_child_output(r, &output);
+    }
+
     #[test]
     fn test_memory_hotplug() {
         #[cfg(target_arch = "aarch64")]
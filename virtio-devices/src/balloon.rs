@@ -20,6 +20,7 @@ use crate::{
 };
 use libc::EFD_NONBLOCK;
 use seccompiler::SeccompAction;
+use serde::{Deserialize, Serialize};
 use std::io;
 use std::mem::size_of;
 use std::os::unix::io::AsRawFd;
@@ -52,10 +53,16 @@ const INFLATE_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
 const DEFLATE_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 3;
 // Reporting virtio queue event.
 const REPORTING_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 4;
+// Statistics virtio queue event.
+const STATS_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 5;
+// Statistics refresh request event.
+const STATS_REQUEST_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 6;
 
 // Size of a PFN in the balloon interface.
 const VIRTIO_BALLOON_PFN_SHIFT: u64 = 12;
 
+// Enable an additional virtqueue to let the guest report memory statistics.
+const VIRTIO_BALLOON_F_STATS_VQ: u64 = 1;
 // Deflate balloon on OOM
 const VIRTIO_BALLOON_F_DEFLATE_ON_OOM: u64 = 2;
 // Enable an additional virtqueue to let the guest notify the host about free
@@ -104,6 +111,42 @@ pub struct VirtioBalloonConfig {
     actual: u32,
 }
 
+// Statistics tags, from include/uapi/linux/virtio_balloon.h
+const VIRTIO_BALLOON_S_SWAP_IN: u16 = 0;
+const VIRTIO_BALLOON_S_SWAP_OUT: u16 = 1;
+const VIRTIO_BALLOON_S_MAJFLT: u16 = 2;
+const VIRTIO_BALLOON_S_MINFLT: u16 = 3;
+const VIRTIO_BALLOON_S_MEMFREE: u16 = 4;
+const VIRTIO_BALLOON_S_MEMTOT: u16 = 5;
+const VIRTIO_BALLOON_S_AVAIL: u16 = 6;
+const VIRTIO_BALLOON_S_CACHES: u16 = 7;
+
+// Size of a statistics entry, a 16 bits tag followed by a 64 bits value.
+const VIRTIO_BALLOON_STAT_SIZE: u64 = 10;
+
+/// Memory statistics reported by the guest through the statistics queue.
+/// Statistics the guest doesn't report are left to `None`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BalloonStatistics {
+    /// Amount of memory swapped in, in bytes.
+    pub swap_in: Option<u64>,
+    /// Amount of memory swapped out, in bytes.
+    pub swap_out: Option<u64>,
+    /// Number of major page faults.
+    pub major_faults: Option<u64>,
+    /// Number of minor page faults.
+    pub minor_faults: Option<u64>,
+    /// Amount of memory not used for any purpose, in bytes.
+    pub free_memory: Option<u64>,
+    /// Total amount of memory available to the guest, in bytes.
+    pub total_memory: Option<u64>,
+    /// Estimation of the memory available to start new applications, in
+    /// bytes.
+    pub available_memory: Option<u64>,
+    /// Amount of memory used by the disk caches, in bytes.
+    pub disk_caches: Option<u64>,
+}
+
 const CONFIG_ACTUAL_OFFSET: u64 = 4;
 const CONFIG_ACTUAL_SIZE: usize = 4;
 
@@ -167,7 +210,13 @@ struct BalloonEpollHandler {
     interrupt_cb: Arc<dyn VirtioInterrupt>,
     inflate_queue_evt: EventFd,
     deflate_queue_evt: EventFd,
+    stats_queue_evt: Option<EventFd>,
     reporting_queue_evt: Option<EventFd>,
+    stats_request_evt: EventFd,
+    statistics: Arc<Mutex<Option<BalloonStatistics>>>,
+    // Buffer held until the statistics get refreshed, the guest filling it
+    // again once it's handed back.
+    stats_desc_index: Option<u16>,
     kill_evt: EventFd,
     pause_evt: EventFd,
 }
@@ -297,6 +346,64 @@ impl BalloonEpollHandler {
         self.notify_queue(queue_index, used_descs)
     }
 
+    fn process_stats_queue(&mut self, queue_index: usize) -> result::Result<(), Error> {
+        let mut used_descs = Vec::new();
+        for mut desc_chain in self.queues[queue_index]
+            .iter()
+            .map_err(Error::QueueIterator)?
+        {
+            let desc = desc_chain.next().ok_or(Error::DescriptorChainTooShort)?;
+            if desc.is_write_only() {
+                error!("The statistics buffer is write only");
+                return Err(Error::UnexpectedWriteOnlyDescriptor);
+            }
+
+            let mut statistics = BalloonStatistics::default();
+            let mut offset = 0u64;
+            while offset + VIRTIO_BALLOON_STAT_SIZE <= desc.len() as u64 {
+                let addr = desc.addr().checked_add(offset).unwrap();
+                let tag: u16 = desc_chain
+                    .memory()
+                    .read_obj(addr)
+                    .map_err(Error::GuestMemory)?;
+                let val: u64 = desc_chain
+                    .memory()
+                    .read_obj(addr.checked_add(2).unwrap())
+                    .map_err(Error::GuestMemory)?;
+                offset += VIRTIO_BALLOON_STAT_SIZE;
+
+                match tag {
+                    VIRTIO_BALLOON_S_SWAP_IN => statistics.swap_in = Some(val),
+                    VIRTIO_BALLOON_S_SWAP_OUT => statistics.swap_out = Some(val),
+                    VIRTIO_BALLOON_S_MAJFLT => statistics.major_faults = Some(val),
+                    VIRTIO_BALLOON_S_MINFLT => statistics.minor_faults = Some(val),
+                    VIRTIO_BALLOON_S_MEMFREE => statistics.free_memory = Some(val),
+                    VIRTIO_BALLOON_S_MEMTOT => statistics.total_memory = Some(val),
+                    VIRTIO_BALLOON_S_AVAIL => statistics.available_memory = Some(val),
+                    VIRTIO_BALLOON_S_CACHES => statistics.disk_caches = Some(val),
+                    _ => {}
+                }
+            }
+            *self.statistics.lock().unwrap() = Some(statistics);
+
+            // Only one buffer is expected, hand back any stale one.
+            if let Some(desc_index) = self.stats_desc_index.replace(desc_chain.head_index()) {
+                used_descs.push((desc_index, 0));
+            }
+        }
+
+        self.notify_queue(queue_index, used_descs)
+    }
+
+    // Hand the statistics buffer back to the guest, which refreshes it.
+    fn request_statistics(&mut self, queue_index: usize) -> result::Result<(), Error> {
+        if let Some(desc_index) = self.stats_desc_index.take() {
+            self.notify_queue(queue_index, vec![(desc_index, 0)])?;
+        }
+
+        Ok(())
+    }
+
     fn process_reporting_queue(&mut self, queue_index: usize) -> result::Result<(), Error> {
         let mut used_descs = Vec::new();
 
@@ -325,6 +432,10 @@ impl BalloonEpollHandler {
         helper.add_event(self.resize_receiver.evt.as_raw_fd(), RESIZE_EVENT)?;
         helper.add_event(self.inflate_queue_evt.as_raw_fd(), INFLATE_QUEUE_EVENT)?;
         helper.add_event(self.deflate_queue_evt.as_raw_fd(), DEFLATE_QUEUE_EVENT)?;
+        if let Some(stats_queue_evt) = self.stats_queue_evt.as_ref() {
+            helper.add_event(stats_queue_evt.as_raw_fd(), STATS_QUEUE_EVENT)?;
+            helper.add_event(self.stats_request_evt.as_raw_fd(), STATS_REQUEST_EVENT)?;
+        }
         if let Some(reporting_queue_evt) = self.reporting_queue_evt.as_ref() {
             helper.add_event(reporting_queue_evt.as_raw_fd(), REPORTING_QUEUE_EVENT)?;
         }
@@ -385,12 +496,37 @@ impl EpollHelperHandler for BalloonEpollHandler {
                     return true;
                 }
             }
+            STATS_QUEUE_EVENT => {
+                if let Some(stats_queue_evt) = self.stats_queue_evt.as_ref() {
+                    if let Err(e) = stats_queue_evt.read() {
+                        error!("Failed to get statistics queue event: {:?}", e);
+                        return true;
+                    } else if let Err(e) = self.process_stats_queue(2) {
+                        error!("Failed to process statistics queue: {:?}", e);
+                        return true;
+                    }
+                } else {
+                    error!("Invalid statistics queue event as no eventfd registered");
+                    return true;
+                }
+            }
+            STATS_REQUEST_EVENT => {
+                if let Err(e) = self.stats_request_evt.read() {
+                    error!("Failed to get statistics request event: {:?}", e);
+                    return true;
+                } else if let Err(e) = self.request_statistics(2) {
+                    error!("Failed to signal used statistics queue: {:?}", e);
+                    return true;
+                }
+            }
             REPORTING_QUEUE_EVENT => {
+                // The reporting queue comes after the statistics one
+                let queue_index = if self.stats_queue_evt.is_some() { 3 } else { 2 };
                 if let Some(reporting_queue_evt) = self.reporting_queue_evt.as_ref() {
                     if let Err(e) = reporting_queue_evt.read() {
                         error!("Failed to get reporting queue event: {:?}", e);
                         return true;
-                    } else if let Err(e) = self.process_reporting_queue(2) {
+                    } else if let Err(e) = self.process_reporting_queue(queue_index) {
                         error!("Failed to signal used inflate queue: {:?}", e);
                         return true;
                     }
@@ -424,6 +560,8 @@ pub struct Balloon {
     id: String,
     resize: VirtioBalloonResize,
     config: Arc<Mutex<VirtioBalloonConfig>>,
+    statistics: Arc<Mutex<Option<BalloonStatistics>>>,
+    stats_request_evt: EventFd,
     seccomp_action: SeccompAction,
     exit_evt: EventFd,
 }
@@ -435,6 +573,7 @@ impl Balloon {
         size: u64,
         deflate_on_oom: bool,
         free_page_reporting: bool,
+        statistics: bool,
         seccomp_action: SeccompAction,
         exit_evt: EventFd,
     ) -> io::Result<Self> {
@@ -443,6 +582,10 @@ impl Balloon {
         if deflate_on_oom {
             avail_features |= 1u64 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM;
         }
+        if statistics {
+            avail_features |= 1u64 << VIRTIO_BALLOON_F_STATS_VQ;
+            queue_sizes.push(QUEUE_SIZE);
+        }
         if free_page_reporting {
             avail_features |= 1u64 << VIRTIO_BALLOON_F_REPORTING;
             queue_sizes.push(REPORTING_QUEUE_SIZE);
@@ -465,6 +608,8 @@ impl Balloon {
             id,
             resize: VirtioBalloonResize::new(size)?,
             config: Arc::new(Mutex::new(config)),
+            statistics: Arc::new(Mutex::new(None)),
+            stats_request_evt: EventFd::new(EFD_NONBLOCK)?,
             seccomp_action,
             exit_evt,
         })
@@ -479,6 +624,20 @@ impl Balloon {
         (self.config.lock().unwrap().actual as u64) << VIRTIO_BALLOON_PFN_SHIFT
     }
 
+    /// Latest memory statistics reported by the guest, `None` until the
+    /// guest reports some or if the statistics queue isn't enabled.
+    pub fn statistics(&self) -> Option<BalloonStatistics> {
+        *self.statistics.lock().unwrap()
+    }
+
+    /// Ask the guest to refresh its memory statistics, which it does
+    /// asynchronously.
+    pub fn request_statistics(&self) -> Result<(), Error> {
+        self.stats_request_evt
+            .write(1)
+            .map_err(Error::EventFdWriteFail)
+    }
+
     fn state(&self) -> BalloonState {
         BalloonState {
             avail_features: self.common.avail_features,
@@ -550,6 +709,12 @@ impl VirtioDevice for Balloon {
 
         let inflate_queue_evt = queue_evts.remove(0);
         let deflate_queue_evt = queue_evts.remove(0);
+        let stats_queue_evt =
+            if self.common.feature_acked(VIRTIO_BALLOON_F_STATS_VQ) && !queue_evts.is_empty() {
+                Some(queue_evts.remove(0))
+            } else {
+                None
+            };
         let reporting_queue_evt =
             if self.common.feature_acked(VIRTIO_BALLOON_F_REPORTING) && !queue_evts.is_empty() {
                 Some(queue_evts.remove(0))
@@ -567,7 +732,14 @@ impl VirtioDevice for Balloon {
             interrupt_cb,
             inflate_queue_evt,
             deflate_queue_evt,
+            stats_queue_evt,
             reporting_queue_evt,
+            stats_request_evt: self.stats_request_evt.try_clone().map_err(|e| {
+                error!("failed to clone statistics request EventFd: {:?}", e);
+                ActivateError::BadActivate
+            })?,
+            statistics: self.statistics.clone(),
+            stats_desc_index: None,
             kill_evt,
             pause_evt,
         };
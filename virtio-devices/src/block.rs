@@ -98,6 +98,12 @@ struct BlockEpollHandler {
     request_list: HashMap<u16, Request>,
     rate_limiter: Option<RateLimiter>,
     access_platform: Option<Arc<dyn AccessPlatform>>,
+    // Bytes of guest data the submitted requests can cover at once.
+    max_inflight_bytes: u64,
+    inflight_bytes: u64,
+    // Whether the queue processing stopped to wait for in flight requests
+    // to complete.
+    inflight_limit_reached: bool,
 }
 
 impl BlockEpollHandler {
@@ -112,6 +118,30 @@ impl BlockEpollHandler {
             let mut request = Request::parse(&mut desc_chain, self.access_platform.as_ref())
                 .map_err(Error::RequestParsing)?;
 
+            let data_len = request.data_len();
+            if data_len > self.max_inflight_bytes {
+                warn!(
+                    "Rejecting request covering {} bytes, above the {} bytes limit",
+                    data_len, self.max_inflight_bytes
+                );
+                // We use unwrap because the request parsing process already
+                // checked that the status_addr was valid.
+                desc_chain
+                    .memory()
+                    .write_obj(VIRTIO_BLK_S_IOERR, request.status_addr)
+                    .unwrap();
+                used_desc_heads.push((desc_chain.head_index(), 0));
+                used_count += 1;
+                continue;
+            }
+            if self.inflight_bytes + data_len > self.max_inflight_bytes {
+                // Leave the descriptor chain on the avail ring until enough
+                // requests have completed.
+                avail_iter.go_to_previous_position();
+                self.inflight_limit_reached = true;
+                break;
+            }
+
             if let Some(rate_limiter) = &mut self.rate_limiter {
                 // If limiter.consume() fails it means there is no more TokenType::Ops
                 // budget and rate limiting is in effect.
@@ -155,6 +185,7 @@ impl BlockEpollHandler {
                 )
                 .map_err(Error::RequestExecuting)?
             {
+                self.inflight_bytes += data_len;
                 self.request_list.insert(desc_chain.head_index(), request);
             } else {
                 // We use unwrap because the request parsing process already
@@ -199,6 +230,7 @@ impl BlockEpollHandler {
                 .remove(&desc_index)
                 .ok_or(Error::MissingEntryRequestList)?;
             request.complete_async().map_err(Error::RequestCompleting)?;
+            self.inflight_bytes -= request.data_len();
 
             let (status, len) = if result >= 0 {
                 match request.request_type {
@@ -337,6 +369,26 @@ impl EpollHelperHandler for BlockEpollHandler {
                         return true;
                     }
                 }
+
+                // Resume the requests left pending because of the in flight
+                // limit, now that some of them completed.
+                if self.inflight_limit_reached {
+                    self.inflight_limit_reached = false;
+                    match self.process_queue_submit() {
+                        Ok(needs_notification) => {
+                            if needs_notification {
+                                if let Err(e) = self.signal_used_queue() {
+                                    error!("Failed to signal used queue: {:?}", e);
+                                    return true;
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            error!("Failed to process queue (submit): {:?}", e);
+                            return true;
+                        }
+                    }
+                }
             }
             RATE_LIMITER_EVENT => {
                 if let Some(rate_limiter) = &mut self.rate_limiter {
@@ -384,6 +436,7 @@ pub struct Block {
     counters: BlockCounters,
     seccomp_action: SeccompAction,
     rate_limiter_config: Option<RateLimiterConfig>,
+    max_inflight_bytes: u64,
     exit_evt: EventFd,
 }
 
@@ -411,6 +464,7 @@ impl Block {
         queue_size: u16,
         seccomp_action: SeccompAction,
         rate_limiter_config: Option<RateLimiterConfig>,
+        max_inflight_bytes: u64,
         exit_evt: EventFd,
     ) -> io::Result<Self> {
         let disk_size = disk_image.size().map_err(|e| {
@@ -492,6 +546,7 @@ impl Block {
             counters: BlockCounters::default(),
             seccomp_action,
             rate_limiter_config,
+            max_inflight_bytes,
             exit_evt,
         })
     }
@@ -630,6 +685,9 @@ impl VirtioDevice for Block {
                 request_list: HashMap::with_capacity(queue_size.into()),
                 rate_limiter,
                 access_platform: self.common.access_platform.clone(),
+                max_inflight_bytes: self.max_inflight_bytes,
+                inflight_bytes: 0,
+                inflight_limit_reached: false,
             };
 
             let paused = self.common.paused.clone();
@@ -715,3 +773,95 @@ impl Snapshottable for Block {
 }
 impl Transportable for Block {}
 impl Migratable for Block {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VirtioInterruptType;
+    use block_util::raw_sync::RawFileSync;
+    use virtio_queue::{defs::VIRTQ_DESC_F_NEXT, defs::VIRTQ_DESC_F_WRITE};
+    use vm_memory::GuestAddress;
+    use vm_virtio::queue::testing::VirtQueue as GuestQ;
+    use vmm_sys_util::tempfile::TempFile;
+
+    struct NoopVirtioInterrupt {}
+
+    impl VirtioInterrupt for NoopVirtioInterrupt {
+        fn trigger(
+            &self,
+            _int_type: VirtioInterruptType,
+        ) -> std::result::Result<(), std::io::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_max_inflight_bytes() {
+        const HDR_ADDR: u64 = 0x1_0000;
+        const STATUS_ADDR: u64 = 0x2_0000;
+        const DATA_ADDR: u64 = 0x3_0000;
+
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10_0000)]).unwrap();
+        let guest_q = GuestQ::new(GuestAddress(0), &mem, 16);
+        let disk_file = TempFile::new().unwrap();
+
+        let mut handler = BlockEpollHandler {
+            queue_index: 0,
+            queue: guest_q.create_queue(),
+            mem: GuestMemoryAtomic::new(mem.clone()),
+            disk_image: Box::new(RawFileSync::new(disk_file.as_file().as_raw_fd())),
+            disk_nsectors: 8,
+            interrupt_cb: Arc::new(NoopVirtioInterrupt {}),
+            disk_image_id: Vec::new(),
+            kill_evt: EventFd::new(0).unwrap(),
+            pause_evt: EventFd::new(0).unwrap(),
+            writeback: Arc::new(AtomicBool::new(true)),
+            counters: BlockCounters::default(),
+            queue_evt: EventFd::new(0).unwrap(),
+            request_list: HashMap::new(),
+            rate_limiter: None,
+            access_platform: None,
+            max_inflight_bytes: 1 << 20,
+            inflight_bytes: 0,
+            inflight_limit_reached: false,
+        };
+
+        // A read request pointing to a huge buffer, which isn't even backed
+        // by guest memory, is rejected without being executed.
+        mem.write_obj(VIRTIO_BLK_T_IN, GuestAddress(HDR_ADDR))
+            .unwrap();
+        guest_q.dtable[0].set(HDR_ADDR, 16, VIRTQ_DESC_F_NEXT, 1);
+        guest_q.dtable[1].set(
+            DATA_ADDR,
+            0x8000_0000,
+            VIRTQ_DESC_F_WRITE | VIRTQ_DESC_F_NEXT,
+            2,
+        );
+        guest_q.dtable[2].set(STATUS_ADDR, 1, VIRTQ_DESC_F_WRITE, 0);
+        guest_q.avail.ring[0].set(0);
+        guest_q.avail.idx.set(1);
+
+        assert!(handler.process_queue_submit().unwrap());
+        assert_eq!(guest_q.used.idx.get(), 1);
+        assert_eq!(
+            mem.read_obj::<u8>(GuestAddress(STATUS_ADDR)).unwrap(),
+            VIRTIO_BLK_S_IOERR as u8
+        );
+        assert_eq!(handler.inflight_bytes, 0);
+
+        // A request within the limit is accounted for until it completes.
+        guest_q.dtable[1].set(DATA_ADDR, 512, VIRTQ_DESC_F_WRITE | VIRTQ_DESC_F_NEXT, 2);
+        guest_q.avail.ring[1].set(0);
+        guest_q.avail.idx.set(2);
+
+        handler.process_queue_submit().unwrap();
+        assert_eq!(handler.inflight_bytes, 512);
+        assert!(handler.process_queue_complete().unwrap());
+        assert_eq!(handler.inflight_bytes, 0);
+        assert_eq!(guest_q.used.idx.get(), 2);
+        assert_eq!(
+            mem.read_obj::<u8>(GuestAddress(STATUS_ADDR)).unwrap(),
+            VIRTIO_BLK_S_OK as u8
+        );
+    }
+}
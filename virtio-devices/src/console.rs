@@ -328,15 +328,20 @@ impl ConsoleResizer {
     pub fn update_console_size(&self) {
         if let Some(tty) = self.tty.as_ref() {
             let (cols, rows) = get_win_size(tty);
-            self.config.lock().unwrap().update_console_size(cols, rows);
-            if self
-                .acked_features
-                .fetch_and(1u64 << VIRTIO_CONSOLE_F_SIZE, Ordering::AcqRel)
-                != 0
-            {
-                // Send the interrupt to the driver
-                let _ = self.config_evt.write(1);
-            }
+            self.set_console_size(cols, rows);
+        }
+    }
+
+    /// Report the console size to the guest, regardless of the tty size.
+    pub fn set_console_size(&self, cols: u16, rows: u16) {
+        self.config.lock().unwrap().update_console_size(cols, rows);
+        if self
+            .acked_features
+            .fetch_and(1u64 << VIRTIO_CONSOLE_F_SIZE, Ordering::AcqRel)
+            != 0
+        {
+            // Send the interrupt to the driver
+            let _ = self.config_evt.write(1);
         }
     }
 }
@@ -143,6 +143,18 @@ pub trait VirtioDevice: Send {
         Ok(())
     }
 
+    /// Whether the guest memory is also accessed by a process other than the
+    /// VMM, such as a vhost-user backend, through its own mappings.
+    fn shares_guest_memory(&self) -> bool {
+        false
+    }
+
+    /// Asks a device relying on an external backend to reconnect to it, for
+    /// instance after the backend restarted.
+    fn reconnect_backend(&mut self) -> std::result::Result<(), Error> {
+        Err(Error::ReconnectNotSupported)
+    }
+
     /// Returns the list of userspace mappings associated with this device.
     fn userspace_mappings(&self) -> Vec<UserspaceMapping> {
         Vec::new()
@@ -103,6 +103,8 @@ pub enum ActivateError {
     CreateRateLimiter(std::io::Error),
     /// Failed activating the vDPA device
     ActivateVdpa(vdpa::Error),
+    /// Failed to create the vhost-user reconnection eventfd
+    VhostUserReconnectEventFd(std::io::Error),
 }
 
 pub type ActivateResult = std::result::Result<(), ActivateError>;
@@ -121,6 +123,8 @@ pub enum Error {
     ApplySeccompFilter(seccompiler::Error),
     QueueAddUsed(virtio_queue::Error),
     QueueIterator(virtio_queue::Error),
+    ReconnectNotSupported,
+    DeviceNotActivated,
 }
 
 #[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
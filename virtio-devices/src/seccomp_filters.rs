@@ -236,6 +236,9 @@ fn get_seccomp_rules(thread_type: Thread) -> Vec<(i64, Vec<SeccompRule>)> {
         Thread::VirtioWatchdog => virtio_watchdog_thread_rules(),
     };
     rules.append(&mut virtio_thread_common());
+    // Needed by the SIGSYS handler recording the violations.
+    #[cfg(feature = "seccomp_trace")]
+    rules.push((libc::SYS_gettid, vec![]));
     rules
 }
 
@@ -316,6 +316,19 @@ impl VirtioPciDeviceActivator {
 
         Ok(())
     }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Release the vCPU waiting on this activation, leaving the device
+    /// inactive.
+    pub fn abort(&mut self) {
+        if let Some(barrier) = self.barrier.take() {
+            info!("{}: Activation aborted, releasing barrier", self.id);
+            barrier.wait();
+        }
+    }
 }
 
 pub struct VirtioPciDevice {
@@ -562,7 +575,9 @@ impl VirtioPciDevice {
         self.queue_evts.as_slice()
     }
 
-    fn is_driver_ready(&self) -> bool {
+    /// Determines if the driver has completed the device initialization,
+    /// setting DRIVER_OK without reporting any failure.
+    pub fn is_driver_ready(&self) -> bool {
         let ready_bits =
             (DEVICE_ACKNOWLEDGE | DEVICE_DRIVER | DEVICE_DRIVER_OK | DEVICE_FEATURES_OK) as u8;
         self.common_config.driver_status == ready_bits
@@ -735,6 +750,23 @@ impl VirtioPciDevice {
         !self.device_activated.load(Ordering::SeqCst) && self.is_driver_ready()
     }
 
+    fn reset_device(&mut self) {
+        let mut device = self.device.lock().unwrap();
+        if let Some(virtio_interrupt) = device.reset() {
+            // Upon reset the device returns its interrupt EventFD
+            self.virtio_interrupt = Some(virtio_interrupt);
+            self.device_activated.store(false, Ordering::SeqCst);
+
+            // Reset queue readiness (changes queue_enable), queue sizes
+            // and selected_queue as per spec for reset
+            self.queues.iter_mut().for_each(Queue::reset);
+            self.common_config.queue_select = 0;
+        } else {
+            error!("Attempt to reset device when not implemented in underlying device");
+            self.common_config.driver_status = crate::DEVICE_FAILED as u8;
+        }
+    }
+
     pub fn dma_handler(&self) -> Option<&Arc<dyn ExternalDmaMapping>> {
         self.dma_handler.as_ref()
     }
@@ -1104,20 +1136,7 @@ impl PciDevice for VirtioPciDevice {
 
         // Device has been reset by the driver
         if self.device_activated.load(Ordering::SeqCst) && self.is_driver_init() {
-            let mut device = self.device.lock().unwrap();
-            if let Some(virtio_interrupt) = device.reset() {
-                // Upon reset the device returns its interrupt EventFD
-                self.virtio_interrupt = Some(virtio_interrupt);
-                self.device_activated.store(false, Ordering::SeqCst);
-
-                // Reset queue readiness (changes queue_enable), queue sizes
-                // and selected_queue as per spec for reset
-                self.queues.iter_mut().for_each(Queue::reset);
-                self.common_config.queue_select = 0;
-            } else {
-                error!("Attempt to reset device when not implemented in underlying device");
-                self.common_config.driver_status = crate::DEVICE_FAILED as u8;
-            }
+            self.reset_device();
         }
 
         None
@@ -1179,6 +1198,12 @@ impl Snapshottable for VirtioPciDevice {
         if let Some(virtio_pci_dev_section) =
             snapshot.snapshot_data.get(&format!("{}-section", self.id))
         {
+            // When restoring an activated device in place, its worker threads
+            // must be stopped first, as they are spawned again below.
+            if self.device_activated.load(Ordering::SeqCst) {
+                self.reset_device();
+            }
+
             // Restore MSI-X
             if let Some(msix_config) = &self.msix_config {
                 let id = msix_config.lock().unwrap().id();
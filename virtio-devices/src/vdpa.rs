@@ -269,6 +269,10 @@ impl VirtioDevice for Vdpa {
         self.common.device_type
     }
 
+    fn shares_guest_memory(&self) -> bool {
+        true
+    }
+
     fn queue_max_sizes(&self) -> &[u16] {
         &self.common.queue_sizes
     }
@@ -375,6 +375,14 @@ impl VirtioDevice for Blk {
     ) -> std::result::Result<(), crate::Error> {
         self.vu_common.add_memory_region(&self.guest_memory, region)
     }
+
+    fn shares_guest_memory(&self) -> bool {
+        true
+    }
+
+    fn reconnect_backend(&mut self) -> std::result::Result<(), crate::Error> {
+        self.vu_common.reconnect()
+    }
 }
 
 impl Pausable for Blk {
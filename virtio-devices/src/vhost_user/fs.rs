@@ -15,6 +15,7 @@ use libc::{self, c_void, off64_t, pread64, pwrite64};
 use seccompiler::SeccompAction;
 use std::io;
 use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
 use std::result;
 use std::sync::{Arc, Barrier, Mutex};
 use std::thread;
@@ -319,8 +320,6 @@ impl Fs {
         exit_evt: EventFd,
         iommu: bool,
     ) -> Result<Fs> {
-        let mut slave_req_support = false;
-
         // Calculate the actual number of queues needed.
         let num_queues = NUM_QUEUE_OFFSET + req_num_queues;
 
@@ -344,7 +343,7 @@ impl Fs {
                 id,
                 config: VirtioFsConfig::default(),
                 cache,
-                slave_req_support,
+                slave_req_support: false,
                 seccomp_action,
                 guest_memory: None,
                 epoll_thread: None,
@@ -354,7 +353,71 @@ impl Fs {
         }
 
         // Connect to the vhost-user socket.
-        let mut vu = VhostUserHandle::connect_vhost_user(false, path, num_queues as u64, false)?;
+        let vu = VhostUserHandle::connect_vhost_user(false, path, num_queues as u64, false)?;
+
+        Self::with_handle(
+            id,
+            vu,
+            path,
+            tag,
+            req_num_queues,
+            queue_size,
+            cache,
+            seccomp_action,
+            exit_evt,
+            iommu,
+        )
+    }
+
+    /// Create a new virtio-fs device from a socket already connected to the
+    /// backend. Without a socket path, the device can't reconnect to the
+    /// backend if the connection is closed.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_stream(
+        id: String,
+        stream: UnixStream,
+        tag: &str,
+        req_num_queues: usize,
+        queue_size: u16,
+        cache: Option<(VirtioSharedMemoryList, MmapRegion)>,
+        seccomp_action: SeccompAction,
+        exit_evt: EventFd,
+        iommu: bool,
+    ) -> Result<Fs> {
+        let num_queues = NUM_QUEUE_OFFSET + req_num_queues;
+        let vu = VhostUserHandle::from_stream(stream, num_queues as u64);
+
+        Self::with_handle(
+            id,
+            vu,
+            "",
+            tag,
+            req_num_queues,
+            queue_size,
+            cache,
+            seccomp_action,
+            exit_evt,
+            iommu,
+        )
+    }
+
+    // Negotiate the features with the backend reached through `vu`, which
+    // validates it speaks the vhost-user protocol.
+    #[allow(clippy::too_many_arguments)]
+    fn with_handle(
+        id: String,
+        mut vu: VhostUserHandle,
+        path: &str,
+        tag: &str,
+        req_num_queues: usize,
+        queue_size: u16,
+        cache: Option<(VirtioSharedMemoryList, MmapRegion)>,
+        seccomp_action: SeccompAction,
+        exit_evt: EventFd,
+        iommu: bool,
+    ) -> Result<Fs> {
+        let mut slave_req_support = false;
+        let num_queues = NUM_QUEUE_OFFSET + req_num_queues;
 
         // Filling device and vring features VMM supports.
         let avail_features = DEFAULT_VIRTIO_FEATURES;
@@ -632,6 +695,14 @@ impl VirtioDevice for Fs {
         self.vu_common.add_memory_region(&self.guest_memory, region)
     }
 
+    fn shares_guest_memory(&self) -> bool {
+        true
+    }
+
+    fn reconnect_backend(&mut self) -> std::result::Result<(), crate::Error> {
+        self.vu_common.reconnect()
+    }
+
     fn userspace_mappings(&self) -> Vec<UserspaceMapping> {
         let mut mappings = Vec::new();
         if let Some(cache) = self.cache.as_ref() {
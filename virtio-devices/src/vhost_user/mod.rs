@@ -155,6 +155,7 @@ pub const DEFAULT_VIRTIO_FEATURES: u64 = 1 << VIRTIO_F_RING_INDIRECT_DESC
 
 const HUP_CONNECTION_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
 const SLAVE_REQ_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
+const RECONNECT_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 3;
 
 #[derive(Default)]
 pub struct Inflight {
@@ -176,6 +177,10 @@ pub struct VhostUserEpollHandler<S: VhostUserMasterReqHandler> {
     pub server: bool,
     pub slave_req_handler: Option<MasterReqHandler<S>>,
     pub inflight: Option<Inflight>,
+    pub reconnect_evt: EventFd,
+    // Whether the connection with the backend is being watched, which isn't
+    // the case anymore once reconnecting failed.
+    pub connected: bool,
 }
 
 impl<S: VhostUserMasterReqHandler> VhostUserEpollHandler<S> {
@@ -194,6 +199,7 @@ impl<S: VhostUserMasterReqHandler> VhostUserEpollHandler<S> {
         if let Some(slave_req_handler) = &self.slave_req_handler {
             helper.add_event(slave_req_handler.as_raw_fd(), SLAVE_REQ_EVENT)?;
         }
+        helper.add_event(self.reconnect_evt.as_raw_fd(), RECONNECT_EVENT)?;
 
         helper.run(paused, paused_sync, self)?;
 
@@ -201,11 +207,21 @@ impl<S: VhostUserMasterReqHandler> VhostUserEpollHandler<S> {
     }
 
     fn reconnect(&mut self, helper: &mut EpollHelper) -> std::result::Result<(), EpollHelperError> {
-        helper.del_event_custom(
-            self.vu.lock().unwrap().socket_handle().as_raw_fd(),
-            HUP_CONNECTION_EVENT,
-            epoll::Events::EPOLLHUP,
-        )?;
+        if self.connected {
+            helper.del_event_custom(
+                self.vu.lock().unwrap().socket_handle().as_raw_fd(),
+                HUP_CONNECTION_EVENT,
+                epoll::Events::EPOLLHUP,
+            )?;
+            self.connected = false;
+        }
+
+        if self.socket_path.is_empty() {
+            return Err(EpollHelperError::IoError(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "no socket path to reconnect the vhost-user backend",
+            )));
+        }
 
         let mut vhost_user = VhostUserHandle::connect_vhost_user(
             self.server,
@@ -251,9 +267,32 @@ impl<S: VhostUserMasterReqHandler> VhostUserEpollHandler<S> {
         // Update vhost-user reference
         let mut vu = self.vu.lock().unwrap();
         *vu = vhost_user;
+        self.connected = true;
 
         Ok(())
     }
+
+    // Reconnect to the backend, the memory table and the vrings being sent
+    // again. If this fails, the device stays disconnected until asked to
+    // reconnect through the reconnection eventfd.
+    fn handle_reconnect(&mut self, helper: &mut EpollHelper) {
+        event!("vhost-user", "reconnecting", "socket", &self.socket_path);
+        match self.reconnect(helper) {
+            Ok(()) => {
+                info!("Reconnected vhost-user backend {}", self.socket_path);
+                event!("vhost-user", "reconnected", "socket", &self.socket_path);
+            }
+            Err(e) => {
+                error!("failed to reconnect vhost-user backend: {:?}", e);
+                event!(
+                    "vhost-user",
+                    "reconnect-failed",
+                    "socket",
+                    &self.socket_path
+                );
+            }
+        }
+    }
 }
 
 impl<S: VhostUserMasterReqHandler> EpollHelperHandler for VhostUserEpollHandler<S> {
@@ -261,10 +300,16 @@ impl<S: VhostUserMasterReqHandler> EpollHelperHandler for VhostUserEpollHandler<
         let ev_type = event.data as u16;
         match ev_type {
             HUP_CONNECTION_EVENT => {
-                if let Err(e) = self.reconnect(helper) {
-                    error!("failed to reconnect vhost-user backend: {:?}", e);
+                warn!("vhost-user backend {} disconnected", self.socket_path);
+                event!("vhost-user", "disconnected", "socket", &self.socket_path);
+                self.handle_reconnect(helper);
+            }
+            RECONNECT_EVENT => {
+                if let Err(e) = self.reconnect_evt.read() {
+                    error!("Failed to get reconnect event: {:?}", e);
                     return true;
                 }
+                self.handle_reconnect(helper);
             }
             SLAVE_REQ_EVENT => {
                 if let Some(slave_req_handler) = self.slave_req_handler.as_mut() {
@@ -292,6 +337,7 @@ pub struct VhostUserCommon {
     pub vu_num_queues: usize,
     pub migration_started: bool,
     pub server: bool,
+    pub reconnect_evt: Option<EventFd>,
 }
 
 impl VhostUserCommon {
@@ -333,6 +379,14 @@ impl VhostUserCommon {
             )
             .map_err(ActivateError::VhostUserBlkSetup)?;
 
+        let reconnect_evt =
+            EventFd::new(libc::EFD_NONBLOCK).map_err(ActivateError::VhostUserReconnectEventFd)?;
+        self.reconnect_evt = Some(
+            reconnect_evt
+                .try_clone()
+                .map_err(ActivateError::VhostUserReconnectEventFd)?,
+        );
+
         Ok(VhostUserEpollHandler {
             vu: vu.clone(),
             mem,
@@ -347,9 +401,20 @@ impl VhostUserCommon {
             server: self.server,
             slave_req_handler,
             inflight,
+            reconnect_evt,
+            connected: true,
         })
     }
 
+    /// Ask the device thread to reconnect to the backend.
+    pub fn reconnect(&self) -> std::result::Result<(), crate::Error> {
+        self.reconnect_evt
+            .as_ref()
+            .ok_or(crate::Error::DeviceNotActivated)?
+            .write(1)
+            .map_err(crate::Error::IoError)
+    }
+
     pub fn restore_backend_connection(&mut self, acked_features: u64) -> Result<()> {
         let mut vu = VhostUserHandle::connect_vhost_user(
             self.server,
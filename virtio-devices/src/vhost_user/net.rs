@@ -403,6 +403,14 @@ impl VirtioDevice for Net {
     ) -> std::result::Result<(), crate::Error> {
         self.vu_common.add_memory_region(&self.guest_memory, region)
     }
+
+    fn shares_guest_memory(&self) -> bool {
+        true
+    }
+
+    fn reconnect_backend(&mut self) -> std::result::Result<(), crate::Error> {
+        self.vu_common.reconnect()
+    }
 }
 
 impl Pausable for Net {
@@ -11,7 +11,7 @@ use std::convert::TryInto;
 use std::ffi;
 use std::fs::File;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
-use std::os::unix::net::UnixListener;
+use std::os::unix::net::{UnixListener, UnixStream};
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::thread::sleep;
@@ -407,6 +407,17 @@ impl VhostUserHandle {
         }
     }
 
+    pub fn from_stream(stream: UnixStream, num_queues: u64) -> Self {
+        VhostUserHandle {
+            vu: Master::from_stream(stream, num_queues),
+            ready: false,
+            supports_migration: false,
+            shm_log: None,
+            acked_features: 0,
+            vrings_info: None,
+        }
+    }
+
     pub fn socket_handle(&mut self) -> &mut Master {
         &mut self.vu
     }
@@ -20,9 +20,9 @@ use std::fs::File;
 use std::io::{self, Read};
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::result;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Barrier, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
 use virtio_queue::Queue;
@@ -43,7 +43,7 @@ const TIMER_EXPIRED_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
 // This needs to match what the driver is using.
 const WATCHDOG_TIMER_INTERVAL: i64 = 15;
 
-// Number of seconds since last ping to trigger reboot
+// Default number of seconds since last ping to trigger reboot
 const WATCHDOG_TIMEOUT: u64 = WATCHDOG_TIMER_INTERVAL as u64 + 5;
 
 struct WatchdogEpollHandler {
@@ -54,6 +54,7 @@ struct WatchdogEpollHandler {
     pause_evt: EventFd,
     timer: File,
     last_ping_time: Arc<Mutex<Option<Instant>>>,
+    timeout: Arc<AtomicU64>,
     reset_evt: EventFd,
 }
 
@@ -143,7 +144,7 @@ impl EpollHelperHandler for WatchdogEpollHandler {
                 if let Some(last_ping_time) = self.last_ping_time.lock().unwrap().as_ref() {
                     let now = Instant::now();
                     let gap = now.duration_since(*last_ping_time).as_secs();
-                    if gap > WATCHDOG_TIMEOUT {
+                    if gap > self.timeout.load(Ordering::Acquire) {
                         error!("Watchdog triggered: {} seconds since last ping", gap);
                         self.reset_evt.write(1).ok();
                     }
@@ -166,6 +167,8 @@ pub struct Watchdog {
     seccomp_action: SeccompAction,
     reset_evt: EventFd,
     last_ping_time: Arc<Mutex<Option<Instant>>>,
+    // Number of seconds since last ping to trigger reboot
+    timeout: Arc<AtomicU64>,
     timer: File,
     exit_evt: EventFd,
 }
@@ -206,11 +209,46 @@ impl Watchdog {
             seccomp_action,
             reset_evt,
             last_ping_time: Arc::new(Mutex::new(None)),
+            timeout: Arc::new(AtomicU64::new(WATCHDOG_TIMEOUT)),
             timer,
             exit_evt,
         })
     }
 
+    /// Time without a ping after which the VM is reset.
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout.load(Ordering::Acquire))
+    }
+
+    /// Change the time without a ping after which the VM is reset. The guest
+    /// pings the device every 15 seconds, hence the timeout must be longer.
+    pub fn set_timeout(&self, timeout: Duration) -> io::Result<()> {
+        if timeout.as_secs() <= WATCHDOG_TIMER_INTERVAL as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Watchdog timeout must be longer than {} seconds",
+                    WATCHDOG_TIMER_INTERVAL
+                ),
+            ));
+        }
+        info!("Watchdog timeout set to {} seconds", timeout.as_secs());
+        self.timeout.store(timeout.as_secs(), Ordering::Release);
+        Ok(())
+    }
+
+    /// Ping the watchdog on behalf of the guest, delaying the reset. Returns
+    /// false if the watchdog isn't running yet, the guest never having
+    /// pinged it, in which case this has no effect.
+    pub fn pet(&self) -> bool {
+        let mut last_ping_time = self.last_ping_time.lock().unwrap();
+        if last_ping_time.is_none() {
+            return false;
+        }
+        last_ping_time.replace(Instant::now());
+        true
+    }
+
     fn state(&self) -> WatchdogState {
         WatchdogState {
             avail_features: self.common.avail_features,
@@ -315,6 +353,7 @@ impl VirtioDevice for Watchdog {
             pause_evt,
             timer,
             last_ping_time: self.last_ping_time.clone(),
+            timeout: self.timeout.clone(),
             reset_evt,
         };
 
@@ -226,6 +226,18 @@ impl Bus {
         }
     }
 
+    /// Returns the address ranges of the devices on the bus, sorted by base
+    /// address. Ranges whose device was dropped are skipped.
+    pub fn ranges(&self) -> Vec<BusRange> {
+        self.devices
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, dev)| dev.strong_count() > 0)
+            .map(|(range, _)| *range)
+            .collect()
+    }
+
     /// Writes `data` to the device that owns the range containing `addr`.
     ///
     /// Returns true on success, otherwise `data` is untouched.
@@ -357,4 +369,19 @@ mod tests {
         assert!(!a.overlaps(0x1400, 0x100));
         assert!(!a.overlaps(0xf00, 0x100));
     }
+
+    #[test]
+    fn bus_ranges() {
+        let bus = Bus::new();
+        let dummy = Arc::new(Mutex::new(DummyDevice));
+        assert!(bus.insert(dummy.clone(), 0x20, 0x10).is_ok());
+        assert!(bus.insert(dummy.clone(), 0x10, 0x8).is_ok());
+        {
+            let dropped = Arc::new(Mutex::new(DummyDevice));
+            assert!(bus.insert(dropped, 0x40, 0x4).is_ok());
+        }
+
+        let ranges: Vec<(u64, u64)> = bus.ranges().iter().map(|r| (r.base, r.len)).collect();
+        assert_eq!(ranges, vec![(0x10, 0x8), (0x20, 0x10)]);
+    }
 }
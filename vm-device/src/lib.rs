@@ -9,7 +9,7 @@ mod bus;
 pub mod dma_mapping;
 pub mod interrupt;
 
-pub use self::bus::{Bus, BusDevice, Error as BusError};
+pub use self::bus::{Bus, BusDevice, BusRange, Error as BusError};
 
 /// Type of Message Signalled Interrupt
 #[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -164,8 +164,10 @@ pub struct Snapshot {
     pub snapshots: std::collections::BTreeMap<String, Box<Snapshot>>,
 
     /// The Snapshottable component's snapshot data.
-    /// A map of snapshot sections, indexed by the section ids.
-    pub snapshot_data: std::collections::HashMap<String, SnapshotDataSection>,
+    /// A map of snapshot sections, indexed by the section ids. Like for the
+    /// sub-component snapshots, the sections are kept sorted so that the
+    /// same state always serializes to the same bytes.
+    pub snapshot_data: std::collections::BTreeMap<String, SnapshotDataSection>,
 }
 
 impl Snapshot {
@@ -9,9 +9,9 @@ use crate::api::vm_coredump;
 use crate::api::{
     vm_add_device, vm_add_disk, vm_add_fs, vm_add_net, vm_add_pmem, vm_add_user_device,
     vm_add_vdpa, vm_add_vsock, vm_boot, vm_counters, vm_create, vm_delete, vm_info, vm_pause,
-    vm_power_button, vm_reboot, vm_receive_migration, vm_remove_device, vm_resize, vm_resize_zone,
-    vm_restore, vm_resume, vm_send_migration, vm_shutdown, vm_snapshot, vmm_ping, vmm_shutdown,
-    ApiRequest, VmAction, VmConfig,
+    vm_power_button, vm_reboot, vm_reboot_with_kernel, vm_receive_migration, vm_remove_device,
+    vm_resize, vm_resize_zone, vm_restore, vm_resume, vm_send_migration, vm_shutdown, vm_snapshot,
+    vmm_ping, vmm_shutdown, ApiRequest, VmAction, VmConfig,
 };
 use crate::config::NetConfig;
 use micro_http::{Body, Method, Request, Response, StatusCode, Version};
@@ -133,6 +133,11 @@ impl EndpointHandler for VmActionHandler {
                     api_sender,
                     Arc::new(serde_json::from_slice(body.raw())?),
                 ),
+                RebootWithKernel(_) => vm_reboot_with_kernel(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
                 Resize(_) => vm_resize(
                     api_notifier,
                     api_sender,
@@ -43,6 +43,7 @@ use crate::vm::{Error as VmError, VmState};
 use micro_http::Body;
 use serde::{Deserialize, Serialize};
 use std::io;
+use std::path::PathBuf;
 use std::sync::mpsc::{channel, RecvError, SendError, Sender};
 use std::sync::{Arc, Mutex};
 use vm_migration::MigratableError;
@@ -175,6 +176,15 @@ pub struct VmResizeData {
     pub desired_balloon: Option<u64>,
 }
 
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmRebootWithKernelData {
+    /// Path to the kernel to boot
+    pub kernel: PathBuf,
+    /// Kernel command line, the current one is kept if not provided
+    #[serde(default)]
+    pub cmdline: Option<String>,
+}
+
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
 pub struct VmResizeZoneData {
     pub id: String,
@@ -190,6 +200,10 @@ pub struct VmRemoveDeviceData {
 pub struct VmSnapshotConfig {
     /// The snapshot destination URL
     pub destination_url: String,
+    /// Only snapshot the guest memory and vCPUs, devices being created from
+    /// the configuration when restoring.
+    #[serde(default)]
+    pub memory_only: bool,
 }
 
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
@@ -275,6 +289,11 @@ pub enum ApiRequest {
     /// will send a VmReboot error back.
     VmReboot(Sender<ApiResponse>),
 
+    /// Reboot the previously booted virtual machine into a different kernel.
+    /// If the VM was not previously booted or created, or if the kernel
+    /// can't be loaded, the VMM API server will send a VmReboot error back.
+    VmRebootWithKernel(Arc<VmRebootWithKernelData>, Sender<ApiResponse>),
+
     /// Shut the VMM down.
     /// This will shutdown and delete the current VM, if any, and then exit the
     /// VMM process.
@@ -367,6 +386,9 @@ pub enum VmAction {
     /// Reboot a VM
     Reboot,
 
+    /// Reboot a VM into a different kernel
+    RebootWithKernel(Arc<VmRebootWithKernelData>),
+
     /// Pause a VM
     Pause,
 
@@ -442,6 +464,7 @@ fn vm_action(
         Delete => ApiRequest::VmDelete(response_sender),
         Shutdown => ApiRequest::VmShutdown(response_sender),
         Reboot => ApiRequest::VmReboot(response_sender),
+        RebootWithKernel(v) => ApiRequest::VmRebootWithKernel(v, response_sender),
         Pause => ApiRequest::VmPause(response_sender),
         Resume => ApiRequest::VmResume(response_sender),
         Counters => ApiRequest::VmCounters(response_sender),
@@ -494,6 +517,14 @@ pub fn vm_reboot(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResult<
     vm_action(api_evt, api_sender, VmAction::Reboot)
 }
 
+pub fn vm_reboot_with_kernel(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmRebootWithKernelData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::RebootWithKernel(data))
+}
+
 pub fn vm_pause(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResult<Option<Body>> {
     vm_action(api_evt, api_sender, VmAction::Pause)
 }
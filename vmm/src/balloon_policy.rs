@@ -0,0 +1,300 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Automatic sizing of the balloon based on the host memory pressure.
+//!
+//! Once a policy is set, an adjuster thread periodically compares the memory
+//! available on the host with the amount the policy asks to keep free. The
+//! balloon is inflated when the host runs short of memory, and deflated back
+//! when the host has memory to spare. The policy bounds the balloon size,
+//! and the memory the guest reports as available through the balloon
+//! statistics limits how much is taken from the guest at once.
+
+use crate::config::VmConfig;
+use crate::device_manager::DeviceManager;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::result;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Time between two adjustments of the balloon.
+pub const BALLOON_POLICY_INTERVAL: Duration = Duration::from_secs(1);
+
+// Balloon sizes are kept a multiple of this, to avoid tiny adjustments.
+const BALLOON_POLICY_GRANULARITY: u64 = 2 << 20;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("No balloon device to apply the policy to")]
+    NoBalloon,
+
+    #[error("Guest minimum RAM {0} is larger than the guest RAM {1}")]
+    MinGuestTooLarge(u64, u64),
+
+    #[error("Error spawning the balloon policy thread: {0}")]
+    ThreadSpawn(#[source] io::Error),
+}
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BalloonPolicy {
+    /// Memory, in bytes, to keep available on the host.
+    pub target_host_free: u64,
+    /// Guest RAM, in bytes, the balloon never takes from the guest.
+    pub min_guest: u64,
+    /// Maximum size of the balloon, in bytes.
+    pub max_balloon: u64,
+}
+
+/// Balloon size keeping `policy.target_host_free` bytes available on the
+/// host, which has `host_available` bytes available with the current
+/// `balloon` size, for a guest of `guest_ram` bytes. When known, the guest
+/// `guest_available` memory bounds the inflation to half of it at once.
+pub fn target_balloon_size(
+    policy: &BalloonPolicy,
+    balloon: u64,
+    guest_ram: u64,
+    host_available: u64,
+    guest_available: Option<u64>,
+) -> u64 {
+    let max_balloon = std::cmp::min(
+        policy.max_balloon,
+        guest_ram.saturating_sub(policy.min_guest),
+    );
+
+    let target = if host_available < policy.target_host_free {
+        let mut inflate = policy.target_host_free - host_available;
+        if let Some(guest_available) = guest_available {
+            inflate = std::cmp::min(inflate, guest_available / 2);
+        }
+        balloon + inflate
+    } else {
+        balloon.saturating_sub(host_available - policy.target_host_free)
+    };
+
+    std::cmp::min(target, max_balloon) / BALLOON_POLICY_GRANULARITY * BALLOON_POLICY_GRANULARITY
+}
+
+fn parse_mem_available(meminfo: &str) -> Option<u64> {
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))
+        .and_then(|value| value.trim().strip_suffix("kB"))
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(|kb| kb << 10)
+}
+
+/// Memory available on the host, in bytes, as reported by /proc/meminfo.
+pub fn host_available_memory() -> io::Result<u64> {
+    parse_mem_available(&fs::read_to_string("/proc/meminfo")?).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "No MemAvailable entry in /proc/meminfo",
+        )
+    })
+}
+
+/// Thread resizing the balloon according to a policy, until dropped.
+pub struct BalloonAdjuster {
+    policy: BalloonPolicy,
+    // Held while adjusting the balloon, the flag telling if adjusting is
+    // allowed. Pausing the VM suspends the adjuster first, as resizing the
+    // balloon of a paused VM would block.
+    active: Arc<Mutex<bool>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl BalloonAdjuster {
+    pub fn new(
+        policy: BalloonPolicy,
+        active: bool,
+        config: Arc<Mutex<VmConfig>>,
+        device_manager: Arc<Mutex<DeviceManager>>,
+    ) -> Result<Self> {
+        let guest_ram = config.lock().unwrap().memory.total_size();
+        if policy.min_guest > guest_ram {
+            return Err(Error::MinGuestTooLarge(policy.min_guest, guest_ram));
+        }
+        if config.lock().unwrap().balloon.is_none() {
+            return Err(Error::NoBalloon);
+        }
+
+        let active = Arc::new(Mutex::new(active));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let active = active.clone();
+            let stop = stop.clone();
+            thread::Builder::new()
+                .name("balloon_policy".to_string())
+                .spawn(move || {
+                    while !stop.load(Ordering::Acquire) {
+                        // Keep the flag locked while adjusting, for suspend()
+                        // to wait for the adjustment to complete.
+                        let active = active.lock().unwrap();
+                        if *active {
+                            Self::adjust(&policy, &config, &device_manager);
+                        }
+                        drop(active);
+                        thread::park_timeout(BALLOON_POLICY_INTERVAL);
+                    }
+                })
+                .map_err(Error::ThreadSpawn)?
+        };
+
+        Ok(BalloonAdjuster {
+            policy,
+            active,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn policy(&self) -> BalloonPolicy {
+        self.policy
+    }
+
+    /// Stop adjusting the balloon, waiting for any ongoing adjustment.
+    pub fn suspend(&self) {
+        *self.active.lock().unwrap() = false;
+    }
+
+    pub fn resume(&self) {
+        *self.active.lock().unwrap() = true;
+    }
+
+    fn adjust(
+        policy: &BalloonPolicy,
+        config: &Arc<Mutex<VmConfig>>,
+        device_manager: &Arc<Mutex<DeviceManager>>,
+    ) {
+        let host_available = match host_available_memory() {
+            Ok(host_available) => host_available,
+            Err(e) => {
+                warn!("Error reading the host available memory: {}", e);
+                return;
+            }
+        };
+        let (balloon, guest_ram) = {
+            let config = config.lock().unwrap();
+            (
+                config.balloon.as_ref().map(|b| b.size).unwrap_or(0),
+                config.memory.total_size(),
+            )
+        };
+        let (actual, statistics) = {
+            let device_manager = device_manager.lock().unwrap();
+            (
+                device_manager.balloon_size(),
+                device_manager.balloon_statistics(),
+            )
+        };
+
+        let target = target_balloon_size(
+            policy,
+            balloon,
+            guest_ram,
+            host_available,
+            statistics.and_then(|s| s.available_memory),
+        );
+        if target == balloon {
+            return;
+        }
+        // Let the guest catch up with the previous inflation first.
+        if target > balloon && actual + BALLOON_POLICY_GRANULARITY < balloon {
+            return;
+        }
+
+        if let Err(e) = device_manager.lock().unwrap().resize_balloon(target) {
+            warn!("Error resizing the balloon to {}: {:?}", target, e);
+            return;
+        }
+        if let Some(balloon_config) = &mut config.lock().unwrap().balloon {
+            balloon_config.size = target;
+        }
+
+        event!(
+            "vm",
+            if target > balloon {
+                "balloon-policy-inflated"
+            } else {
+                "balloon-policy-deflated"
+            },
+            "size",
+            target.to_string(),
+            "host_available",
+            host_available.to_string()
+        );
+    }
+}
+
+impl Drop for BalloonAdjuster {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            if thread.join().is_err() {
+                error!("balloon_policy thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_balloon_size() {
+        let policy = BalloonPolicy {
+            target_host_free: 4 << 30,
+            min_guest: 1 << 30,
+            max_balloon: 2 << 30,
+        };
+
+        // Host short of 512M, the balloon grows by as much.
+        assert_eq!(
+            target_balloon_size(&policy, 0, 4 << 30, 3584 << 20, None),
+            512 << 20
+        );
+        // Never more than half of what the guest has available.
+        assert_eq!(
+            target_balloon_size(&policy, 0, 4 << 30, 3584 << 20, Some(256 << 20)),
+            128 << 20
+        );
+        // Bounded by the maximum balloon size.
+        assert_eq!(
+            target_balloon_size(&policy, 1 << 30, 4 << 30, 1 << 30, None),
+            2 << 30
+        );
+        // Bounded by the guest minimum RAM.
+        assert_eq!(
+            target_balloon_size(&policy, 1 << 30, 2 << 30, 1 << 30, None),
+            1 << 30
+        );
+        // Host with memory to spare, the balloon deflates.
+        assert_eq!(
+            target_balloon_size(&policy, 1 << 30, 4 << 30, 4608 << 20, None),
+            512 << 20
+        );
+        assert_eq!(
+            target_balloon_size(&policy, 1 << 30, 4 << 30, 8 << 30, None),
+            0
+        );
+    }
+
+    #[test]
+    fn test_parse_mem_available() {
+        let meminfo = "MemTotal:       16314716 kB\n\
+                       MemFree:         1037436 kB\n\
+                       MemAvailable:    9412616 kB\n\
+                       Buffers:          512120 kB\n";
+        assert_eq!(parse_mem_available(meminfo), Some(9412616 << 10));
+        assert_eq!(parse_mem_available("MemTotal: 16314716 kB\n"), None);
+    }
+}
@@ -27,14 +27,27 @@ pub const DEFAULT_MEMORY_MB: u64 = 512;
 // paging.
 pub const DEFAULT_MAX_PHYS_BITS: u8 = 46;
 
+// Below this number of vCPUs, creating them in parallel isn't worth spawning
+// threads for.
+pub const DEFAULT_PARALLEL_CREATION_THRESHOLD: u8 = 16;
+
 pub const DEFAULT_RNG_SOURCE: &str = "/dev/urandom";
 pub const DEFAULT_NUM_QUEUES_VUNET: usize = 2;
 pub const DEFAULT_QUEUE_SIZE_VUNET: u16 = 256;
 pub const DEFAULT_NUM_QUEUES_VUBLK: usize = 1;
 pub const DEFAULT_QUEUE_SIZE_VUBLK: u16 = 128;
+pub const DEFAULT_DISK_MAX_INFLIGHT_BYTES: u64 = 256 << 20;
 
 pub const DEFAULT_NUM_PCI_SEGMENTS: u16 = 1;
 const MAX_NUM_PCI_SEGMENTS: u16 = 16;
+// Number of legacy COM ports available after the first serial port.
+pub const MAX_EXTRA_SERIAL_PORTS: usize = 3;
+// The SMBIOS OEM strings structure stores the number of strings on a byte.
+const MAX_OEM_STRINGS: usize = 255;
+const MAX_OEM_STRING_LEN: usize = 255;
+// The SMBIOS tables must fit in the 64KiB below the high RAM the kernel is
+// loaded in, bound the OEM strings, NUL terminators included, well below.
+const MAX_OEM_STRINGS_SIZE: usize = 16 << 10;
 
 /// Errors associated with VM configuration parameters.
 #[derive(Debug, Error)]
@@ -107,10 +120,15 @@ pub enum Error {
     ParseUserDeviceSocketMissing,
     /// Failed parsing platform parameters
     ParsePlatform(OptionParserError),
+    /// Failed parsing guest panic parameters
+    #[cfg(target_arch = "x86_64")]
+    ParseGuestPanic(OptionParserError),
     /// Failed parsing vDPA device
     ParseVdpa(OptionParserError),
     /// Missing path for vDPA device
     ParseVdpaPathMissing,
+    /// Failed parsing reset action
+    ParseOnReset(ParseResetActionError),
 }
 
 #[derive(Debug, PartialEq, Error)]
@@ -119,6 +137,9 @@ pub enum ValidationError {
     DoubleTtyMode,
     /// No kernel specified
     KernelMissing,
+    /// Kernel load address overlaps the boot structures or is out of RAM
+    #[cfg(target_arch = "x86_64")]
+    InvalidKernelLoadAddress(u64),
     /// Missing file value for console
     ConsoleFileMissing,
     /// Max is less than boot
@@ -150,6 +171,10 @@ pub enum ValidationError {
     HugePageSizeWithoutHugePages,
     /// Huge page size is not power of 2
     InvalidHugePageSize(u64),
+    /// Huge page size is neither 2MiB nor 1GiB
+    UnsupportedHugePageSize(u64),
+    /// Memory size is not a multiple of the huge page size
+    MemorySizeNotHugePageAligned(u64, u64),
     /// CPU Hotplug is not permitted with TDX
     #[cfg(feature = "tdx")]
     TdxNoCpuHotplug,
@@ -163,6 +188,16 @@ pub enum ValidationError {
     InvalidNumPciSegments(u16),
     /// Invalid PCI segment id
     InvalidPciSegment(u16),
+    /// Disk in flight limit doesn't allow any request
+    InvalidMaxInflightBytes,
+    /// Invalid platform UUID
+    InvalidUuid(String),
+    /// Too many SMBIOS OEM strings
+    TooManyOemStrings(usize),
+    /// SMBIOS OEM string is empty or too long
+    InvalidOemString(String),
+    /// SMBIOS OEM strings too large altogether
+    OemStringsTooLarge(usize),
     /// Balloon too big
     BalloonLargerThanRam(u64, u64),
     /// On a IOMMU segment but not behind IOMMU
@@ -175,6 +210,20 @@ pub enum ValidationError {
     InvalidIdentifier(String),
     /// Placing the device behind a virtual IOMMU is not supported
     IommuNotSupported,
+    /// Too many serial ports
+    TooManySerialPorts(usize),
+    /// Additional serial ports can't use tty mode
+    ExtraSerialTtyMode,
+    /// Legacy serial ports can't be hot-plugged
+    SerialDeviceLegacyBackend,
+    /// Hot-plugged serial devices only support pty, file and null modes
+    SerialDeviceMode,
+    /// Dumping the guest on panic requires the guest_debug feature
+    #[cfg(target_arch = "x86_64")]
+    GuestPanicCoredumpUnsupported,
+    /// Reserved memory range is misaligned, out of the boot RAM, or overlaps
+    /// the boot structures or another reserved range
+    InvalidReservedMemory(u64, u64),
 }
 
 type ValidationResult<T> = std::result::Result<T, ValidationError>;
@@ -185,6 +234,13 @@ impl fmt::Display for ValidationError {
         match self {
             DoubleTtyMode => write!(f, "Console mode tty specified for both serial and console"),
             KernelMissing => write!(f, "No kernel specified"),
+            #[cfg(target_arch = "x86_64")]
+            InvalidKernelLoadAddress(address) => write!(
+                f,
+                "Kernel load address 0x{:x} must be page aligned and within the low RAM, above 0x{:x}",
+                address,
+                arch::layout::HIGH_RAM_START.raw_value()
+            ),
             ConsoleFileMissing => write!(f, "Path missing when using file console mode"),
             CpusMaxLowerThanBoot => write!(f, "Max CPUs lower than boot CPUs"),
             DiskSocketAndPath => write!(f, "Disk path and vhost socket both provided"),
@@ -213,6 +269,16 @@ impl fmt::Display for ValidationError {
             InvalidHugePageSize(s) => {
                 write!(f, "Huge page size is not power of 2: {}", s)
             }
+            UnsupportedHugePageSize(s) => {
+                write!(f, "Huge page size is neither 2MiB nor 1GiB: {}", s)
+            }
+            MemorySizeNotHugePageAligned(size, hugepage_size) => {
+                write!(
+                    f,
+                    "Memory size {} is not a multiple of the huge page size {}",
+                    size, hugepage_size
+                )
+            }
             #[cfg(feature = "tdx")]
             TdxNoCpuHotplug => {
                 write!(f, "CPU hotplug is not permitted with TDX")
@@ -240,6 +306,33 @@ impl fmt::Display for ValidationError {
             InvalidPciSegment(pci_segment) => {
                 write!(f, "Invalid PCI segment id: {}", pci_segment)
             }
+            InvalidMaxInflightBytes => {
+                write!(f, "Disk max_inflight_bytes must be greater than 0")
+            }
+            InvalidUuid(uuid) => {
+                write!(f, "Invalid platform UUID: {}", uuid)
+            }
+            TooManyOemStrings(n) => {
+                write!(
+                    f,
+                    "Number of OEM strings ({}) greater than {}",
+                    n, MAX_OEM_STRINGS
+                )
+            }
+            InvalidOemString(s) => {
+                write!(
+                    f,
+                    "OEM string \"{}\" must be between 1 and {} bytes long",
+                    s, MAX_OEM_STRING_LEN
+                )
+            }
+            OemStringsTooLarge(size) => {
+                write!(
+                    f,
+                    "OEM strings take {} bytes, more than {}",
+                    size, MAX_OEM_STRINGS_SIZE
+                )
+            }
             BalloonLargerThanRam(balloon_size, ram_size) => {
                 write!(
                     f,
@@ -270,6 +363,44 @@ impl fmt::Display for ValidationError {
             IommuNotSupported => {
                 write!(f, "Device does not support being placed behind IOMMU")
             }
+            TooManySerialPorts(n) => {
+                write!(
+                    f,
+                    "Too many additional serial ports ({}), the maximum is {}",
+                    n, MAX_EXTRA_SERIAL_PORTS
+                )
+            }
+            ExtraSerialTtyMode => {
+                write!(
+                    f,
+                    "Console mode tty is not supported for additional serial ports"
+                )
+            }
+            SerialDeviceLegacyBackend => {
+                write!(
+                    f,
+                    "Legacy ISA serial ports can't be hot-plugged, use the virtio-console backend"
+                )
+            }
+            SerialDeviceMode => {
+                write!(
+                    f,
+                    "Serial devices only support pty, file and null console modes"
+                )
+            }
+            #[cfg(target_arch = "x86_64")]
+            GuestPanicCoredumpUnsupported => {
+                write!(
+                    f,
+                    "Dumping the guest on panic requires the guest_debug feature"
+                )
+            }
+            InvalidReservedMemory(start, size) => write!(
+                f,
+                "Reserved memory range 0x{:x} of size 0x{:x} must be page aligned, within the boot RAM, \
+                and must not overlap the boot structures, the ACPI tables or another reserved range",
+                start, size
+            ),
         }
     }
 }
@@ -320,8 +451,11 @@ impl fmt::Display for Error {
             #[cfg(feature = "tdx")]
             FirmwarePathMissing => write!(f, "TDX firmware missing"),
             ParsePlatform(o) => write!(f, "Error parsing --platform: {}", o),
+            #[cfg(target_arch = "x86_64")]
+            ParseGuestPanic(o) => write!(f, "Error parsing --guest-panic: {}", o),
             ParseVdpa(o) => write!(f, "Error parsing --vdpa: {}", o),
             ParseVdpaPathMissing => write!(f, "Error parsing --vdpa: path missing"),
+            ParseOnReset(e) => write!(f, "Error parsing --on-reset: {:?}", e),
         }
     }
 }
@@ -351,6 +485,7 @@ pub struct VmParams<'a> {
     pub pmem: Option<Vec<&'a str>>,
     pub serial: &'a str,
     pub console: &'a str,
+    pub extra_serial: Option<Vec<&'a str>>,
     pub devices: Option<Vec<&'a str>>,
     pub user_devices: Option<Vec<&'a str>>,
     pub vdpa: Option<Vec<&'a str>>,
@@ -364,6 +499,12 @@ pub struct VmParams<'a> {
     #[cfg(feature = "gdb")]
     pub gdb: bool,
     pub platform: Option<&'a str>,
+    #[cfg(target_arch = "x86_64")]
+    pub guest_panic: Option<&'a str>,
+    pub stop_on_boot: bool,
+    pub break_on_triple_fault: bool,
+    pub on_reset: Option<&'a str>,
+    pub no_cmdline_console: bool,
 }
 
 impl<'a> VmParams<'a> {
@@ -382,6 +523,7 @@ impl<'a> VmParams<'a> {
         let disks: Option<Vec<&str>> = args.values_of("disk").map(|x| x.collect());
         let net: Option<Vec<&str>> = args.values_of("net").map(|x| x.collect());
         let console = args.value_of("console").unwrap();
+        let extra_serial: Option<Vec<&str>> = args.values_of("extra-serial").map(|x| x.collect());
         let balloon = args.value_of("balloon");
         let fs: Option<Vec<&str>> = args.values_of("fs").map(|x| x.collect());
         let pmem: Option<Vec<&str>> = args.values_of("pmem").map(|x| x.collect());
@@ -394,10 +536,16 @@ impl<'a> VmParams<'a> {
         let numa: Option<Vec<&str>> = args.values_of("numa").map(|x| x.collect());
         let watchdog = args.is_present("watchdog");
         let platform = args.value_of("platform");
+        #[cfg(target_arch = "x86_64")]
+        let guest_panic = args.value_of("guest-panic");
         #[cfg(feature = "tdx")]
         let tdx = args.value_of("tdx");
         #[cfg(feature = "gdb")]
         let gdb = args.is_present("gdb");
+        let stop_on_boot = args.is_present("stop-on-boot");
+        let break_on_triple_fault = args.is_present("break-on-triple-fault");
+        let no_cmdline_console = args.is_present("no-cmdline-console");
+        let on_reset = args.value_of("on-reset");
         VmParams {
             cpus,
             memory,
@@ -413,6 +561,7 @@ impl<'a> VmParams<'a> {
             pmem,
             serial,
             console,
+            extra_serial,
             devices,
             user_devices,
             vdpa,
@@ -426,6 +575,12 @@ impl<'a> VmParams<'a> {
             #[cfg(feature = "gdb")]
             gdb,
             platform,
+            #[cfg(target_arch = "x86_64")]
+            guest_panic,
+            stop_on_boot,
+            break_on_triple_fault,
+            no_cmdline_console,
+            on_reset,
         }
     }
 }
@@ -516,6 +671,15 @@ fn default_cpuconfig_max_phys_bits() -> u8 {
     DEFAULT_MAX_PHYS_BITS
 }
 
+#[cfg(target_arch = "x86_64")]
+fn default_cpuconfig_tsc_deadline_timer() -> bool {
+    true
+}
+
+fn default_cpuconfig_parallel_creation_threshold() -> u8 {
+    DEFAULT_PARALLEL_CREATION_THRESHOLD
+}
+
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct CpusConfig {
     pub boot_vcpus: u8,
@@ -530,6 +694,14 @@ pub struct CpusConfig {
     pub affinity: Option<Vec<CpuAffinity>>,
     #[serde(default)]
     pub features: CpuFeatures,
+    /// Expose the TSC-deadline mode of the APIC timer in the guest CPUID.
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default = "default_cpuconfig_tsc_deadline_timer")]
+    pub tsc_deadline_timer: bool,
+    /// Minimum number of vCPUs created at once for them to be created in
+    /// parallel, 0 disabling the parallel creation.
+    #[serde(default = "default_cpuconfig_parallel_creation_threshold")]
+    pub parallel_creation_threshold: u8,
 }
 
 impl CpusConfig {
@@ -542,7 +714,10 @@ impl CpusConfig {
             .add("kvm_hyperv")
             .add("max_phys_bits")
             .add("affinity")
-            .add("features");
+            .add("features")
+            .add("parallel_creation_threshold");
+        #[cfg(target_arch = "x86_64")]
+        parser.add("tsc_deadline_timer");
         parser.parse(cpus).map_err(Error::ParseCpus)?;
 
         let boot_vcpus: u8 = parser
@@ -563,6 +738,16 @@ impl CpusConfig {
             .convert::<u8>("max_phys_bits")
             .map_err(Error::ParseCpus)?
             .unwrap_or(DEFAULT_MAX_PHYS_BITS);
+        #[cfg(target_arch = "x86_64")]
+        let tsc_deadline_timer = parser
+            .convert::<Toggle>("tsc_deadline_timer")
+            .map_err(Error::ParseCpus)?
+            .unwrap_or(Toggle(true))
+            .0;
+        let parallel_creation_threshold = parser
+            .convert("parallel_creation_threshold")
+            .map_err(Error::ParseCpus)?
+            .unwrap_or(DEFAULT_PARALLEL_CREATION_THRESHOLD);
         let affinity = parser
             .convert::<Tuple<u8, Vec<u8>>>("affinity")
             .map_err(Error::ParseCpus)?
@@ -604,6 +789,9 @@ impl CpusConfig {
             max_phys_bits,
             affinity,
             features,
+            #[cfg(target_arch = "x86_64")]
+            tsc_deadline_timer,
+            parallel_creation_threshold,
         })
     }
 }
@@ -618,6 +806,9 @@ impl Default for CpusConfig {
             max_phys_bits: DEFAULT_MAX_PHYS_BITS,
             affinity: None,
             features: CpuFeatures::default(),
+            #[cfg(target_arch = "x86_64")]
+            tsc_deadline_timer: true,
+            parallel_creation_threshold: DEFAULT_PARALLEL_CREATION_THRESHOLD,
         }
     }
 }
@@ -626,6 +817,163 @@ fn default_platformconfig_num_pci_segments() -> u16 {
     DEFAULT_NUM_PCI_SEGMENTS
 }
 
+// Reads from a missing PCI device return all ones, do the same for any
+// unregistered address.
+const DEFAULT_UNREGISTERED_READ_FILL: u8 = 0xff;
+
+fn default_platformconfig_unregistered_read_fill() -> u8 {
+    DEFAULT_UNREGISTERED_READ_FILL
+}
+
+/// What to do when the guest accesses an MMIO or PIO address no device is
+/// registered for.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum UnregisteredIoPolicy {
+    /// Log a warning and let the guest continue.
+    Warn,
+    /// Fail the access, pausing the VM into the `BreakPoint` state so that
+    /// the guest state can be inspected.
+    Fault,
+}
+
+impl Default for UnregisteredIoPolicy {
+    fn default() -> Self {
+        UnregisteredIoPolicy::Warn
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseUnregisteredIoPolicyError {
+    InvalidValue(String),
+}
+
+impl FromStr for UnregisteredIoPolicy {
+    type Err = ParseUnregisteredIoPolicyError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "warn" => Ok(UnregisteredIoPolicy::Warn),
+            "fault" => Ok(UnregisteredIoPolicy::Fault),
+            _ => Err(ParseUnregisteredIoPolicyError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+/// What to do when the guest requests a reset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ResetAction {
+    /// Rebuild the VM in place and boot it again.
+    Restart,
+    /// Shut the VMM down, leaving the restart to whoever manages it.
+    Exit,
+    /// Pause the VM, keeping the guest state around.
+    Pause,
+}
+
+impl Default for ResetAction {
+    fn default() -> Self {
+        ResetAction::Restart
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseResetActionError {
+    InvalidValue(String),
+}
+
+impl FromStr for ResetAction {
+    type Err = ParseResetActionError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "restart" => Ok(ResetAction::Restart),
+            "exit" => Ok(ResetAction::Exit),
+            "pause" => Ok(ResetAction::Pause),
+            _ => Err(ParseResetActionError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+/// What to do once the guest reported a panic, after it has optionally been
+/// dumped.
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum GuestPanicAction {
+    /// Let the guest handle its own panic.
+    None,
+    Pause,
+    Reset,
+    Shutdown,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Default for GuestPanicAction {
+    fn default() -> Self {
+        GuestPanicAction::None
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[derive(Debug)]
+pub enum ParseGuestPanicActionError {
+    InvalidValue(String),
+}
+
+#[cfg(target_arch = "x86_64")]
+impl FromStr for GuestPanicAction {
+    type Err = ParseGuestPanicActionError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(GuestPanicAction::None),
+            "pause" => Ok(GuestPanicAction::Pause),
+            "reset" => Ok(GuestPanicAction::Reset),
+            "shutdown" => Ok(GuestPanicAction::Shutdown),
+            _ => Err(ParseGuestPanicActionError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+/// Policy applied when the guest reports a panic through the pvpanic device.
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct GuestPanicConfig {
+    /// Write an ELF coredump of the guest to this path when it panics.
+    #[serde(default)]
+    pub coredump: Option<PathBuf>,
+    #[serde(default)]
+    pub action: GuestPanicAction,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl GuestPanicConfig {
+    pub const SYNTAX: &'static str = "Guest panic parameters \
+         \"coredump=<coredump_path>,action=none|pause|reset|shutdown\"";
+
+    pub fn parse(guest_panic: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("coredump").add("action");
+        parser.parse(guest_panic).map_err(Error::ParseGuestPanic)?;
+
+        let coredump = parser.get("coredump").map(PathBuf::from);
+        let action = parser
+            .convert("action")
+            .map_err(Error::ParseGuestPanic)?
+            .unwrap_or_default();
+
+        Ok(GuestPanicConfig { coredump, action })
+    }
+
+    pub fn validate(&self) -> ValidationResult<()> {
+        #[cfg(not(feature = "guest_debug"))]
+        if self.coredump.is_some() {
+            return Err(ValidationError::GuestPanicCoredumpUnsupported);
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct PlatformConfig {
     #[serde(default = "default_platformconfig_num_pci_segments")]
@@ -634,6 +982,14 @@ pub struct PlatformConfig {
     pub iommu_segments: Option<Vec<u16>>,
     #[serde(default)]
     pub serial_number: Option<String>,
+    #[serde(default)]
+    pub uuid: Option<String>,
+    #[serde(default)]
+    pub oem_strings: Option<Vec<String>>,
+    #[serde(default)]
+    pub unregistered_io: UnregisteredIoPolicy,
+    #[serde(default = "default_platformconfig_unregistered_read_fill")]
+    pub unregistered_read_fill: u8,
 }
 
 impl PlatformConfig {
@@ -642,6 +998,10 @@ impl PlatformConfig {
         parser.add("num_pci_segments");
         parser.add("iommu_segments");
         parser.add("serial_number");
+        parser.add("uuid");
+        parser.add("oem_strings");
+        parser.add("unregistered_io");
+        parser.add("unregistered_read_fill");
         parser.parse(platform).map_err(Error::ParsePlatform)?;
 
         let num_pci_segments: u16 = parser
@@ -655,10 +1015,27 @@ impl PlatformConfig {
         let serial_number = parser
             .convert("serial_number")
             .map_err(Error::ParsePlatform)?;
+        let uuid = parser.convert("uuid").map_err(Error::ParsePlatform)?;
+        let oem_strings = parser
+            .convert::<StringList>("oem_strings")
+            .map_err(Error::ParsePlatform)?
+            .map(|v| v.0);
+        let unregistered_io = parser
+            .convert("unregistered_io")
+            .map_err(Error::ParsePlatform)?
+            .unwrap_or_default();
+        let unregistered_read_fill = parser
+            .convert("unregistered_read_fill")
+            .map_err(Error::ParsePlatform)?
+            .unwrap_or(DEFAULT_UNREGISTERED_READ_FILL);
         Ok(PlatformConfig {
             num_pci_segments,
             iommu_segments,
             serial_number,
+            uuid,
+            oem_strings,
+            unregistered_io,
+            unregistered_read_fill,
         })
     }
 
@@ -677,6 +1054,29 @@ impl PlatformConfig {
             }
         }
 
+        if let Some(uuid) = &self.uuid {
+            uuid::Uuid::parse_str(uuid).map_err(|_| ValidationError::InvalidUuid(uuid.clone()))?;
+        }
+
+        if let Some(oem_strings) = &self.oem_strings {
+            if oem_strings.len() > MAX_OEM_STRINGS {
+                return Err(ValidationError::TooManyOemStrings(oem_strings.len()));
+            }
+
+            for s in oem_strings {
+                // SMBIOS strings are NUL terminated and an empty string
+                // would terminate the set of strings.
+                if s.is_empty() || s.len() > MAX_OEM_STRING_LEN || s.contains('\0') {
+                    return Err(ValidationError::InvalidOemString(s.clone()));
+                }
+            }
+
+            let size: usize = oem_strings.iter().map(|s| s.len() + 1).sum();
+            if size > MAX_OEM_STRINGS_SIZE {
+                return Err(ValidationError::OemStringsTooLarge(size));
+            }
+        }
+
         Ok(())
     }
 }
@@ -687,6 +1087,10 @@ impl Default for PlatformConfig {
             num_pci_segments: DEFAULT_NUM_PCI_SEGMENTS,
             iommu_segments: None,
             serial_number: None,
+            uuid: None,
+            oem_strings: None,
+            unregistered_io: UnregisteredIoPolicy::default(),
+            unregistered_read_fill: DEFAULT_UNREGISTERED_READ_FILL,
         }
     }
 }
@@ -713,6 +1117,27 @@ pub struct MemoryZoneConfig {
     pub prefault: bool,
 }
 
+/// Range of the guest RAM reported as reserved in the guest memory map, so
+/// that the guest OS leaves it alone. `start` is an offset from the start of
+/// the RAM, which isn't at address 0 on aarch64.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ReservedMemoryConfig {
+    pub start: u64,
+    pub size: u64,
+}
+
+impl ReservedMemoryConfig {
+    /// Guest physical address of the start of the range.
+    pub fn guest_address(&self) -> u64 {
+        #[cfg(target_arch = "x86_64")]
+        let ram_start = arch::layout::LOW_RAM_START.raw_value();
+        #[cfg(target_arch = "aarch64")]
+        let ram_start = arch::layout::RAM_START.raw_value();
+
+        ram_start.saturating_add(self.start)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct MemoryConfig {
     pub size: u64,
@@ -733,7 +1158,15 @@ pub struct MemoryConfig {
     #[serde(default)]
     pub prefault: bool,
     #[serde(default)]
+    pub wipe_on_shutdown: bool,
+    #[serde(default)]
+    pub prefault_timeout: Option<u64>,
+    #[serde(default)]
+    pub mlock: bool,
+    #[serde(default)]
     pub zones: Option<Vec<MemoryZoneConfig>>,
+    #[serde(default)]
+    pub reserved: Option<Vec<ReservedMemoryConfig>>,
 }
 
 impl MemoryConfig {
@@ -749,7 +1182,11 @@ impl MemoryConfig {
             .add("shared")
             .add("hugepages")
             .add("hugepage_size")
-            .add("prefault");
+            .add("prefault")
+            .add("wipe_on_shutdown")
+            .add("prefault_timeout")
+            .add("mlock")
+            .add("reserved");
         parser.parse(memory).map_err(Error::ParseMemory)?;
 
         let size = parser
@@ -793,6 +1230,30 @@ impl MemoryConfig {
             .map_err(Error::ParseMemory)?
             .unwrap_or(Toggle(false))
             .0;
+        let wipe_on_shutdown = parser
+            .convert::<Toggle>("wipe_on_shutdown")
+            .map_err(Error::ParseMemory)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let prefault_timeout = parser
+            .convert::<u64>("prefault_timeout")
+            .map_err(Error::ParseMemory)?;
+        let mlock = parser
+            .convert::<Toggle>("mlock")
+            .map_err(Error::ParseMemory)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let reserved = parser
+            .convert::<Tuple<ByteSized, ByteSized>>("reserved")
+            .map_err(Error::ParseMemory)?
+            .map(|v| {
+                v.0.iter()
+                    .map(|(start, size)| ReservedMemoryConfig {
+                        start: start.0,
+                        size: size.0,
+                    })
+                    .collect()
+            });
 
         let zones: Option<Vec<MemoryZoneConfig>> = if let Some(memory_zones) = &memory_zones {
             let mut zones = Vec::new();
@@ -878,7 +1339,11 @@ impl MemoryConfig {
             hugepages,
             hugepage_size,
             prefault,
+            wipe_on_shutdown,
+            prefault_timeout,
+            mlock,
             zones,
+            reserved,
         })
     }
 
@@ -913,7 +1378,11 @@ impl Default for MemoryConfig {
             hugepages: false,
             hugepage_size: None,
             prefault: false,
+            wipe_on_shutdown: false,
+            prefault_timeout: None,
+            mlock: false,
             zones: None,
+            reserved: None,
         }
     }
 }
@@ -921,6 +1390,8 @@ impl Default for MemoryConfig {
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct KernelConfig {
     pub path: PathBuf,
+    #[serde(default)]
+    pub highmem_start: Option<u64>,
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -928,18 +1399,45 @@ pub struct InitramfsConfig {
     pub path: PathBuf,
 }
 
-#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct CmdlineConfig {
     pub args: String,
+    /// Append the console setup required by the devices, such as the aarch64
+    /// early console, to the user provided arguments.
+    #[serde(default = "default_cmdlineconfig_console_additions")]
+    pub console_additions: bool,
+}
+
+fn default_cmdlineconfig_console_additions() -> bool {
+    true
+}
+
+impl Default for CmdlineConfig {
+    fn default() -> Self {
+        CmdlineConfig {
+            args: String::new(),
+            console_additions: default_cmdlineconfig_console_additions(),
+        }
+    }
 }
 
 impl CmdlineConfig {
-    pub fn parse(cmdline: Option<&str>) -> Result<Self> {
+    pub fn parse(cmdline: Option<&str>, console_additions: bool) -> Result<Self> {
         let args = cmdline
             .map(std::string::ToString::to_string)
             .unwrap_or_else(String::new);
 
-        Ok(CmdlineConfig { args })
+        Ok(CmdlineConfig {
+            args,
+            console_additions,
+        })
+    }
+
+    /// Whether the user provided arguments already set the console up.
+    pub fn has_console(&self) -> bool {
+        self.args
+            .split_whitespace()
+            .any(|arg| arg.starts_with("console=") || arg.starts_with("earlycon"))
     }
 }
 
@@ -970,6 +1468,8 @@ pub struct DiskConfig {
     pub disable_io_uring: bool,
     #[serde(default)]
     pub pci_segment: u16,
+    #[serde(default = "default_diskconfig_max_inflight_bytes")]
+    pub max_inflight_bytes: u64,
 }
 
 fn default_diskconfig_num_queues() -> usize {
@@ -984,6 +1484,10 @@ fn default_diskconfig_poll_queue() -> bool {
     true
 }
 
+fn default_diskconfig_max_inflight_bytes() -> u64 {
+    DEFAULT_DISK_MAX_INFLIGHT_BYTES
+}
+
 impl Default for DiskConfig {
     fn default() -> Self {
         Self {
@@ -1000,6 +1504,7 @@ impl Default for DiskConfig {
             disable_io_uring: false,
             rate_limiter_config: None,
             pci_segment: 0,
+            max_inflight_bytes: default_diskconfig_max_inflight_bytes(),
         }
     }
 }
@@ -1011,7 +1516,7 @@ impl DiskConfig {
          vhost_user=on|off,socket=<vhost_user_socket_path>,poll_queue=on|off,\
          bw_size=<bytes>,bw_one_time_burst=<bytes>,bw_refill_time=<ms>,\
          ops_size=<io_ops>,ops_one_time_burst=<io_ops>,ops_refill_time=<ms>,\
-         id=<device_id>,pci_segment=<segment_id>\"";
+         id=<device_id>,pci_segment=<segment_id>,max_inflight_bytes=<bytes>\"";
 
     pub fn parse(disk: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
@@ -1033,7 +1538,8 @@ impl DiskConfig {
             .add("ops_refill_time")
             .add("id")
             .add("_disable_io_uring")
-            .add("pci_segment");
+            .add("pci_segment")
+            .add("max_inflight_bytes");
         parser.parse(disk).map_err(Error::ParseDisk)?;
 
         let path = parser.get("path").map(PathBuf::from);
@@ -1081,6 +1587,11 @@ impl DiskConfig {
             .convert("pci_segment")
             .map_err(Error::ParseDisk)?
             .unwrap_or_default();
+        let max_inflight_bytes = parser
+            .convert::<ByteSized>("max_inflight_bytes")
+            .map_err(Error::ParseDisk)?
+            .map(|v| v.0)
+            .unwrap_or_else(default_diskconfig_max_inflight_bytes);
         let bw_size = parser
             .convert("bw_size")
             .map_err(Error::ParseDisk)?
@@ -1150,6 +1661,7 @@ impl DiskConfig {
             id,
             disable_io_uring,
             pci_segment,
+            max_inflight_bytes,
         })
     }
 
@@ -1158,6 +1670,10 @@ impl DiskConfig {
             return Err(ValidationError::TooManyQueues);
         }
 
+        if self.max_inflight_bytes == 0 {
+            return Err(ValidationError::InvalidMaxInflightBytes);
+        }
+
         if self.vhost_user && self.iommu {
             return Err(ValidationError::IommuNotSupported);
         }
@@ -1524,18 +2040,22 @@ pub struct BalloonConfig {
     /// Option to enable free page reporting from the guest.
     #[serde(default)]
     pub free_page_reporting: bool,
+    /// Option to let the guest report its memory statistics.
+    #[serde(default)]
+    pub statistics: bool,
 }
 
 impl BalloonConfig {
     pub const SYNTAX: &'static str =
         "Balloon parameters \"size=<balloon_size>,deflate_on_oom=on|off,\
-        free_page_reporting=on|off\"";
+        free_page_reporting=on|off,statistics=on|off\"";
 
     pub fn parse(balloon: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
         parser.add("size");
         parser.add("deflate_on_oom");
         parser.add("free_page_reporting");
+        parser.add("statistics");
         parser.parse(balloon).map_err(Error::ParseBalloon)?;
 
         let size = parser
@@ -1556,10 +2076,17 @@ impl BalloonConfig {
             .unwrap_or(Toggle(false))
             .0;
 
+        let statistics = parser
+            .convert::<Toggle>("statistics")
+            .map_err(Error::ParseBalloon)?
+            .unwrap_or(Toggle(false))
+            .0;
+
         Ok(BalloonConfig {
             size,
             deflate_on_oom,
             free_page_reporting,
+            statistics,
         })
     }
 }
@@ -1824,9 +2351,28 @@ impl ConsoleConfig {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
-pub struct DeviceConfig {
-    pub path: PathBuf,
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SerialBackend {
+    /// Legacy ISA serial port, can't be hot-plugged
+    Legacy,
+    /// Additional virtio-console PCI device
+    VirtioConsole,
+}
+
+impl Default for SerialBackend {
+    fn default() -> Self {
+        SerialBackend::VirtioConsole
+    }
+}
+
+/// Serial device added to a running VM.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SerialDeviceConfig {
+    #[serde(default)]
+    pub backend: SerialBackend,
+    pub mode: ConsoleOutputMode,
+    #[serde(default = "default_consoleconfig_file")]
+    pub file: Option<PathBuf>,
     #[serde(default)]
     pub iommu: bool,
     #[serde(default)]
@@ -1835,28 +2381,73 @@ pub struct DeviceConfig {
     pub pci_segment: u16,
 }
 
-impl DeviceConfig {
-    pub const SYNTAX: &'static str =
-        "Direct device assignment parameters \"path=<device_path>,iommu=on|off,id=<device_id>,pci_segment=<segment_id>\"";
-    pub fn parse(device: &str) -> Result<Self> {
-        let mut parser = OptionParser::new();
-        parser.add("path").add("id").add("iommu").add("pci_segment");
-        parser.parse(device).map_err(Error::ParseDevice)?;
+impl SerialDeviceConfig {
+    pub fn validate(&self, vm_config: &VmConfig) -> ValidationResult<()> {
+        if self.backend == SerialBackend::Legacy {
+            return Err(ValidationError::SerialDeviceLegacyBackend);
+        }
 
-        let path = parser
-            .get("path")
-            .map(PathBuf::from)
-            .ok_or(Error::ParseDevicePathMissing)?;
-        let iommu = parser
-            .convert::<Toggle>("iommu")
-            .map_err(Error::ParseDevice)?
-            .unwrap_or(Toggle(false))
-            .0;
-        let id = parser.get("id");
-        let pci_segment = parser
-            .convert::<u16>("pci_segment")
-            .map_err(Error::ParseDevice)?
-            .unwrap_or_default();
+        match self.mode {
+            ConsoleOutputMode::Pty | ConsoleOutputMode::Null => {}
+            ConsoleOutputMode::File => {
+                if self.file.is_none() {
+                    return Err(ValidationError::ConsoleFileMissing);
+                }
+            }
+            ConsoleOutputMode::Off | ConsoleOutputMode::Tty => {
+                return Err(ValidationError::SerialDeviceMode);
+            }
+        }
+
+        if let Some(platform_config) = vm_config.platform.as_ref() {
+            if self.pci_segment >= platform_config.num_pci_segments {
+                return Err(ValidationError::InvalidPciSegment(self.pci_segment));
+            }
+
+            if let Some(iommu_segments) = platform_config.iommu_segments.as_ref() {
+                if iommu_segments.contains(&self.pci_segment) && !self.iommu {
+                    return Err(ValidationError::OnIommuSegment(self.pci_segment));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
+pub struct DeviceConfig {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub iommu: bool,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub pci_segment: u16,
+}
+
+impl DeviceConfig {
+    pub const SYNTAX: &'static str =
+        "Direct device assignment parameters \"path=<device_path>,iommu=on|off,id=<device_id>,pci_segment=<segment_id>\"";
+    pub fn parse(device: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("path").add("id").add("iommu").add("pci_segment");
+        parser.parse(device).map_err(Error::ParseDevice)?;
+
+        let path = parser
+            .get("path")
+            .map(PathBuf::from)
+            .ok_or(Error::ParseDevicePathMissing)?;
+        let iommu = parser
+            .convert::<Toggle>("iommu")
+            .map_err(Error::ParseDevice)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let id = parser.get("id");
+        let pci_segment = parser
+            .convert::<u16>("pci_segment")
+            .map_err(Error::ParseDevice)?
+            .unwrap_or_default();
 
         Ok(DeviceConfig {
             path,
@@ -2219,16 +2810,19 @@ pub struct RestoreConfig {
     pub source_url: PathBuf,
     #[serde(default)]
     pub prefault: bool,
+    #[serde(default)]
+    pub paused: bool,
 }
 
 impl RestoreConfig {
     pub const SYNTAX: &'static str = "Restore from a VM snapshot. \
-        \nRestore parameters \"source_url=<source_url>,prefault=on|off\" \
+        \nRestore parameters \"source_url=<source_url>,prefault=on|off,paused=on|off\" \
         \n`source_url` should be a valid URL (e.g file:///foo/bar or tcp://192.168.1.10/foo) \
-        \n`prefault` brings memory pages in when enabled (disabled by default)";
+        \n`prefault` brings memory pages in when enabled (disabled by default) \
+        \n`paused` only starts the vCPU threads when the VM is resumed (disabled by default)";
     pub fn parse(restore: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
-        parser.add("source_url").add("prefault");
+        parser.add("source_url").add("prefault").add("paused");
         parser.parse(restore).map_err(Error::ParseRestore)?;
 
         let source_url = parser
@@ -2240,10 +2834,16 @@ impl RestoreConfig {
             .map_err(Error::ParseRestore)?
             .unwrap_or(Toggle(false))
             .0;
+        let paused = parser
+            .convert::<Toggle>("paused")
+            .map_err(Error::ParseRestore)?
+            .unwrap_or(Toggle(false))
+            .0;
 
         Ok(RestoreConfig {
             source_url,
             prefault,
+            paused,
         })
     }
 }
@@ -2270,6 +2870,10 @@ pub struct VmConfig {
     pub serial: ConsoleConfig,
     #[serde(default = "ConsoleConfig::default_console")]
     pub console: ConsoleConfig,
+    #[serde(default)]
+    pub extra_serial: Option<Vec<ConsoleConfig>>,
+    #[serde(default)]
+    pub serial_devices: Option<Vec<SerialDeviceConfig>>,
     pub devices: Option<Vec<DeviceConfig>>,
     pub user_devices: Option<Vec<UserDeviceConfig>>,
     pub vdpa: Option<Vec<VdpaConfig>>,
@@ -2286,6 +2890,15 @@ pub struct VmConfig {
     #[cfg(feature = "gdb")]
     pub gdb: bool,
     pub platform: Option<PlatformConfig>,
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default)]
+    pub guest_panic: Option<GuestPanicConfig>,
+    #[serde(default)]
+    pub stop_on_boot: bool,
+    #[serde(default)]
+    pub break_on_triple_fault: bool,
+    #[serde(default)]
+    pub on_reset: ResetAction,
 }
 
 impl VmConfig {
@@ -2306,6 +2919,80 @@ impl VmConfig {
         Ok(())
     }
 
+    // Validate the huge page size selected for some memory, along with the
+    // sizes that must be backed by huge pages of that size.
+    fn validate_hugepage_size(
+        hugepages: bool,
+        hugepage_size: Option<u64>,
+        sizes: &[Option<u64>],
+    ) -> ValidationResult<()> {
+        if let Some(hugepage_size) = hugepage_size {
+            if !hugepages {
+                return Err(ValidationError::HugePageSizeWithoutHugePages);
+            }
+            if !hugepage_size.is_power_of_two() {
+                return Err(ValidationError::InvalidHugePageSize(hugepage_size));
+            }
+            #[cfg(target_arch = "x86_64")]
+            if hugepage_size != 2 << 20 && hugepage_size != 1 << 30 {
+                return Err(ValidationError::UnsupportedHugePageSize(hugepage_size));
+            }
+            for size in sizes.iter().flatten() {
+                if size % hugepage_size != 0 {
+                    return Err(ValidationError::MemorySizeNotHugePageAligned(
+                        *size,
+                        hugepage_size,
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Reserved ranges are carved from the RAM available at boot, away from
+    // the boot structures and ACPI tables the VMM writes to it.
+    fn validate_reserved_memory(&self, reserved: &[ReservedMemoryConfig]) -> ValidationResult<()> {
+        let mut boot_ram_size = self.memory.size;
+        if let Some(zones) = &self.memory.zones {
+            boot_ram_size += zones.iter().map(|zone| zone.size).sum::<u64>();
+        }
+        let ram_ranges: Vec<(u64, u64)> = arch::arch_memory_regions(boot_ram_size)
+            .iter()
+            .filter(|r| r.2 == arch::RegionType::Ram)
+            .map(|r| (r.0.raw_value(), r.0.raw_value() + r.1 as u64))
+            .collect();
+        #[cfg(target_arch = "x86_64")]
+        let boot_range = (0, arch::layout::HIGH_RAM_START.raw_value());
+        #[cfg(target_arch = "aarch64")]
+        let boot_range = (
+            arch::layout::RAM_START.raw_value(),
+            arch::layout::KERNEL_START.raw_value(),
+        );
+
+        let mut reserved = reserved.to_vec();
+        reserved.sort_by_key(|r| r.start);
+        let mut previous_end = 0;
+        for r in reserved.iter() {
+            let start = r.guest_address();
+            let end = start.saturating_add(r.size);
+            if r.size == 0
+                || start & 0xfff != 0
+                || r.size & 0xfff != 0
+                || !ram_ranges
+                    .iter()
+                    .any(|(ram_start, ram_end)| start >= *ram_start && end <= *ram_end)
+                || (start < boot_range.1 && end > boot_range.0)
+                || start < previous_end
+            {
+                return Err(ValidationError::InvalidReservedMemory(r.start, r.size));
+            }
+            previous_end = end;
+        }
+
+        Ok(())
+    }
+
     // Also enables virtio-iommu if the config needs it
     // Returns the list of unique identifiers provided through the
     // configuration.
@@ -2326,6 +3013,23 @@ impl VmConfig {
             }
         }
 
+        // Everything below the high RAM is used by the boot structures and
+        // the command line, while the initramfs is placed at the end of the
+        // low RAM.
+        #[cfg(target_arch = "x86_64")]
+        if let Some(highmem_start) = self.kernel.as_ref().and_then(|k| k.highmem_start) {
+            let lowmem_end = std::cmp::min(
+                self.memory.total_size(),
+                arch::layout::MEM_32BIT_RESERVED_START.raw_value(),
+            );
+            if highmem_start < arch::layout::HIGH_RAM_START.raw_value()
+                || highmem_start >= lowmem_end
+                || highmem_start & 0xfff != 0
+            {
+                return Err(ValidationError::InvalidKernelLoadAddress(highmem_start));
+            }
+        }
+
         if self.console.mode == ConsoleOutputMode::Tty && self.serial.mode == ConsoleOutputMode::Tty
         {
             return Err(ValidationError::DoubleTtyMode);
@@ -2339,6 +3043,25 @@ impl VmConfig {
             return Err(ValidationError::ConsoleFileMissing);
         }
 
+        if let Some(extra_serial) = &self.extra_serial {
+            // Additional serial ports are legacy COM ports, only available on x86_64
+            #[cfg(target_arch = "x86_64")]
+            let max_extra_serial = MAX_EXTRA_SERIAL_PORTS;
+            #[cfg(target_arch = "aarch64")]
+            let max_extra_serial = 0;
+            for serial in extra_serial {
+                if serial.mode == ConsoleOutputMode::Tty {
+                    return Err(ValidationError::ExtraSerialTtyMode);
+                }
+                if serial.mode == ConsoleOutputMode::File && serial.file.is_none() {
+                    return Err(ValidationError::ConsoleFileMissing);
+                }
+            }
+            if extra_serial.len() > max_extra_serial {
+                return Err(ValidationError::TooManySerialPorts(extra_serial.len()));
+            }
+        }
+
         if self.cpus.max_vcpus < self.cpus.boot_vcpus {
             return Err(ValidationError::CpusMaxLowerThanBoot);
         }
@@ -2419,15 +3142,30 @@ impl VmConfig {
             }
         }
 
-        if let Some(hugepage_size) = &self.memory.hugepage_size {
-            if !self.memory.hugepages {
-                return Err(ValidationError::HugePageSizeWithoutHugePages);
-            }
-            if !hugepage_size.is_power_of_two() {
-                return Err(ValidationError::InvalidHugePageSize(*hugepage_size));
+        Self::validate_hugepage_size(
+            self.memory.hugepages,
+            self.memory.hugepage_size,
+            &[
+                Some(self.memory.size).filter(|size| *size != 0),
+                self.memory.hotplug_size,
+                self.memory.hotplugged_size,
+            ],
+        )?;
+
+        if let Some(zones) = &self.memory.zones {
+            for zone in zones {
+                Self::validate_hugepage_size(
+                    zone.hugepages,
+                    zone.hugepage_size,
+                    &[Some(zone.size), zone.hotplug_size, zone.hotplugged_size],
+                )?;
             }
         }
 
+        if let Some(reserved) = &self.memory.reserved {
+            self.validate_reserved_memory(reserved)?;
+        }
+
         if let Some(user_devices) = &self.user_devices {
             if !user_devices.is_empty() && !self.memory.shared {
                 return Err(ValidationError::UserDevicesRequireSharedMemory);
@@ -2482,6 +3220,15 @@ impl VmConfig {
             Self::validate_identifier(&mut id_list, &vsock.id)?;
         }
 
+        if let Some(serial_devices) = &self.serial_devices {
+            for serial_device in serial_devices {
+                serial_device.validate(self)?;
+                self.iommu |= serial_device.iommu;
+
+                Self::validate_identifier(&mut id_list, &serial_device.id)?;
+            }
+        }
+
         if let Some(numa) = &self.numa {
             let mut used_numa_node_memory_zones = HashMap::new();
             for numa_node in numa.iter() {
@@ -2516,6 +3263,11 @@ impl VmConfig {
         }
 
         self.platform.as_ref().map(|p| p.validate()).transpose()?;
+        #[cfg(target_arch = "x86_64")]
+        self.guest_panic
+            .as_ref()
+            .map(|g| g.validate())
+            .transpose()?;
         self.iommu |= self
             .platform
             .as_ref()
@@ -2575,6 +3327,16 @@ impl VmConfig {
         let console = ConsoleConfig::parse(vm_params.console)?;
         let serial = ConsoleConfig::parse(vm_params.serial)?;
 
+        let mut extra_serial: Option<Vec<ConsoleConfig>> = None;
+        if let Some(extra_serial_list) = &vm_params.extra_serial {
+            let mut extra_serial_config_list = Vec::new();
+            for item in extra_serial_list.iter() {
+                let serial_config = ConsoleConfig::parse(item)?;
+                extra_serial_config_list.push(serial_config);
+            }
+            extra_serial = Some(extra_serial_config_list);
+        }
+
         let mut devices: Option<Vec<DeviceConfig>> = None;
         if let Some(device_list) = &vm_params.devices {
             let mut device_config_list = Vec::new();
@@ -2613,6 +3375,12 @@ impl VmConfig {
 
         let platform = vm_params.platform.map(PlatformConfig::parse).transpose()?;
 
+        #[cfg(target_arch = "x86_64")]
+        let guest_panic = vm_params
+            .guest_panic
+            .map(GuestPanicConfig::parse)
+            .transpose()?;
+
         #[cfg(target_arch = "x86_64")]
         let mut sgx_epc: Option<Vec<SgxEpcConfig>> = None;
         #[cfg(target_arch = "x86_64")]
@@ -2641,6 +3409,7 @@ impl VmConfig {
         if let Some(k) = vm_params.kernel {
             kernel = Some(KernelConfig {
                 path: PathBuf::from(k),
+                highmem_start: None,
             });
         }
 
@@ -2657,12 +3426,18 @@ impl VmConfig {
         #[cfg(feature = "gdb")]
         let gdb = vm_params.gdb;
 
+        let on_reset = vm_params
+            .on_reset
+            .map(|s| s.parse().map_err(Error::ParseOnReset))
+            .transpose()?
+            .unwrap_or_default();
+
         let mut config = VmConfig {
             cpus: CpusConfig::parse(vm_params.cpus)?,
             memory: MemoryConfig::parse(vm_params.memory, vm_params.memory_zones)?,
             kernel,
             initramfs,
-            cmdline: CmdlineConfig::parse(vm_params.cmdline)?,
+            cmdline: CmdlineConfig::parse(vm_params.cmdline, !vm_params.no_cmdline_console)?,
             disks,
             net,
             rng,
@@ -2671,6 +3446,8 @@ impl VmConfig {
             pmem,
             serial,
             console,
+            extra_serial,
+            serial_devices: None,
             devices,
             user_devices,
             vdpa,
@@ -2685,6 +3462,11 @@ impl VmConfig {
             #[cfg(feature = "gdb")]
             gdb,
             platform,
+            #[cfg(target_arch = "x86_64")]
+            guest_panic,
+            stop_on_boot: vm_params.stop_on_boot,
+            break_on_triple_fault: vm_params.break_on_triple_fault,
+            on_reset,
         };
         config.validate().map_err(Error::Validation)?;
         Ok(config)
@@ -2760,6 +3542,26 @@ mod tests {
                 ..Default::default()
             }
         );
+        assert_eq!(
+            CpusConfig::parse("boot=32,parallel_creation_threshold=0")?,
+            CpusConfig {
+                boot_vcpus: 32,
+                max_vcpus: 32,
+                parallel_creation_threshold: 0,
+                ..Default::default()
+            }
+        );
+        assert!(CpusConfig::parse("parallel_creation_threshold=256").is_err());
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            CpusConfig::parse("boot=1,tsc_deadline_timer=off")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                tsc_deadline_timer: false,
+                ..Default::default()
+            }
+        );
         Ok(())
     }
 
@@ -2824,6 +3626,49 @@ mod tests {
                 ..Default::default()
             }
         );
+        assert_eq!(
+            MemoryConfig::parse("size=1G,wipe_on_shutdown=on", None)?,
+            MemoryConfig {
+                size: 1 << 30,
+                wipe_on_shutdown: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("size=1G,prefault=on,prefault_timeout=500", None)?,
+            MemoryConfig {
+                size: 1 << 30,
+                prefault: true,
+                prefault_timeout: Some(500),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("size=1G,mlock=on", None)?,
+            MemoryConfig {
+                size: 1 << 30,
+                mlock: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("size=1G,reserved=[256M@2M,1048576@4K]", None)?,
+            MemoryConfig {
+                size: 1 << 30,
+                reserved: Some(vec![
+                    ReservedMemoryConfig {
+                        start: 256 << 20,
+                        size: 2 << 20,
+                    },
+                    ReservedMemoryConfig {
+                        start: 1 << 20,
+                        size: 4 << 10,
+                    },
+                ]),
+                ..Default::default()
+            }
+        );
+        assert!(MemoryConfig::parse("size=1G,reserved=[256M]", None).is_err());
         Ok(())
     }
 
@@ -2901,6 +3746,14 @@ mod tests {
                 ..Default::default()
             }
         );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,max_inflight_bytes=16M")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                max_inflight_bytes: 16 << 20,
+                ..Default::default()
+            }
+        );
         assert_eq!(
             DiskConfig::parse("path=/path/to_file,poll_queue=false")?,
             DiskConfig {
@@ -3246,6 +4099,57 @@ mod tests {
         Ok(())
     }
 
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_guest_panic_parsing() -> Result<()> {
+        assert_eq!(GuestPanicConfig::parse("")?, GuestPanicConfig::default());
+        assert_eq!(
+            GuestPanicConfig::parse("coredump=/tmp/guest.core,action=shutdown")?,
+            GuestPanicConfig {
+                coredump: Some(PathBuf::from("/tmp/guest.core")),
+                action: GuestPanicAction::Shutdown,
+            }
+        );
+        assert!(GuestPanicConfig::parse("action=explode").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_platform_unregistered_io_parsing() -> Result<()> {
+        assert_eq!(
+            PlatformConfig::parse("num_pci_segments=1")?.unregistered_io,
+            UnregisteredIoPolicy::Warn
+        );
+        assert_eq!(
+            PlatformConfig::parse("unregistered_io=fault")?,
+            PlatformConfig {
+                unregistered_io: UnregisteredIoPolicy::Fault,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            PlatformConfig::parse("unregistered_io=Warn,unregistered_read_fill=0")?,
+            PlatformConfig {
+                unregistered_io: UnregisteredIoPolicy::Warn,
+                unregistered_read_fill: 0,
+                ..Default::default()
+            }
+        );
+        assert!(PlatformConfig::parse("unregistered_io=ignore").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cmdline_parsing() -> Result<()> {
+        assert_eq!(CmdlineConfig::parse(None, true)?, CmdlineConfig::default());
+        let cmdline = CmdlineConfig::parse(Some("root=/dev/vda1 console=ttyAMA0"), false)?;
+        assert!(!cmdline.console_additions);
+        assert!(cmdline.has_console());
+        assert!(CmdlineConfig::parse(Some("earlycon=pl011,mmio,0x9000000"), true)?.has_console());
+        assert!(!CmdlineConfig::parse(Some("root=/dev/vda1 rw"), true)?.has_console());
+        Ok(())
+    }
+
     #[test]
     fn test_config_validation() {
         let mut valid_config = VmConfig {
@@ -3264,14 +4168,20 @@ mod tests {
                 hugepages: false,
                 hugepage_size: None,
                 prefault: false,
+                wipe_on_shutdown: false,
+                prefault_timeout: None,
+                mlock: false,
                 zones: None,
+                reserved: None,
             },
             kernel: Some(KernelConfig {
                 path: PathBuf::from("/path/to/kernel"),
+                highmem_start: None,
             }),
             initramfs: None,
             cmdline: CmdlineConfig {
                 args: String::from(""),
+                console_additions: true,
             },
             disks: None,
             net: None,
@@ -3292,6 +4202,8 @@ mod tests {
                 mode: ConsoleOutputMode::Tty,
                 iommu: false,
             },
+            extra_serial: None,
+            serial_devices: None,
             devices: None,
             user_devices: None,
             vdpa: None,
@@ -3306,6 +4218,11 @@ mod tests {
             #[cfg(feature = "gdb")]
             gdb: false,
             platform: None,
+            #[cfg(target_arch = "x86_64")]
+            guest_panic: None,
+            stop_on_boot: false,
+            break_on_triple_fault: false,
+            on_reset: ResetAction::Restart,
         };
 
         assert!(valid_config.validate().is_ok());
@@ -3318,6 +4235,64 @@ mod tests {
             Err(ValidationError::DoubleTtyMode)
         );
 
+        let mut invalid_config = valid_config.clone();
+        invalid_config.extra_serial = Some(vec![ConsoleConfig {
+            file: None,
+            mode: ConsoleOutputMode::Tty,
+            iommu: false,
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::ExtraSerialTtyMode)
+        );
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let mut invalid_config = valid_config.clone();
+            invalid_config.extra_serial = Some(vec![
+                ConsoleConfig::default_serial();
+                MAX_EXTRA_SERIAL_PORTS + 1
+            ]);
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::TooManySerialPorts(
+                    MAX_EXTRA_SERIAL_PORTS + 1
+                ))
+            );
+        }
+
+        let serial_device = SerialDeviceConfig {
+            backend: SerialBackend::VirtioConsole,
+            mode: ConsoleOutputMode::Pty,
+            file: None,
+            iommu: false,
+            id: Some("serial0".to_owned()),
+            pci_segment: 0,
+        };
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.serial_devices = Some(vec![serial_device.clone()]);
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.serial_devices = Some(vec![SerialDeviceConfig {
+            backend: SerialBackend::Legacy,
+            ..serial_device.clone()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::SerialDeviceLegacyBackend)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.serial_devices = Some(vec![SerialDeviceConfig {
+            mode: ConsoleOutputMode::Tty,
+            ..serial_device
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::SerialDeviceMode)
+        );
+
         let mut invalid_config = valid_config.clone();
         invalid_config.kernel = None;
         assert_eq!(
@@ -3325,6 +4300,27 @@ mod tests {
             Err(ValidationError::KernelMissing)
         );
 
+        #[cfg(target_arch = "x86_64")]
+        {
+            let mut still_valid_config = valid_config.clone();
+            still_valid_config.kernel.as_mut().unwrap().highmem_start = Some(0x20_0000);
+            assert!(still_valid_config.validate().is_ok());
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.kernel.as_mut().unwrap().highmem_start = Some(0x2_0000);
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::InvalidKernelLoadAddress(0x2_0000))
+            );
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.kernel.as_mut().unwrap().highmem_start = Some(1 << 40);
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::InvalidKernelLoadAddress(1 << 40))
+            );
+        }
+
         let mut invalid_config = valid_config.clone();
         invalid_config.serial.mode = ConsoleOutputMode::File;
         invalid_config.serial.file = None;
@@ -3464,6 +4460,54 @@ mod tests {
             Err(ValidationError::InvalidHugePageSize(3 << 20))
         );
 
+        #[cfg(target_arch = "x86_64")]
+        {
+            let mut invalid_config = valid_config.clone();
+            invalid_config.memory.hugepages = true;
+            invalid_config.memory.hugepage_size = Some(4 << 20);
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::UnsupportedHugePageSize(4 << 20))
+            );
+        }
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.hugepages = true;
+        invalid_config.memory.hugepage_size = Some(1 << 30);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::MemorySizeNotHugePageAligned(
+                valid_config.memory.size,
+                1 << 30
+            ))
+        );
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.memory.size = 0;
+        still_valid_config.memory.zones = Some(vec![MemoryZoneConfig {
+            id: "mem0".to_owned(),
+            size: 2 << 30,
+            file: None,
+            shared: false,
+            hugepages: true,
+            hugepage_size: Some(1 << 30),
+            host_numa_node: None,
+            hotplug_size: None,
+            hotplugged_size: None,
+            prefault: false,
+        }]);
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = still_valid_config.clone();
+        invalid_config.memory.zones.as_mut().unwrap()[0].size = 3 << 29;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::MemorySizeNotHugePageAligned(
+                3 << 29,
+                1 << 30
+            ))
+        );
+
         let mut still_valid_config = valid_config.clone();
         still_valid_config.platform = Some(PlatformConfig {
             num_pci_segments: 16,
@@ -3481,6 +4525,58 @@ mod tests {
             Err(ValidationError::InvalidNumPciSegments(17))
         );
 
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.platform = Some(PlatformConfig {
+            uuid: Some("1e8aa28a-435d-4027-87f4-40dceff1fa0a".to_owned()),
+            oem_strings: Some(vec!["foo".to_owned(), "bar".to_owned()]),
+            ..Default::default()
+        });
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.platform = Some(PlatformConfig {
+            uuid: Some("1e8aa28a-435d-4027".to_owned()),
+            ..Default::default()
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidUuid(
+                "1e8aa28a-435d-4027".to_owned()
+            ))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.platform = Some(PlatformConfig {
+            oem_strings: Some(vec!["foo".to_owned(), "".to_owned()]),
+            ..Default::default()
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidOemString("".to_owned()))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.platform = Some(PlatformConfig {
+            oem_strings: Some(vec!["foo".to_owned(); MAX_OEM_STRINGS + 1]),
+            ..Default::default()
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::TooManyOemStrings(MAX_OEM_STRINGS + 1))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.platform = Some(PlatformConfig {
+            oem_strings: Some(vec!["a".repeat(MAX_OEM_STRING_LEN); MAX_OEM_STRINGS]),
+            ..Default::default()
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::OemStringsTooLarge(
+                (MAX_OEM_STRING_LEN + 1) * MAX_OEM_STRINGS
+            ))
+        );
+
         let mut still_valid_config = valid_config.clone();
         still_valid_config.platform = Some(PlatformConfig {
             num_pci_segments: 16,
@@ -3676,6 +4772,57 @@ mod tests {
             Err(ValidationError::OnIommuSegment(1))
         );
 
+        let reserved = |start: u64, size: u64| Some(vec![ReservedMemoryConfig { start, size }]);
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.memory.reserved = reserved(256 << 20, 2 << 20);
+        assert!(still_valid_config.validate().is_ok());
+
+        // The ranges are relative to the start of the RAM
+        #[cfg(target_arch = "x86_64")]
+        let ram_start = 0;
+        #[cfg(target_arch = "aarch64")]
+        let ram_start = arch::layout::RAM_START.raw_value();
+        assert_eq!(
+            still_valid_config.memory.reserved.as_ref().unwrap()[0].guest_address(),
+            ram_start + (256 << 20)
+        );
+
+        // Beyond the end of the RAM
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.reserved = reserved(511 << 20, 2 << 20);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidReservedMemory(511 << 20, 2 << 20))
+        );
+
+        // Not page aligned
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.reserved = reserved((256 << 20) + 512, 2 << 20);
+        assert!(invalid_config.validate().is_err());
+
+        // Over the boot structures and ACPI tables
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.reserved = reserved(0, 2 << 20);
+        assert!(invalid_config.validate().is_err());
+
+        // Overlapping ranges
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.reserved = Some(vec![
+            ReservedMemoryConfig {
+                start: 256 << 20,
+                size: 2 << 20,
+            },
+            ReservedMemoryConfig {
+                start: 257 << 20,
+                size: 2 << 20,
+            },
+        ]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidReservedMemory(257 << 20, 2 << 20))
+        );
+
         let mut invalid_config = valid_config;
         invalid_config.memory.shared = true;
         invalid_config.platform = Some(PlatformConfig {
@@ -50,15 +50,17 @@ use hypervisor::{CpuState, HypervisorCpuError, VmExit, VmOps};
 use libc::{c_void, siginfo_t};
 #[cfg(feature = "guest_debug")]
 use linux_loader::elf::Elf64_Nhdr;
-use seccompiler::{apply_filter, SeccompAction};
+use seccompiler::{apply_filter, BpfProgram, SeccompAction};
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 #[cfg(feature = "guest_debug")]
 use std::io::Write;
 #[cfg(feature = "guest_debug")]
 use std::mem::size_of;
 use std::os::unix::thread::JoinHandleExt;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Barrier, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Barrier, Condvar, Mutex};
+use std::time::{Duration, Instant};
 use std::{cmp, io, result, thread};
 use thiserror::Error;
 use vm_device::BusDevice;
@@ -76,6 +78,9 @@ use vmm_sys_util::signal::{register_signal_handler, SIGRTMIN};
 
 pub const CPU_MANAGER_ACPI_SIZE: usize = 0xc;
 
+// Time given to a vCPU thread to leave KVM_RUN when killed by force.
+const VCPU_KILL_TIMEOUT: Duration = Duration::from_secs(1);
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Error creating vCPU: {0}")]
@@ -87,6 +92,9 @@ pub enum Error {
     #[error("Error spawning vCPU thread: {0}")]
     VcpuSpawn(#[source] io::Error),
 
+    #[error("Error spawning the thread of vCPU {0}: {1}")]
+    VcpuThreadSpawn(u8, #[source] io::Error),
+
     #[error("Error generating common CPUID: {0}")]
     CommonCpuId(#[source] arch::Error),
 
@@ -119,6 +127,12 @@ pub enum Error {
     #[error("Error starting vCPU after restore: {0}")]
     StartRestoreVcpu(#[source] anyhow::Error),
 
+    #[error("Snapshot doesn't match the vCPUs of the VM")]
+    SnapshotVcpusMismatch,
+
+    #[error("Error restoring vCPU state: {0}")]
+    RestoreVcpu(#[source] MigratableError),
+
     #[error("Unexpected VmExit")]
     UnexpectedVmExit,
 
@@ -133,6 +147,23 @@ pub enum Error {
     #[error("Error initializing PMU: {0}")]
     InitPmu(#[source] hypervisor::HypervisorCpuError),
 
+    #[cfg(target_arch = "aarch64")]
+    #[error("PMU is not enabled")]
+    PmuNotEnabled,
+
+    #[cfg(target_arch = "aarch64")]
+    #[error("Error reading PMU counters: {0}")]
+    ReadPmuCounters(#[source] hypervisor::HypervisorCpuError),
+
+    #[error("vCPU {0} does not exist")]
+    UnknownVcpu(u8),
+
+    #[error("vCPU {0} is not running")]
+    VcpuNotRunning(u8),
+
+    #[error("Cannot kill vCPU {0} while the vCPUs are paused")]
+    KillPausedVcpu(u8),
+
     #[cfg(all(target_arch = "x86_64", feature = "gdb"))]
     #[error("Error during CPU debug: {0}")]
     CpuDebug(#[source] hypervisor::HypervisorCpuError),
@@ -147,6 +178,48 @@ pub enum Error {
 }
 pub type Result<T> = result::Result<T, Error>;
 
+/// Common architectural PMU event numbers.
+#[cfg(target_arch = "aarch64")]
+pub const PMU_EVENT_L1D_CACHE_REFILL: u16 = 0x03;
+#[cfg(target_arch = "aarch64")]
+pub const PMU_EVENT_INST_RETIRED: u16 = 0x08;
+
+#[cfg(target_arch = "aarch64")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PmuEventCounter {
+    /// Event number, as programmed in PMEVTYPER<n>_EL0.
+    pub event: u16,
+    pub value: u64,
+}
+
+/// Guest PMU counters of a vCPU. Only the counters enabled by the guest are
+/// reported, the cycle counter being `None` when disabled.
+#[cfg(target_arch = "aarch64")]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PmuCounters {
+    pub cycles: Option<u64>,
+    pub events: Vec<PmuEventCounter>,
+}
+
+#[cfg(target_arch = "aarch64")]
+impl PmuCounters {
+    /// Value of the first counter the guest programmed to count `event`.
+    pub fn event(&self, event: u16) -> Option<u64> {
+        self.events
+            .iter()
+            .find(|counter| counter.event == event)
+            .map(|counter| counter.value)
+    }
+
+    pub fn instructions(&self) -> Option<u64> {
+        self.event(PMU_EVENT_INST_RETIRED)
+    }
+
+    pub fn cache_misses(&self) -> Option<u64> {
+        self.event(PMU_EVENT_L1D_CACHE_REFILL)
+    }
+}
+
 #[cfg(target_arch = "x86_64")]
 #[allow(dead_code)]
 #[repr(packed)]
@@ -263,6 +336,17 @@ macro_rules! round_up {
     };
 }
 
+/// Identifiers of a vCPU, to match the vCPU indices used by the VMM with
+/// what the guest reports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct VcpuIdentity {
+    pub index: u8,
+    /// Local APIC id on x86_64, MPIDR_EL1 value on AArch64.
+    pub hardware_id: u64,
+    /// Whether the vCPU is currently plugged into the guest.
+    pub active: bool,
+}
+
 /// A wrapper around creating and using a kvm-based VCPU.
 pub struct Vcpu {
     // The hypervisor abstracted CPU.
@@ -411,6 +495,21 @@ impl Snapshottable for Vcpu {
     }
 }
 
+/// Number of vCPUs in each state, consistent with the guest view of the
+/// vCPUs through ACPI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct VcpuCounts {
+    /// vCPUs the VM was booted with.
+    pub boot: u8,
+    /// vCPUs plugged into the guest, including the hotplugged ones and the
+    /// ones the guest has been asked to eject but hasn't ejected yet.
+    pub present: u8,
+    /// vCPUs the guest can have at most.
+    pub max: u8,
+    /// Present vCPUs allowed to run, none while the VM is paused.
+    pub active: u8,
+}
+
 pub struct CpuManager {
     config: CpusConfig,
     #[cfg_attr(target_arch = "aarch64", allow(dead_code))]
@@ -423,6 +522,12 @@ pub struct CpuManager {
     vm: Arc<dyn hypervisor::Vm>,
     vcpus_kill_signalled: Arc<AtomicBool>,
     vcpus_pause_signalled: Arc<AtomicBool>,
+    // Accesses to unregistered MMIO or PIO addresses failed by the VM ops, and
+    // not picked up yet by the vCPUs which made them.
+    unregistered_io_faults: Arc<AtomicUsize>,
+    // Set by a vCPU stopped by one of these accesses, for the VMM to pause the
+    // VM.
+    unregistered_io_fault: Arc<AtomicBool>,
     exit_evt: EventFd,
     #[cfg_attr(target_arch = "aarch64", allow(dead_code))]
     reset_evt: EventFd,
@@ -431,6 +536,10 @@ pub struct CpuManager {
     vcpu_states: Vec<VcpuState>,
     selected_cpu: u8,
     vcpus: Vec<Arc<Mutex<Vcpu>>>,
+    // MPIDR of each vCPU, cached once configured as the vCPU threads hold
+    // their vCPU lock while running.
+    #[cfg(target_arch = "aarch64")]
+    mpidrs: Vec<u64>,
     seccomp_action: SeccompAction,
     vm_ops: Arc<dyn VmOps>,
     #[cfg_attr(target_arch = "aarch64", allow(dead_code))]
@@ -438,6 +547,17 @@ pub struct CpuManager {
     proximity_domain_per_cpu: BTreeMap<u8, u32>,
     affinity: BTreeMap<u8, Vec<u8>>,
     dynamic: bool,
+    #[cfg(target_arch = "x86_64")]
+    triple_fault: Arc<AtomicBool>,
+    #[cfg(target_arch = "x86_64")]
+    break_on_triple_fault: bool,
+    pause_on_reset: bool,
+    // Breakpoints and single stepping applied to every vCPU, including the
+    // ones hot-plugged later on.
+    #[cfg(feature = "gdb")]
+    guest_debug: Option<(Vec<GuestAddress>, bool)>,
+    #[cfg(target_arch = "aarch64")]
+    pmu_supported: bool,
 }
 
 const CPU_ENABLE_FLAG: usize = 0;
@@ -523,6 +643,97 @@ impl BusDevice for CpuManager {
     }
 }
 
+// Barrier the threads of the vCPUs being started wait on until all of them
+// are ready. Unlike std::sync::Barrier, it can be aborted when one of the
+// threads could not be spawned, releasing the ones already waiting.
+struct VcpuStartBarrier {
+    count: usize,
+    // Number of threads that reached the barrier, and whether it was aborted.
+    state: Mutex<(usize, bool)>,
+    cvar: Condvar,
+}
+
+impl VcpuStartBarrier {
+    fn new(count: usize) -> Self {
+        VcpuStartBarrier {
+            count,
+            state: Mutex::new((0, false)),
+            cvar: Condvar::new(),
+        }
+    }
+
+    // Returns false if the barrier was aborted, in which case the vCPU thread
+    // must exit without running the vCPU.
+    fn wait(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.0 += 1;
+        if state.0 == self.count {
+            self.cvar.notify_all();
+        }
+        let state = self
+            .cvar
+            .wait_while(state, |(arrived, aborted)| {
+                *arrived < self.count && !*aborted
+            })
+            .unwrap();
+        !state.1
+    }
+
+    fn abort(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.cvar.notify_all();
+    }
+}
+
+// Seccomp filter a vCPU thread is asked to apply to itself, along with where
+// to report whether it could.
+type SeccompFilterRequest = (
+    BpfProgram,
+    mpsc::Sender<result::Result<(), seccompiler::Error>>,
+);
+
+// Apply the seccomp filters requested since the last call, as a seccomp
+// filter can only be applied by the thread it restricts.
+fn apply_requested_seccomp_filters(requests: &Mutex<mpsc::Receiver<SeccompFilterRequest>>) {
+    for (filter, result) in requests.lock().unwrap().try_iter() {
+        result.send(apply_filter(&filter)).ok();
+    }
+}
+
+// Parks a vCPU thread that must not run the guest any further until the VMM
+// either pauses the vCPUs, or tells them to stop. A vCPU parked here counts as
+// interrupted, so that pausing it doesn't wait for it. It still applies the
+// seccomp filters it is asked to meanwhile.
+fn park_until_paused_or_killed(
+    pause_signalled: &AtomicBool,
+    kill_signalled: &AtomicBool,
+    kill: &AtomicBool,
+    run_interrupted: &AtomicBool,
+    seccomp_filter_requests: &Mutex<mpsc::Receiver<SeccompFilterRequest>>,
+) {
+    run_interrupted.store(true, Ordering::SeqCst);
+    while !pause_signalled.load(Ordering::SeqCst)
+        && !kill_signalled.load(Ordering::SeqCst)
+        && !kill.load(Ordering::SeqCst)
+    {
+        apply_requested_seccomp_filters(seccomp_filter_requests);
+        thread::park();
+    }
+}
+
+// Release the threads of the vCPUs already spawned, starting from
+// `first_vcpu`, from their start barrier and join them, so that a failed
+// start doesn't leak them.
+fn abort_vcpus_start(barrier: &VcpuStartBarrier, vcpu_states: &mut [VcpuState], first_vcpu: u8) {
+    barrier.abort();
+    for (vcpu_id, state) in (first_vcpu..).zip(vcpu_states.iter_mut()) {
+        if let Err(e) = state.join_thread() {
+            error!("Error joining the thread of vCPU {}: {:?}", vcpu_id, e);
+        }
+        state.inserting = false;
+    }
+}
+
 #[derive(Default)]
 struct VcpuState {
     inserting: bool,
@@ -530,6 +741,12 @@ struct VcpuState {
     handle: Option<thread::JoinHandle<()>>,
     kill: Arc<AtomicBool>,
     vcpu_run_interrupted: Arc<AtomicBool>,
+    // Wall clock and thread CPU time taken at the end of the last
+    // utilization sampling window.
+    usage_sample: Option<(Instant, Duration)>,
+    // Sends the additional seccomp filters the vCPU thread must apply to
+    // itself. Sending fails once the thread exited.
+    seccomp_filter_requests: Option<mpsc::Sender<SeccompFilterRequest>>,
 }
 
 impl VcpuState {
@@ -537,7 +754,60 @@ impl VcpuState {
         self.handle.is_some()
     }
 
+    // CPU time consumed by the vCPU thread. The time a halted vCPU spends
+    // blocked in the kernel isn't accounted for, unlike halt polling.
+    fn cpu_time(&self) -> Option<Duration> {
+        let handle = self.handle.as_ref()?;
+        let mut clock_id: libc::clockid_t = 0;
+        // SAFETY: the thread can't be released until its handle is joined.
+        let ret = unsafe { libc::pthread_getcpuclockid(handle.as_pthread_t() as _, &mut clock_id) };
+        if ret != 0 {
+            return None;
+        }
+
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: ts is a valid timespec the kernel can write to.
+        if unsafe { libc::clock_gettime(clock_id, &mut ts) } != 0 {
+            return None;
+        }
+
+        Some(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+    }
+
+    // Fraction of the time the vCPU thread was running since the previous
+    // sample, starting a new sampling window.
+    fn sample_utilization(&mut self) -> f64 {
+        let (now, cpu_time) = match self.cpu_time() {
+            Some(cpu_time) => (Instant::now(), cpu_time),
+            None => return 0.0,
+        };
+
+        let utilization = match self.usage_sample {
+            Some((last_sample, last_cpu_time)) => {
+                let elapsed = now.duration_since(last_sample).as_secs_f64();
+                if elapsed > 0.0 {
+                    (cpu_time.saturating_sub(last_cpu_time).as_secs_f64() / elapsed).min(1.0)
+                } else {
+                    0.0
+                }
+            }
+            None => 0.0,
+        };
+        self.usage_sample = Some((now, cpu_time));
+
+        utilization
+    }
+
     fn signal_thread(&self) {
+        self.signal_thread_until(None);
+    }
+
+    // Signal the thread until it interrupts the vCPU run, giving up once
+    // `deadline` is reached. Returns false if the thread didn't react in time.
+    fn signal_thread_until(&self, deadline: Option<Instant>) -> bool {
         if let Some(handle) = self.handle.as_ref() {
             loop {
                 unsafe {
@@ -545,17 +815,21 @@ impl VcpuState {
                 }
                 if self.vcpu_run_interrupted.load(Ordering::SeqCst) {
                     break;
+                } else if matches!(deadline, Some(deadline) if Instant::now() >= deadline) {
+                    return false;
                 } else {
                     // This is more effective than thread::yield_now() at
                     // avoiding a priority inversion with the vCPU thread
-                    thread::sleep(std::time::Duration::from_millis(1));
+                    thread::sleep(Duration::from_millis(1));
                 }
             }
         }
+        true
     }
 
     fn join_thread(&mut self) -> Result<()> {
         if let Some(handle) = self.handle.take() {
+            self.usage_sample = None;
             handle.join().map_err(Error::ThreadCleanup)?
         }
 
@@ -598,7 +872,7 @@ impl CpuManager {
             .as_ref()
             .map(|sgx_epc_region| sgx_epc_region.epc_sections().values().cloned().collect());
         #[cfg(target_arch = "x86_64")]
-        let cpuid = {
+        let mut cpuid = {
             let phys_bits = physical_bits(config.max_phys_bits);
             arch::generate_common_cpuid(
                 hypervisor,
@@ -614,6 +888,8 @@ impl CpuManager {
             )
             .map_err(Error::CommonCpuId)?
         };
+        #[cfg(target_arch = "x86_64")]
+        arch::update_cpuid_tsc_deadline_timer(&mut cpuid, config.tsc_deadline_timer);
         #[cfg(all(feature = "amx", target_arch = "x86_64"))]
         if config.features.amx {
             const ARCH_GET_XCOMP_GUEST_PERM: usize = 0x1024;
@@ -696,6 +972,8 @@ impl CpuManager {
             vm,
             vcpus_kill_signalled: Arc::new(AtomicBool::new(false)),
             vcpus_pause_signalled: Arc::new(AtomicBool::new(false)),
+            unregistered_io_faults: Arc::new(AtomicUsize::new(0)),
+            unregistered_io_fault: Arc::new(AtomicBool::new(false)),
             vcpu_states,
             exit_evt,
             reset_evt,
@@ -703,12 +981,23 @@ impl CpuManager {
             vm_debug_evt,
             selected_cpu: 0,
             vcpus: Vec::with_capacity(usize::from(config.max_vcpus)),
+            #[cfg(target_arch = "aarch64")]
+            mpidrs: Vec::with_capacity(usize::from(config.max_vcpus)),
             seccomp_action,
             vm_ops,
             acpi_address,
             proximity_domain_per_cpu,
             affinity,
             dynamic,
+            #[cfg(target_arch = "x86_64")]
+            triple_fault: Arc::new(AtomicBool::new(false)),
+            #[cfg(target_arch = "x86_64")]
+            break_on_triple_fault: false,
+            pause_on_reset: false,
+            #[cfg(feature = "gdb")]
+            guest_debug: None,
+            #[cfg(target_arch = "aarch64")]
+            pmu_supported: false,
         }));
 
         if let Some(acpi_address) = acpi_address {
@@ -756,13 +1045,18 @@ impl CpuManager {
                 .expect("Failed to configure vCPU");
         }
 
-        // Adding vCPU to the CpuManager's vCPU list.
-        let vcpu = Arc::new(Mutex::new(vcpu));
-        self.vcpus.push(vcpu);
+        self.add_vcpu(vcpu);
 
         Ok(())
     }
 
+    // Adding vCPU to the CpuManager's vCPU list.
+    fn add_vcpu(&mut self, vcpu: Vcpu) {
+        #[cfg(target_arch = "aarch64")]
+        self.mpidrs.push(vcpu.get_mpidr());
+        self.vcpus.push(Arc::new(Mutex::new(vcpu)));
+    }
+
     /// Only create new vCPUs if there aren't any inactive ones to reuse
     fn create_vcpus(&mut self, desired_vcpus: u8, entry_point: Option<EntryPoint>) -> Result<()> {
         info!(
@@ -778,15 +1072,90 @@ impl CpuManager {
         }
 
         // Only create vCPUs in excess of all the allocated vCPUs.
-        for cpu_id in self.vcpus.len() as u8..desired_vcpus {
-            self.create_vcpu(cpu_id, entry_point, None)?;
+        let cpu_ids: Vec<u8> = (self.vcpus.len() as u8..desired_vcpus).collect();
+        let threshold = usize::from(self.config.parallel_creation_threshold);
+        if threshold == 0 || cpu_ids.len() < threshold {
+            for cpu_id in cpu_ids {
+                self.create_vcpu(cpu_id, entry_point, None)?;
+            }
+            return Ok(());
+        }
+
+        let start = Instant::now();
+        let count = cpu_ids.len();
+        self.create_vcpus_parallel(cpu_ids, entry_point)?;
+        info!(
+            "Created {} vCPUs in {} ms",
+            count,
+            start.elapsed().as_millis()
+        );
+
+        Ok(())
+    }
+
+    // Creating and configuring a vCPU doesn't depend on the other vCPUs, so
+    // large numbers of vCPUs are split across threads. The vCPUs are only
+    // added to the list once they are all created, ordered by identifier.
+    fn create_vcpus_parallel(
+        &mut self,
+        cpu_ids: Vec<u8>,
+        entry_point: Option<EntryPoint>,
+    ) -> Result<()> {
+        let threads = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .min(cpu_ids.len());
+        let chunk_size = (cpu_ids.len() + threads - 1) / threads;
+
+        let mut handles = Vec::with_capacity(threads);
+        for (i, chunk) in cpu_ids.chunks(chunk_size).enumerate() {
+            let chunk = chunk.to_vec();
+            let vm = self.vm.clone();
+            let vm_ops = self.vm_ops.clone();
+            #[cfg(target_arch = "x86_64")]
+            let vm_memory = self.vm_memory.clone();
+            #[cfg(target_arch = "x86_64")]
+            let cpuid = self.cpuid.clone();
+            #[cfg(target_arch = "x86_64")]
+            let kvm_hyperv = self.config.kvm_hyperv;
+
+            let handle = thread::Builder::new()
+                .name(format!("vcpu_creator{}", i))
+                .spawn(move || -> Result<Vec<Vcpu>> {
+                    chunk
+                        .into_iter()
+                        .map(|cpu_id| {
+                            info!("Creating vCPU: cpu_id = {}", cpu_id);
+                            let mut vcpu = Vcpu::new(cpu_id, &vm, Some(vm_ops.clone()))?;
+
+                            #[cfg(target_arch = "x86_64")]
+                            vcpu.configure(entry_point, &vm_memory, cpuid.clone(), kvm_hyperv)?;
+
+                            #[cfg(target_arch = "aarch64")]
+                            vcpu.configure(&vm, entry_point)?;
+
+                            Ok(vcpu)
+                        })
+                        .collect()
+                })
+                .map_err(Error::VcpuSpawn)?;
+            handles.push(handle);
+        }
+
+        // Join all the threads before bailing out on the first error.
+        let results: Vec<_> = handles.into_iter().map(|h| h.join()).collect();
+        for result in results {
+            let vcpus = result.map_err(Error::ThreadCleanup)??;
+            for vcpu in vcpus {
+                self.add_vcpu(vcpu);
+            }
         }
 
         Ok(())
     }
 
     #[cfg(target_arch = "aarch64")]
-    pub fn init_pmu(&self, irq: u32) -> Result<bool> {
+    pub fn init_pmu(&mut self, irq: u32) -> Result<bool> {
         let cpu_attr = kvm_bindings::kvm_device_attr {
             group: kvm_bindings::KVM_ARM_VCPU_PMU_V3_CTRL,
             attr: u64::from(kvm_bindings::KVM_ARM_VCPU_PMU_V3_INIT),
@@ -827,14 +1196,57 @@ impl CpuManager {
             }
         }
 
+        self.pmu_supported = true;
         Ok(true)
     }
 
+    /// Read the PMU counters of vCPU `cpu_id`, as programmed by the guest.
+    #[cfg(target_arch = "aarch64")]
+    pub fn pmu_counters(&self, cpu_id: u8) -> Result<PmuCounters> {
+        use hypervisor::kvm::aarch64::{
+            pmevcntr_el0, pmevtyper_el0, PMCCNTR_EL0, PMCNTENSET_EL0, PMCR_EL0,
+        };
+
+        if !self.pmu_supported {
+            return Err(Error::PmuNotEnabled);
+        }
+        let vcpu = self
+            .vcpus
+            .get(usize::from(cpu_id))
+            .ok_or(Error::UnknownVcpu(cpu_id))?
+            .lock()
+            .unwrap();
+        let get_reg = |reg_id| vcpu.vcpu.get_reg(reg_id).map_err(Error::ReadPmuCounters);
+
+        // PMCR_EL0.N holds the number of event counters, and PMCNTENSET_EL0
+        // the counters enabled by the guest, bit 31 being the cycle counter.
+        let counters_number = (get_reg(PMCR_EL0)? >> 11) & 0x1f;
+        let enabled = get_reg(PMCNTENSET_EL0)?;
+
+        let cycles = if enabled & (1 << 31) != 0 {
+            Some(get_reg(PMCCNTR_EL0)?)
+        } else {
+            None
+        };
+        let mut events = Vec::new();
+        for n in 0..counters_number {
+            if enabled & (1 << n) == 0 {
+                continue;
+            }
+            events.push(PmuEventCounter {
+                event: (get_reg(pmevtyper_el0(n))? & 0xffff) as u16,
+                value: get_reg(pmevcntr_el0(n))?,
+            });
+        }
+
+        Ok(PmuCounters { cycles, events })
+    }
+
     fn start_vcpu(
         &mut self,
         vcpu: Arc<Mutex<Vcpu>>,
         vcpu_id: u8,
-        vcpu_thread_barrier: Arc<Barrier>,
+        vcpu_thread_barrier: Arc<VcpuStartBarrier>,
         inserting: bool,
     ) -> Result<()> {
         let reset_evt = self.reset_evt.try_clone().unwrap();
@@ -844,12 +1256,22 @@ impl CpuManager {
         let panic_exit_evt = self.exit_evt.try_clone().unwrap();
         let vcpu_kill_signalled = self.vcpus_kill_signalled.clone();
         let vcpu_pause_signalled = self.vcpus_pause_signalled.clone();
+        let unregistered_io_faults = self.unregistered_io_faults.clone();
+        let vcpu_unregistered_io_fault = self.unregistered_io_fault.clone();
+        #[cfg(target_arch = "x86_64")]
+        let vcpu_triple_fault = self.triple_fault.clone();
+        #[cfg(target_arch = "x86_64")]
+        let break_on_triple_fault = self.break_on_triple_fault;
+        let pause_on_reset = self.pause_on_reset;
 
         let vcpu_kill = self.vcpu_states[usize::from(vcpu_id)].kill.clone();
         let vcpu_run_interrupted = self.vcpu_states[usize::from(vcpu_id)]
             .vcpu_run_interrupted
             .clone();
         let panic_vcpu_run_interrupted = vcpu_run_interrupted.clone();
+        // Behind a mutex so that the vCPU loop stays unwind safe.
+        let (seccomp_filter_sender, seccomp_filter_requests) = mpsc::channel();
+        let seccomp_filter_requests = Mutex::new(seccomp_filter_requests);
 
         // Prepare the CPU set the current vCPU is expected to run onto.
         let cpuset = self.affinity.get(&vcpu_id).map(|host_cpus| {
@@ -908,7 +1330,10 @@ impl CpuManager {
                     register_signal_handler(SIGRTMIN(), handle_signal)
                         .expect("Failed to register vcpu signal handler");
                     // Block until all CPUs are ready.
-                    vcpu_thread_barrier.wait();
+                    if !vcpu_thread_barrier.wait() {
+                        vcpu_run_interrupted.store(true, Ordering::SeqCst);
+                        return;
+                    }
 
                     std::panic::catch_unwind(move || {
                         loop {
@@ -952,6 +1377,7 @@ impl CpuManager {
 
                                 vcpu_run_interrupted.store(true, Ordering::SeqCst);
                                 while vcpu_pause_signalled.load(Ordering::SeqCst) {
+                                    apply_requested_seccomp_filters(&seccomp_filter_requests);
                                     thread::park();
                                 }
                                 vcpu_run_interrupted.store(false, Ordering::SeqCst);
@@ -965,10 +1391,15 @@ impl CpuManager {
                                 break;
                             }
 
+                            apply_requested_seccomp_filters(&seccomp_filter_requests);
+
                             #[cfg(feature = "tdx")]
                             let mut vcpu = vcpu.lock().unwrap();
                             #[cfg(not(feature = "tdx"))]
                             let vcpu = vcpu.lock().unwrap();
+                            // Set when the vCPU must not run the guest any
+                            // further until the VM is paused.
+                            let mut stopped = false;
                             // vcpu.run() returns false on a triple-fault so trigger a reset
                             match vcpu.run() {
                                 Ok(run) => match run {
@@ -995,6 +1426,14 @@ impl CpuManager {
                                     }
                                     VmExit::Ignore => {}
                                     VmExit::Hyperv => {}
+                                    // The VM is about to be paused rather than
+                                    // rebuilt, the vCPU must stay around for it
+                                    // to be resumed.
+                                    VmExit::Reset if pause_on_reset => {
+                                        info!("VmExit::Reset");
+                                        reset_evt.write(1).unwrap();
+                                        stopped = true;
+                                    }
                                     VmExit::Reset => {
                                         info!("VmExit::Reset");
                                         vcpu_run_interrupted.store(true, Ordering::SeqCst);
@@ -1007,6 +1446,28 @@ impl CpuManager {
                                         exit_evt.write(1).unwrap();
                                         break;
                                     }
+                                    // Reset the VM like any other reset, unless
+                                    // it must be kept around for inspection. In
+                                    // that case the vCPU waits for the VMM to
+                                    // pause the VM.
+                                    #[cfg(target_arch = "x86_64")]
+                                    VmExit::TripleFault => {
+                                        if !break_on_triple_fault {
+                                            info!("VmExit::TripleFault");
+                                            vcpu_run_interrupted.store(true, Ordering::SeqCst);
+                                            reset_evt.write(1).unwrap();
+                                            break;
+                                        }
+                                        error!(
+                                            "vCPU {} triple faulted: regs = {:?}, sregs = {:?}",
+                                            vcpu_id,
+                                            vcpu.vcpu.get_regs(),
+                                            vcpu.vcpu.get_sregs()
+                                        );
+                                        vcpu_triple_fault.store(true, Ordering::SeqCst);
+                                        reset_evt.write(1).unwrap();
+                                        stopped = true;
+                                    }
                                     #[cfg(feature = "tdx")]
                                     VmExit::Tdx => {
                                         if let Some(vcpu) = Arc::get_mut(&mut vcpu.vcpu) {
@@ -1036,12 +1497,44 @@ impl CpuManager {
                                     }
                                 },
 
+                                // An unregistered I/O access made to fault
+                                // stops the VM, which the VMM finds out about
+                                // when handling the reset event.
+                                Err(e)
+                                    if unregistered_io_faults
+                                        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                                            n.checked_sub(1)
+                                        })
+                                        .is_ok() =>
+                                {
+                                    error!("vCPU {} stopped: {:?}", vcpu_id, Error::VcpuRun(e.into()));
+                                    vcpu_unregistered_io_fault.store(true, Ordering::SeqCst);
+                                    reset_evt.write(1).unwrap();
+                                    stopped = true;
+                                }
                                 Err(e) => {
                                     error!("VCPU generated error: {:?}", Error::VcpuRun(e.into()));
                                     break;
                                 }
                             }
 
+                            // The vCPU must not run the guest again before
+                            // the VM gets paused, it would only fault or
+                            // request a reset again. The pause request is
+                            // handled at the top of the loop, without
+                            // holding the vCPU lock meanwhile.
+                            if stopped {
+                                drop(vcpu);
+                                park_until_paused_or_killed(
+                                    &vcpu_pause_signalled,
+                                    &vcpu_kill_signalled,
+                                    &vcpu_kill,
+                                    &vcpu_run_interrupted,
+                                    &seccomp_filter_requests,
+                                );
+                                continue;
+                            }
+
                             // We've been told to terminate
                             if vcpu_kill_signalled.load(Ordering::SeqCst)
                                 || vcpu_kill.load(Ordering::SeqCst)
@@ -1058,13 +1551,17 @@ impl CpuManager {
                     })
                     .ok();
                 })
-                .map_err(Error::VcpuSpawn)?,
+                .map_err(|e| Error::VcpuThreadSpawn(vcpu_id, e))?,
         );
 
         // On hot plug calls into this function entry_point is None. It is for
         // those hotplug CPU additions that we need to set the inserting flag.
         self.vcpu_states[usize::from(vcpu_id)].handle = handle;
         self.vcpu_states[usize::from(vcpu_id)].inserting = inserting;
+        self.vcpu_states[usize::from(vcpu_id)].usage_sample =
+            Some((Instant::now(), Duration::ZERO));
+        self.vcpu_states[usize::from(vcpu_id)].seccomp_filter_requests =
+            Some(seccomp_filter_sender);
 
         Ok(())
     }
@@ -1075,7 +1572,7 @@ impl CpuManager {
             return Err(Error::DesiredVCpuCountExceedsMax);
         }
 
-        let vcpu_thread_barrier = Arc::new(Barrier::new(
+        let vcpu_thread_barrier = Arc::new(VcpuStartBarrier::new(
             (desired_vcpus - self.present_vcpus() + 1) as usize,
         ));
 
@@ -1087,9 +1584,28 @@ impl CpuManager {
         );
 
         // This reuses any inactive vCPUs as well as any that were newly created
-        for vcpu_id in self.present_vcpus()..desired_vcpus {
+        let first_vcpu = self.present_vcpus();
+        #[cfg(all(feature = "kvm", feature = "gdb"))]
+        if let Some((addrs, singlestep)) = &self.guest_debug {
+            for vcpu in &self.vcpus[usize::from(first_vcpu)..usize::from(desired_vcpus)] {
+                vcpu.lock()
+                    .unwrap()
+                    .vcpu
+                    .set_guest_debug(addrs, *singlestep)
+                    .map_err(Error::CpuDebug)?;
+            }
+        }
+        for vcpu_id in first_vcpu..desired_vcpus {
             let vcpu = Arc::clone(&self.vcpus[vcpu_id as usize]);
-            self.start_vcpu(vcpu, vcpu_id, vcpu_thread_barrier.clone(), inserting)?;
+            if let Err(e) = self.start_vcpu(vcpu, vcpu_id, vcpu_thread_barrier.clone(), inserting) {
+                // Don't leave the threads already spawned waiting forever.
+                abort_vcpus_start(
+                    &vcpu_thread_barrier,
+                    &mut self.vcpu_states[usize::from(first_vcpu)..usize::from(vcpu_id)],
+                    first_vcpu,
+                );
+                return Err(e);
+            }
         }
 
         // Unblock all CPU threads.
@@ -1104,6 +1620,52 @@ impl CpuManager {
         }
     }
 
+    /// Stop waiting for the guest to eject the vCPUs previously marked for
+    /// removal, leaving them running.
+    pub fn cancel_vcpus_removal(&mut self) {
+        for state in self.vcpu_states.iter_mut() {
+            state.removing = false;
+        }
+    }
+
+    /// Kill the thread of vCPU `cpu_id`, as a last resort to recover from a
+    /// vCPU that doesn't stop running when asked to pause. The thread is
+    /// left behind if it doesn't even leave KVM_RUN once signalled.
+    ///
+    /// The guest is left in an inconsistent state, with a vCPU it considers
+    /// online that no longer runs. The VM is only expected to be paused,
+    /// snapshotted for analysis, or shut down afterwards.
+    pub fn force_kill_vcpu(&mut self, cpu_id: u8) -> Result<()> {
+        if self.vcpus_pause_signalled.load(Ordering::SeqCst) {
+            return Err(Error::KillPausedVcpu(cpu_id));
+        }
+        let state = self
+            .vcpu_states
+            .get_mut(usize::from(cpu_id))
+            .ok_or(Error::UnknownVcpu(cpu_id))?;
+        if !state.active() {
+            return Err(Error::VcpuNotRunning(cpu_id));
+        }
+
+        warn!("Killing vCPU {} by force", cpu_id);
+        state.kill.store(true, Ordering::SeqCst);
+        if state.signal_thread_until(Some(Instant::now() + VCPU_KILL_TIMEOUT)) {
+            state.join_thread()?;
+            state.kill.store(false, Ordering::SeqCst);
+        } else {
+            // The kill request stays set for the thread to exit, rather than
+            // run the guest again, should it ever leave KVM_RUN.
+            error!(
+                "vCPU {} did not leave KVM_RUN, leaving its thread behind",
+                cpu_id
+            );
+            state.handle = None;
+            state.usage_sample = None;
+        }
+
+        Ok(())
+    }
+
     fn remove_vcpu(&mut self, cpu_id: u8) -> Result<()> {
         info!("Removing vCPU: cpu_id = {}", cpu_id);
         let mut state = &mut self.vcpu_states[usize::from(cpu_id)];
@@ -1129,23 +1691,59 @@ impl CpuManager {
 
     pub fn start_restored_vcpus(&mut self) -> Result<()> {
         let vcpu_numbers = self.vcpus.len() as u8;
-        let vcpu_thread_barrier = Arc::new(Barrier::new((vcpu_numbers + 1) as usize));
+        let vcpu_thread_barrier = Arc::new(VcpuStartBarrier::new((vcpu_numbers + 1) as usize));
         // Restore the vCPUs in "paused" state.
         self.vcpus_pause_signalled.store(true, Ordering::SeqCst);
 
         for vcpu_id in 0..vcpu_numbers {
             let vcpu = Arc::clone(&self.vcpus[vcpu_id as usize]);
 
-            self.start_vcpu(vcpu, vcpu_id, vcpu_thread_barrier.clone(), false)
-                .map_err(|e| {
-                    Error::StartRestoreVcpu(anyhow!("Failed to start restored vCPUs: {:#?}", e))
-                })?;
+            if let Err(e) = self.start_vcpu(vcpu, vcpu_id, vcpu_thread_barrier.clone(), false) {
+                abort_vcpus_start(
+                    &vcpu_thread_barrier,
+                    &mut self.vcpu_states[..usize::from(vcpu_id)],
+                    0,
+                );
+                return Err(Error::StartRestoreVcpu(anyhow!(
+                    "Failed to start restored vCPUs: {:#?}",
+                    e
+                )));
+            }
         }
         // Unblock all restored CPU threads.
         vcpu_thread_barrier.wait();
         Ok(())
     }
 
+    /// Whether `snapshot` holds the state of the existing vCPUs, and only
+    /// theirs.
+    pub fn snapshot_compatible(&self, snapshot: &Snapshot) -> bool {
+        snapshot.snapshots.len() == self.vcpus.len()
+            && snapshot.snapshots.keys().all(
+                |cpu_id| matches!(cpu_id.parse::<usize>(), Ok(cpu_id) if cpu_id < self.vcpus.len()),
+            )
+    }
+
+    /// Restore the state of the existing vCPUs from `snapshot`, without
+    /// recreating them. The vCPUs must be paused, and the snapshot must hold
+    /// the state of the very same vCPUs.
+    pub fn restore_vcpus_in_place(&mut self, snapshot: &Snapshot) -> Result<()> {
+        if !self.snapshot_compatible(snapshot) {
+            return Err(Error::SnapshotVcpusMismatch);
+        }
+
+        for (cpu_id, snapshot) in snapshot.snapshots.iter() {
+            info!("Restoring vCPU {} in place", cpu_id);
+            self.vcpus[cpu_id.parse::<usize>().unwrap()]
+                .lock()
+                .unwrap()
+                .restore(*snapshot.clone())
+                .map_err(Error::RestoreVcpu)?;
+        }
+
+        Ok(())
+    }
+
     pub fn resize(&mut self, desired_vcpus: u8) -> Result<bool> {
         if desired_vcpus.cmp(&self.present_vcpus()) == cmp::Ordering::Equal {
             return Ok(false);
@@ -1169,6 +1767,54 @@ impl CpuManager {
         }
     }
 
+    /// Layer the filter matching `seccomp_action` on top of the one the
+    /// running vCPU threads already apply. The vCPUs started afterwards only
+    /// get the new filter.
+    ///
+    /// The returned receiver gets the outcome of applying the filter from
+    /// each running vCPU thread, and is closed once they all answered. It
+    /// must be waited on without holding the `CpuManager` lock, which a
+    /// vCPU may need before getting to the filter.
+    pub fn set_seccomp_action(
+        &mut self,
+        seccomp_action: SeccompAction,
+    ) -> Result<mpsc::Receiver<result::Result<(), seccompiler::Error>>> {
+        let filter = get_seccomp_filter(&seccomp_action, Thread::Vcpu)
+            .map_err(Error::CreateSeccompFilter)?;
+        self.seccomp_action = seccomp_action;
+        let (result_sender, results) = mpsc::channel();
+        if filter.is_empty() {
+            return Ok(results);
+        }
+
+        for state in self.vcpu_states.iter().filter(|s| s.active()) {
+            let requested = match state.seccomp_filter_requests.as_ref() {
+                Some(requests) => requests
+                    .send((filter.clone(), result_sender.clone()))
+                    .is_ok(),
+                None => false,
+            };
+            // The vCPU thread already exited.
+            if !requested {
+                continue;
+            }
+
+            // Kick the vCPU out of KVM_RUN so that it goes through its loop,
+            // and wake it up if it is parked.
+            state.unpark_thread();
+            if let Some(handle) = state.handle.as_ref() {
+                // SAFETY: the thread can't be released until its handle is
+                // joined, and SIGRTMIN() has a handler installed by the vCPU
+                // thread.
+                unsafe {
+                    libc::pthread_kill(handle.as_pthread_t() as _, SIGRTMIN());
+                }
+            }
+        }
+
+        Ok(results)
+    }
+
     pub fn shutdown(&mut self) -> Result<()> {
         // Tell the vCPUs to stop themselves next time they go through the loop
         self.vcpus_kill_signalled.store(true, Ordering::SeqCst);
@@ -1221,18 +1867,40 @@ impl CpuManager {
         self.cpuid.clone()
     }
 
-    fn present_vcpus(&self) -> u8 {
+    /// Only applies to the vCPUs created afterwards.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_tsc_deadline_timer(&mut self, enabled: bool) {
+        self.config.tsc_deadline_timer = enabled;
+        arch::update_cpuid_tsc_deadline_timer(&mut self.cpuid, enabled);
+    }
+
+    pub fn present_vcpus(&self) -> u8 {
         self.vcpu_states
             .iter()
             .fold(0, |acc, state| acc + state.active() as u8)
     }
 
+    /// Identifiers of the vCPUs created so far.
+    pub fn vcpu_ids(&self) -> Vec<VcpuIdentity> {
+        // The vCPUs are indexed by their id, and not locked as they might be
+        // running.
+        (0..self.vcpus.len())
+            .map(|index| VcpuIdentity {
+                index: index as u8,
+                // The APIC id of each vCPU is its index, as described by the
+                // MADT.
+                #[cfg(target_arch = "x86_64")]
+                hardware_id: index as u64,
+                #[cfg(target_arch = "aarch64")]
+                hardware_id: self.mpidrs[index],
+                active: self.vcpu_states[index].active(),
+            })
+            .collect()
+    }
+
     #[cfg(target_arch = "aarch64")]
     pub fn get_mpidrs(&self) -> Vec<u64> {
-        self.vcpus
-            .iter()
-            .map(|cpu| cpu.lock().unwrap().get_mpidr())
-            .collect()
+        self.mpidrs.clone()
     }
 
     #[cfg(target_arch = "aarch64")]
@@ -1513,6 +2181,61 @@ impl CpuManager {
     pub fn vcpus_paused(&self) -> bool {
         self.vcpus_pause_signalled.load(Ordering::SeqCst)
     }
+
+    /// Keep a vCPU that triple faulted from running until the VM is paused,
+    /// instead of resetting the VM. This applies to the vCPUs started
+    /// afterwards.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_break_on_triple_fault(&mut self, enable: bool) {
+        self.break_on_triple_fault = enable;
+    }
+
+    /// Keep a vCPU that requested a reset from running until the VM is
+    /// paused, instead of exiting its thread, for the VMM to pause the VM
+    /// rather than rebuild it. This applies to the vCPUs started afterwards.
+    pub fn set_pause_on_reset(&mut self, enable: bool) {
+        self.pause_on_reset = enable;
+    }
+
+    /// Counter the VM ops increment when they fail an access to an
+    /// unregistered address, so that the vCPU which made it stops until the
+    /// VM is paused rather than exiting its thread.
+    pub fn set_unregistered_io_faults(&mut self, faults: Arc<AtomicUsize>) {
+        self.unregistered_io_faults = faults;
+    }
+
+    /// Whether a vCPU got stopped by an access to an unregistered address
+    /// since the last call.
+    pub fn take_unregistered_io_fault(&self) -> bool {
+        self.unregistered_io_fault.swap(false, Ordering::SeqCst)
+    }
+
+    /// Whether a vCPU triple faulted since the last call.
+    #[cfg(target_arch = "x86_64")]
+    pub fn take_triple_fault(&self) -> bool {
+        self.triple_fault.swap(false, Ordering::SeqCst)
+    }
+
+    /// Busy fraction, between 0 and 1, of each present vCPU since the
+    /// previous call, or since the vCPU was started. Each call starts a new
+    /// sampling window.
+    pub fn cpu_utilization(&mut self) -> Vec<f64> {
+        self.vcpu_states
+            .iter_mut()
+            .filter(|state| state.active())
+            .map(|state| state.sample_utilization())
+            .collect()
+    }
+
+    pub fn vcpu_counts(&self) -> VcpuCounts {
+        let present = self.present_vcpus();
+        VcpuCounts {
+            boot: self.boot_vcpus(),
+            present,
+            max: self.max_vcpus(),
+            active: if self.vcpus_paused() { 0 } else { present },
+        }
+    }
 }
 
 struct Cpu {
@@ -1850,19 +2573,52 @@ impl Aml for CpuManager {
     }
 }
 
-impl Pausable for CpuManager {
-    fn pause(&mut self) -> std::result::Result<(), MigratableError> {
+impl CpuManager {
+    /// Pause the vCPUs like Pausable::pause(), but fail if one of them doesn't
+    /// stop running by `deadline`. The vCPUs are resumed in that case.
+    pub fn pause_with_deadline(
+        &mut self,
+        deadline: Instant,
+    ) -> std::result::Result<(), MigratableError> {
+        self.pause_vcpus(Some(deadline))
+    }
+
+    fn pause_vcpus(
+        &mut self,
+        deadline: Option<Instant>,
+    ) -> std::result::Result<(), MigratableError> {
         // Tell the vCPUs to pause themselves next time they exit
         self.vcpus_pause_signalled.store(true, Ordering::SeqCst);
 
         // Signal to the spawned threads (vCPUs and console signal handler). For the vCPU threads
         // this will interrupt the KVM_RUN ioctl() allowing the loop to check the boolean set
         // above.
+        for (cpu_id, state) in self.vcpu_states.iter().enumerate() {
+            if !state.signal_thread_until(deadline) {
+                // The stuck vCPU still holds its lock, so only undo the
+                // pause request rather than going through resume().
+                self.vcpus_pause_signalled.store(false, Ordering::SeqCst);
+                for state in self.vcpu_states.iter() {
+                    state.unpark_thread();
+                }
+                return Err(MigratableError::Pause(anyhow!(
+                    "vCPU {} did not stop running in time",
+                    cpu_id
+                )));
+            }
+        }
+
+        // Wake up the vCPUs waiting in park_until_paused_or_killed(), the
+        // signal doesn't.
         for state in self.vcpu_states.iter() {
-            state.signal_thread();
+            state.unpark_thread();
         }
 
-        for vcpu in self.vcpus.iter() {
+        for (vcpu, state) in self.vcpus.iter().zip(self.vcpu_states.iter()) {
+            // A vCPU thread left behind by force_kill_vcpu() holds the lock.
+            if state.kill.load(Ordering::SeqCst) {
+                continue;
+            }
             let mut vcpu = vcpu.lock().unwrap();
             vcpu.pause()?;
             #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
@@ -1878,9 +2634,18 @@ impl Pausable for CpuManager {
 
         Ok(())
     }
+}
+
+impl Pausable for CpuManager {
+    fn pause(&mut self) -> std::result::Result<(), MigratableError> {
+        self.pause_vcpus(None)
+    }
 
     fn resume(&mut self) -> std::result::Result<(), MigratableError> {
-        for vcpu in self.vcpus.iter() {
+        for (vcpu, state) in self.vcpus.iter().zip(self.vcpu_states.iter()) {
+            if state.kill.load(Ordering::SeqCst) {
+                continue;
+            }
             vcpu.lock().unwrap().resume()?;
         }
 
@@ -1946,6 +2711,27 @@ impl Debuggable for CpuManager {
             .map_err(DebuggableError::SetDebug)
     }
 
+    #[cfg(feature = "kvm")]
+    fn set_guest_debug_all(
+        &mut self,
+        addrs: &[GuestAddress],
+        singlestep: bool,
+    ) -> std::result::Result<(), DebuggableError> {
+        for vcpu in self.vcpus.iter() {
+            vcpu.lock()
+                .unwrap()
+                .vcpu
+                .set_guest_debug(addrs, singlestep)
+                .map_err(DebuggableError::SetDebug)?;
+        }
+        self.guest_debug = if addrs.is_empty() && !singlestep {
+            None
+        } else {
+            Some((addrs.to_vec(), singlestep))
+        };
+        Ok(())
+    }
+
     fn debug_pause(&mut self) -> std::result::Result<(), DebuggableError> {
         Ok(())
     }
@@ -2328,6 +3114,152 @@ impl CpuElf64Writable for CpuManager {
     }
 }
 
+#[cfg(test)]
+mod vcpu_thread_tests {
+    use super::*;
+
+    #[test]
+    fn test_vcpu_start_barrier_abort() {
+        // Only two of the three vCPU threads could be spawned.
+        let barrier = Arc::new(VcpuStartBarrier::new(4));
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let barrier = barrier.clone();
+                thread::spawn(move || barrier.wait())
+            })
+            .collect();
+
+        barrier.abort();
+        for thread in threads {
+            assert!(!thread.join().unwrap());
+        }
+
+        let barrier = Arc::new(VcpuStartBarrier::new(3));
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let barrier = barrier.clone();
+                thread::spawn(move || barrier.wait())
+            })
+            .collect();
+        assert!(barrier.wait());
+        for thread in threads {
+            assert!(thread.join().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_abort_vcpus_start() {
+        // Stubs of the vCPU threads, waiting on the start barrier.
+        let barrier = Arc::new(VcpuStartBarrier::new(4));
+        let mut vcpu_states: Vec<VcpuState> = (0..3).map(|_| VcpuState::default()).collect();
+        for state in vcpu_states[..2].iter_mut() {
+            let barrier = barrier.clone();
+            let run_interrupted = state.vcpu_run_interrupted.clone();
+            state.handle = Some(thread::spawn(move || {
+                if !barrier.wait() {
+                    run_interrupted.store(true, Ordering::SeqCst);
+                }
+            }));
+            state.inserting = true;
+        }
+
+        // The thread of the third vCPU could not be spawned.
+        abort_vcpus_start(&barrier, &mut vcpu_states[..2], 0);
+        for state in vcpu_states.iter() {
+            assert!(!state.active());
+            assert!(!state.inserting);
+        }
+        assert!(vcpu_states[..2]
+            .iter()
+            .all(|state| state.vcpu_run_interrupted.load(Ordering::SeqCst)));
+        assert!(!vcpu_states[2].vcpu_run_interrupted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_signal_thread_deadline() {
+        extern "C" fn handle_signal(_: i32, _: *mut siginfo_t, _: *mut c_void) {}
+        register_signal_handler(SIGRTMIN(), handle_signal).unwrap();
+
+        // vCPU thread stub ignoring the signals asking it to pause.
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut state = VcpuState {
+            handle: Some({
+                let stop = stop.clone();
+                thread::spawn(move || {
+                    while !stop.load(Ordering::SeqCst) {
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                })
+            }),
+            ..Default::default()
+        };
+
+        let start = Instant::now();
+        assert!(!state.signal_thread_until(Some(start + Duration::from_millis(50))));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // The pause is acknowledged once the vCPU run is interrupted.
+        state.vcpu_run_interrupted.store(true, Ordering::SeqCst);
+        assert!(state.signal_thread_until(Some(Instant::now())));
+
+        stop.store(true, Ordering::SeqCst);
+        state.join_thread().unwrap();
+    }
+
+    #[test]
+    fn test_park_until_paused_or_killed() {
+        for kill_instead in [false, true] {
+            let pause_signalled = Arc::new(AtomicBool::new(false));
+            let kill_signalled = Arc::new(AtomicBool::new(false));
+            let run_interrupted = Arc::new(AtomicBool::new(false));
+            let handle = {
+                let pause_signalled = pause_signalled.clone();
+                let kill_signalled = kill_signalled.clone();
+                let run_interrupted = run_interrupted.clone();
+                thread::spawn(move || {
+                    park_until_paused_or_killed(
+                        &pause_signalled,
+                        &kill_signalled,
+                        &AtomicBool::new(false),
+                        &run_interrupted,
+                        &Mutex::new(mpsc::channel().1),
+                    )
+                })
+            };
+
+            // The parked vCPU must not hold up pausing the VM.
+            while !run_interrupted.load(Ordering::SeqCst) {
+                thread::yield_now();
+            }
+
+            if kill_instead {
+                kill_signalled.store(true, Ordering::SeqCst);
+            } else {
+                pause_signalled.store(true, Ordering::SeqCst);
+            }
+            handle.thread().unpark();
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_apply_requested_seccomp_filters() {
+        let (requests, receiver) = mpsc::channel();
+        let receiver = Mutex::new(receiver);
+        let (result_sender, results) = mpsc::channel();
+
+        // An empty filter can't be applied, which must be reported.
+        requests.send((BpfProgram::new(), result_sender)).unwrap();
+        apply_requested_seccomp_filters(&receiver);
+        assert!(matches!(
+            results.recv().unwrap(),
+            Err(seccompiler::Error::EmptyFilter)
+        ));
+        // Every request got its answer.
+        assert!(results.recv().is_err());
+    }
+}
+
 #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
 #[cfg(test)]
 mod tests {
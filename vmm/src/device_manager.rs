@@ -10,12 +10,14 @@
 //
 
 use crate::config::{
-    ConsoleOutputMode, DeviceConfig, DiskConfig, FsConfig, NetConfig, PmemConfig, UserDeviceConfig,
-    VdpaConfig, VhostMode, VmConfig, VsockConfig,
+    ConsoleConfig, ConsoleOutputMode, DeviceConfig, DiskConfig, FsConfig, NetConfig, PmemConfig,
+    SerialDeviceConfig, UserDeviceConfig, VdpaConfig, VhostMode, VmConfig, VsockConfig,
 };
 use crate::device_tree::{DeviceNode, DeviceTree};
 use crate::interrupt::LegacyUserspaceInterruptManager;
 use crate::interrupt::MsiInterruptManager;
+use crate::interrupt::{GsiRouteKind, InterruptControllerType, IrqRoutingInfo, LegacyIrqInfo};
+use crate::io_trace::IoSpace;
 use crate::memory_manager::MEMORY_MANAGER_ACPI_SIZE;
 use crate::memory_manager::{Error as MemoryManagerError, MemoryManager};
 use crate::pci_segment::PciSegment;
@@ -64,7 +66,7 @@ use pci::{
 };
 use seccompiler::SeccompAction;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::convert::TryInto;
 use std::fs::{read_link, File, OpenOptions};
 use std::io::{self, stdout, Seek, SeekFrom};
@@ -72,10 +74,11 @@ use std::mem::zeroed;
 use std::num::Wrapping;
 use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
 use std::path::PathBuf;
 use std::result;
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use vfio_ioctls::{VfioContainer, VfioDevice};
 use virtio_devices::transport::VirtioTransport;
 use virtio_devices::transport::{VirtioPciDevice, VirtioPciDeviceActivator};
@@ -90,7 +93,7 @@ use vm_device::dma_mapping::ExternalDmaMapping;
 use vm_device::interrupt::{
     InterruptIndex, InterruptManager, LegacyIrqGroupConfig, MsiIrqGroupConfig,
 };
-use vm_device::{Bus, BusDevice, Resource};
+use vm_device::{Bus, BusDevice, PciBarType, Resource};
 use vm_memory::guest_memory::FileOffset;
 #[cfg(target_arch = "aarch64")]
 use vm_memory::GuestMemoryAtomic;
@@ -112,14 +115,25 @@ const MMIO_LEN: u64 = 0x1000;
 // Singleton devices / devices the user cannot name
 #[cfg(target_arch = "x86_64")]
 const IOAPIC_DEVICE_NAME: &str = "__ioapic";
+const GED_DEVICE_NAME: &str = "__ged";
 const SERIAL_DEVICE_NAME: &str = "__serial";
 #[cfg(target_arch = "aarch64")]
 const GPIO_DEVICE_NAME: &str = "__gpio";
+#[cfg(target_arch = "aarch64")]
+pub(crate) const RTC_DEVICE_NAME: &str = "rtc";
 const RNG_DEVICE_NAME: &str = "__rng";
 const IOMMU_DEVICE_NAME: &str = "__iommu";
 const BALLOON_DEVICE_NAME: &str = "__balloon";
 const CONSOLE_DEVICE_NAME: &str = "__console";
 
+// Legacy COM ports used by the serial ports following COM1
+#[cfg(target_arch = "x86_64")]
+const EXTRA_SERIAL_IO_PORTS: [u64; crate::config::MAX_EXTRA_SERIAL_PORTS] = [0x2f8, 0x3e8, 0x2e8];
+
+// Same I/O port QEMU uses for its ISA pvpanic device
+#[cfg(target_arch = "x86_64")]
+const PVPANIC_IO_PORT: u64 = 0x505;
+
 // Devices that the user may name and for which we generate
 // identifiers if the user doesn't give one
 const DISK_DEVICE_NAME_PREFIX: &str = "_disk";
@@ -128,6 +142,7 @@ const NET_DEVICE_NAME_PREFIX: &str = "_net";
 const PMEM_DEVICE_NAME_PREFIX: &str = "_pmem";
 const VDPA_DEVICE_NAME_PREFIX: &str = "_vdpa";
 const VSOCK_DEVICE_NAME_PREFIX: &str = "_vsock";
+const SERIAL_DEVICE_NAME_PREFIX: &str = "_serial";
 const WATCHDOG_DEVICE_NAME: &str = "__watchdog";
 const VFIO_DEVICE_NAME_PREFIX: &str = "_vfio";
 const VFIO_USER_DEVICE_NAME_PREFIX: &str = "_vfio_user";
@@ -349,6 +364,9 @@ pub enum DeviceManagerError {
     /// Failed to find device corresponding to the given identifier.
     UnknownDeviceId(String),
 
+    /// The device corresponding to the given identifier isn't a virtio one.
+    NotVirtioDevice(String),
+
     /// Failed to find an available PCI device ID.
     NextPciDeviceId(pci::PciRootError),
 
@@ -406,6 +424,12 @@ pub enum DeviceManagerError {
     /// Missing virtio-balloon, can't proceed as expected.
     MissingVirtioBalloon,
 
+    /// Missing virtio-watchdog, can't proceed as expected.
+    MissingVirtioWatchdog,
+
+    /// Invalid virtio-watchdog timeout
+    VirtioWatchdogTimeout(io::Error),
+
     /// Missing virtual IOMMU device
     MissingVirtualIommu,
 
@@ -478,6 +502,12 @@ pub enum DeviceManagerError {
 
     /// Error activating virtio device
     VirtioActivate(ActivateError),
+
+    /// Unsupported console mode for a serial device
+    InvalidSerialDeviceMode,
+
+    /// Failed to reconnect a vhost-user device to its backend
+    ReconnectVhostUser(virtio_devices::Error),
 }
 pub type DeviceManagerResult<T> = result::Result<T, DeviceManagerError>;
 
@@ -549,6 +579,12 @@ impl Console {
             resizer.update_console_size()
         }
     }
+
+    pub fn set_console_size(&self, cols: u16, rows: u16) {
+        if let Some(resizer) = self.console_resizer.as_ref() {
+            resizer.set_console_size(cols, rows)
+        }
+    }
 }
 
 pub(crate) struct AddressManager {
@@ -794,6 +830,42 @@ impl Clone for PtyPair {
     }
 }
 
+/// Outcome of a virtio device activation request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActivationDecision {
+    /// Activate the device right away
+    Approve,
+    /// Leave the device inactive
+    Deny,
+    /// Keep the request pending until the next activation attempt
+    Defer,
+}
+
+/// Policy consulted with the device identifier every time a virtio device
+/// requests activation.
+pub type ActivationPolicy = Box<dyn Fn(&str) -> ActivationDecision + Send + Sync>;
+
+/// PCI device found at a given guest visible address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceSummary {
+    /// Identifier of the device, as found in the configuration. For
+    /// virtio-pci devices, this is the identifier of the virtio device.
+    pub id: String,
+    pub bdf: PciBdf,
+    /// "vfio", "vfio-user", or the virtio device type such as "block".
+    pub device_type: String,
+}
+
+/// Address range registered on the PIO or MMIO bus.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BusRangeInfo {
+    pub base: u64,
+    pub size: u64,
+    /// Identifier of the device owning the range. Internal devices have
+    /// their name prefixed with "__".
+    pub device: Option<String>,
+}
+
 #[derive(Clone)]
 pub enum PciDeviceHandle {
     Vfio(Arc<Mutex<VfioPciDevice>>),
@@ -826,6 +898,20 @@ pub struct DeviceManager {
     // Serial Manager
     serial_manager: Option<Arc<SerialManager>>,
 
+    // Additional serial ports PTYs, indexed like the extra_serial config
+    extra_serial_ptys: Vec<Option<Arc<Mutex<PtyPair>>>>,
+
+    // Additional serial ports index in the extra_serial config, I/O port and
+    // IRQ, needed to describe them through ACPI
+    #[cfg(target_arch = "x86_64")]
+    extra_serial_resources: Vec<(usize, u64, u32)>,
+
+    // Serial Managers for the additional serial ports
+    extra_serial_managers: Vec<Arc<SerialManager>>,
+
+    // PTYs backing the virtio-console serial devices, indexed by device id
+    serial_device_ptys: HashMap<String, Arc<Mutex<PtyPair>>>,
+
     // pty foreground status,
     console_resize_pipe: Option<Arc<File>>,
 
@@ -866,6 +952,20 @@ pub struct DeviceManager {
     // MSI Interrupt Manager
     msi_interrupt_manager: Arc<dyn InterruptManager<GroupConfig = MsiIrqGroupConfig>>,
 
+    // Same object as msi_interrupt_manager, kept with its concrete type
+    // so that the GSI routes can be inspected.
+    gsi_routing: Arc<MsiInterruptManager>,
+
+    // Legacy IRQ assigned to each platform device, indexed by device id.
+    // The INTx lines of PCI devices are found from the PCI segments.
+    legacy_irqs: BTreeMap<String, u32>,
+
+    // Name of the devices missing from the device tree, indexed by the base
+    // of their range on the PIO and MMIO buses.
+    #[cfg(target_arch = "x86_64")]
+    io_bus_names: BTreeMap<u64, String>,
+    mmio_bus_names: BTreeMap<u64, String>,
+
     #[cfg_attr(feature = "mshv", allow(dead_code))]
     // Legacy Interrupt Manager
     legacy_interrupt_manager: Option<Arc<dyn InterruptManager<GroupConfig = LegacyIrqGroupConfig>>>,
@@ -896,6 +996,10 @@ pub struct DeviceManager {
     exit_evt: EventFd,
     reset_evt: EventFd,
 
+    #[cfg(target_arch = "x86_64")]
+    // Signalled by the pvpanic device when the guest panics
+    guest_panic_evt: EventFd,
+
     #[cfg(target_arch = "aarch64")]
     id_to_dev_info: HashMap<(DeviceType, String), MmioDeviceInfo>,
 
@@ -908,6 +1012,9 @@ pub struct DeviceManager {
     // Possible handle to the virtio-balloon device
     balloon: Option<Arc<Mutex<virtio_devices::Balloon>>>,
 
+    // Possible handle to the virtio-watchdog device
+    watchdog: Option<Arc<Mutex<virtio_devices::Watchdog>>>,
+
     // Virtio Device activation EventFd to allow the VMM thread to trigger device
     // activation and thus start the threads from the VMM thread
     activate_evt: EventFd,
@@ -919,6 +1026,10 @@ pub struct DeviceManager {
     // Possible handle to the virtio-mem device
     virtio_mem_devices: Vec<Arc<Mutex<virtio_devices::Mem>>>,
 
+    #[cfg(target_arch = "x86_64")]
+    // pvpanic device used by the guest to report panics
+    pvpanic_device: Option<Arc<Mutex<devices::legacy::PvPanic>>>,
+
     #[cfg(target_arch = "aarch64")]
     // GPIO device for AArch64
     gpio_device: Option<Arc<Mutex<devices::legacy::Gpio>>>,
@@ -944,6 +1055,9 @@ pub struct DeviceManager {
 
     // Pending activations
     pending_activations: Arc<Mutex<Vec<VirtioPciDeviceActivator>>>,
+
+    // Optional policy deciding whether pending activations can proceed
+    activation_policy: Option<ActivationPolicy>,
 }
 
 impl DeviceManager {
@@ -954,6 +1068,7 @@ impl DeviceManager {
         memory_manager: Arc<Mutex<MemoryManager>>,
         exit_evt: &EventFd,
         reset_evt: &EventFd,
+        #[cfg(target_arch = "x86_64")] guest_panic_evt: &EventFd,
         seccomp_action: SeccompAction,
         numa_nodes: NumaNodes,
         activate_evt: &EventFd,
@@ -1004,11 +1119,12 @@ impl DeviceManager {
         // and then the legacy interrupt manager needs an IOAPIC. So we're
         // handling a linear dependency chain:
         // msi_interrupt_manager <- IOAPIC <- legacy_interrupt_manager.
+        let gsi_routing = Arc::new(MsiInterruptManager::new(
+            Arc::clone(&address_manager.allocator),
+            vm,
+        ));
         let msi_interrupt_manager: Arc<dyn InterruptManager<GroupConfig = MsiIrqGroupConfig>> =
-            Arc::new(MsiInterruptManager::new(
-                Arc::clone(&address_manager.allocator),
-                vm,
-            ));
+            gsi_routing.clone();
 
         let acpi_address = address_manager
             .allocator
@@ -1051,6 +1167,11 @@ impl DeviceManager {
             bus_devices: Vec::new(),
             device_id_cnt: Wrapping(0),
             msi_interrupt_manager,
+            gsi_routing,
+            legacy_irqs: BTreeMap::new(),
+            #[cfg(target_arch = "x86_64")]
+            io_bus_names: BTreeMap::new(),
+            mmio_bus_names: BTreeMap::new(),
             legacy_interrupt_manager: None,
             passthrough_device: None,
             vfio_container: None,
@@ -1061,11 +1182,16 @@ impl DeviceManager {
             device_tree,
             exit_evt: exit_evt.try_clone().map_err(DeviceManagerError::EventFd)?,
             reset_evt: reset_evt.try_clone().map_err(DeviceManagerError::EventFd)?,
+            #[cfg(target_arch = "x86_64")]
+            guest_panic_evt: guest_panic_evt
+                .try_clone()
+                .map_err(DeviceManagerError::EventFd)?,
             #[cfg(target_arch = "aarch64")]
             id_to_dev_info: HashMap::new(),
             seccomp_action,
             numa_nodes,
             balloon: None,
+            watchdog: None,
             activate_evt: activate_evt
                 .try_clone()
                 .map_err(DeviceManagerError::EventFd)?,
@@ -1073,9 +1199,16 @@ impl DeviceManager {
             selected_segment: 0,
             serial_pty: None,
             serial_manager: None,
+            extra_serial_ptys: Vec::new(),
+            #[cfg(target_arch = "x86_64")]
+            extra_serial_resources: Vec::new(),
+            extra_serial_managers: Vec::new(),
+            serial_device_ptys: HashMap::new(),
             console_pty: None,
             console_resize_pipe: None,
             virtio_mem_devices: Vec::new(),
+            #[cfg(target_arch = "x86_64")]
+            pvpanic_device: None,
             #[cfg(target_arch = "aarch64")]
             gpio_device: None,
             #[cfg(target_arch = "aarch64")]
@@ -1086,8 +1219,12 @@ impl DeviceManager {
             boot_id_list,
             timestamp,
             pending_activations: Arc::new(Mutex::new(Vec::default())),
+            activation_policy: None,
         };
 
+        device_manager
+            .mmio_bus_names
+            .insert(acpi_address.0, "__device_manager".to_string());
         let device_manager = Arc::new(Mutex::new(device_manager));
 
         address_manager
@@ -1108,6 +1245,20 @@ impl DeviceManager {
             .map(|pty| pty.lock().unwrap().clone())
     }
 
+    /// PTY of the additional serial port at `index`, if it uses pty mode.
+    pub fn extra_serial_pty(&self, index: usize) -> Option<PtyPair> {
+        self.extra_serial_ptys
+            .get(index)
+            .and_then(|pty| pty.as_ref())
+            .map(|pty| pty.lock().unwrap().clone())
+    }
+
+    pub fn extra_serial_ptys(&self) -> Vec<Option<PtyPair>> {
+        (0..self.extra_serial_ptys.len())
+            .map(|index| self.extra_serial_pty(index))
+            .collect()
+    }
+
     pub fn console_pty(&self) -> Option<PtyPair> {
         self.console_pty
             .as_ref()
@@ -1121,6 +1272,7 @@ impl DeviceManager {
     pub fn create_devices(
         &mut self,
         serial_pty: Option<PtyPair>,
+        extra_serial_ptys: Vec<Option<PtyPair>>,
         console_pty: Option<PtyPair>,
         console_resize_pipe: Option<File>,
     ) -> DeviceManagerResult<()> {
@@ -1146,6 +1298,8 @@ impl DeviceManager {
                         MEMORY_MANAGER_ACPI_SIZE as u64,
                     )
                     .map_err(DeviceManagerError::BusError)?;
+                self.mmio_bus_names
+                    .insert(acpi_address.0, "__memory_manager".to_string());
             }
         }
 
@@ -1175,6 +1329,7 @@ impl DeviceManager {
             &legacy_interrupt_manager,
             &mut virtio_devices,
             serial_pty,
+            extra_serial_ptys,
             console_pty,
             console_resize_pipe,
         )?;
@@ -1367,6 +1522,8 @@ impl DeviceManager {
             .mmio_bus
             .insert(interrupt_controller.clone(), IOAPIC_START.0, IOAPIC_SIZE)
             .map_err(DeviceManagerError::BusError)?;
+        self.mmio_bus_names
+            .insert(IOAPIC_START.0, IOAPIC_DEVICE_NAME.to_string());
 
         self.bus_devices
             .push(Arc::clone(&interrupt_controller) as Arc<Mutex<dyn BusDevice>>);
@@ -1408,6 +1565,7 @@ impl DeviceManager {
                 .io_bus
                 .insert(shutdown_device, 0x3c0, 0x4)
                 .map_err(DeviceManagerError::BusError)?;
+            self.io_bus_names.insert(0x3c0, "__shutdown".to_string());
         }
 
         let ged_irq = self
@@ -1438,6 +1596,8 @@ impl DeviceManager {
             ged_irq,
             ged_address,
         )));
+        self.legacy_irqs
+            .insert(GED_DEVICE_NAME.to_string(), ged_irq);
         self.address_manager
             .mmio_bus
             .insert(
@@ -1467,6 +1627,7 @@ impl DeviceManager {
                 .io_bus
                 .insert(pm_timer_device, 0xb008, 0x4)
                 .map_err(DeviceManagerError::BusError)?;
+            self.io_bus_names.insert(0xb008, "__pm_timer".to_string());
         }
 
         Ok(Some(ged_device))
@@ -1486,6 +1647,7 @@ impl DeviceManager {
             .io_bus
             .insert(i8042, 0x61, 0x4)
             .map_err(DeviceManagerError::BusError)?;
+        self.io_bus_names.insert(0x61, "__i8042".to_string());
         {
             // Add a CMOS emulated device
             let mem_size = self
@@ -1513,6 +1675,7 @@ impl DeviceManager {
                 .io_bus
                 .insert(cmos, 0x70, 0x2)
                 .map_err(DeviceManagerError::BusError)?;
+            self.io_bus_names.insert(0x70, "__cmos".to_string());
         }
         #[cfg(feature = "fwdebug")]
         {
@@ -1525,6 +1688,7 @@ impl DeviceManager {
                 .io_bus
                 .insert(fwdebug, 0x402, 0x1)
                 .map_err(DeviceManagerError::BusError)?;
+            self.io_bus_names.insert(0x402, "__fwdebug".to_string());
         }
 
         // 0x80 debug port
@@ -1535,6 +1699,24 @@ impl DeviceManager {
             .io_bus
             .insert(debug_port, 0x80, 0x1)
             .map_err(DeviceManagerError::BusError)?;
+        self.io_bus_names.insert(0x80, "__debug_port".to_string());
+
+        // pvpanic device
+        let pvpanic = Arc::new(Mutex::new(devices::legacy::PvPanic::new(
+            self.timestamp,
+            self.guest_panic_evt
+                .try_clone()
+                .map_err(DeviceManagerError::EventFd)?,
+        )));
+        self.bus_devices
+            .push(Arc::clone(&pvpanic) as Arc<Mutex<dyn BusDevice>>);
+        self.address_manager
+            .io_bus
+            .insert(pvpanic.clone(), PVPANIC_IO_PORT, 0x1)
+            .map_err(DeviceManagerError::BusError)?;
+        self.io_bus_names
+            .insert(PVPANIC_IO_PORT, "__pvpanic".to_string());
+        self.pvpanic_device = Some(pvpanic);
 
         Ok(())
     }
@@ -1560,6 +1742,8 @@ impl DeviceManager {
             .map_err(DeviceManagerError::CreateInterruptGroup)?;
 
         let rtc_device = Arc::new(Mutex::new(devices::legacy::Rtc::new(interrupt_group)));
+        self.legacy_irqs
+            .insert(RTC_DEVICE_NAME.to_string(), rtc_irq);
 
         self.bus_devices
             .push(Arc::clone(&rtc_device) as Arc<Mutex<dyn BusDevice>>);
@@ -1570,9 +1754,11 @@ impl DeviceManager {
             .mmio_bus
             .insert(rtc_device, addr.0, MMIO_LEN)
             .map_err(DeviceManagerError::BusError)?;
+        self.mmio_bus_names
+            .insert(addr.0, RTC_DEVICE_NAME.to_string());
 
         self.id_to_dev_info.insert(
-            (DeviceType::Rtc, "rtc".to_string()),
+            (DeviceType::Rtc, RTC_DEVICE_NAME.to_string()),
             MmioDeviceInfo {
                 addr: addr.0,
                 len: MMIO_LEN,
@@ -1600,6 +1786,7 @@ impl DeviceManager {
             id.clone(),
             interrupt_group,
         )));
+        self.legacy_irqs.insert(id.clone(), gpio_irq);
 
         self.bus_devices
             .push(Arc::clone(&gpio_device) as Arc<Mutex<dyn BusDevice>>);
@@ -1610,6 +1797,7 @@ impl DeviceManager {
             .mmio_bus
             .insert(gpio_device.clone(), addr.0, MMIO_LEN)
             .map_err(DeviceManagerError::BusError)?;
+        self.mmio_bus_names.insert(addr.0, id.clone());
 
         self.gpio_device = Some(gpio_device.clone());
 
@@ -1669,10 +1857,24 @@ impl DeviceManager {
         serial_writer: Option<Box<dyn io::Write + Send>>,
     ) -> DeviceManagerResult<Arc<Mutex<Serial>>> {
         // Serial is tied to IRQ #4
-        let serial_irq = 4;
-
-        let id = String::from(SERIAL_DEVICE_NAME);
+        self.add_legacy_serial_device(
+            String::from(SERIAL_DEVICE_NAME),
+            0x3f8,
+            4,
+            interrupt_manager,
+            serial_writer,
+        )
+    }
 
+    #[cfg(target_arch = "x86_64")]
+    fn add_legacy_serial_device(
+        &mut self,
+        id: String,
+        io_port: u64,
+        serial_irq: u32,
+        interrupt_manager: &Arc<dyn InterruptManager<GroupConfig = LegacyIrqGroupConfig>>,
+        serial_writer: Option<Box<dyn io::Write + Send>>,
+    ) -> DeviceManagerResult<Arc<Mutex<Serial>>> {
         let interrupt_group = interrupt_manager
             .create_group(LegacyIrqGroupConfig {
                 irq: serial_irq as InterruptIndex,
@@ -1684,6 +1886,7 @@ impl DeviceManager {
             interrupt_group,
             serial_writer,
         )));
+        self.legacy_irqs.insert(id.clone(), serial_irq);
 
         self.bus_devices
             .push(Arc::clone(&serial) as Arc<Mutex<dyn BusDevice>>);
@@ -1692,13 +1895,14 @@ impl DeviceManager {
             .allocator
             .lock()
             .unwrap()
-            .allocate_io_addresses(Some(GuestAddress(0x3f8)), 0x8, None)
+            .allocate_io_addresses(Some(GuestAddress(io_port)), 0x8, None)
             .ok_or(DeviceManagerError::AllocateIoPort)?;
 
         self.address_manager
             .io_bus
-            .insert(serial.clone(), 0x3f8, 0x8)
+            .insert(serial.clone(), io_port, 0x8)
             .map_err(DeviceManagerError::BusError)?;
+        self.io_bus_names.insert(io_port, id.clone());
 
         // Fill the device tree with a new node. In case of restore, we
         // know there is nothing to do, so we can simply override the
@@ -1711,6 +1915,74 @@ impl DeviceManager {
         Ok(serial)
     }
 
+    #[cfg(target_arch = "x86_64")]
+    fn add_extra_serial_devices(
+        &mut self,
+        interrupt_manager: &Arc<dyn InterruptManager<GroupConfig = LegacyIrqGroupConfig>>,
+        mut extra_serial_ptys: Vec<Option<PtyPair>>,
+    ) -> DeviceManagerResult<()> {
+        let extra_serial = self
+            .config
+            .lock()
+            .unwrap()
+            .extra_serial
+            .clone()
+            .unwrap_or_default();
+        extra_serial_ptys.resize_with(extra_serial.len(), || None);
+
+        for (index, (serial_config, serial_pty)) in
+            extra_serial.iter().zip(extra_serial_ptys).enumerate()
+        {
+            let (serial_writer, serial_pty) = self.serial_writer(serial_config, serial_pty)?;
+            if let Some(pty) = &serial_pty {
+                self.config.lock().unwrap().extra_serial.as_mut().unwrap()[index].file =
+                    Some(pty.lock().unwrap().path.clone());
+            }
+            self.extra_serial_ptys.push(serial_pty.clone());
+
+            if serial_config.mode == ConsoleOutputMode::Off {
+                continue;
+            }
+
+            let io_port = EXTRA_SERIAL_IO_PORTS[index];
+            let serial_irq = self
+                .address_manager
+                .allocator
+                .lock()
+                .unwrap()
+                .allocate_irq()
+                .ok_or(DeviceManagerError::AllocateIrq)?;
+
+            let serial = self.add_legacy_serial_device(
+                format!("{}{}", SERIAL_DEVICE_NAME, index + 1),
+                io_port,
+                serial_irq,
+                interrupt_manager,
+                serial_writer,
+            )?;
+            self.extra_serial_resources
+                .push((index, io_port, serial_irq));
+
+            if serial_config.mode == ConsoleOutputMode::Pty {
+                if let Some(mut serial_manager) =
+                    SerialManager::new(serial, serial_pty, serial_config.mode)
+                        .map_err(DeviceManagerError::CreateSerialManager)?
+                {
+                    serial_manager
+                        .start_thread(
+                            self.exit_evt
+                                .try_clone()
+                                .map_err(DeviceManagerError::EventFd)?,
+                        )
+                        .map_err(DeviceManagerError::SpawnSerialManager)?;
+                    self.extra_serial_managers.push(Arc::new(serial_manager));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     #[cfg(target_arch = "aarch64")]
     fn add_serial_device(
         &mut self,
@@ -1739,6 +2011,7 @@ impl DeviceManager {
             serial_writer,
             self.timestamp,
         )));
+        self.legacy_irqs.insert(id.clone(), serial_irq);
 
         self.bus_devices
             .push(Arc::clone(&serial) as Arc<Mutex<dyn BusDevice>>);
@@ -1749,6 +2022,7 @@ impl DeviceManager {
             .mmio_bus
             .insert(serial.clone(), addr.0, MMIO_LEN)
             .map_err(DeviceManagerError::BusError)?;
+        self.mmio_bus_names.insert(addr.0, id.clone());
 
         self.id_to_dev_info.insert(
             (DeviceType::Serial, DeviceType::Serial.to_string()),
@@ -1928,37 +2202,56 @@ impl DeviceManager {
         })
     }
 
-    fn add_console_device(
+    fn serial_writer(
         &mut self,
-        interrupt_manager: &Arc<dyn InterruptManager<GroupConfig = LegacyIrqGroupConfig>>,
-        virtio_devices: &mut Vec<MetaVirtioDevice>,
+        serial_config: &ConsoleConfig,
         serial_pty: Option<PtyPair>,
-        console_pty: Option<PtyPair>,
-        console_resize_pipe: Option<File>,
-    ) -> DeviceManagerResult<Arc<Console>> {
-        let serial_config = self.config.lock().unwrap().serial.clone();
-        let serial_writer: Option<Box<dyn io::Write + Send>> = match serial_config.mode {
-            ConsoleOutputMode::File => Some(Box::new(
-                File::create(serial_config.file.as_ref().unwrap())
-                    .map_err(DeviceManagerError::SerialOutputFileOpen)?,
-            )),
+    ) -> DeviceManagerResult<(
+        Option<Box<dyn io::Write + Send>>,
+        Option<Arc<Mutex<PtyPair>>>,
+    )> {
+        Ok(match serial_config.mode {
+            ConsoleOutputMode::File => (
+                Some(Box::new(
+                    File::create(serial_config.file.as_ref().unwrap())
+                        .map_err(DeviceManagerError::SerialOutputFileOpen)?,
+                )),
+                None,
+            ),
             ConsoleOutputMode::Pty => {
                 if let Some(pty) = serial_pty {
-                    self.config.lock().unwrap().serial.file = Some(pty.path.clone());
-                    self.serial_pty = Some(Arc::new(Mutex::new(pty)));
+                    (None, Some(Arc::new(Mutex::new(pty))))
                 } else {
                     let (main, mut sub, path) =
                         create_pty(true).map_err(DeviceManagerError::SerialPtyOpen)?;
                     self.set_raw_mode(&mut sub)
                         .map_err(DeviceManagerError::SetPtyRaw)?;
-                    self.config.lock().unwrap().serial.file = Some(path.clone());
-                    self.serial_pty = Some(Arc::new(Mutex::new(PtyPair { main, sub, path })));
+                    (
+                        None,
+                        Some(Arc::new(Mutex::new(PtyPair { main, sub, path }))),
+                    )
                 }
-                None
             }
-            ConsoleOutputMode::Tty => Some(Box::new(stdout())),
-            ConsoleOutputMode::Off | ConsoleOutputMode::Null => None,
-        };
+            ConsoleOutputMode::Tty => (Some(Box::new(stdout())), None),
+            ConsoleOutputMode::Off | ConsoleOutputMode::Null => (None, None),
+        })
+    }
+
+    fn add_console_device(
+        &mut self,
+        interrupt_manager: &Arc<dyn InterruptManager<GroupConfig = LegacyIrqGroupConfig>>,
+        virtio_devices: &mut Vec<MetaVirtioDevice>,
+        serial_pty: Option<PtyPair>,
+        extra_serial_ptys: Vec<Option<PtyPair>>,
+        console_pty: Option<PtyPair>,
+        console_resize_pipe: Option<File>,
+    ) -> DeviceManagerResult<Arc<Console>> {
+        let serial_config = self.config.lock().unwrap().serial.clone();
+        let (serial_writer, serial_pty) = self.serial_writer(&serial_config, serial_pty)?;
+        if let Some(pty) = &serial_pty {
+            self.config.lock().unwrap().serial.file = Some(pty.lock().unwrap().path.clone());
+            self.serial_pty = serial_pty;
+        }
         if serial_config.mode != ConsoleOutputMode::Off {
             let serial = self.add_serial_device(interrupt_manager, serial_writer)?;
             self.serial_manager = match serial_config.mode {
@@ -1983,6 +2276,11 @@ impl DeviceManager {
             };
         }
 
+        #[cfg(target_arch = "x86_64")]
+        self.add_extra_serial_devices(interrupt_manager, extra_serial_ptys)?;
+        #[cfg(target_arch = "aarch64")]
+        let _ = extra_serial_ptys;
+
         let console_resizer =
             self.add_virtio_console_device(virtio_devices, console_pty, console_resize_pipe)?;
 
@@ -2006,6 +2304,9 @@ impl DeviceManager {
         // Add virtio-vsock if required
         devices.append(&mut self.make_virtio_vsock_devices()?);
 
+        // Add serial devices added at runtime before a reboot
+        devices.append(&mut self.make_virtio_serial_devices()?);
+
         devices.append(&mut self.make_virtio_mem_devices()?);
 
         // Add virtio-balloon if required
@@ -2154,6 +2455,7 @@ impl DeviceManager {
                     disk_cfg.queue_size,
                     self.seccomp_action.clone(),
                     disk_cfg.rate_limiter_config,
+                    disk_cfg.max_inflight_bytes,
                     self.exit_evt
                         .try_clone()
                         .map_err(DeviceManagerError::EventFd)?,
@@ -2387,6 +2689,7 @@ impl DeviceManager {
     fn make_virtio_fs_device(
         &mut self,
         fs_cfg: &mut FsConfig,
+        socket: Option<UnixStream>,
     ) -> DeviceManagerResult<MetaVirtioDevice> {
         let id = if let Some(id) = &fs_cfg.id {
             id.clone()
@@ -2400,40 +2703,55 @@ impl DeviceManager {
 
         let mut node = device_node!(id);
 
-        if let Some(fs_socket) = fs_cfg.socket.to_str() {
-            let virtio_fs_device = Arc::new(Mutex::new(
-                virtio_devices::vhost_user::Fs::new(
-                    id.clone(),
-                    fs_socket,
-                    &fs_cfg.tag,
-                    fs_cfg.num_queues,
-                    fs_cfg.queue_size,
-                    None,
-                    self.seccomp_action.clone(),
-                    self.restoring,
-                    self.exit_evt
-                        .try_clone()
-                        .map_err(DeviceManagerError::EventFd)?,
-                    self.force_iommu,
-                )
-                .map_err(DeviceManagerError::CreateVirtioFs)?,
-            ));
+        let exit_evt = self
+            .exit_evt
+            .try_clone()
+            .map_err(DeviceManagerError::EventFd)?;
+        let virtio_fs_device = if let Some(socket) = socket {
+            virtio_devices::vhost_user::Fs::from_stream(
+                id.clone(),
+                socket,
+                &fs_cfg.tag,
+                fs_cfg.num_queues,
+                fs_cfg.queue_size,
+                None,
+                self.seccomp_action.clone(),
+                exit_evt,
+                self.force_iommu,
+            )
+        } else if let Some(fs_socket) = fs_cfg.socket.to_str() {
+            virtio_devices::vhost_user::Fs::new(
+                id.clone(),
+                fs_socket,
+                &fs_cfg.tag,
+                fs_cfg.num_queues,
+                fs_cfg.queue_size,
+                None,
+                self.seccomp_action.clone(),
+                self.restoring,
+                exit_evt,
+                self.force_iommu,
+            )
+        } else {
+            return Err(DeviceManagerError::NoVirtioFsSock);
+        };
 
-            // Update the device tree with the migratable device.
-            node.migratable = Some(Arc::clone(&virtio_fs_device) as Arc<Mutex<dyn Migratable>>);
-            self.device_tree.lock().unwrap().insert(id.clone(), node);
+        let virtio_fs_device = Arc::new(Mutex::new(
+            virtio_fs_device.map_err(DeviceManagerError::CreateVirtioFs)?,
+        ));
 
-            Ok(MetaVirtioDevice {
-                virtio_device: Arc::clone(&virtio_fs_device)
-                    as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
-                iommu: false,
-                id,
-                pci_segment: fs_cfg.pci_segment,
-                dma_handler: None,
-            })
-        } else {
-            Err(DeviceManagerError::NoVirtioFsSock)
-        }
+        // Update the device tree with the migratable device.
+        node.migratable = Some(Arc::clone(&virtio_fs_device) as Arc<Mutex<dyn Migratable>>);
+        self.device_tree.lock().unwrap().insert(id.clone(), node);
+
+        Ok(MetaVirtioDevice {
+            virtio_device: Arc::clone(&virtio_fs_device)
+                as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
+            iommu: false,
+            id,
+            pci_segment: fs_cfg.pci_segment,
+            dma_handler: None,
+        })
     }
 
     fn make_virtio_fs_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
@@ -2442,7 +2760,7 @@ impl DeviceManager {
         let mut fs_devices = self.config.lock().unwrap().fs.clone();
         if let Some(fs_list_cfg) = &mut fs_devices {
             for fs_cfg in fs_list_cfg.iter_mut() {
-                devices.push(self.make_virtio_fs_device(fs_cfg)?);
+                devices.push(self.make_virtio_fs_device(fs_cfg, None)?);
             }
         }
         self.config.lock().unwrap().fs = fs_devices;
@@ -2701,6 +3019,92 @@ impl DeviceManager {
         Ok(devices)
     }
 
+    fn make_virtio_serial_device(
+        &mut self,
+        serial_cfg: &mut SerialDeviceConfig,
+    ) -> DeviceManagerResult<MetaVirtioDevice> {
+        let id = if let Some(id) = &serial_cfg.id {
+            id.clone()
+        } else {
+            let id = self.next_device_name(SERIAL_DEVICE_NAME_PREFIX)?;
+            serial_cfg.id = Some(id.clone());
+            id
+        };
+
+        info!("Creating virtio-console serial device: {:?}", serial_cfg);
+
+        let endpoint = match serial_cfg.mode {
+            ConsoleOutputMode::File => {
+                let file = File::create(serial_cfg.file.as_ref().unwrap())
+                    .map_err(DeviceManagerError::ConsoleOutputFileOpen)?;
+                Endpoint::File(file)
+            }
+            ConsoleOutputMode::Pty => {
+                // A new PTY is created on every boot, its path being reported
+                // through the device configuration.
+                let (main, mut sub, path) =
+                    create_pty(false).map_err(DeviceManagerError::ConsolePtyOpen)?;
+                self.set_raw_mode(&mut sub)
+                    .map_err(DeviceManagerError::SetPtyRaw)?;
+                serial_cfg.file = Some(path.clone());
+                let file = main.try_clone().unwrap();
+                self.serial_device_ptys.insert(
+                    id.clone(),
+                    Arc::new(Mutex::new(PtyPair { main, sub, path })),
+                );
+                Endpoint::FilePair(file.try_clone().unwrap(), file)
+            }
+            ConsoleOutputMode::Null => Endpoint::Null,
+            ConsoleOutputMode::Off | ConsoleOutputMode::Tty => {
+                return Err(DeviceManagerError::InvalidSerialDeviceMode)
+            }
+        };
+
+        let (virtio_console_device, _) = virtio_devices::Console::new(
+            id.clone(),
+            endpoint,
+            None,
+            self.force_iommu | serial_cfg.iommu,
+            self.seccomp_action.clone(),
+            self.exit_evt
+                .try_clone()
+                .map_err(DeviceManagerError::EventFd)?,
+        )
+        .map_err(DeviceManagerError::CreateVirtioConsole)?;
+        let virtio_console_device = Arc::new(Mutex::new(virtio_console_device));
+
+        // Fill the device tree with a new node. In case of restore, we
+        // know there is nothing to do, so we can simply override the
+        // existing entry.
+        self.device_tree
+            .lock()
+            .unwrap()
+            .insert(id.clone(), device_node!(id, virtio_console_device));
+
+        Ok(MetaVirtioDevice {
+            virtio_device: Arc::clone(&virtio_console_device)
+                as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
+            iommu: serial_cfg.iommu,
+            id,
+            pci_segment: serial_cfg.pci_segment,
+            dma_handler: None,
+        })
+    }
+
+    fn make_virtio_serial_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
+        let mut devices = Vec::new();
+
+        let mut serial_devices = self.config.lock().unwrap().serial_devices.clone();
+        if let Some(serial_device_list_cfg) = &mut serial_devices {
+            for serial_device_cfg in serial_device_list_cfg.iter_mut() {
+                devices.push(self.make_virtio_serial_device(serial_device_cfg)?);
+            }
+        }
+        self.config.lock().unwrap().serial_devices = serial_devices;
+
+        Ok(devices)
+    }
+
     fn make_virtio_mem_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
         let mut devices = Vec::new();
 
@@ -2770,6 +3174,7 @@ impl DeviceManager {
                     balloon_config.size,
                     balloon_config.deflate_on_oom,
                     balloon_config.free_page_reporting,
+                    balloon_config.statistics,
                     self.seccomp_action.clone(),
                     self.exit_evt
                         .try_clone()
@@ -2828,6 +3233,7 @@ impl DeviceManager {
             dma_handler: None,
         });
 
+        self.watchdog = Some(virtio_watchdog_device.clone());
         self.device_tree
             .lock()
             .unwrap()
@@ -3517,6 +3923,56 @@ impl DeviceManager {
         &self.pci_segments
     }
 
+    /// Find the PCI device the guest sees at `bdf`, if any.
+    pub fn device_by_bdf(&self, bdf: PciBdf) -> Option<DeviceSummary> {
+        let device_tree = self.device_tree.lock().unwrap();
+        let node = device_tree
+            .pci_devices()
+            .into_iter()
+            .find(|node| node.pci_bdf == Some(bdf))?;
+
+        let (id, device_type) = match node.pci_device_handle.as_ref()? {
+            PciDeviceHandle::Virtio(virtio_pci_device) => {
+                let device_type = VirtioDeviceType::from(
+                    virtio_pci_device
+                        .lock()
+                        .unwrap()
+                        .virtio_device()
+                        .lock()
+                        .unwrap()
+                        .device_type(),
+                );
+                let id = if node.children.len() == 1 {
+                    node.children[0].clone()
+                } else {
+                    node.id.clone()
+                };
+                (id, device_type.to_string())
+            }
+            PciDeviceHandle::Vfio(_) => (node.id.clone(), "vfio".to_string()),
+            PciDeviceHandle::VfioUser(_) => (node.id.clone(), "vfio-user".to_string()),
+        };
+
+        Some(DeviceSummary {
+            id,
+            bdf,
+            device_type,
+        })
+    }
+
+    /// Number of free PCI slots on each segment, indexed by segment id.
+    pub fn pci_free_slots(&self) -> Vec<usize> {
+        self.pci_segments
+            .iter()
+            .map(|segment| segment.pci_bus.lock().unwrap().free_device_ids())
+            .collect()
+    }
+
+    /// Seccomp action used by the threads of the devices added from now on.
+    pub fn set_seccomp_action(&mut self, seccomp_action: SeccompAction) {
+        self.seccomp_action = seccomp_action;
+    }
+
     pub fn console(&self) -> &Arc<Console> {
         &self.console
     }
@@ -3578,11 +4034,32 @@ impl DeviceManager {
         Ok(())
     }
 
+    pub fn set_activation_policy(&mut self, policy: Option<ActivationPolicy>) {
+        self.activation_policy = policy;
+    }
+
     pub fn activate_virtio_devices(&self) -> DeviceManagerResult<()> {
-        for mut activator in self.pending_activations.lock().unwrap().drain(..) {
-            activator
-                .activate()
-                .map_err(DeviceManagerError::VirtioActivate)?;
+        let mut pending_activations = self.pending_activations.lock().unwrap();
+        for mut activator in std::mem::take(&mut *pending_activations) {
+            let decision = self
+                .activation_policy
+                .as_ref()
+                .map(|policy| policy(activator.id()))
+                .unwrap_or(ActivationDecision::Approve);
+
+            match decision {
+                ActivationDecision::Approve => activator
+                    .activate()
+                    .map_err(DeviceManagerError::VirtioActivate)?,
+                ActivationDecision::Deny => {
+                    warn!("Activation of virtio device {} denied", activator.id());
+                    activator.abort();
+                }
+                ActivationDecision::Defer => {
+                    info!("Activation of virtio device {} deferred", activator.id());
+                    pending_activations.push(activator);
+                }
+            }
         }
         Ok(())
     }
@@ -3639,6 +4116,51 @@ impl DeviceManager {
         })
     }
 
+    /// Whether the guest driver has completed the initialization of the
+    /// virtio device `id`, which can either identify the virtio device or
+    /// the virtio-pci device exposing it.
+    pub fn virtio_device_ready(&self, id: &str) -> DeviceManagerResult<bool> {
+        let device_tree = self.device_tree.lock().unwrap();
+        let node = device_tree
+            .get(id)
+            .ok_or_else(|| DeviceManagerError::UnknownDeviceId(id.to_string()))?;
+
+        let pci_device_node = if node.pci_device_handle.is_some() {
+            node
+        } else {
+            let parent = node
+                .parent
+                .as_ref()
+                .ok_or_else(|| DeviceManagerError::NotVirtioDevice(id.to_string()))?;
+            device_tree
+                .get(parent)
+                .ok_or(DeviceManagerError::MissingNode)?
+        };
+
+        match &pci_device_node.pci_device_handle {
+            Some(PciDeviceHandle::Virtio(virtio_pci_device)) => {
+                Ok(virtio_pci_device.lock().unwrap().is_driver_ready())
+            }
+            _ => Err(DeviceManagerError::NotVirtioDevice(id.to_string())),
+        }
+    }
+
+    /// Ask the vhost-user device `id` to reconnect to its backend.
+    pub fn reconnect_vhost_user(&self, id: &str) -> DeviceManagerResult<()> {
+        let handle = self
+            .virtio_devices
+            .iter()
+            .find(|handle| handle.id == id)
+            .ok_or_else(|| DeviceManagerError::UnknownDeviceId(id.to_string()))?;
+
+        handle
+            .virtio_device
+            .lock()
+            .unwrap()
+            .reconnect_backend()
+            .map_err(DeviceManagerError::ReconnectVhostUser)
+    }
+
     pub fn remove_device(&mut self, id: String) -> DeviceManagerResult<()> {
         // The node can be directly a PCI node in case the 'id' refers to a
         // VFIO device or a virtio-pci one.
@@ -3647,7 +4169,7 @@ impl DeviceManager {
         let device_tree = self.device_tree.lock().unwrap();
         let node = device_tree
             .get(&id)
-            .ok_or(DeviceManagerError::UnknownDeviceId(id))?;
+            .ok_or_else(|| DeviceManagerError::UnknownDeviceId(id.clone()))?;
 
         let pci_device_node = if node.pci_bdf.is_some() && node.pci_device_handle.is_some() {
             node
@@ -3687,6 +4209,9 @@ impl DeviceManager {
                 | VirtioDeviceType::Pmem
                 | VirtioDeviceType::Fs
                 | VirtioDeviceType::Vsock => {}
+                // Only the serial devices added at runtime can be removed,
+                // not the default virtio-console.
+                VirtioDeviceType::Console if id != CONSOLE_DEVICE_NAME => {}
                 _ => return Err(DeviceManagerError::RemovalNotAllowed(device_type)),
             }
         }
@@ -3694,6 +4219,8 @@ impl DeviceManager {
         // Update the PCID bitmap
         self.pci_segments[pci_segment_id as usize].pci_devices_down |= 1 << pci_device_bdf.device();
 
+        self.serial_device_ptys.remove(&id);
+
         Ok(())
     }
 
@@ -3950,7 +4477,20 @@ impl DeviceManager {
     pub fn add_fs(&mut self, fs_cfg: &mut FsConfig) -> DeviceManagerResult<PciDeviceInfo> {
         self.validate_identifier(&fs_cfg.id)?;
 
-        let device = self.make_virtio_fs_device(fs_cfg)?;
+        let device = self.make_virtio_fs_device(fs_cfg, None)?;
+        self.hotplug_virtio_pci_device(device)
+    }
+
+    /// Hotplug a virtio-fs device driven by the backend reached through
+    /// `socket`, already connected by the caller.
+    pub fn add_fs_with_socket(
+        &mut self,
+        fs_cfg: &mut FsConfig,
+        socket: UnixStream,
+    ) -> DeviceManagerResult<PciDeviceInfo> {
+        self.validate_identifier(&fs_cfg.id)?;
+
+        let device = self.make_virtio_fs_device(fs_cfg, Some(socket))?;
         self.hotplug_virtio_pci_device(device)
     }
 
@@ -3998,6 +4538,42 @@ impl DeviceManager {
         self.hotplug_virtio_pci_device(device)
     }
 
+    pub fn add_serial(
+        &mut self,
+        serial_cfg: &mut SerialDeviceConfig,
+    ) -> DeviceManagerResult<PciDeviceInfo> {
+        self.validate_identifier(&serial_cfg.id)?;
+
+        if serial_cfg.iommu && !self.is_iommu_segment(serial_cfg.pci_segment) {
+            return Err(DeviceManagerError::InvalidIommuHotplug);
+        }
+
+        let device = self.make_virtio_serial_device(serial_cfg)?;
+        self.hotplug_virtio_pci_device(device)
+    }
+
+    /// Devices accessing the guest memory through mappings of their own,
+    /// outside of the VMM: vhost-user and vDPA devices, and VFIO devices
+    /// through their DMA mappings.
+    pub fn guest_memory_sharers(&self) -> Vec<String> {
+        let mut sharers: Vec<String> = self
+            .virtio_devices
+            .iter()
+            .filter(|handle| handle.virtio_device.lock().unwrap().shares_guest_memory())
+            .map(|handle| handle.id.clone())
+            .collect();
+        for (id, node) in self.device_tree.lock().unwrap().iter() {
+            if matches!(
+                node.pci_device_handle,
+                Some(PciDeviceHandle::Vfio(_)) | Some(PciDeviceHandle::VfioUser(_))
+            ) {
+                sharers.push(id.clone());
+            }
+        }
+
+        sharers
+    }
+
     pub fn counters(&self) -> HashMap<String, HashMap<&'static str, Wrapping<u64>>> {
         let mut counters = HashMap::new();
 
@@ -4024,6 +4600,27 @@ impl DeviceManager {
         Err(DeviceManagerError::MissingVirtioBalloon)
     }
 
+    pub fn set_watchdog_timeout(&self, timeout: Duration) -> DeviceManagerResult<()> {
+        self.watchdog
+            .as_ref()
+            .ok_or(DeviceManagerError::MissingVirtioWatchdog)?
+            .lock()
+            .unwrap()
+            .set_timeout(timeout)
+            .map_err(DeviceManagerError::VirtioWatchdogTimeout)
+    }
+
+    /// Ping the watchdog, returning whether it is running.
+    pub fn pet_watchdog(&self) -> DeviceManagerResult<bool> {
+        Ok(self
+            .watchdog
+            .as_ref()
+            .ok_or(DeviceManagerError::MissingVirtioWatchdog)?
+            .lock()
+            .unwrap()
+            .pet())
+    }
+
     pub fn balloon_size(&self) -> u64 {
         if let Some(balloon) = &self.balloon {
             return balloon.lock().unwrap().get_actual();
@@ -4032,10 +4629,25 @@ impl DeviceManager {
         0
     }
 
+    /// Latest memory statistics reported by the guest through the balloon,
+    /// asking the guest to refresh them along the way.
+    pub fn balloon_statistics(&self) -> Option<virtio_devices::BalloonStatistics> {
+        let balloon = self.balloon.as_ref()?.lock().unwrap();
+        if let Err(e) = balloon.request_statistics() {
+            warn!("Error requesting balloon statistics: {:?}", e);
+        }
+
+        balloon.statistics()
+    }
+
     pub fn device_tree(&self) -> Arc<Mutex<DeviceTree>> {
         self.device_tree.clone()
     }
 
+    pub fn boot_id_list(&self) -> &BTreeSet<String> {
+        &self.boot_id_list
+    }
+
     pub fn restore_devices(
         &mut self,
         snapshot: Snapshot,
@@ -4073,6 +4685,160 @@ impl DeviceManager {
         Ok(())
     }
 
+    /// Whether `snapshot` holds the state of the very same devices as the
+    /// ones currently attached, meaning they can be restored in place with
+    /// `restore_devices()`.
+    pub fn snapshot_compatible(&self, snapshot: &Snapshot) -> bool {
+        let device_tree = self.device_tree.lock().unwrap();
+        let migratable_devices = device_tree
+            .iter()
+            .filter(|(_, node)| node.migratable.is_some())
+            .count();
+
+        migratable_devices == snapshot.snapshots.len()
+            && snapshot
+                .snapshots
+                .keys()
+                .all(|id| matches!(device_tree.get(id), Some(node) if node.migratable.is_some()))
+    }
+
+    /// Create the devices from the configuration, with their initial state,
+    /// when restoring from a snapshot which doesn't include any device state.
+    pub fn create_devices_for_restore(&mut self) -> DeviceManagerResult<()> {
+        self.restoring = false;
+        self.create_devices(None, Vec::new(), None, None)
+    }
+
+    /// Describe which devices the legacy interrupt lines and the GSI routes
+    /// programmed into the hypervisor belong to.
+    pub fn interrupt_routing(&self) -> IrqRoutingInfo {
+        let mut legacy_irqs: Vec<LegacyIrqInfo> = self
+            .legacy_irqs
+            .iter()
+            .map(|(id, irq)| LegacyIrqInfo {
+                irq: *irq,
+                device: id.clone(),
+            })
+            .collect();
+
+        // Map each PCI device b/d/f to its identifier, using the identifier
+        // of the underlying virtio device for virtio-pci devices.
+        let mut pci_devices: HashMap<u32, String> = HashMap::new();
+        for node in self.device_tree.lock().unwrap().pci_devices() {
+            let pci_bdf = node.pci_bdf.unwrap();
+            let id = match node.pci_device_handle {
+                Some(PciDeviceHandle::Virtio(_)) if node.children.len() == 1 => {
+                    node.children[0].clone()
+                }
+                _ => node.id.clone(),
+            };
+            if let Some(segment) = self.pci_segments.get(pci_bdf.segment() as usize) {
+                legacy_irqs.push(LegacyIrqInfo {
+                    irq: segment.pci_irq_slots[pci_bdf.device() as usize] as u32,
+                    device: id.clone(),
+                });
+            }
+            pci_devices.insert(pci_bdf.into(), id);
+        }
+        legacy_irqs.sort_by(|a, b| (a.irq, &a.device).cmp(&(b.irq, &b.device)));
+
+        let mut gsi_routes = self.gsi_routing.gsi_routes();
+        for route in gsi_routes.iter_mut() {
+            route.device = match route.kind {
+                GsiRouteKind::Msi { devid, .. } => pci_devices.get(&devid).cloned(),
+                // On AArch64 the GIC routes its SPIs by pin number.
+                #[cfg(target_arch = "aarch64")]
+                GsiRouteKind::Irqchip { pin, .. } => {
+                    legacy_irq_device(&self.legacy_irqs, pin + arch::layout::IRQ_BASE)
+                }
+                #[cfg(target_arch = "x86_64")]
+                GsiRouteKind::Irqchip { .. } => None,
+            };
+        }
+
+        IrqRoutingInfo {
+            #[cfg(target_arch = "x86_64")]
+            controller: InterruptControllerType::Ioapic,
+            #[cfg(target_arch = "aarch64")]
+            controller: InterruptControllerType::Gic,
+            legacy_irqs,
+            gsi_routes,
+        }
+    }
+
+    /// Describe the address ranges registered on the PIO or MMIO bus, sorted
+    /// by base address, along with the device owning each of them.
+    pub fn bus_address_map(&self, space: IoSpace) -> Vec<BusRangeInfo> {
+        let (ranges, mut owners) = match space {
+            #[cfg(target_arch = "x86_64")]
+            IoSpace::Pio => (
+                self.address_manager.io_bus.ranges(),
+                self.io_bus_names.clone(),
+            ),
+            // There is no PIO bus on AArch64.
+            #[cfg(target_arch = "aarch64")]
+            IoSpace::Pio => return Vec::new(),
+            IoSpace::Mmio => (
+                self.address_manager.mmio_bus.ranges(),
+                self.mmio_bus_names.clone(),
+            ),
+        };
+
+        for segment in &self.pci_segments {
+            if space == IoSpace::Mmio {
+                owners.insert(
+                    segment.mmio_config_address,
+                    format!("__pci_config_mmio{}", segment.id),
+                );
+            }
+            #[cfg(target_arch = "x86_64")]
+            if space == IoSpace::Pio && segment.pci_config_io.is_some() {
+                owners.insert(pci::PCI_CONFIG_IO_PORT, "__pci_config_io".to_string());
+            }
+        }
+
+        // BARs of virtio-pci devices are reported under the identifier of
+        // the virtio device, as for the interrupt routing.
+        for (_, node) in self.device_tree.lock().unwrap().iter() {
+            let id = match node.pci_device_handle {
+                Some(PciDeviceHandle::Virtio(_)) if node.children.len() == 1 => &node.children[0],
+                _ => &node.id,
+            };
+            for resource in node.resources.iter() {
+                let base = match (space, resource) {
+                    (IoSpace::Pio, Resource::PioAddressRange { base, .. }) => *base as u64,
+                    (IoSpace::Pio, Resource::PciBar { base, type_, .. })
+                        if *type_ == PciBarType::Io =>
+                    {
+                        *base
+                    }
+                    (IoSpace::Mmio, Resource::MmioAddressRange { base, .. }) => *base,
+                    (IoSpace::Mmio, Resource::PciBar { base, type_, .. })
+                        if *type_ != PciBarType::Io =>
+                    {
+                        *base
+                    }
+                    _ => continue,
+                };
+                owners.insert(base, id.clone());
+            }
+        }
+
+        ranges
+            .into_iter()
+            .map(|range| BusRangeInfo {
+                base: range.base,
+                size: range.len,
+                device: owners.get(&range.base).cloned(),
+            })
+            .collect()
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    pub fn pvpanic_device(&self) -> Option<&Arc<Mutex<devices::legacy::PvPanic>>> {
+        self.pvpanic_device.as_ref()
+    }
+
     #[cfg(target_arch = "x86_64")]
     pub fn notify_power_button(&self) -> DeviceManagerResult<()> {
         self.ged_notification_device
@@ -4293,6 +5059,47 @@ impl Aml for DeviceManager {
             .append_aml_bytes(bytes);
         }
 
+        #[cfg(target_arch = "x86_64")]
+        for (index, io_port, irq) in self.extra_serial_resources.iter() {
+            let io_port = *io_port as u16;
+            let com = format!("COM{}", index + 2);
+            aml::Device::new(
+                format!("_SB_.{}", com).as_str().into(),
+                vec![
+                    &aml::Name::new("_HID".into(), &aml::EisaName::new("PNP0501")),
+                    &aml::Name::new("_UID".into(), &(*index as u32 + 1)),
+                    &aml::Name::new("_DDN".into(), &com),
+                    &aml::Name::new(
+                        "_CRS".into(),
+                        &aml::ResourceTemplate::new(vec![
+                            &aml::Interrupt::new(true, true, false, false, *irq),
+                            &aml::Io::new(io_port, io_port, 0, 0x8),
+                        ]),
+                    ),
+                ],
+            )
+            .append_aml_bytes(bytes);
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        aml::Device::new(
+            "_SB_.PEVT".into(),
+            vec![
+                &aml::Name::new("_HID".into(), &"QEMU0001"),
+                &aml::Name::new("_STA".into(), &0x0bu8),
+                &aml::Name::new(
+                    "_CRS".into(),
+                    &aml::ResourceTemplate::new(vec![&aml::Io::new(
+                        PVPANIC_IO_PORT as u16,
+                        PVPANIC_IO_PORT as u16,
+                        1,
+                        0x1,
+                    )]),
+                ),
+            ],
+        )
+        .append_aml_bytes(bytes);
+
         aml::Name::new("_S5_".into(), &aml::Package::new(vec![&5u8])).append_aml_bytes(bytes);
 
         aml::Device::new(
@@ -4378,7 +5185,7 @@ impl Snapshottable for DeviceManager {
 
         // Now that DeviceManager is updated with the right states, it's time
         // to create the devices based on the configuration.
-        self.create_devices(None, None, None)
+        self.create_devices(None, Vec::new(), None, None)
             .map_err(|e| MigratableError::Restore(anyhow!("Could not create devices {:?}", e)))?;
 
         Ok(())
@@ -4542,3 +5349,39 @@ impl Drop for DeviceManager {
         }
     }
 }
+
+// Identifier of the platform device a legacy IRQ is assigned to.
+#[cfg(target_arch = "aarch64")]
+fn legacy_irq_device(legacy_irqs: &BTreeMap<String, u32>, irq: u32) -> Option<String> {
+    legacy_irqs
+        .iter()
+        .find(|(_, device_irq)| **device_irq == irq)
+        .map(|(id, _)| id.clone())
+}
+
+#[cfg(target_arch = "aarch64")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_irq_device() {
+        let legacy_irqs: BTreeMap<String, u32> = [
+            (RTC_DEVICE_NAME.to_string(), 33),
+            (GPIO_DEVICE_NAME.to_string(), 34),
+            (SERIAL_DEVICE_NAME.to_string(), 35),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            legacy_irq_device(&legacy_irqs, 33).as_deref(),
+            Some(RTC_DEVICE_NAME)
+        );
+        assert_eq!(
+            legacy_irq_device(&legacy_irqs, 34).as_deref(),
+            Some(GPIO_DEVICE_NAME)
+        );
+        assert_eq!(legacy_irq_device(&legacy_irqs, 36), None);
+    }
+}
@@ -5,7 +5,7 @@
 use crate::device_manager::PciDeviceHandle;
 use pci::PciBdf;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::sync::{Arc, Mutex};
 use vm_device::Resource;
 use vm_migration::Migratable;
@@ -50,12 +50,14 @@ macro_rules! device_node {
     };
 }
 
+// Sorted by identifier, so that the tree and the device snapshots built
+// from it are the same from one run to another.
 #[derive(Clone, Default, Serialize, Deserialize)]
-pub struct DeviceTree(HashMap<String, DeviceNode>);
+pub struct DeviceTree(BTreeMap<String, DeviceNode>);
 
 impl DeviceTree {
     pub fn new() -> Self {
-        DeviceTree(HashMap::new())
+        DeviceTree(BTreeMap::new())
     }
     pub fn contains_key(&self, k: &str) -> bool {
         self.0.contains_key(k)
@@ -72,7 +74,7 @@ impl DeviceTree {
     pub fn remove(&mut self, k: &str) -> Option<DeviceNode> {
         self.0.remove(k)
     }
-    pub fn iter(&self) -> std::collections::hash_map::Iter<String, DeviceNode> {
+    pub fn iter(&self) -> std::collections::btree_map::Iter<String, DeviceNode> {
         self.0.iter()
     }
     pub fn breadth_first_traversal(&self) -> BftIter {
@@ -108,10 +110,10 @@ pub struct BftIter<'a> {
 }
 
 impl<'a> BftIter<'a> {
-    fn new(hash_map: &'a HashMap<String, DeviceNode>) -> Self {
+    fn new(nodes_map: &'a BTreeMap<String, DeviceNode>) -> Self {
         let mut nodes = Vec::new();
 
-        for (_, node) in hash_map.iter() {
+        for (_, node) in nodes_map.iter() {
             if node.parent.is_none() {
                 nodes.push(node);
             }
@@ -123,7 +125,7 @@ impl<'a> BftIter<'a> {
 
             for node in node_layer.iter() {
                 for child_node_id in node.children.iter() {
-                    if let Some(child_node) = hash_map.get(child_node_id) {
+                    if let Some(child_node) = nodes_map.get(child_node_id) {
                         next_node_layer.push(child_node);
                     }
                 }
@@ -164,6 +166,7 @@ impl<'a> DoubleEndedIterator for BftIter<'a> {
 #[cfg(test)]
 mod tests {
     use super::{DeviceNode, DeviceTree};
+    use vm_migration::{Snapshot, SnapshotDataSection};
 
     #[test]
     fn test_device_tree() {
@@ -280,4 +283,42 @@ mod tests {
         assert_eq!(iter_vec[1].id, child_2_id);
         assert_eq!(iter_vec[0].id, child_3_id);
     }
+
+    #[test]
+    fn test_deterministic_serialization() {
+        let ids: Vec<String> = (0..32).map(|i| format!("_disk{}", i)).collect();
+
+        // Build the same device tree and snapshot, adding the devices in
+        // opposite orders.
+        fn build<'a>(ids: impl Iterator<Item = &'a String>) -> (Vec<u8>, Vec<u8>) {
+            let mut device_tree = DeviceTree::new();
+            let mut snapshot = Snapshot::new("device-manager");
+            for id in ids {
+                device_tree.insert(id.clone(), device_node!(id));
+
+                let mut device_snapshot = Snapshot::new(id);
+                device_snapshot.add_data_section(SnapshotDataSection {
+                    id: format!("{}-section", id),
+                    snapshot: id.as_bytes().to_vec(),
+                });
+                snapshot.add_snapshot(device_snapshot);
+                snapshot.add_data_section(SnapshotDataSection {
+                    id: format!("{}-extra-section", id),
+                    snapshot: Vec::new(),
+                });
+            }
+            (
+                serde_json::to_vec(&device_tree).unwrap(),
+                serde_json::to_vec(&snapshot).unwrap(),
+            )
+        }
+
+        let (tree, snapshot) = build(ids.iter());
+        let (reversed_tree, reversed_snapshot) = build(ids.iter().rev());
+        assert_eq!(tree, reversed_tree);
+        assert_eq!(snapshot, reversed_snapshot);
+
+        // Serializing the same state twice gives the same bytes.
+        assert_eq!(build(ids.iter()), (tree, snapshot));
+    }
 }
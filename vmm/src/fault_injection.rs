@@ -0,0 +1,118 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Injection of faults into the guest memory accesses the VMM performs on
+//! behalf of the vCPUs, such as instruction emulation. This is only meant to
+//! exercise the error paths of these accesses from tests, and is not built
+//! unless the `fault_injection` feature is enabled.
+
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+struct MemoryFault {
+    range: Range<u64>,
+    probability: f64,
+}
+
+// Fails the guest memory accesses overlapping the configured ranges. When no
+// fault is configured, the only cost on the access path is the atomic load
+// done by enabled().
+pub(crate) struct MemoryFaultInjector {
+    enabled: AtomicBool,
+    faults: Mutex<Vec<MemoryFault>>,
+    // xorshift64* state, the quality of the randomness not mattering here.
+    rng: AtomicU64,
+}
+
+impl Default for MemoryFaultInjector {
+    fn default() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        MemoryFaultInjector {
+            enabled: AtomicBool::new(false),
+            faults: Mutex::new(Vec::new()),
+            rng: AtomicU64::new(seed | 1),
+        }
+    }
+}
+
+impl MemoryFaultInjector {
+    #[inline]
+    pub(crate) fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Fail the accesses to `range` with the given `probability`, replacing
+    /// any fault previously injected for the same range. A probability of 0
+    /// removes the fault.
+    pub(crate) fn inject(&self, range: Range<u64>, probability: f64) {
+        let mut faults = self.faults.lock().unwrap();
+        faults.retain(|f| f.range != range);
+        if probability > 0.0 {
+            faults.push(MemoryFault { range, probability });
+        }
+        self.enabled.store(!faults.is_empty(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn clear(&self) {
+        self.faults.lock().unwrap().clear();
+        self.enabled.store(false, Ordering::Relaxed);
+    }
+
+    fn random(&self) -> f64 {
+        let mut x = self.rng.load(Ordering::Relaxed);
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.rng.store(x, Ordering::Relaxed);
+        (x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Whether the access of `len` bytes at `gpa` must fail.
+    pub(crate) fn should_fail(&self, gpa: u64, len: usize) -> bool {
+        let end = gpa.saturating_add(len as u64);
+        let faults = self.faults.lock().unwrap();
+        faults
+            .iter()
+            .filter(|f| f.range.start < end && gpa < f.range.end)
+            .any(|f| f.probability >= 1.0 || self.random() < f.probability)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_fault_injection() {
+        let injector = MemoryFaultInjector::default();
+        assert!(!injector.enabled());
+
+        injector.inject(0x1000..0x2000, 1.0);
+        assert!(injector.enabled());
+        assert!(injector.should_fail(0x1800, 4));
+        // Accesses straddling the range fail as well.
+        assert!(injector.should_fail(0xffc, 8));
+        assert!(!injector.should_fail(0xff8, 8));
+        assert!(!injector.should_fail(0x2000, 4));
+
+        // Roughly half of the accesses fail.
+        injector.inject(0x1000..0x2000, 0.5);
+        let failed = (0..1000)
+            .filter(|_| injector.should_fail(0x1000, 4))
+            .count();
+        assert!(failed > 350 && failed < 650);
+
+        injector.inject(0x1000..0x2000, 0.0);
+        assert!(!injector.enabled());
+        assert!(!injector.should_fail(0x1000, 4));
+
+        injector.inject(0x3000..0x4000, 1.0);
+        injector.clear();
+        assert!(!injector.enabled());
+    }
+}
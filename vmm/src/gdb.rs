@@ -9,6 +9,7 @@ use gdbstub::{
     arch::Arch,
     common::{Signal, Tid},
     conn::{Connection, ConnectionExt},
+    outputln,
     stub::{run_blocking, DisconnectReason, MultiThreadStopReason},
     target::{
         ext::{
@@ -20,6 +21,7 @@ use gdbstub::{
                 BaseOps,
             },
             breakpoints::{Breakpoints, BreakpointsOps, HwBreakpoint, HwBreakpointOps},
+            monitor_cmd::{ConsoleOutput, MonitorCmd, MonitorCmdOps},
         },
         Target, TargetError, TargetResult,
     },
@@ -54,6 +56,14 @@ pub trait Debuggable: vm_migration::Pausable {
         addrs: &[GuestAddress],
         singlestep: bool,
     ) -> Result<(), DebuggableError>;
+    /// Apply the same breakpoints and single stepping to every vCPU, as well
+    /// as to the vCPUs created while they're set. Passing no address and no
+    /// single stepping clears them.
+    fn set_guest_debug_all(
+        &mut self,
+        addrs: &[GuestAddress],
+        singlestep: bool,
+    ) -> Result<(), DebuggableError>;
     fn debug_pause(&mut self) -> std::result::Result<(), DebuggableError>;
     fn debug_resume(&mut self) -> std::result::Result<(), DebuggableError>;
     fn read_regs(&self, cpu_id: usize) -> std::result::Result<CoreRegs, DebuggableError>;
@@ -100,6 +110,10 @@ pub enum GdbRequestPayload {
     WriteRegs(Box<CoreRegs>),
     ReadMem(GuestAddress, usize),
     WriteMem(GuestAddress, Vec<u8>),
+    // Access guest memory by physical address, bypassing the translation
+    // through the vCPU page tables.
+    ReadPhysMem(GuestAddress, usize),
+    WritePhysMem(GuestAddress, Vec<u8>),
     Pause,
     Resume,
     SetSingleStep(bool),
@@ -175,6 +189,11 @@ impl Target for GdbStub {
         Some(self)
     }
 
+    #[inline(always)]
+    fn support_monitor_cmd(&mut self) -> Option<MonitorCmdOps<Self>> {
+        Some(self)
+    }
+
     #[inline(always)]
     fn guard_rail_implicit_sw_breakpoints(&self) -> bool {
         true
@@ -370,6 +389,72 @@ impl MultiThreadSingleStep for GdbStub {
     }
 }
 
+fn parse_u64(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn parse_hex_bytes(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+const MONITOR_HELP: &str = "Supported commands:
+  phys-read <gpa> <len>     read guest memory by physical address
+  phys-write <gpa> <hex>    write hex encoded bytes to guest memory by physical address";
+
+impl MonitorCmd for GdbStub {
+    fn handle_monitor_cmd(
+        &mut self,
+        cmd: &[u8],
+        mut out: ConsoleOutput<'_>,
+    ) -> Result<(), Self::Error> {
+        let cmd = String::from_utf8_lossy(cmd);
+        let args: Vec<&str> = cmd.split_whitespace().collect();
+        let payload = match args.as_slice() {
+            ["phys-read", addr, len] => match (parse_u64(addr), parse_u64(len)) {
+                (Some(addr), Some(len)) => {
+                    GdbRequestPayload::ReadPhysMem(GuestAddress(addr), len as usize)
+                }
+                _ => {
+                    outputln!(out, "Invalid address or length");
+                    return Ok(());
+                }
+            },
+            ["phys-write", addr, data] => match (parse_u64(addr), parse_hex_bytes(data)) {
+                (Some(addr), Some(data)) => {
+                    GdbRequestPayload::WritePhysMem(GuestAddress(addr), data)
+                }
+                _ => {
+                    outputln!(out, "Invalid address or data");
+                    return Ok(());
+                }
+            },
+            _ => {
+                outputln!(out, "{}", MONITOR_HELP);
+                return Ok(());
+            }
+        };
+
+        match self.vm_request(payload, 0) {
+            Ok(GdbResponsePayload::MemoryRegion(r)) => {
+                let hex: String = r.iter().map(|b| format!("{:02x}", b)).collect();
+                outputln!(out, "{}", hex);
+            }
+            Ok(_) => outputln!(out, "OK"),
+            Err(e) => outputln!(out, "Failed to access guest memory: {:?}", e),
+        }
+        Ok(())
+    }
+}
+
 impl Breakpoints for GdbStub {
     #[inline(always)]
     fn support_hw_breakpoint(&mut self) -> Option<HwBreakpointOps<Self>> {
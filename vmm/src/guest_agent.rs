@@ -0,0 +1,241 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Host side of a minimal guest agent protocol, running over virtio-vsock.
+//!
+//! The VMM connects to the agent listening on `GUEST_AGENT_VSOCK_PORT`
+//! through the vsock Unix socket, then exchanges one JSON object per line.
+//! Every request carries the protocol version, a request identifier and the
+//! command to run. The agent answers with the same version and identifier,
+//! and either some optional `data` or an `error` message.
+
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::result;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Version of the protocol spoken with the guest agent.
+pub const GUEST_AGENT_PROTOCOL_VERSION: u32 = 1;
+
+/// Guest vsock port the agent listens on.
+pub const GUEST_AGENT_VSOCK_PORT: u32 = 9000;
+
+/// Default time given to the agent to answer a request.
+pub const GUEST_AGENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("No vsock device to reach the guest agent")]
+    NoVsock,
+
+    #[error("Error connecting to the vsock socket: {0}")]
+    Connect(#[source] io::Error),
+
+    #[error("Error communicating with the guest agent: {0}")]
+    Io(#[source] io::Error),
+
+    #[error("No guest agent listening on vsock port {0}")]
+    NoAgent(u32),
+
+    #[error("Timed out waiting for the guest agent")]
+    Timeout,
+
+    #[error("Error serializing the guest agent request: {0}")]
+    Serialize(#[source] serde_json::Error),
+
+    #[error("Invalid guest agent response: {0}")]
+    InvalidResponse(#[source] serde_json::Error),
+
+    #[error("Unexpected guest agent response: version {version}, id {id}")]
+    UnexpectedResponse { version: u32, id: u64 },
+
+    #[error("Guest agent failed running the command: {0}")]
+    Agent(String),
+}
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GuestAgentCommand {
+    /// Check the agent is alive.
+    Ping,
+    /// Freeze the guest filesystems.
+    FsFreeze,
+    /// Thaw the guest filesystems.
+    FsThaw,
+    /// Flush the guest filesystem buffers.
+    Sync,
+}
+
+#[derive(Serialize)]
+struct GuestAgentRequest {
+    version: u32,
+    id: u64,
+    command: GuestAgentCommand,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct GuestAgentResponse {
+    pub version: u32,
+    pub id: u64,
+    /// Command specific payload, such as the number of frozen filesystems.
+    #[serde(default)]
+    pub data: Option<serde_json::Value>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+fn map_io_error(e: io::Error) -> Error {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Error::Timeout,
+        _ => Error::Io(e),
+    }
+}
+
+/// Send `command` to the guest agent through the vsock Unix socket `socket`,
+/// waiting at most `timeout` for each step of the exchange.
+pub fn request(
+    socket: &Path,
+    port: u32,
+    command: GuestAgentCommand,
+    timeout: Duration,
+) -> Result<GuestAgentResponse> {
+    let mut stream = UnixStream::connect(socket).map_err(Error::Connect)?;
+    stream.set_read_timeout(Some(timeout)).map_err(Error::Io)?;
+    stream.set_write_timeout(Some(timeout)).map_err(Error::Io)?;
+    let mut reader = BufReader::new(stream.try_clone().map_err(Error::Io)?);
+
+    // The vsock device answers "OK <host port>" once the guest accepted the
+    // connection, and closes it if nothing listens on the port.
+    stream
+        .write_all(format!("CONNECT {}\n", port).as_bytes())
+        .map_err(map_io_error)?;
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(map_io_error)?;
+    if !line.starts_with("OK ") {
+        return Err(Error::NoAgent(port));
+    }
+
+    let id = NEXT_REQUEST_ID.fetch_add(1, Ordering::SeqCst);
+    let mut request = serde_json::to_vec(&GuestAgentRequest {
+        version: GUEST_AGENT_PROTOCOL_VERSION,
+        id,
+        command,
+    })
+    .map_err(Error::Serialize)?;
+    request.push(b'\n');
+    stream.write_all(&request).map_err(map_io_error)?;
+
+    line.clear();
+    if reader.read_line(&mut line).map_err(map_io_error)? == 0 {
+        return Err(Error::NoAgent(port));
+    }
+    let response: GuestAgentResponse =
+        serde_json::from_str(&line).map_err(Error::InvalidResponse)?;
+    if response.version != GUEST_AGENT_PROTOCOL_VERSION || response.id != id {
+        return Err(Error::UnexpectedResponse {
+            version: response.version,
+            id: response.id,
+        });
+    }
+    if let Some(error) = response.error {
+        return Err(Error::Agent(error));
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+    use vmm_sys_util::tempdir::TempDir;
+
+    // Play both the vsock device and the guest agent.
+    fn fake_agent(listener: UnixListener, reply: fn(serde_json::Value) -> String) {
+        let (stream, _) = listener.accept().unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, format!("CONNECT {}\n", GUEST_AGENT_VSOCK_PORT));
+        writer.write_all(b"OK 1073741824\n").unwrap();
+
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        let request: serde_json::Value = serde_json::from_str(&line).unwrap();
+        writer.write_all(reply(request).as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_guest_agent_request() {
+        let tmp_dir = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let socket = tmp_dir.as_path().join("vsock");
+
+        let listener = UnixListener::bind(&socket).unwrap();
+        let agent = thread::spawn(move || {
+            fake_agent(listener, |request| {
+                assert_eq!(request["command"], "fsfreeze");
+                format!(
+                    "{{\"version\":1,\"id\":{},\"data\":{{\"frozen\":2}}}}\n",
+                    request["id"]
+                )
+            })
+        });
+        let response = request(
+            &socket,
+            GUEST_AGENT_VSOCK_PORT,
+            GuestAgentCommand::FsFreeze,
+            GUEST_AGENT_TIMEOUT,
+        )
+        .unwrap();
+        agent.join().unwrap();
+        assert_eq!(response.data.unwrap()["frozen"], 2);
+
+        std::fs::remove_file(&socket).unwrap();
+        let listener = UnixListener::bind(&socket).unwrap();
+        let agent = thread::spawn(move || {
+            fake_agent(listener, |request| {
+                format!(
+                    "{{\"version\":1,\"id\":{},\"error\":\"busy\"}}\n",
+                    request["id"]
+                )
+            })
+        });
+        assert!(matches!(
+            request(
+                &socket,
+                GUEST_AGENT_VSOCK_PORT,
+                GuestAgentCommand::Sync,
+                GUEST_AGENT_TIMEOUT,
+            ),
+            Err(Error::Agent(e)) if e == "busy"
+        ));
+        agent.join().unwrap();
+
+        // Nothing listening in the guest, the connection gets closed.
+        std::fs::remove_file(&socket).unwrap();
+        let listener = UnixListener::bind(&socket).unwrap();
+        let agent = thread::spawn(move || {
+            let _ = listener.accept().unwrap();
+        });
+        assert!(matches!(
+            request(
+                &socket,
+                GUEST_AGENT_VSOCK_PORT,
+                GuestAgentCommand::Ping,
+                GUEST_AGENT_TIMEOUT,
+            ),
+            Err(Error::NoAgent(_)) | Err(Error::Io(_))
+        ));
+        agent.join().unwrap();
+    }
+}
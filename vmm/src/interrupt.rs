@@ -5,6 +5,7 @@
 
 use devices::interrupt_controller::InterruptController;
 use hypervisor::IrqRoutingEntry;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::io;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -86,9 +87,69 @@ impl InterruptRoute {
 
 pub struct RoutingEntry {
     route: IrqRoutingEntry,
+    config: InterruptSourceConfig,
     masked: bool,
 }
 
+/// Destination of a GSI route, as programmed into the hypervisor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum GsiRouteKind {
+    /// Routed to a pin of the in-kernel interrupt controller.
+    Irqchip { irqchip: u32, pin: u32 },
+    /// Message signalled interrupt. `devid` holds the PCI b/d/f of the
+    /// device when the message comes from MSI-X.
+    Msi { address: u64, data: u32, devid: u32 },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct GsiRouteInfo {
+    pub gsi: u32,
+    pub kind: GsiRouteKind,
+    pub masked: bool,
+    /// Identifier of the device owning the route, if it could be found.
+    pub device: Option<String>,
+}
+
+/// Legacy, pin based, interrupt line assigned to a device.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LegacyIrqInfo {
+    pub irq: u32,
+    pub device: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum InterruptControllerType {
+    Ioapic,
+    Gic,
+}
+
+/// Interrupt routing of the VM. Legacy lines are wired to the IOAPIC pins
+/// on x86_64 and to the GIC SPIs on AArch64, while `gsi_routes` reflects
+/// the routing table given to the hypervisor. On x86_64 the IOAPIC pins
+/// are themselves delivered as MSIs.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct IrqRoutingInfo {
+    pub controller: InterruptControllerType,
+    pub legacy_irqs: Vec<LegacyIrqInfo>,
+    pub gsi_routes: Vec<GsiRouteInfo>,
+}
+
+impl From<InterruptSourceConfig> for GsiRouteKind {
+    fn from(config: InterruptSourceConfig) -> Self {
+        match config {
+            InterruptSourceConfig::LegacyIrq(cfg) => GsiRouteKind::Irqchip {
+                irqchip: cfg.irqchip,
+                pin: cfg.pin,
+            },
+            InterruptSourceConfig::MsiIrq(cfg) => GsiRouteKind::Msi {
+                address: (cfg.high_addr as u64) << 32 | cfg.low_addr as u64,
+                data: cfg.data,
+                devid: cfg.devid,
+            },
+        }
+    }
+}
+
 pub struct MsiInterruptGroup {
     vm: Arc<dyn hypervisor::Vm>,
     gsi_msi_routes: Arc<Mutex<HashMap<u32, RoutingEntry>>>,
@@ -174,6 +235,7 @@ impl InterruptSourceGroup for MsiInterruptGroup {
         if let Some(route) = self.irq_routes.get(&index) {
             let entry = RoutingEntry {
                 route: self.vm.make_routing_entry(route.gsi, &config),
+                config,
                 masked,
             };
             if masked {
@@ -262,6 +324,26 @@ impl MsiInterruptManager {
             gsi_msi_routes,
         }
     }
+
+    /// List the GSI routes currently known by the manager, sorted by GSI.
+    /// Masked routes are included even though they are not programmed into
+    /// the hypervisor.
+    pub fn gsi_routes(&self) -> Vec<GsiRouteInfo> {
+        let mut routes: Vec<GsiRouteInfo> = self
+            .gsi_msi_routes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(gsi, entry)| GsiRouteInfo {
+                gsi: *gsi,
+                kind: entry.config.into(),
+                masked: entry.masked,
+                device: None,
+            })
+            .collect();
+        routes.sort_by_key(|r| r.gsi);
+        routes
+    }
 }
 
 impl InterruptManager for LegacyUserspaceInterruptManager {
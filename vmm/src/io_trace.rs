@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Default number of accesses kept in the trace buffer.
+pub const DEFAULT_IO_TRACE_CAPACITY: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum IoSpace {
+    Mmio,
+    Pio,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum IoDirection {
+    Read,
+    Write,
+}
+
+/// A single guest MMIO or PIO access.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct IoTraceEntry {
+    pub space: IoSpace,
+    pub direction: IoDirection,
+    pub address: u64,
+    pub size: usize,
+    /// Little endian value of the access, truncated to 8 bytes.
+    pub value: u64,
+    /// Index of the vCPU performing the access, if it could be identified.
+    pub vcpu: Option<u8>,
+}
+
+struct IoTraceBuffer {
+    entries: VecDeque<IoTraceEntry>,
+    capacity: usize,
+    filter: Option<Range<u64>>,
+}
+
+// Records guest I/O accesses into a ring buffer. When tracing is disabled,
+// the only cost on the I/O path is the atomic load done by enabled().
+pub(crate) struct IoTracer {
+    enabled: AtomicBool,
+    buffer: Mutex<IoTraceBuffer>,
+}
+
+impl Default for IoTracer {
+    fn default() -> Self {
+        IoTracer {
+            enabled: AtomicBool::new(false),
+            buffer: Mutex::new(IoTraceBuffer {
+                entries: VecDeque::new(),
+                capacity: DEFAULT_IO_TRACE_CAPACITY,
+                filter: None,
+            }),
+        }
+    }
+}
+
+impl IoTracer {
+    #[inline]
+    pub(crate) fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Start tracing, dropping any previously recorded access. Only the
+    /// accesses falling into `filter` are recorded if one is provided.
+    pub(crate) fn enable(&self, capacity: usize, filter: Option<Range<u64>>) {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.entries.clear();
+        buffer.capacity = capacity.max(1);
+        buffer.filter = filter;
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn disable(&self) {
+        self.enabled.store(false, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record(&self, space: IoSpace, direction: IoDirection, address: u64, data: &[u8]) {
+        let mut buffer = self.buffer.lock().unwrap();
+        if let Some(filter) = &buffer.filter {
+            if !filter.contains(&address) {
+                return;
+            }
+        }
+
+        let mut value = [0u8; 8];
+        let len = data.len().min(value.len());
+        value[..len].copy_from_slice(&data[..len]);
+
+        if buffer.entries.len() == buffer.capacity {
+            buffer.entries.pop_front();
+        }
+        buffer.entries.push_back(IoTraceEntry {
+            space,
+            direction,
+            address,
+            size: data.len(),
+            value: u64::from_le_bytes(value),
+            vcpu: current_vcpu(),
+        });
+    }
+
+    pub(crate) fn entries(&self) -> Vec<IoTraceEntry> {
+        self.buffer
+            .lock()
+            .unwrap()
+            .entries
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
+
+// vCPU threads are named after their index, see CpuManager::start_vcpu().
+fn current_vcpu() -> Option<u8> {
+    thread::current()
+        .name()
+        .and_then(|name| name.strip_prefix("vcpu"))
+        .and_then(|id| id.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_trace() {
+        let tracer = IoTracer::default();
+
+        // Nothing is recorded unless explicitly enabled
+        assert!(!tracer.enabled());
+
+        tracer.enable(2, Some(0x1000..0x2000));
+        tracer.record(IoSpace::Mmio, IoDirection::Write, 0x1000, &[0x1, 0x2]);
+        tracer.record(IoSpace::Mmio, IoDirection::Read, 0x3000, &[0xff]);
+        tracer.record(IoSpace::Pio, IoDirection::Read, 0x1004, &[0x3]);
+        tracer.record(IoSpace::Pio, IoDirection::Write, 0x1008, &[0x4; 16]);
+
+        let entries = tracer.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].address, 0x1004);
+        assert_eq!(entries[0].value, 0x3);
+        assert_eq!(entries[1].size, 16);
+        assert_eq!(entries[1].value, 0x0404_0404_0404_0404);
+
+        tracer.disable();
+        assert!(!tracer.enabled());
+    }
+}
@@ -14,17 +14,19 @@ use crate::api::{
     ApiError, ApiRequest, ApiResponse, ApiResponsePayload, VmInfo, VmReceiveMigrationData,
     VmSendMigrationData, VmmPingResponse,
 };
+#[cfg(target_arch = "x86_64")]
+use crate::config::GuestPanicAction;
 use crate::config::{
-    add_to_config, DeviceConfig, DiskConfig, FsConfig, NetConfig, PmemConfig, RestoreConfig,
-    UserDeviceConfig, VdpaConfig, VmConfig, VsockConfig,
+    add_to_config, CmdlineConfig, DeviceConfig, DiskConfig, FsConfig, KernelConfig, NetConfig,
+    PmemConfig, ResetAction, RestoreConfig, UserDeviceConfig, VdpaConfig, VmConfig, VsockConfig,
 };
 #[cfg(feature = "guest_debug")]
 use crate::coredump::GuestDebuggable;
 #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
 use crate::migration::get_vm_snapshot;
-use crate::migration::{recv_vm_config, recv_vm_state};
+use crate::migration::{recv_vm_config, recv_vm_state, MigrationPolicy};
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
-use crate::vm::{Error as VmError, Vm, VmState};
+use crate::vm::{Error as VmError, MigrationReceiveConfig, Vm, VmEventFds, VmState};
 use anyhow::anyhow;
 use libc::EFD_NONBLOCK;
 use memory_manager::MemoryManagerSnapshotData;
@@ -32,26 +34,21 @@ use pci::PciBdf;
 use seccompiler::{apply_filter, SeccompAction};
 use serde::ser::{SerializeStruct, Serializer};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 use std::fs::File;
 use std::io;
-use std::io::{Read, Write};
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
-use std::os::unix::net::UnixListener;
-use std::os::unix::net::UnixStream;
 use std::path::PathBuf;
 use std::sync::mpsc::{Receiver, RecvError, SendError, Sender};
 use std::sync::{Arc, Mutex};
 use std::{result, thread};
 use thiserror::Error;
 use vm_memory::bitmap::AtomicBitmap;
-use vm_migration::{protocol::*, Migratable};
-use vm_migration::{MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
+use vm_migration::{MigratableError, Pausable, Snapshottable, Transportable};
 use vmm_sys_util::eventfd::EventFd;
-use vmm_sys_util::sock_ctrl_msg::ScmSocket;
 
 mod acpi;
 pub mod api;
+pub mod balloon_policy;
 mod clone3;
 pub mod config;
 #[cfg(feature = "guest_debug")]
@@ -59,9 +56,13 @@ mod coredump;
 pub mod cpu;
 pub mod device_manager;
 pub mod device_tree;
+#[cfg(feature = "fault_injection")]
+mod fault_injection;
 #[cfg(feature = "gdb")]
 mod gdb;
+pub mod guest_agent;
 pub mod interrupt;
+pub mod io_trace;
 pub mod memory_manager;
 pub mod migration;
 mod pci_segment;
@@ -69,6 +70,7 @@ pub mod seccomp_filters;
 mod serial_buffer;
 mod serial_manager;
 mod sigwinch_listener;
+pub mod terminal;
 pub mod vm;
 
 type GuestMemoryMmap = vm_memory::GuestMemoryMmap<AtomicBitmap>;
@@ -102,6 +104,10 @@ pub enum Error {
     #[error("Error reading from EventFd: {0}")]
     EventFdRead(#[source] io::Error),
 
+    /// Cannot write to EventFd.
+    #[error("Error writing to EventFd: {0}")]
+    EventFdWrite(#[source] io::Error),
+
     /// Cannot create epoll context.
     #[error("Error creating epoll context: {0}")]
     Epoll(#[source] io::Error),
@@ -118,6 +124,11 @@ pub enum Error {
     #[error("Error handling VM pty: {0:?}")]
     Pty(VmError),
 
+    /// Cannot handle a guest panic
+    #[cfg(target_arch = "x86_64")]
+    #[error("Error handling guest panic: {0:?}")]
+    GuestPanic(VmError),
+
     /// Cannot reboot the VM
     #[error("Error rebooting VM: {0:?}")]
     VmReboot(VmError),
@@ -174,6 +185,7 @@ pub enum EpollDispatch {
     Api = 2,
     ActivateVirtioDevices = 3,
     Debug = 4,
+    GuestPanic = 5,
     Unknown,
 }
 
@@ -186,6 +198,7 @@ impl From<u64> for EpollDispatch {
             2 => Api,
             3 => ActivateVirtioDevices,
             4 => Debug,
+            5 => GuestPanic,
             _ => Unknown,
         }
     }
@@ -340,17 +353,19 @@ pub fn start_vmm_thread(
 }
 
 #[derive(Clone, Deserialize, Serialize)]
-struct VmMigrationConfig {
-    vm_config: Arc<Mutex<VmConfig>>,
+pub(crate) struct VmMigrationConfig {
+    pub(crate) vm_config: Arc<Mutex<VmConfig>>,
     #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-    common_cpuid: hypervisor::x86_64::CpuId,
-    memory_manager_data: MemoryManagerSnapshotData,
+    pub(crate) common_cpuid: hypervisor::x86_64::CpuId,
+    pub(crate) memory_manager_data: MemoryManagerSnapshotData,
 }
 
 pub struct Vmm {
     epoll: EpollContext,
     exit_evt: EventFd,
     reset_evt: EventFd,
+    #[cfg(target_arch = "x86_64")]
+    guest_panic_evt: EventFd,
     api_evt: EventFd,
     #[cfg(feature = "gdb")]
     debug_evt: EventFd,
@@ -377,6 +392,8 @@ impl Vmm {
         let mut epoll = EpollContext::new().map_err(Error::Epoll)?;
         let reset_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
         let activate_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
+        #[cfg(target_arch = "x86_64")]
+        let guest_panic_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
 
         epoll
             .add_event(&exit_evt, EpollDispatch::Exit)
@@ -386,6 +403,11 @@ impl Vmm {
             .add_event(&reset_evt, EpollDispatch::Reset)
             .map_err(Error::Epoll)?;
 
+        #[cfg(target_arch = "x86_64")]
+        epoll
+            .add_event(&guest_panic_evt, EpollDispatch::GuestPanic)
+            .map_err(Error::Epoll)?;
+
         epoll
             .add_event(&activate_evt, EpollDispatch::ActivateVirtioDevices)
             .map_err(Error::Epoll)?;
@@ -403,6 +425,8 @@ impl Vmm {
             epoll,
             exit_evt,
             reset_evt,
+            #[cfg(target_arch = "x86_64")]
+            guest_panic_evt,
             api_evt,
             #[cfg(feature = "gdb")]
             debug_evt,
@@ -417,6 +441,19 @@ impl Vmm {
         })
     }
 
+    // Event fds for a new VM to notify the VMM through.
+    fn vm_event_fds(&self) -> io::Result<VmEventFds> {
+        Ok(VmEventFds {
+            exit_evt: self.exit_evt.try_clone()?,
+            reset_evt: self.reset_evt.try_clone()?,
+            #[cfg(target_arch = "x86_64")]
+            guest_panic_evt: self.guest_panic_evt.try_clone()?,
+            #[cfg(feature = "gdb")]
+            vm_debug_evt: self.vm_debug_evt.try_clone()?,
+            activate_evt: self.activate_evt.try_clone()?,
+        })
+    }
+
     fn vm_create(&mut self, config: Arc<Mutex<VmConfig>>) -> result::Result<(), VmError> {
         // We only store the passed VM config.
         // The VM will be created when being asked to boot it.
@@ -436,28 +473,16 @@ impl Vmm {
 
         // Create a new VM if we don't have one yet.
         if self.vm.is_none() {
-            let exit_evt = self.exit_evt.try_clone().map_err(VmError::EventFdClone)?;
-            let reset_evt = self.reset_evt.try_clone().map_err(VmError::EventFdClone)?;
-            #[cfg(feature = "gdb")]
-            let vm_debug_evt = self
-                .vm_debug_evt
-                .try_clone()
-                .map_err(VmError::EventFdClone)?;
-            let activate_evt = self
-                .activate_evt
-                .try_clone()
-                .map_err(VmError::EventFdClone)?;
+            let event_fds = self.vm_event_fds().map_err(VmError::EventFdClone)?;
 
             if let Some(ref vm_config) = self.vm_config {
                 let vm = Vm::new(
                     Arc::clone(vm_config),
-                    exit_evt,
-                    reset_evt,
-                    #[cfg(feature = "gdb")]
-                    vm_debug_evt,
+                    event_fds,
                     &self.seccomp_action,
                     self.hypervisor.clone(),
-                    activate_evt,
+                    None,
+                    Vec::new(),
                     None,
                     None,
                     None,
@@ -491,14 +516,21 @@ impl Vmm {
         }
     }
 
-    fn vm_snapshot(&mut self, destination_url: &str) -> result::Result<(), VmError> {
+    fn vm_snapshot(
+        &mut self,
+        destination_url: &str,
+        memory_only: bool,
+    ) -> result::Result<(), VmError> {
         if let Some(ref mut vm) = self.vm {
-            vm.snapshot()
-                .map_err(VmError::Snapshot)
-                .and_then(|snapshot| {
-                    vm.send(&snapshot, destination_url)
-                        .map_err(VmError::SnapshotSend)
-                })
+            let snapshot = if memory_only {
+                vm.snapshot_memory_only()
+            } else {
+                vm.snapshot()
+            };
+            snapshot.map_err(VmError::Snapshot).and_then(|snapshot| {
+                vm.send(&snapshot, destination_url)
+                    .map_err(VmError::SnapshotSend)
+            })
         } else {
             Err(VmError::VmNotRunning)
         }
@@ -524,35 +556,26 @@ impl Vmm {
         let vm_snapshot = get_vm_snapshot(&snapshot).map_err(VmError::Restore)?;
 
         #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-        self.vm_check_cpuid_compatibility(&vm_config, &vm_snapshot.common_cpuid)
-            .map_err(VmError::Restore)?;
+        vm::check_cpuid_compatibility(
+            self.hypervisor.clone(),
+            &vm_config,
+            &vm_snapshot.common_cpuid,
+        )
+        .map_err(VmError::Restore)?;
 
         self.vm_config = Some(Arc::clone(&vm_config));
 
-        let exit_evt = self.exit_evt.try_clone().map_err(VmError::EventFdClone)?;
-        let reset_evt = self.reset_evt.try_clone().map_err(VmError::EventFdClone)?;
-        #[cfg(feature = "gdb")]
-        let debug_evt = self
-            .vm_debug_evt
-            .try_clone()
-            .map_err(VmError::EventFdClone)?;
-        let activate_evt = self
-            .activate_evt
-            .try_clone()
-            .map_err(VmError::EventFdClone)?;
+        let event_fds = self.vm_event_fds().map_err(VmError::EventFdClone)?;
 
         let vm = Vm::new_from_snapshot(
             &snapshot,
             vm_config,
-            exit_evt,
-            reset_evt,
-            #[cfg(feature = "gdb")]
-            debug_evt,
+            event_fds,
             Some(source_url),
             restore_cfg.prefault,
+            restore_cfg.paused,
             &self.seccomp_action,
             self.hypervisor.clone(),
-            activate_evt,
         )?;
         self.vm = Some(vm);
 
@@ -583,32 +606,38 @@ impl Vmm {
 
     fn vm_reboot(&mut self) -> result::Result<(), VmError> {
         // First we stop the current VM
-        let (config, serial_pty, console_pty, console_resize_pipe) =
-            if let Some(mut vm) = self.vm.take() {
-                let config = vm.get_config();
-                let serial_pty = vm.serial_pty();
-                let console_pty = vm.console_pty();
-                let console_resize_pipe = vm
-                    .console_resize_pipe()
-                    .as_ref()
-                    .map(|pipe| pipe.try_clone().unwrap());
-                vm.shutdown()?;
-                (config, serial_pty, console_pty, console_resize_pipe)
-            } else {
-                return Err(VmError::VmNotCreated);
-            };
+        let (
+            config,
+            serial_pty,
+            extra_serial_ptys,
+            console_pty,
+            console_resize_pipe,
+            reboot_stats,
+            terminal,
+        ) = if let Some(mut vm) = self.vm.take() {
+            let config = vm.get_config();
+            let serial_pty = vm.serial_pty();
+            let extra_serial_ptys = vm.extra_serial_ptys();
+            let console_pty = vm.console_pty();
+            let console_resize_pipe = vm
+                .console_resize_pipe()
+                .as_ref()
+                .map(|pipe| pipe.try_clone().unwrap());
+            vm.shutdown()?;
+            (
+                config,
+                serial_pty,
+                extra_serial_ptys,
+                console_pty,
+                console_resize_pipe,
+                vm.reboot_stats(),
+                vm.terminal_backend(),
+            )
+        } else {
+            return Err(VmError::VmNotCreated);
+        };
 
-        let exit_evt = self.exit_evt.try_clone().map_err(VmError::EventFdClone)?;
-        let reset_evt = self.reset_evt.try_clone().map_err(VmError::EventFdClone)?;
-        #[cfg(feature = "gdb")]
-        let debug_evt = self
-            .vm_debug_evt
-            .try_clone()
-            .map_err(VmError::EventFdClone)?;
-        let activate_evt = self
-            .activate_evt
-            .try_clone()
-            .map_err(VmError::EventFdClone)?;
+        let event_fds = self.vm_event_fds().map_err(VmError::EventFdClone)?;
 
         // The Linux kernel fires off an i8042 reset after doing the ACPI reset so there may be
         // an event sitting in the shared reset_evt. Without doing this we get very early reboots
@@ -620,17 +649,17 @@ impl Vmm {
         // Then we create the new VM
         let mut vm = Vm::new(
             config,
-            exit_evt,
-            reset_evt,
-            #[cfg(feature = "gdb")]
-            debug_evt,
+            event_fds,
             &self.seccomp_action,
             self.hypervisor.clone(),
-            activate_evt,
             serial_pty,
+            extra_serial_ptys,
             console_pty,
             console_resize_pipe,
+            None,
         )?;
+        vm.set_reboot_stats(reboot_stats);
+        vm.set_terminal_backend(terminal)?;
 
         // And we boot it
         vm.boot()?;
@@ -640,6 +669,35 @@ impl Vmm {
         Ok(())
     }
 
+    fn vm_reboot_with_kernel(
+        &mut self,
+        kernel: PathBuf,
+        cmdline: Option<String>,
+    ) -> result::Result<(), VmError> {
+        let vm = self.vm.as_ref().ok_or(VmError::VmNotCreated)?;
+
+        // Make sure the new kernel can be loaded before tearing down the
+        // current VM, so that a bad kernel doesn't leave us without any VM.
+        vm.check_kernel(&kernel)?;
+
+        // Update VmConfig so that the new kernel is also used on subsequent
+        // reboots.
+        {
+            let config = vm.get_config();
+            let mut config = config.lock().unwrap();
+            let highmem_start = config.kernel.as_ref().and_then(|k| k.highmem_start);
+            config.kernel = Some(KernelConfig {
+                path: kernel,
+                highmem_start,
+            });
+            if let Some(cmdline) = cmdline {
+                config.cmdline.args = cmdline;
+            }
+        }
+
+        self.vm_reboot()
+    }
+
     fn vm_info(&self) -> result::Result<VmInfo, VmError> {
         match &self.vm_config {
             Some(config) => {
@@ -1011,129 +1069,6 @@ impl Vmm {
         }
     }
 
-    fn vm_receive_config<T>(
-        &mut self,
-        req: &Request,
-        socket: &mut T,
-        existing_memory_files: Option<HashMap<u32, File>>,
-    ) -> std::result::Result<Vm, MigratableError>
-    where
-        T: Read + Write,
-    {
-        // Read in config data along with memory manager data
-        let mut data: Vec<u8> = Vec::new();
-        data.resize_with(req.length() as usize, Default::default);
-        socket
-            .read_exact(&mut data)
-            .map_err(MigratableError::MigrateSocket)?;
-
-        let vm_migration_config: VmMigrationConfig =
-            serde_json::from_slice(&data).map_err(|e| {
-                MigratableError::MigrateReceive(anyhow!("Error deserialising config: {}", e))
-            })?;
-
-        #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-        self.vm_check_cpuid_compatibility(
-            &vm_migration_config.vm_config,
-            &vm_migration_config.common_cpuid,
-        )?;
-
-        let exit_evt = self.exit_evt.try_clone().map_err(|e| {
-            MigratableError::MigrateReceive(anyhow!("Error cloning exit EventFd: {}", e))
-        })?;
-        let reset_evt = self.reset_evt.try_clone().map_err(|e| {
-            MigratableError::MigrateReceive(anyhow!("Error cloning reset EventFd: {}", e))
-        })?;
-        #[cfg(feature = "gdb")]
-        let debug_evt = self.vm_debug_evt.try_clone().map_err(|e| {
-            MigratableError::MigrateReceive(anyhow!("Error cloning debug EventFd: {}", e))
-        })?;
-        let activate_evt = self.activate_evt.try_clone().map_err(|e| {
-            MigratableError::MigrateReceive(anyhow!("Error cloning activate EventFd: {}", e))
-        })?;
-
-        self.vm_config = Some(vm_migration_config.vm_config);
-        let vm = Vm::new_from_migration(
-            self.vm_config.clone().unwrap(),
-            exit_evt,
-            reset_evt,
-            #[cfg(feature = "gdb")]
-            debug_evt,
-            &self.seccomp_action,
-            self.hypervisor.clone(),
-            activate_evt,
-            &vm_migration_config.memory_manager_data,
-            existing_memory_files,
-        )
-        .map_err(|e| {
-            MigratableError::MigrateReceive(anyhow!("Error creating VM from snapshot: {:?}", e))
-        })?;
-
-        Response::ok().write_to(socket)?;
-
-        Ok(vm)
-    }
-
-    fn vm_receive_state<T>(
-        &mut self,
-        req: &Request,
-        socket: &mut T,
-        mut vm: Vm,
-    ) -> std::result::Result<(), MigratableError>
-    where
-        T: Read + Write,
-    {
-        // Read in state data
-        let mut data: Vec<u8> = Vec::new();
-        data.resize_with(req.length() as usize, Default::default);
-        socket
-            .read_exact(&mut data)
-            .map_err(MigratableError::MigrateSocket)?;
-        let snapshot: Snapshot = serde_json::from_slice(&data).map_err(|e| {
-            MigratableError::MigrateReceive(anyhow!("Error deserialising snapshot: {}", e))
-        })?;
-
-        // Create VM
-        vm.restore(snapshot).map_err(|e| {
-            Response::error().write_to(socket).ok();
-            e
-        })?;
-        self.vm = Some(vm);
-
-        Response::ok().write_to(socket)?;
-
-        Ok(())
-    }
-
-    fn vm_receive_memory<T>(
-        &mut self,
-        req: &Request,
-        socket: &mut T,
-        vm: &mut Vm,
-    ) -> std::result::Result<(), MigratableError>
-    where
-        T: Read + Write,
-    {
-        // Read table
-        let table = MemoryRangeTable::read_from(socket, req.length())?;
-
-        // And then read the memory itself
-        vm.receive_memory_regions(&table, socket).map_err(|e| {
-            Response::error().write_to(socket).ok();
-            e
-        })?;
-        Response::ok().write_to(socket)?;
-        Ok(())
-    }
-
-    fn socket_url_to_path(url: &str) -> result::Result<PathBuf, MigratableError> {
-        url.strip_prefix("unix:")
-            .ok_or_else(|| {
-                MigratableError::MigrateSend(anyhow!("Could not extract path from URL: {}", url))
-            })
-            .map(|s| s.into())
-    }
-
     fn vm_receive_migration(
         &mut self,
         receive_data_migration: VmReceiveMigrationData,
@@ -1143,307 +1078,27 @@ impl Vmm {
             receive_data_migration.receiver_url
         );
 
-        let path = Self::socket_url_to_path(&receive_data_migration.receiver_url)?;
-        let listener = UnixListener::bind(&path).map_err(|e| {
-            MigratableError::MigrateReceive(anyhow!("Error binding to UNIX socket: {}", e))
-        })?;
-        let (mut socket, _addr) = listener.accept().map_err(|e| {
-            MigratableError::MigrateReceive(anyhow!("Error accepting on UNIX socket: {}", e))
-        })?;
-        std::fs::remove_file(&path).map_err(|e| {
-            MigratableError::MigrateReceive(anyhow!("Error unlinking UNIX socket: {}", e))
-        })?;
-
-        let mut started = false;
-        let mut vm: Option<Vm> = None;
-        let mut existing_memory_files = None;
-        loop {
-            let req = Request::read_from(&mut socket)?;
-            match req.command() {
-                Command::Invalid => info!("Invalid Command Received"),
-                Command::Start => {
-                    info!("Start Command Received");
-                    started = true;
-
-                    Response::ok().write_to(&mut socket)?;
-                }
-                Command::Config => {
-                    info!("Config Command Received");
-
-                    if !started {
-                        warn!("Migration not started yet");
-                        Response::error().write_to(&mut socket)?;
-                        continue;
-                    }
-                    vm = Some(self.vm_receive_config(
-                        &req,
-                        &mut socket,
-                        existing_memory_files.take(),
-                    )?);
-                }
-                Command::State => {
-                    info!("State Command Received");
-
-                    if !started {
-                        warn!("Migration not started yet");
-                        Response::error().write_to(&mut socket)?;
-                        continue;
-                    }
-                    if let Some(vm) = vm.take() {
-                        self.vm_receive_state(&req, &mut socket, vm)?;
-                    } else {
-                        warn!("Configuration not sent yet");
-                        Response::error().write_to(&mut socket)?;
-                    }
-                }
-                Command::Memory => {
-                    info!("Memory Command Received");
-
-                    if !started {
-                        warn!("Migration not started yet");
-                        Response::error().write_to(&mut socket)?;
-                        continue;
-                    }
-                    if let Some(ref mut vm) = vm.as_mut() {
-                        self.vm_receive_memory(&req, &mut socket, vm)?;
-                    } else {
-                        warn!("Configuration not sent yet");
-                        Response::error().write_to(&mut socket)?;
-                    }
-                }
-                Command::MemoryFd => {
-                    info!("MemoryFd Command Received");
-
-                    if !started {
-                        warn!("Migration not started yet");
-                        Response::error().write_to(&mut socket)?;
-                        continue;
-                    }
-
-                    let mut buf = [0u8; 4];
-                    let (_, file) = socket.recv_with_fd(&mut buf).map_err(|e| {
-                        MigratableError::MigrateReceive(anyhow!(
-                            "Error receiving slot from socket: {}",
-                            e
-                        ))
-                    })?;
-
-                    if existing_memory_files.is_none() {
-                        existing_memory_files = Some(HashMap::default())
-                    }
-
-                    if let Some(ref mut existing_memory_files) = existing_memory_files {
-                        let slot = u32::from_le_bytes(buf);
-                        existing_memory_files.insert(slot, file.unwrap());
-                    }
-
-                    Response::ok().write_to(&mut socket)?;
-                }
-                Command::Complete => {
-                    info!("Complete Command Received");
-                    if let Some(ref mut vm) = self.vm.as_mut() {
-                        vm.resume()?;
-                        Response::ok().write_to(&mut socket)?;
-                    } else {
-                        warn!("VM not created yet");
-                        Response::error().write_to(&mut socket)?;
-                    }
-                    break;
-                }
-                Command::Abandon => {
-                    info!("Abandon Command Received");
-                    self.vm = None;
-                    self.vm_config = None;
-                    Response::ok().write_to(&mut socket).ok();
-                    break;
-                }
-            }
-        }
-
-        Ok(())
-    }
-
-    // Returns true if there were dirty pages to send
-    fn vm_maybe_send_dirty_pages<T>(
-        vm: &mut Vm,
-        socket: &mut T,
-    ) -> result::Result<bool, MigratableError>
-    where
-        T: Read + Write,
-    {
-        // Send (dirty) memory table
-        let table = vm.dirty_log()?;
-
-        // But if there are no regions go straight to pause
-        if table.regions().is_empty() {
-            return Ok(false);
-        }
-
-        Request::memory(table.length()).write_to(socket).unwrap();
-        table.write_to(socket)?;
-        // And then the memory itself
-        vm.send_memory_regions(&table, socket)?;
-        let res = Response::read_from(socket)?;
-        if res.status() != Status::Ok {
-            warn!("Error during dirty memory migration");
-            Request::abandon().write_to(socket)?;
-            Response::read_from(socket).ok();
-            return Err(MigratableError::MigrateSend(anyhow!(
-                "Error during dirty memory migration"
-            )));
-        }
-
-        Ok(true)
-    }
-
-    fn send_migration(
-        vm: &mut Vm,
-        #[cfg(all(feature = "kvm", target_arch = "x86_64"))] hypervisor: Arc<
-            dyn hypervisor::Hypervisor,
-        >,
-        send_data_migration: VmSendMigrationData,
-    ) -> result::Result<(), MigratableError> {
-        let path = Self::socket_url_to_path(&send_data_migration.destination_url)?;
-        let mut socket = UnixStream::connect(&path).map_err(|e| {
-            MigratableError::MigrateSend(anyhow!("Error connecting to UNIX socket: {}", e))
-        })?;
-
-        // Start the migration
-        Request::start().write_to(&mut socket)?;
-        let res = Response::read_from(&mut socket)?;
-        if res.status() != Status::Ok {
-            warn!("Error starting migration");
-            Request::abandon().write_to(&mut socket)?;
-            Response::read_from(&mut socket).ok();
-            return Err(MigratableError::MigrateSend(anyhow!(
-                "Error starting migration"
-            )));
-        }
-
-        // Send config
-        let vm_config = vm.get_config();
-        #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-        let common_cpuid = {
-            #[cfg(feature = "tdx")]
-            let tdx_enabled = vm_config.lock().unwrap().tdx.is_some();
-            let phys_bits = vm::physical_bits(vm_config.lock().unwrap().cpus.max_phys_bits);
-            arch::generate_common_cpuid(
-                hypervisor,
-                None,
-                None,
-                phys_bits,
-                vm_config.lock().unwrap().cpus.kvm_hyperv,
-                #[cfg(feature = "tdx")]
-                tdx_enabled,
-            )
-            .map_err(|e| {
-                MigratableError::MigrateReceive(anyhow!("Error generating common cpuid': {:?}", e))
-            })?
+        let config = MigrationReceiveConfig {
+            event_fds: self.vm_event_fds().map_err(|e| {
+                MigratableError::MigrateReceive(anyhow!("Error cloning EventFd: {}", e))
+            })?,
+            seccomp_action: self.seccomp_action.clone(),
+            hypervisor: self.hypervisor.clone(),
+            socket_options: Default::default(),
         };
 
-        if send_data_migration.local {
-            vm.send_memory_fds(&mut socket)?;
-        }
-
-        let vm_migration_config = VmMigrationConfig {
-            vm_config,
-            #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-            common_cpuid,
-            memory_manager_data: vm.memory_manager_data(),
-        };
-        let config_data = serde_json::to_vec(&vm_migration_config).unwrap();
-        Request::config(config_data.len() as u64).write_to(&mut socket)?;
-        socket
-            .write_all(&config_data)
-            .map_err(MigratableError::MigrateSocket)?;
-        let res = Response::read_from(&mut socket)?;
-        if res.status() != Status::Ok {
-            warn!("Error during config migration");
-            Request::abandon().write_to(&mut socket)?;
-            Response::read_from(&mut socket).ok();
-            return Err(MigratableError::MigrateSend(anyhow!(
-                "Error during config migration"
-            )));
-        }
-
-        // Let every Migratable object know about the migration being started.
-        vm.start_migration()?;
-
-        if send_data_migration.local {
-            // Now pause VM
-            vm.pause()?;
-        } else {
-            // Start logging dirty pages
-            vm.start_dirty_log()?;
-
-            // Send memory table
-            let table = vm.memory_range_table()?;
-            Request::memory(table.length())
-                .write_to(&mut socket)
-                .unwrap();
-            table.write_to(&mut socket)?;
-            // And then the memory itself
-            vm.send_memory_regions(&table, &mut socket)?;
-            let res = Response::read_from(&mut socket)?;
-            if res.status() != Status::Ok {
-                warn!("Error during memory migration");
-                Request::abandon().write_to(&mut socket)?;
-                Response::read_from(&mut socket).ok();
-                return Err(MigratableError::MigrateSend(anyhow!(
-                    "Error during memory migration"
-                )));
+        match Vm::receive_migration(&receive_data_migration.receiver_url, config)? {
+            Some(vm) => {
+                self.vm_config = Some(vm.get_config());
+                self.vm = Some(vm);
             }
-
-            // Try at most 5 passes of dirty memory sending
-            const MAX_DIRTY_MIGRATIONS: usize = 5;
-            for i in 0..MAX_DIRTY_MIGRATIONS {
-                info!("Dirty memory migration {} of {}", i, MAX_DIRTY_MIGRATIONS);
-                if !Self::vm_maybe_send_dirty_pages(vm, &mut socket)? {
-                    break;
-                }
+            None => {
+                self.vm = None;
+                self.vm_config = None;
             }
-
-            // Now pause VM
-            vm.pause()?;
-
-            // Send last batch of dirty pages
-            Self::vm_maybe_send_dirty_pages(vm, &mut socket)?;
-
-            // Stop logging dirty pages
-            vm.stop_dirty_log()?;
-        }
-        // Capture snapshot and send it
-        let vm_snapshot = vm.snapshot()?;
-        let snapshot_data = serde_json::to_vec(&vm_snapshot).unwrap();
-        Request::state(snapshot_data.len() as u64).write_to(&mut socket)?;
-        socket
-            .write_all(&snapshot_data)
-            .map_err(MigratableError::MigrateSocket)?;
-        let res = Response::read_from(&mut socket)?;
-        if res.status() != Status::Ok {
-            warn!("Error during state migration");
-            Request::abandon().write_to(&mut socket)?;
-            Response::read_from(&mut socket).ok();
-            return Err(MigratableError::MigrateSend(anyhow!(
-                "Error during state migration"
-            )));
-        }
-
-        // Complete the migration
-        Request::complete().write_to(&mut socket)?;
-        let res = Response::read_from(&mut socket)?;
-        if res.status() != Status::Ok {
-            warn!("Error completing migration");
-            Request::abandon().write_to(&mut socket)?;
-            Response::read_from(&mut socket).ok();
-            return Err(MigratableError::MigrateSend(anyhow!(
-                "Error completing migration"
-            )));
         }
-        info!("Migration complete");
 
-        // Let every Migratable object know about the migration being complete
-        vm.complete_migration()
+        Ok(())
     }
 
     fn vm_send_migration(
@@ -1455,44 +1110,16 @@ impl Vmm {
             send_data_migration.destination_url, send_data_migration.local
         );
 
-        if !self
-            .vm_config
-            .as_ref()
-            .unwrap()
-            .lock()
-            .unwrap()
-            .memory
-            .shared
-            && send_data_migration.local
-        {
-            return Err(MigratableError::MigrateSend(anyhow!(
-                "Local migration requires shared memory enabled"
-            )));
-        }
-
         if let Some(vm) = self.vm.as_mut() {
-            Self::send_migration(
-                vm,
-                #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-                self.hypervisor.clone(),
-                send_data_migration,
-            )
-            .map_err(|migration_err| {
-                error!("Migration failed: {:?}", migration_err);
-
-                // Stop logging dirty pages
-                if let Err(e) = vm.stop_dirty_log() {
-                    return e;
-                }
-
-                if vm.get_state().unwrap() == VmState::Paused {
-                    if let Err(e) = vm.resume() {
-                        return e;
-                    }
-                }
-
-                migration_err
-            })?;
+            let policy = MigrationPolicy {
+                local: send_data_migration.local,
+                ..Default::default()
+            };
+            vm.migrate_to(
+                &send_data_migration.destination_url,
+                &policy,
+                &mut |phase| info!("Migration phase: {:?}", phase),
+            )?;
 
             // Shutdown the VM after the migration succeeded
             self.exit_evt.write(1).map_err(|e| {
@@ -1506,41 +1133,6 @@ impl Vmm {
         }
     }
 
-    #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-    fn vm_check_cpuid_compatibility(
-        &self,
-        src_vm_config: &Arc<Mutex<VmConfig>>,
-        src_vm_cpuid: &hypervisor::x86_64::CpuId,
-    ) -> result::Result<(), MigratableError> {
-        // We check the `CPUID` compatibility of between the source vm and destination, which is
-        // mostly about feature compatibility and "topology/sgx" leaves are not relevant.
-        let dest_cpuid = &{
-            let vm_config = &src_vm_config.lock().unwrap();
-
-            #[cfg(feature = "tdx")]
-            let tdx_enabled = vm_config.tdx.is_some();
-            let phys_bits = vm::physical_bits(vm_config.cpus.max_phys_bits);
-            arch::generate_common_cpuid(
-                self.hypervisor.clone(),
-                None,
-                None,
-                phys_bits,
-                vm_config.cpus.kvm_hyperv,
-                #[cfg(feature = "tdx")]
-                tdx_enabled,
-            )
-            .map_err(|e| {
-                MigratableError::MigrateReceive(anyhow!("Error generating common cpuid: {:?}", e))
-            })?
-        };
-        arch::CpuidFeatureEntry::check_cpuid_compatibility(src_vm_cpuid, dest_cpuid).map_err(|e| {
-            MigratableError::MigrateReceive(anyhow!(
-                "Error checking cpu feature compatibility': {:?}",
-                e
-            ))
-        })
-    }
-
     fn control_loop(
         &mut self,
         api_receiver: Arc<Receiver<ApiRequest>>,
@@ -1577,7 +1169,10 @@ impl Vmm {
                         warn!("Unknown VMM loop event: {}", event);
                     }
                     EpollDispatch::Exit => {
-                        info!("VM exit event");
+                        match self.vm.as_ref().and_then(|vm| vm.exit_reason()) {
+                            Some(reason) => info!("VM exit event: {}", reason),
+                            None => info!("VM exit event"),
+                        }
                         // Consume the event.
                         self.exit_evt.read().map_err(Error::EventFdRead)?;
                         self.vmm_shutdown().map_err(Error::VmmShutdown)?;
@@ -1588,8 +1183,48 @@ impl Vmm {
                         info!("VM reset event");
                         // Consume the event.
                         self.reset_evt.read().map_err(Error::EventFdRead)?;
-                        self.vm_reboot().map_err(Error::VmReboot)?;
+                        if let Some(ref mut vm) = self.vm {
+                            if vm.handle_unregistered_io_fault().map_err(Error::VmReboot)? {
+                                continue;
+                            }
+                        }
+                        #[cfg(target_arch = "x86_64")]
+                        if let Some(ref mut vm) = self.vm {
+                            if vm.handle_triple_fault().map_err(Error::VmReboot)? {
+                                continue;
+                            }
+                        }
+                        let action = match self.vm {
+                            Some(ref mut vm) => vm.handle_reset().map_err(Error::VmReboot)?,
+                            None => ResetAction::Restart,
+                        };
+                        match action {
+                            ResetAction::Restart => self.vm_reboot().map_err(Error::VmReboot)?,
+                            ResetAction::Exit => {
+                                self.exit_evt.write(1).map_err(Error::EventFdWrite)?
+                            }
+                            ResetAction::Pause => {}
+                        }
                     }
+                    #[cfg(target_arch = "x86_64")]
+                    EpollDispatch::GuestPanic => {
+                        info!("VM guest panic event");
+                        // Consume the event.
+                        self.guest_panic_evt.read().map_err(Error::EventFdRead)?;
+                        if let Some(ref mut vm) = self.vm {
+                            match vm.handle_guest_panic().map_err(Error::GuestPanic)? {
+                                GuestPanicAction::Reset => {
+                                    self.reset_evt.write(1).map_err(Error::EventFdWrite)?
+                                }
+                                GuestPanicAction::Shutdown => {
+                                    self.exit_evt.write(1).map_err(Error::EventFdWrite)?
+                                }
+                                GuestPanicAction::None | GuestPanicAction::Pause => {}
+                            }
+                        }
+                    }
+                    #[cfg(not(target_arch = "x86_64"))]
+                    EpollDispatch::GuestPanic => {}
                     EpollDispatch::ActivateVirtioDevices => {
                         if let Some(ref vm) = self.vm {
                             let count = self.activate_evt.read().map_err(Error::EventFdRead)?;
@@ -1650,6 +1285,17 @@ impl Vmm {
 
                                 sender.send(response).map_err(Error::ApiResponseSend)?;
                             }
+                            ApiRequest::VmRebootWithKernel(reboot_data, sender) => {
+                                let response = self
+                                    .vm_reboot_with_kernel(
+                                        reboot_data.kernel.clone(),
+                                        reboot_data.cmdline.clone(),
+                                    )
+                                    .map_err(ApiError::VmReboot)
+                                    .map(|_| ApiResponsePayload::Empty);
+
+                                sender.send(response).map_err(Error::ApiResponseSend)?;
+                            }
                             ApiRequest::VmInfo(sender) => {
                                 let response = self
                                     .vm_info()
@@ -1681,7 +1327,10 @@ impl Vmm {
                             }
                             ApiRequest::VmSnapshot(snapshot_data, sender) => {
                                 let response = self
-                                    .vm_snapshot(&snapshot_data.destination_url)
+                                    .vm_snapshot(
+                                        &snapshot_data.destination_url,
+                                        snapshot_data.memory_only,
+                                    )
                                     .map_err(ApiError::VmSnapshot)
                                     .map(|_| ApiResponsePayload::Empty);
 
@@ -1896,6 +1545,9 @@ mod unit_tests {
                 max_phys_bits: 46,
                 affinity: None,
                 features: config::CpuFeatures::default(),
+                #[cfg(target_arch = "x86_64")]
+                tsc_deadline_timer: true,
+                parallel_creation_threshold: 16,
             },
             memory: MemoryConfig {
                 size: 536_870_912,
@@ -1907,14 +1559,20 @@ mod unit_tests {
                 hugepages: false,
                 hugepage_size: None,
                 prefault: false,
+                wipe_on_shutdown: false,
+                prefault_timeout: None,
+                mlock: false,
                 zones: None,
+                reserved: None,
             },
             kernel: Some(KernelConfig {
                 path: PathBuf::from("/path/to/kernel"),
+                highmem_start: None,
             }),
             initramfs: None,
             cmdline: CmdlineConfig {
                 args: String::from(""),
+                console_additions: true,
             },
             disks: None,
             net: None,
@@ -1935,6 +1593,8 @@ mod unit_tests {
                 mode: ConsoleOutputMode::Tty,
                 iommu: false,
             },
+            extra_serial: None,
+            serial_devices: None,
             devices: None,
             user_devices: None,
             vdpa: None,
@@ -1949,6 +1609,11 @@ mod unit_tests {
             #[cfg(feature = "gdb")]
             gdb: false,
             platform: None,
+            #[cfg(target_arch = "x86_64")]
+            guest_panic: None,
+            stop_on_boot: false,
+            break_on_triple_fault: false,
+            on_reset: ResetAction::Restart,
         }))
     }
 
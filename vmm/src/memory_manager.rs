@@ -9,7 +9,7 @@ use crate::config::{HotplugMethod, MemoryConfig, MemoryZoneConfig};
 use crate::coredump::{CoredumpMemoryRegion, CoredumpMemoryRegions};
 #[cfg(feature = "guest_debug")]
 use crate::coredump::{DumpState, GuestDebuggableError};
-use crate::migration::url_to_path;
+use crate::migration::{url_to_path, url_to_stream};
 use crate::MEMORY_MANAGER_SNAPSHOT_ID;
 use crate::{GuestMemoryMmap, GuestRegionMmap};
 use acpi_tables::{aml, aml::Aml};
@@ -28,12 +28,13 @@ use std::collections::HashMap;
 use std::convert::TryInto;
 use std::ffi;
 use std::fs::{File, OpenOptions};
-use std::io;
+use std::io::{self, Seek, SeekFrom, Write};
 use std::ops::Deref;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::result;
 use std::sync::{Arc, Barrier, Mutex};
+use std::time::{Duration, Instant};
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
 use virtio_devices::BlocksState;
@@ -45,7 +46,8 @@ use vm_memory::bitmap::AtomicBitmap;
 use vm_memory::guest_memory::FileOffset;
 use vm_memory::{
     mmap::MmapRegionError, Address, Bytes, Error as MmapError, GuestAddress, GuestAddressSpace,
-    GuestMemory, GuestMemoryAtomic, GuestMemoryError, GuestMemoryRegion, GuestUsize, MmapRegion,
+    GuestMemory, GuestMemoryAtomic, GuestMemoryError, GuestMemoryRegion, GuestUsize,
+    MemoryRegionAddress, MmapRegion,
 };
 use vm_migration::{
     protocol::MemoryRange, protocol::MemoryRangeTable, Migratable, MigratableError, Pausable,
@@ -56,7 +58,7 @@ pub const MEMORY_MANAGER_ACPI_SIZE: usize = 0x18;
 
 const DEFAULT_MEMORY_ZONE: &str = "mem0";
 
-const SNAPSHOT_FILENAME: &str = "memory-ranges";
+pub const SNAPSHOT_FILENAME: &str = "memory-ranges";
 
 #[cfg(target_arch = "x86_64")]
 const X86_64_IRQ_BASE: u32 = 5;
@@ -74,6 +76,21 @@ const MPOL_MF_MOVE: u32 = 1 << 1;
 // Reserve 1 MiB for platform MMIO devices (e.g. ACPI control devices)
 const PLATFORM_DEVICE_AREA_SIZE: u64 = 1 << 20;
 
+// Filesystem magic from include/uapi/linux/magic.h
+const HUGETLBFS_MAGIC: u64 = 0x9584_58f6;
+
+// madvise(2) advices from include/uapi/asm-generic/mman-common.h
+const MADV_COLD: i32 = 20;
+const MADV_PAGEOUT: i32 = 21;
+const MADV_POPULATE_WRITE: i32 = 23;
+
+// Amount of guest RAM prefaulted at once when prefaulting is time limited.
+const PREFAULT_CHUNK_SIZE: usize = 128 << 20;
+
+// Granularity at which zeroed guest RAM is left out of qcow2 memory dumps,
+// matching the default qcow2 cluster size.
+const QCOW_DUMP_CHUNK_SIZE: usize = 64 << 10;
+
 #[derive(Clone, Default, Serialize, Deserialize, Versionize)]
 struct HotPlugState {
     base: u64,
@@ -113,6 +130,15 @@ impl VirtioMemZone {
             .unwrap()
             .memory_ranges(self.region.start_addr().raw_value(), true)
     }
+    /// Amount of memory the guest actually plugged, which lags behind
+    /// hotplugged_size() until the guest has caught up with the request.
+    pub fn plugged_size(&self) -> u64 {
+        self.plugged_ranges()
+            .regions()
+            .iter()
+            .map(|range| range.length)
+            .sum()
+    }
 }
 
 #[derive(Default)]
@@ -132,6 +158,56 @@ impl MemoryZone {
 
 pub type MemoryZones = HashMap<String, MemoryZone>;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RegionBacking {
+    /// Anonymous memory, backed by a memfd
+    Anonymous,
+    /// File from the host filesystem
+    File,
+    /// Hugetlbfs file or memfd
+    Hugepage,
+}
+
+/// How the host should treat the guest RAM of a paused VM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MemoryReclaimAdvice {
+    /// Deactivate the pages so that they get reclaimed first under memory
+    /// pressure (MADV_COLD).
+    Cold,
+    /// Reclaim the pages right away (MADV_PAGEOUT).
+    Pageout,
+}
+
+/// Host side view of a guest RAM region.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RegionUsage {
+    pub memory_zone: String,
+    pub start: u64,
+    pub size: u64,
+    pub backing: RegionBacking,
+    pub host_address: u64,
+    /// Number of host pages currently resident in memory, only computed
+    /// when explicitly requested.
+    pub resident_pages: Option<u64>,
+}
+
+/// Memory zone being moved to a new backing file, see
+/// `MemoryManager::prepare_zone_migration()`.
+pub struct ZoneMigration {
+    memory_zone: String,
+    // New regions, in the same order as the ones of the zone.
+    regions: Vec<Arc<GuestRegionMmap>>,
+}
+
+/// Outcome of prefaulting the RAM of a memory zone.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ZonePrefault {
+    pub memory_zone: String,
+    /// Number of host pages that were not resident before prefaulting.
+    pub faulted_pages: u64,
+    pub duration_ms: u64,
+}
+
 #[derive(Clone, Serialize, Deserialize, Versionize)]
 struct GuestRamMapping {
     slot: u32,
@@ -185,6 +261,12 @@ pub struct MemoryManager {
     guest_ram_mappings: Vec<GuestRamMapping>,
 
     pub acpi_address: Option<GuestAddress>,
+
+    // Time it took to create the guest RAM regions.
+    init_duration: Duration,
+
+    // Whether the guest RAM is locked in host memory.
+    memory_locked: bool,
 }
 
 #[derive(Debug)]
@@ -314,6 +396,77 @@ pub enum Error {
 
     /// Failed to allocate MMIO address
     AllocateMmioAddress,
+
+    /// Failed to retrieve the resident pages of a region
+    Mincore(io::Error),
+
+    /// Failed to read the host hugepages information
+    HugepagesInfo(io::Error),
+
+    /// Not enough free hugepages on the host
+    InsufficientHugepages {
+        requested: u64,
+        available: u64,
+    },
+
+    /// Memory zone size is not a multiple of the hugepage size
+    UnalignedHugepageZone {
+        size: u64,
+        hugepage_size: u64,
+    },
+
+    /// Failed to wipe the guest memory
+    WipeMemory(io::Error),
+
+    /// Failed to advise the host about reclaiming the guest memory
+    ReclaimMemory(io::Error),
+
+    /// Failed to prefault the guest memory
+    PrefaultMemory(io::Error),
+
+    /// Failed to create the file of a qcow2 memory dump
+    QcowDumpFile(io::Error),
+
+    /// Failed to create the qcow2 image of a memory dump
+    QcowDumpCreate(qcow::Error),
+
+    /// Failed to read the guest memory to dump
+    QcowDumpRead(GuestMemoryError),
+
+    /// Failed to write to the qcow2 image of a memory dump
+    QcowDumpWrite(io::Error),
+
+    /// Failed to lock the guest memory, with the RLIMIT_MEMLOCK soft limit
+    /// of the process (None if unlimited)
+    LockMemory {
+        error: io::Error,
+        limit: Option<u64>,
+    },
+
+    /// Failed to unlock the guest memory
+    UnlockMemory(io::Error),
+
+    /// Guest memory region not backed by a file mapped as shared
+    UnsharedMemoryRegion(GuestAddress),
+
+    /// Error seeking into the snapshot file
+    SnapshotSeek(io::Error),
+
+    /// Snapshot taken with a different guest memory layout
+    SnapshotLayoutMismatch,
+
+    /// Memory zone backed, even partly, by a virtio-mem device
+    ZoneMigrationVirtioMem,
+
+    /// Failed to open the new backing file of a memory zone
+    ZoneMigrationOpen(io::Error),
+
+    /// New backing file smaller than the memory zone, with its size and
+    /// the size of the zone
+    ZoneMigrationSize(u64, u64),
+
+    /// Failed to copy the memory zone to its new backing
+    ZoneMigrationCopy(GuestMemoryError),
 }
 
 const ENABLE_FLAG: usize = 0;
@@ -590,13 +743,34 @@ impl MemoryManager {
         Ok((memory_regions, memory_zones))
     }
 
+    // Offsets within `range` of the parts covered by `ranges`, or the whole
+    // range if None.
+    fn range_parts(range: &MemoryRange, ranges: Option<&MemoryRangeTable>) -> Vec<(u64, u64)> {
+        match ranges {
+            None => vec![(0, range.length)],
+            Some(ranges) => ranges
+                .regions()
+                .iter()
+                .filter_map(|d| {
+                    let start = d.gpa.max(range.gpa);
+                    let end = (d.gpa + d.length).min(range.gpa + range.length);
+                    (start < end).then(|| (start - range.gpa, end - range.gpa))
+                })
+                .collect(),
+        }
+    }
+
+    // Copy the saved regions from the snapshot file, which holds them one
+    // after the other. When `dirty` is provided, only the parts of the saved
+    // regions it covers are copied. Returns the number of bytes copied.
     fn fill_saved_regions(
         &mut self,
         file_path: PathBuf,
-        saved_regions: MemoryRangeTable,
-    ) -> Result<(), Error> {
+        saved_regions: &MemoryRangeTable,
+        dirty: Option<&MemoryRangeTable>,
+    ) -> Result<u64, Error> {
         if saved_regions.is_empty() {
-            return Ok(());
+            return Ok(0);
         }
 
         // Open (read only) the snapshot file.
@@ -606,30 +780,40 @@ impl MemoryManager {
             .map_err(Error::SnapshotOpen)?;
 
         let guest_memory = self.guest_memory.memory();
+        let mut file_offset: u64 = 0;
+        let mut copied: u64 = 0;
         for range in saved_regions.regions() {
-            let mut offset: u64 = 0;
-            // Here we are manually handling the retry in case we can't write
-            // the whole region at once because we can't use the implementation
-            // from vm-memory::GuestMemory of read_exact_from() as it is not
-            // following the correct behavior. For more info about this issue
-            // see: https://github.com/rust-vmm/vm-memory/issues/174
-            loop {
-                let bytes_read = guest_memory
-                    .read_from(
-                        GuestAddress(range.gpa + offset),
-                        &mut memory_file,
-                        (range.length - offset) as usize,
-                    )
-                    .map_err(Error::SnapshotCopy)?;
-                offset += bytes_read as u64;
-
-                if offset == range.length {
-                    break;
+            for (start, end) in Self::range_parts(range, dirty) {
+                memory_file
+                    .seek(SeekFrom::Start(file_offset + start))
+                    .map_err(Error::SnapshotSeek)?;
+
+                let mut offset = start;
+                // Here we are manually handling the retry in case we can't write
+                // the whole region at once because we can't use the implementation
+                // from vm-memory::GuestMemory of read_exact_from() as it is not
+                // following the correct behavior. For more info about this issue
+                // see: https://github.com/rust-vmm/vm-memory/issues/174
+                loop {
+                    let bytes_read = guest_memory
+                        .read_from(
+                            GuestAddress(range.gpa + offset),
+                            &mut memory_file,
+                            (end - offset) as usize,
+                        )
+                        .map_err(Error::SnapshotCopy)?;
+                    offset += bytes_read as u64;
+
+                    if offset == end {
+                        break;
+                    }
                 }
+                copied += end - start;
             }
+            file_offset += range.length;
         }
 
-        Ok(())
+        Ok(copied)
     }
 
     fn validate_memory_config(
@@ -757,6 +941,104 @@ impl MemoryManager {
         }
     }
 
+    // Default hugepage size of the host, in bytes.
+    fn default_hugepage_size() -> Result<u64, Error> {
+        let meminfo = std::fs::read_to_string("/proc/meminfo").map_err(Error::HugepagesInfo)?;
+        meminfo
+            .lines()
+            .find_map(|line| line.strip_prefix("Hugepagesize:"))
+            .and_then(|size| size.trim().strip_suffix("kB"))
+            .and_then(|size| size.trim().parse::<u64>().ok())
+            .map(|size| size << 10)
+            .ok_or_else(|| {
+                Error::HugepagesInfo(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Missing default hugepage size",
+                ))
+            })
+    }
+
+    // Number of free hugepages of the given size, either from the global
+    // pool or from the pool of a specific host NUMA node.
+    fn free_hugepages(hugepage_size: u64, host_numa_node: Option<u32>) -> Result<u64, Error> {
+        let pool = if let Some(node) = host_numa_node {
+            PathBuf::from(format!("/sys/devices/system/node/node{}/hugepages", node))
+        } else {
+            PathBuf::from("/sys/kernel/mm/hugepages")
+        }
+        .join(format!("hugepages-{}kB", hugepage_size >> 10));
+
+        let read_count = |name: &str| -> Result<u64, Error> {
+            std::fs::read_to_string(pool.join(name))
+                .map_err(Error::HugepagesInfo)?
+                .trim()
+                .parse::<u64>()
+                .map_err(|e| Error::HugepagesInfo(io::Error::new(io::ErrorKind::InvalidData, e)))
+        };
+
+        let free = read_count("free_hugepages")?;
+        // Reserved pages are counted as free although they are already
+        // promised to another mapping. This is only reported globally.
+        let reserved = if host_numa_node.is_none() {
+            read_count("resv_hugepages")?
+        } else {
+            0
+        };
+
+        Ok(free.saturating_sub(reserved))
+    }
+
+    /// Check the host has enough free hugepages to back the guest RAM
+    /// described by `config`, taking into account the NUMA node the memory
+    /// zones are bound to.
+    pub fn validate_hugepages(config: &MemoryConfig) -> Result<(), Error> {
+        let (_, zones, _) = Self::validate_memory_config(config, config.size == 0)?;
+
+        // Hugepages are only used for anonymous memory, a backing file is
+        // used as is.
+        let mut requested_pages: BTreeMap<(u64, Option<u32>), u64> = BTreeMap::new();
+        for zone in zones.iter().filter(|z| z.hugepages && z.file.is_none()) {
+            let hugepage_size = match zone.hugepage_size {
+                Some(hugepage_size) => hugepage_size,
+                None => Self::default_hugepage_size()?,
+            };
+            if zone.size % hugepage_size != 0 {
+                error!(
+                    "Memory zone '{}' size {} is not a multiple of the hugepage size {}",
+                    zone.id, zone.size, hugepage_size
+                );
+                return Err(Error::UnalignedHugepageZone {
+                    size: zone.size,
+                    hugepage_size,
+                });
+            }
+            *requested_pages
+                .entry((hugepage_size, zone.host_numa_node))
+                .or_default() += zone.size / hugepage_size;
+        }
+
+        for ((hugepage_size, host_numa_node), requested) in requested_pages {
+            let available = Self::free_hugepages(hugepage_size, host_numa_node)?;
+            if requested > available {
+                error!(
+                    "Not enough free {} KiB hugepages{}: requested {}, available {}",
+                    hugepage_size >> 10,
+                    host_numa_node
+                        .map(|node| format!(" on host NUMA node {}", node))
+                        .unwrap_or_default(),
+                    requested,
+                    available
+                );
+                return Err(Error::InsufficientHugepages {
+                    requested,
+                    available,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
     fn allocate_address_space(&mut self) -> Result<(), Error> {
         let mut list = Vec::new();
 
@@ -850,6 +1132,13 @@ impl MemoryManager {
         let (ram_size, zones, allow_mem_hotplug) =
             Self::validate_memory_config(config, user_provided_zones)?;
 
+        let init_start = Instant::now();
+
+        // Guest RAM is not allocated when reusing existing memory files
+        if existing_memory_files.is_none() {
+            Self::validate_hugepages(config)?;
+        }
+
         let (
             start_of_device_area,
             boot_ram,
@@ -904,8 +1193,36 @@ impl MemoryManager {
                 })
                 .collect();
 
-            let (mem_regions, mut memory_zones) =
-                Self::create_memory_regions_from_zones(&ram_regions, &zones, prefault)?;
+            // With a time limit, the regions are prefaulted once they are
+            // all mapped so that prefaulting can be stopped at any point.
+            let (mem_regions, mut memory_zones) = Self::create_memory_regions_from_zones(
+                &ram_regions,
+                &zones,
+                if config.prefault_timeout.is_some() {
+                    Some(false)
+                } else {
+                    prefault
+                },
+            )?;
+
+            if let Some(timeout) = config.prefault_timeout {
+                let deadline = init_start + Duration::from_millis(timeout);
+                let regions = zones
+                    .iter()
+                    .filter(|zone| zone.prefault)
+                    .filter_map(|zone| memory_zones.get(&zone.id))
+                    .flat_map(|memory_zone| memory_zone.regions().iter());
+                for region in regions {
+                    if !Self::prefault_region(region, Some(deadline))? {
+                        warn!(
+                            "Guest memory prefaulting exceeded {} ms, the \
+                            remaining memory will be faulted in on access",
+                            timeout
+                        );
+                        break;
+                    }
+                }
+            }
 
             let mut guest_memory =
                 GuestMemoryMmap::from_arc_regions(mem_regions).map_err(Error::GuestMemory)?;
@@ -999,6 +1316,12 @@ impl MemoryManager {
             )
         };
 
+        let init_duration = init_start.elapsed();
+        info!(
+            "Guest memory initialized in {} ms",
+            init_duration.as_millis()
+        );
+
         let guest_memory = GuestMemoryAtomic::new(guest_memory);
 
         // Both MMIO and PIO address spaces start at address 0.
@@ -1081,6 +1404,8 @@ impl MemoryManager {
             arch_mem_regions,
             ram_allocator,
             dynamic,
+            init_duration,
+            memory_locked: false,
         };
 
         memory_manager.allocate_address_space()?;
@@ -1089,6 +1414,11 @@ impl MemoryManager {
             memory_manager.setup_sgx(sgx_epc_config)?;
         }
 
+        if config.mlock {
+            let locked = memory_manager.lock_guest_memory()?;
+            info!("Locked {} bytes of guest memory", locked);
+        }
+
         Ok(Arc::new(Mutex::new(memory_manager)))
     }
 
@@ -1121,9 +1451,11 @@ impl MemoryManager {
                 None,
             )?;
 
-            mm.lock()
-                .unwrap()
-                .fill_saved_regions(memory_file_path, mem_snapshot.memory_ranges)?;
+            mm.lock().unwrap().fill_saved_regions(
+                memory_file_path,
+                &mem_snapshot.memory_ranges,
+                None,
+            )?;
 
             Ok(mm)
         } else {
@@ -1259,6 +1591,10 @@ impl MemoryManager {
             Self::open_memory_file(backing_file, file_offset, size, hugepages, hugepage_size)?
         };
 
+        // Unless prefaulting is requested, the guest RAM is only populated
+        // when first accessed, leaving the host kernel zero the pages lazily.
+        // This includes TDX guests, as only the TDVF sections are written to
+        // memory before boot.
         let mut mmap_flags = libc::MAP_NORESERVE
             | if shared {
                 libc::MAP_SHARED
@@ -1392,6 +1728,10 @@ impl MemoryManager {
 
         self.add_region(Arc::clone(&region))?;
 
+        if self.memory_locked {
+            Self::mlock_range(region.as_ptr(), region.len() as usize)?;
+        }
+
         Ok(region)
     }
 
@@ -1475,6 +1815,30 @@ impl MemoryManager {
         log_dirty: bool,
     ) -> Result<u32, Error> {
         let slot = self.allocate_memory_slot();
+        self.create_userspace_mapping_in_slot(
+            slot,
+            guest_phys_addr,
+            memory_size,
+            userspace_addr,
+            mergeable,
+            readonly,
+            log_dirty,
+        )?;
+
+        Ok(slot)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_userspace_mapping_in_slot(
+        &self,
+        slot: u32,
+        guest_phys_addr: u64,
+        memory_size: u64,
+        userspace_addr: u64,
+        mergeable: bool,
+        readonly: bool,
+        log_dirty: bool,
+    ) -> Result<(), Error> {
         let mem_region = self.vm.make_user_memory_region(
             slot,
             guest_phys_addr,
@@ -1523,7 +1887,7 @@ impl MemoryManager {
             guest_phys_addr, userspace_addr, memory_size
         );
 
-        Ok(slot)
+        Ok(())
     }
 
     pub fn remove_userspace_mapping(
@@ -1643,6 +2007,54 @@ impl MemoryManager {
         Ok(region)
     }
 
+    /// Hotplug method the guest memory was set up for.
+    pub fn hotplug_method(&self) -> HotplugMethod {
+        self.hotplug_method
+    }
+
+    /// Base address of the virtio-mem region of a memory zone, the default
+    /// one if `id` is None, along with the amount of memory plugged into it.
+    pub fn virtio_mem_plugged(&self, id: Option<&str>) -> Option<(GuestAddress, u64)> {
+        self.memory_zones
+            .get(id.unwrap_or(DEFAULT_MEMORY_ZONE))
+            .and_then(|memory_zone| memory_zone.virtio_mem_zone().as_ref())
+            .map(|virtio_mem_zone| {
+                (
+                    virtio_mem_zone.region().start_addr(),
+                    virtio_mem_zone.hotplugged_size,
+                )
+            })
+    }
+
+    /// Amount of RAM the guest was booted with.
+    pub fn boot_ram(&self) -> u64 {
+        self.boot_ram
+    }
+
+    /// Amount of RAM added to the guest since it was booted.
+    pub fn hotplugged_ram(&self) -> u64 {
+        self.current_ram.saturating_sub(self.boot_ram)
+    }
+
+    /// Amount of RAM added to the guest since it was booted, only counting
+    /// the virtio-mem blocks the guest actually plugged rather than the
+    /// requested size.
+    pub fn plugged_ram(&self) -> u64 {
+        let virtio_mem: u64 = self
+            .memory_zones
+            .values()
+            .filter_map(|memory_zone| memory_zone.virtio_mem_zone().as_ref())
+            .map(|virtio_mem_zone| virtio_mem_zone.plugged_size())
+            .sum();
+
+        // Without virtio-mem, the RAM is added through ACPI hotplug.
+        if self.virtio_mem_plugged(None).is_some() {
+            virtio_mem
+        } else {
+            self.hotplugged_ram() + virtio_mem
+        }
+    }
+
     pub fn resize_zone(&mut self, id: &str, virtio_mem_size: u64) -> Result<(), Error> {
         if !self.user_provided_zones {
             error!(
@@ -1777,6 +2189,598 @@ impl MemoryManager {
         &self.memory_zones
     }
 
+    /// Host files backing the guest RAM of each memory zone, sorted by zone.
+    /// Only the files from the host filesystem are listed: the anonymous
+    /// memory, backed by a memfd, and the unlinked files created when the
+    /// backing file is a directory are omitted.
+    pub fn memory_backing_files(&self) -> Vec<(String, PathBuf)> {
+        let mut files = Vec::new();
+
+        for (memory_zone_id, memory_zone) in self.memory_zones.iter() {
+            let virtio_mem_region = memory_zone
+                .virtio_mem_zone()
+                .as_ref()
+                .map(|virtio_mem_zone| virtio_mem_zone.region());
+
+            for region in memory_zone.regions().iter().chain(virtio_mem_region) {
+                let file = match region.file_offset() {
+                    Some(file_offset) => file_offset.file(),
+                    None => continue,
+                };
+                if !Self::is_hardlink(file) {
+                    continue;
+                }
+
+                match std::fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd())) {
+                    Ok(path) => files.push((memory_zone_id.clone(), path)),
+                    Err(e) => warn!(
+                        "Couldn't find the path of the file backing memory zone {}: {}",
+                        memory_zone_id, e
+                    ),
+                }
+            }
+        }
+
+        // Several regions of a zone can share the same file.
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    fn region_backing(region: &GuestRegionMmap) -> RegionBacking {
+        let file = match region.file_offset() {
+            Some(file_offset) => file_offset.file(),
+            None => return RegionBacking::Anonymous,
+        };
+
+        let mut statfs = std::mem::MaybeUninit::<libc::statfs>::uninit();
+        // SAFETY: FFI call with a valid fd and a correctly sized buffer.
+        let ret = unsafe { libc::fstatfs(file.as_raw_fd(), statfs.as_mut_ptr()) };
+        // SAFETY: statfs has been filled by the kernel if the call succeeded.
+        if ret == 0 && unsafe { statfs.assume_init() }.f_type as u64 == HUGETLBFS_MAGIC {
+            return RegionBacking::Hugepage;
+        }
+
+        // Anonymous memory is backed by a memfd, which has no link on the
+        // host filesystem.
+        if Self::is_hardlink(file) {
+            RegionBacking::File
+        } else {
+            RegionBacking::Anonymous
+        }
+    }
+
+    fn resident_pages(region: &GuestRegionMmap) -> Result<u64, Error> {
+        // SAFETY: FFI call. Trivially safe.
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let len = region.len() as usize;
+        let mut vec = vec![0u8; (len + page_size - 1) / page_size];
+
+        // SAFETY: the mapping is owned by the region and vec is large enough
+        // to hold one byte per page of the mapping.
+        let ret = unsafe {
+            libc::mincore(
+                region.as_ptr() as *mut libc::c_void,
+                len,
+                vec.as_mut_ptr() as *mut libc::c_uchar,
+            )
+        };
+        if ret != 0 {
+            return Err(Error::Mincore(io::Error::last_os_error()));
+        }
+
+        Ok(vec.iter().filter(|v| **v & 0x1 != 0).count() as u64)
+    }
+
+    /// List the guest RAM regions along with their host backing, sorted by
+    /// guest physical address. Counting the resident pages walks the whole
+    /// mapping, hence it is only done if `resident` is set.
+    pub fn memory_usage_by_region(&self, resident: bool) -> Result<Vec<RegionUsage>, Error> {
+        let mut usage = Vec::new();
+
+        for (memory_zone_id, memory_zone) in self.memory_zones.iter() {
+            let virtio_mem_region = memory_zone
+                .virtio_mem_zone()
+                .as_ref()
+                .map(|virtio_mem_zone| virtio_mem_zone.region());
+
+            for region in memory_zone.regions().iter().chain(virtio_mem_region) {
+                usage.push(RegionUsage {
+                    memory_zone: memory_zone_id.clone(),
+                    start: region.start_addr().raw_value(),
+                    size: region.len(),
+                    backing: Self::region_backing(region),
+                    host_address: region.as_ptr() as u64,
+                    resident_pages: if resident {
+                        Some(Self::resident_pages(region)?)
+                    } else {
+                        None
+                    },
+                });
+            }
+        }
+
+        usage.sort_by_key(|region| region.start);
+
+        Ok(usage)
+    }
+
+    fn wipe_region(region: &GuestRegionMmap) -> Result<(), Error> {
+        let addr = region.as_ptr();
+        let len = region.len() as usize;
+
+        if Self::region_backing(region) == RegionBacking::File {
+            // The backing file lives on the host filesystem, explicitly zero
+            // it and make sure the zeroes reach the file before it's closed.
+            // SAFETY: the mapping is owned by the region and is len bytes
+            // long. The guest can't access it anymore at this point.
+            unsafe { std::ptr::write_bytes(addr, 0, len) };
+            if region.flags() & libc::MAP_SHARED == libc::MAP_SHARED {
+                // SAFETY: FFI call with a valid mapping.
+                let ret = unsafe { libc::msync(addr as *mut libc::c_void, len, libc::MS_SYNC) };
+                if ret != 0 {
+                    return Err(Error::WipeMemory(io::Error::last_os_error()));
+                }
+            }
+            return Ok(());
+        }
+
+        // Anonymous and hugepage memory is backed by a memfd or a hugetlbfs
+        // file, punching a hole releases the pages from the page cache.
+        if let Some(file_offset) = region.file_offset() {
+            // SAFETY: FFI call with a valid fd.
+            let ret = unsafe {
+                libc::fallocate(
+                    file_offset.file().as_raw_fd(),
+                    libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                    file_offset.start() as libc::off_t,
+                    len as libc::off_t,
+                )
+            };
+            if ret != 0 {
+                let err = io::Error::last_os_error();
+                warn!(
+                    "Could not punch hole in guest memory backing: {}, zeroing it instead",
+                    err
+                );
+                // SAFETY: the mapping is owned by the region and is len
+                // bytes long.
+                unsafe { std::ptr::write_bytes(addr, 0, len) };
+            }
+        }
+
+        // Drop the private copies of the pages.
+        // SAFETY: FFI call with a valid mapping.
+        let ret = unsafe { libc::madvise(addr as *mut libc::c_void, len, libc::MADV_DONTNEED) };
+        if ret != 0 {
+            return Err(Error::WipeMemory(io::Error::last_os_error()));
+        }
+
+        Ok(())
+    }
+
+    /// Populate the whole RAM of memory zone `id`, so that the guest doesn't
+    /// take page faults when first accessing it. The memory of a virtio-mem
+    /// device attached to the zone is left as is.
+    pub fn prefault_zone(&self, id: &str) -> Result<ZonePrefault, Error> {
+        let start = Instant::now();
+        let memory_zone = self.memory_zones.get(id).ok_or(Error::UnknownMemoryZone)?;
+
+        let mut faulted_pages = 0;
+        for region in memory_zone.regions() {
+            let resident = Self::resident_pages(region)?;
+            Self::prefault_region(region, None)?;
+            faulted_pages += Self::resident_pages(region)?.saturating_sub(resident);
+        }
+
+        Ok(ZonePrefault {
+            memory_zone: id.to_string(),
+            faulted_pages,
+            duration_ms: start.elapsed().as_millis() as u64,
+        })
+    }
+
+    /// Write the guest RAM to a new qcow2 image at `path`, the guest physical
+    /// addresses being used as offsets into the image. Zeroed memory isn't
+    /// written, keeping the image sparse. Returns the number of bytes of
+    /// guest RAM written. The guest must not run while dumping.
+    pub fn dump_memory_qcow(&self, path: &Path) -> Result<u64, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(Error::QcowDumpFile)?;
+        let guest_memory = self.guest_memory.memory();
+        let virtual_size = guest_memory.last_addr().raw_value() + 1;
+        let mut qcow = qcow::QcowFile::new(qcow::RawFile::new(file, false), 3, virtual_size)
+            .map_err(Error::QcowDumpCreate)?;
+
+        let mut buf = vec![0u8; QCOW_DUMP_CHUNK_SIZE];
+        let mut written = 0;
+        for region in guest_memory.iter() {
+            let start = region.start_addr().raw_value();
+            let mut offset = 0;
+            while offset < region.len() {
+                let len =
+                    std::cmp::min(QCOW_DUMP_CHUNK_SIZE as u64, region.len() - offset) as usize;
+                let chunk = &mut buf[..len];
+                guest_memory
+                    .read_slice(chunk, GuestAddress(start + offset))
+                    .map_err(Error::QcowDumpRead)?;
+                // Clusters never written to read back as zeroes.
+                if chunk.iter().any(|b| *b != 0) {
+                    qcow.seek(SeekFrom::Start(start + offset))
+                        .map_err(Error::QcowDumpWrite)?;
+                    qcow.write_all(chunk).map_err(Error::QcowDumpWrite)?;
+                    written += len as u64;
+                }
+                offset += len as u64;
+            }
+        }
+        qcow.flush().map_err(Error::QcowDumpWrite)?;
+
+        Ok(written)
+    }
+
+    /// Zero the whole guest RAM so that its content doesn't linger on the
+    /// host once the VM is gone. This must only be called after the vCPUs
+    /// and the devices are stopped. Returns the time it took.
+    pub fn wipe_guest_memory(&self) -> Result<Duration, Error> {
+        let start = Instant::now();
+
+        for region in self.guest_memory.memory().iter() {
+            Self::wipe_region(region)?;
+        }
+
+        Ok(start.elapsed())
+    }
+
+    // Populate the region chunk by chunk until the deadline, if any, is
+    // reached. Returns whether the whole region could be prefaulted in time.
+    fn prefault_region(region: &GuestRegionMmap, deadline: Option<Instant>) -> Result<bool, Error> {
+        let addr = region.as_ptr();
+        let len = region.len() as usize;
+
+        let mut offset = 0;
+        while offset < len {
+            if matches!(deadline, Some(deadline) if Instant::now() >= deadline) {
+                return Ok(false);
+            }
+
+            let size = PREFAULT_CHUNK_SIZE.min(len - offset);
+            // SAFETY: FFI call with a valid mapping. Populating the pages
+            // doesn't change their content.
+            let ret = unsafe {
+                libc::madvise(
+                    addr.add(offset) as *mut libc::c_void,
+                    size,
+                    MADV_POPULATE_WRITE,
+                )
+            };
+            if ret != 0 {
+                return Err(Error::PrefaultMemory(io::Error::last_os_error()));
+            }
+            offset += size;
+        }
+
+        Ok(true)
+    }
+
+    fn mlock_range(addr: *mut u8, len: usize) -> Result<(), Error> {
+        // SAFETY: FFI call with a valid mapping.
+        let ret = unsafe { libc::mlock(addr as *const libc::c_void, len) };
+        if ret != 0 {
+            let error = io::Error::last_os_error();
+            let mut rlimit = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            // SAFETY: FFI call with a valid rlimit struct.
+            let limit = if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut rlimit) } == 0
+                && rlimit.rlim_cur != libc::RLIM_INFINITY
+            {
+                Some(rlimit.rlim_cur as u64)
+            } else {
+                None
+            };
+            return Err(Error::LockMemory { error, limit });
+        }
+
+        Ok(())
+    }
+
+    // The virtio-mem regions are left out as locking them would populate
+    // the memory which hasn't been plugged yet.
+    fn lockable_ram(&self) -> Vec<(*mut u8, usize)> {
+        let guest_memory = self.guest_memory.memory();
+        self.guest_ram_mappings
+            .iter()
+            .filter(|mapping| !mapping.virtio_mem)
+            .filter_map(|mapping| {
+                guest_memory
+                    .get_host_address(GuestAddress(mapping.gpa))
+                    .ok()
+                    .map(|addr| (addr, mapping.size as usize))
+            })
+            .collect()
+    }
+
+    /// Lock the guest RAM in host memory so that it can't be swapped out,
+    /// returning the number of bytes locked. RAM hotplugged afterwards is
+    /// locked as well, except for virtio-mem devices.
+    pub fn lock_guest_memory(&mut self) -> Result<u64, Error> {
+        let mut locked = 0;
+        for (addr, len) in self.lockable_ram() {
+            if let Err(e) = Self::mlock_range(addr, len) {
+                // Don't leave the guest RAM partially locked
+                self.memory_locked = true;
+                let _ = self.unlock_guest_memory();
+                return Err(e);
+            }
+            locked += len as u64;
+        }
+        self.memory_locked = true;
+
+        Ok(locked)
+    }
+
+    /// Let the host swap the guest RAM out again, returning the number of
+    /// bytes unlocked.
+    pub fn unlock_guest_memory(&mut self) -> Result<u64, Error> {
+        let mut unlocked = 0;
+        for (addr, len) in self.lockable_ram() {
+            // SAFETY: FFI call with a valid mapping.
+            let ret = unsafe { libc::munlock(addr as *const libc::c_void, len) };
+            if ret != 0 {
+                return Err(Error::UnlockMemory(io::Error::last_os_error()));
+            }
+            unlocked += len as u64;
+        }
+        self.memory_locked = false;
+
+        Ok(unlocked)
+    }
+
+    pub fn memory_locked(&self) -> bool {
+        self.memory_locked
+    }
+
+    /// Time it took to create and, if requested, prefault the guest RAM.
+    pub fn init_duration(&self) -> Duration {
+        self.init_duration
+    }
+
+    /// Let the host reclaim the guest RAM, the pages are faulted back in
+    /// when the guest accesses them again. Pages pinned for device DMA are
+    /// left untouched by the kernel. Requires Linux 5.4 or newer.
+    pub fn reclaim_guest_memory(&self, advice: MemoryReclaimAdvice) -> Result<(), Error> {
+        let advice = match advice {
+            MemoryReclaimAdvice::Cold => MADV_COLD,
+            MemoryReclaimAdvice::Pageout => MADV_PAGEOUT,
+        };
+
+        for region in self.guest_memory.memory().iter() {
+            // SAFETY: FFI call with a valid mapping. These advices don't
+            // change the content of the memory.
+            let ret = unsafe {
+                libc::madvise(
+                    region.as_ptr() as *mut libc::c_void,
+                    region.len() as usize,
+                    advice,
+                )
+            };
+            if ret != 0 {
+                return Err(Error::ReclaimMemory(io::Error::last_os_error()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Map the file at `backing` as the new, shared, backing of the memory
+    /// zone `id`. The file is created, and sized, if it doesn't exist or is
+    /// empty. The guest keeps using the current backing until the migration
+    /// is completed.
+    pub fn prepare_zone_migration(&self, id: &str, backing: &Path) -> Result<ZoneMigration, Error> {
+        let memory_zone = self.memory_zones.get(id).ok_or(Error::UnknownMemoryZone)?;
+        if memory_zone.virtio_mem_zone().is_some() {
+            return Err(Error::ZoneMigrationVirtioMem);
+        }
+        let zone_size: u64 = memory_zone.regions().iter().map(|r| r.len()).sum();
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(backing)
+            .map_err(Error::ZoneMigrationOpen)?;
+        // Block devices have no length in their metadata.
+        let mut size = file
+            .seek(SeekFrom::End(0))
+            .map_err(Error::ZoneMigrationOpen)?;
+        if size == 0 && file.metadata().map_err(Error::ZoneMigrationOpen)?.is_file() {
+            file.set_len(zone_size).map_err(Error::ZoneMigrationOpen)?;
+            size = zone_size;
+        }
+        if size < zone_size {
+            return Err(Error::ZoneMigrationSize(size, zone_size));
+        }
+
+        let mut regions = Vec::new();
+        let mut file_offset = 0;
+        for region in memory_zone.regions() {
+            let new_region = MemoryManager::create_ram_region(
+                &None,
+                file_offset,
+                region.start_addr(),
+                region.len() as usize,
+                false,
+                true,
+                false,
+                None,
+                None,
+                Some(file.try_clone().map_err(Error::ZoneMigrationOpen)?),
+            )?;
+            regions.push(new_region);
+            file_offset += region.len();
+        }
+
+        Ok(ZoneMigration {
+            memory_zone: id.to_string(),
+            regions,
+        })
+    }
+
+    /// Copy the memory zone content to its new backing, either entirely or
+    /// only the given `ranges`. Returns the number of bytes copied.
+    pub fn copy_zone_ranges(
+        &self,
+        migration: &ZoneMigration,
+        ranges: Option<&MemoryRangeTable>,
+    ) -> Result<u64, Error> {
+        Self::copy_regions(&self.guest_memory.memory(), &migration.regions, ranges)
+    }
+
+    // Copy the content of `guest_memory` to the `regions` at the same guest
+    // addresses, either entirely or only the given `ranges`.
+    fn copy_regions(
+        guest_memory: &GuestMemoryMmap,
+        regions: &[Arc<GuestRegionMmap>],
+        ranges: Option<&MemoryRangeTable>,
+    ) -> Result<u64, Error> {
+        let mut copied = 0;
+        for new_region in regions {
+            let old_region = guest_memory.find_region(new_region.start_addr()).ok_or(
+                Error::ZoneMigrationCopy(GuestMemoryError::InvalidGuestAddress(
+                    new_region.start_addr(),
+                )),
+            )?;
+            let range = MemoryRange {
+                gpa: new_region.start_addr().raw_value(),
+                length: new_region.len(),
+            };
+
+            for (start, end) in Self::range_parts(&range, ranges) {
+                let offset = MemoryRegionAddress(start);
+                let len = (end - start) as usize;
+                let src = old_region
+                    .get_slice(offset, len)
+                    .map_err(Error::ZoneMigrationCopy)?;
+                let dst = new_region
+                    .get_slice(offset, len)
+                    .map_err(Error::ZoneMigrationCopy)?;
+                src.copy_to_volatile_slice(dst);
+                copied += len as u64;
+            }
+        }
+
+        Ok(copied)
+    }
+
+    /// Switch the guest over to the new backing of the memory zone. The
+    /// vCPUs and the devices must be paused, and the whole zone copied.
+    /// Should switching a region fail, the guest keeps using the previous
+    /// backing for it and the regions that follow.
+    pub fn complete_zone_migration(&mut self, migration: ZoneMigration) -> Result<(), Error> {
+        for (index, new_region) in migration.regions.iter().enumerate() {
+            self.switch_zone_region(new_region)?;
+            if let Some(memory_zone) = self.memory_zones.get_mut(&migration.memory_zone) {
+                memory_zone.regions[index] = Arc::clone(new_region);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Replace the guest RAM region at the address of `new_region` with it,
+    // keeping the memory slot of the previous one.
+    fn switch_zone_region(&mut self, new_region: &Arc<GuestRegionMmap>) -> Result<(), Error> {
+        let addr = new_region.start_addr();
+        let gpa = addr.raw_value();
+        let size = new_region.len();
+        let old_host_addr = self
+            .guest_memory
+            .memory()
+            .get_host_address(addr)
+            .map_err(Error::ZoneMigrationCopy)? as u64;
+        let mapping = self
+            .guest_ram_mappings
+            .iter()
+            .position(|m| m.gpa == gpa)
+            .ok_or(Error::ZoneMigrationCopy(
+                GuestMemoryError::InvalidGuestAddress(addr),
+            ))?;
+        let slot = self.guest_ram_mappings[mapping].slot;
+
+        // Do everything that can fail before the guest mapping is changed.
+        let (guest_memory, _) = self
+            .guest_memory
+            .memory()
+            .remove_region(addr, size)
+            .map_err(Error::GuestMemory)?;
+        let guest_memory = guest_memory
+            .insert_region(Arc::clone(new_region))
+            .map_err(Error::GuestMemory)?;
+        let boot_guest_memory = if self.boot_guest_memory.find_region(addr).is_some() {
+            let (boot_guest_memory, _) = self
+                .boot_guest_memory
+                .remove_region(addr, size)
+                .map_err(Error::GuestMemory)?;
+            Some(
+                boot_guest_memory
+                    .insert_region(Arc::clone(new_region))
+                    .map_err(Error::GuestMemory)?,
+            )
+        } else {
+            None
+        };
+        if self.memory_locked {
+            Self::mlock_range(new_region.as_ptr(), size as usize)?;
+        }
+        // The copy marked the new pages as written by the VMM.
+        new_region.bitmap().reset();
+
+        // Reuse the slot, not to run out of memory slots after a few
+        // migrations.
+        self.remove_userspace_mapping(gpa, size, old_host_addr, self.mergeable, slot)?;
+        if let Err(e) = self.create_userspace_mapping_in_slot(
+            slot,
+            gpa,
+            size,
+            new_region.as_ptr() as u64,
+            self.mergeable,
+            false,
+            self.log_dirty,
+        ) {
+            // Don't leave a hole in the guest RAM.
+            if let Err(e) = self.create_userspace_mapping_in_slot(
+                slot,
+                gpa,
+                size,
+                old_host_addr,
+                self.mergeable,
+                false,
+                self.log_dirty,
+            ) {
+                error!(
+                    "Error restoring the guest RAM mapping at 0x{:x}: {:?}",
+                    gpa, e
+                );
+            }
+            return Err(e);
+        }
+
+        self.guest_ram_mappings[mapping].file_offset =
+            new_region.file_offset().map(|f| f.start()).unwrap_or(0);
+        self.guest_memory.lock().unwrap().replace(guest_memory);
+        if let Some(boot_guest_memory) = boot_guest_memory {
+            self.boot_guest_memory = boot_guest_memory;
+        }
+
+        Ok(())
+    }
+
     pub fn memory_range_table(
         &self,
         snapshot: bool,
@@ -1818,6 +2822,14 @@ impl MemoryManager {
         Ok(table)
     }
 
+    /// Size of the guest memory content which would be written by a
+    /// snapshot taken now.
+    pub fn snapshot_memory_size(&self) -> u64 {
+        self.memory_range_table(true)
+            .map(|table| table.regions().iter().map(|r| r.length).sum())
+            .unwrap_or(0)
+    }
+
     pub fn snapshot_data(&self) -> MemoryManagerSnapshotData {
         MemoryManagerSnapshotData {
             memory_ranges: self.snapshot_memory_ranges.clone(),
@@ -1833,6 +2845,39 @@ impl MemoryManager {
         }
     }
 
+    /// Restore the guest memory in place from the snapshot saved at
+    /// `source_url`, whose memory manager state is `snapshot_data`. Only the
+    /// pages covered by `dirty` are copied back when provided. The snapshot
+    /// must have been taken with the same guest memory layout. Returns the
+    /// number of bytes restored.
+    pub fn restore_in_place(
+        &mut self,
+        snapshot_data: &MemoryManagerSnapshotData,
+        source_url: &str,
+        dirty: Option<&MemoryRangeTable>,
+    ) -> Result<u64, Error> {
+        if !self.snapshot_compatible(snapshot_data) {
+            return Err(Error::SnapshotLayoutMismatch);
+        }
+
+        let mut memory_file_path = url_to_path(source_url).map_err(Error::Restore)?;
+        memory_file_path.push(String::from(SNAPSHOT_FILENAME));
+
+        self.fill_saved_regions(memory_file_path, &snapshot_data.memory_ranges, dirty)
+    }
+
+    /// Whether `snapshot_data` describes the same guest RAM as the current
+    /// one, meaning the snapshot can be restored in place.
+    pub fn snapshot_compatible(&self, snapshot_data: &MemoryManagerSnapshotData) -> bool {
+        self.current_ram == snapshot_data.current_ram
+            && self.guest_ram_mappings.len() == snapshot_data.guest_ram_mappings.len()
+            && self
+                .guest_ram_mappings
+                .iter()
+                .zip(snapshot_data.guest_ram_mappings.iter())
+                .all(|(a, b)| a.slot == b.slot && a.gpa == b.gpa && a.size == b.size)
+    }
+
     pub fn memory_slot_fds(&self) -> HashMap<u32, RawFd> {
         let mut memory_slot_fds = HashMap::new();
         for guest_ram_mapping in &self.guest_ram_mappings {
@@ -1849,6 +2894,33 @@ impl MemoryManager {
         memory_slot_fds
     }
 
+    /// Guest address, size, file descriptor and file offset of every guest
+    /// RAM region, so that another process can map the guest memory. All
+    /// the regions must be backed by a file mapped as shared, otherwise the
+    /// other process wouldn't see the guest writes.
+    pub fn export_memory_fds(&self) -> Result<Vec<(GuestAddress, u64, RawFd, u64)>, Error> {
+        let guest_memory = self.guest_memory.memory();
+        let mut memory_fds = Vec::new();
+        for guest_ram_mapping in &self.guest_ram_mappings {
+            let gpa = GuestAddress(guest_ram_mapping.gpa);
+            let region = guest_memory
+                .find_region(gpa)
+                .ok_or(Error::UnsharedMemoryRegion(gpa))?;
+            let file_offset = region
+                .file_offset()
+                .filter(|_| region.flags() & libc::MAP_SHARED != 0)
+                .ok_or(Error::UnsharedMemoryRegion(gpa))?;
+            memory_fds.push((
+                gpa,
+                guest_ram_mapping.size,
+                file_offset.file().as_raw_fd(),
+                guest_ram_mapping.file_offset,
+            ));
+        }
+
+        Ok(memory_fds)
+    }
+
     pub fn acpi_address(&self) -> Option<GuestAddress> {
         self.acpi_address
     }
@@ -2313,20 +3385,37 @@ impl Transportable for MemoryManager {
         _snapshot: &Snapshot,
         destination_url: &str,
     ) -> result::Result<(), MigratableError> {
-        if self.snapshot_memory_ranges.is_empty() {
+        let stream = url_to_stream(destination_url)?;
+
+        if self.snapshot_memory_ranges.is_empty() && stream.is_none() {
             return Ok(());
         }
 
-        let mut memory_file_path = url_to_path(destination_url)?;
-        memory_file_path.push(String::from(SNAPSHOT_FILENAME));
+        let mut memory_file = if let Some(mut stream) = stream {
+            // The memory is the last section of the stream, its length is
+            // the sum of the ranges being written.
+            let length: u64 = self
+                .snapshot_memory_ranges
+                .regions()
+                .iter()
+                .map(|r| r.length)
+                .sum();
+            stream
+                .write_all(&length.to_le_bytes())
+                .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+            stream
+        } else {
+            let mut memory_file_path = url_to_path(destination_url)?;
+            memory_file_path.push(String::from(SNAPSHOT_FILENAME));
 
-        // Create the snapshot file for the entire memory
-        let mut memory_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create_new(true)
-            .open(memory_file_path)
-            .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+            // Create the snapshot file for the entire memory
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(memory_file_path)
+                .map_err(|e| MigratableError::MigrateSend(e.into()))?
+        };
 
         let guest_memory = self.guest_memory.memory();
 
@@ -2426,3 +3515,87 @@ impl Migratable for MemoryManager {
         Ok(table)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range_table(ranges: &[(u64, u64)]) -> MemoryRangeTable {
+        let mut table = MemoryRangeTable::default();
+        for (gpa, length) in ranges {
+            table.push(MemoryRange {
+                gpa: *gpa,
+                length: *length,
+            });
+        }
+        table
+    }
+
+    #[test]
+    fn test_range_parts() {
+        let range = MemoryRange {
+            gpa: 0x10_0000,
+            length: 0x10_0000,
+        };
+
+        assert_eq!(
+            MemoryManager::range_parts(&range, None),
+            vec![(0, 0x10_0000)]
+        );
+
+        // Pages outside of the range are ignored, the ones straddling its
+        // bounds clipped.
+        let dirty = range_table(&[
+            (0, 0x1000),
+            (0xf_f000, 0x2000),
+            (0x14_0000, 0x3000),
+            (0x1f_f000, 0x2000),
+            (0x20_0000, 0x1000),
+        ]);
+        assert_eq!(
+            MemoryManager::range_parts(&range, Some(&dirty)),
+            vec![(0, 0x1000), (0x4_0000, 0x4_3000), (0xf_f000, 0x10_0000)]
+        );
+
+        assert!(MemoryManager::range_parts(&range, Some(&MemoryRangeTable::default())).is_empty());
+    }
+
+    #[test]
+    fn test_copy_regions() {
+        let guest_memory = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x4000)]).unwrap();
+        for page in 0..4u8 {
+            guest_memory
+                .write_slice(&[page + 1; 0x1000], GuestAddress(page as u64 * 0x1000))
+                .unwrap();
+        }
+        let regions = vec![Arc::new(
+            GuestRegionMmap::new(MmapRegion::new(0x4000).unwrap(), GuestAddress(0)).unwrap(),
+        )];
+        let new_memory = GuestMemoryMmap::from_arc_regions(regions.clone()).unwrap();
+        let page = |addr: u64| {
+            let mut data = [0u8; 0x1000];
+            new_memory
+                .read_slice(&mut data, GuestAddress(addr))
+                .unwrap();
+            data
+        };
+
+        // Only the parts of the ranges within the regions are copied.
+        let ranges = range_table(&[(0x1000, 0x1000), (0x3000, 0x2000), (0x8000, 0x1000)]);
+        assert_eq!(
+            MemoryManager::copy_regions(&guest_memory, &regions, Some(&ranges)).unwrap(),
+            0x2000
+        );
+        assert_eq!(page(0), [0; 0x1000]);
+        assert_eq!(page(0x1000), [2; 0x1000]);
+        assert_eq!(page(0x2000), [0; 0x1000]);
+        assert_eq!(page(0x3000), [4; 0x1000]);
+
+        assert_eq!(
+            MemoryManager::copy_regions(&guest_memory, &regions, None).unwrap(),
+            0x4000
+        );
+        assert_eq!(page(0), [1; 0x1000]);
+        assert_eq!(page(0x2000), [3; 0x1000]);
+    }
+}
@@ -9,21 +9,313 @@ use crate::{
     vm::{VmSnapshot, VM_SNAPSHOT_ID},
 };
 use anyhow::anyhow;
-use std::fs::File;
-use std::io::BufReader;
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 use vm_migration::{MigratableError, Snapshot};
 
 pub const SNAPSHOT_STATE_FILE: &str = "state.json";
 pub const SNAPSHOT_CONFIG_FILE: &str = "config.json";
 
-pub fn url_to_path(url: &str) -> std::result::Result<PathBuf, MigratableError> {
-    let path: PathBuf = url
-        .strip_prefix("file://")
+/// Summary of the VM configuration stored along with a snapshot.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SnapshotConfigSummary {
+    pub boot_vcpus: u8,
+    pub max_vcpus: u8,
+    pub memory_size: u64,
+    pub kernel: Option<PathBuf>,
+}
+
+/// Description of a snapshot directory, as returned by list_snapshots().
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SnapshotInfo {
+    /// Name of the snapshot directory.
+    pub id: String,
+    pub path: PathBuf,
+    /// Modification time of the snapshot, in seconds since the epoch.
+    pub timestamp: Option<u64>,
+    /// Size of all the snapshot files, memory included.
+    pub size: u64,
+    pub config: Option<SnapshotConfigSummary>,
+    /// Whether the snapshot is complete and can be restored from.
+    pub valid: bool,
+    /// Reason why the snapshot is invalid.
+    pub error: Option<String>,
+}
+
+fn read_snapshot_info(path: &Path) -> SnapshotInfo {
+    let mut info = SnapshotInfo {
+        id: path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        path: path.to_path_buf(),
+        timestamp: None,
+        size: 0,
+        config: None,
+        valid: false,
+        error: None,
+    };
+
+    if let Ok(entries) = fs::read_dir(path) {
+        for metadata in entries.flatten().filter_map(|e| e.metadata().ok()) {
+            if metadata.is_file() {
+                info.size += metadata.len();
+            }
+        }
+    }
+
+    let config_path = path.join(SNAPSHOT_CONFIG_FILE);
+    info.timestamp = fs::metadata(&config_path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs());
+
+    let config: VmConfig = match File::open(&config_path)
+        .map_err(|e| e.to_string())
+        .and_then(|f| serde_json::from_reader(BufReader::new(f)).map_err(|e| e.to_string()))
+    {
+        Ok(config) => config,
+        Err(e) => {
+            info.error = Some(format!("Invalid {}: {}", SNAPSHOT_CONFIG_FILE, e));
+            return info;
+        }
+    };
+    info.config = Some(SnapshotConfigSummary {
+        boot_vcpus: config.cpus.boot_vcpus,
+        max_vcpus: config.cpus.max_vcpus,
+        memory_size: config.memory.total_size(),
+        kernel: config.kernel.map(|k| k.path),
+    });
+
+    // The state is small compared to the guest memory, which is stored in
+    // separate files and never read here.
+    if let Err(e) = File::open(path.join(SNAPSHOT_STATE_FILE))
+        .map_err(|e| e.to_string())
+        .and_then(|f| {
+            serde_json::from_reader::<_, Snapshot>(BufReader::new(f)).map_err(|e| e.to_string())
+        })
+    {
+        info.error = Some(format!("Invalid {}: {}", SNAPSHOT_STATE_FILE, e));
+        return info;
+    }
+
+    info.valid = true;
+    info
+}
+
+/// List the snapshots stored as subdirectories of `dir`, sorted by name.
+/// Incomplete or corrupted snapshots are reported as invalid. Directories
+/// containing none of the snapshot files are ignored.
+pub fn list_snapshots(dir: &Path) -> io::Result<Vec<SnapshotInfo>> {
+    let mut snapshots = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_dir()
+            || !(path.join(SNAPSHOT_CONFIG_FILE).exists()
+                || path.join(SNAPSHOT_STATE_FILE).exists())
+        {
+            continue;
+        }
+
+        snapshots.push(read_snapshot_info(&path));
+    }
+
+    snapshots.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Ok(snapshots)
+}
+
+/// Location of a snapshot, as described by its URL.
+pub enum SnapshotUrl {
+    /// `file://<dir>`: each part of the snapshot is stored as a file in
+    /// the directory.
+    Path(PathBuf),
+    /// `fd://<n>`: the parts of the snapshot are streamed one after the
+    /// other through the file descriptor, each one prefixed by its length
+    /// as a little endian u64. The file descriptor is left open.
+    Fd(RawFd),
+}
+
+pub fn parse_snapshot_url(url: &str) -> std::result::Result<SnapshotUrl, MigratableError> {
+    if let Some(path) = url.strip_prefix("file://") {
+        return Ok(SnapshotUrl::Path(path.into()));
+    }
+
+    if let Some(fd) = url.strip_prefix("fd://") {
+        let fd = fd.parse::<RawFd>().map_err(|e| {
+            MigratableError::MigrateSend(anyhow!("Invalid file descriptor in URL {}: {}", url, e))
+        })?;
+        return Ok(SnapshotUrl::Fd(fd));
+    }
+
+    Err(MigratableError::MigrateSend(anyhow!(
+        "Unsupported URL scheme: {} (expected file:// or fd://)",
+        url
+    )))
+}
+
+/// How `Vm::migrate_to()` sends the guest memory to the destination.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MigrationPolicy {
+    /// Send the guest memory file descriptors instead of the memory itself,
+    /// for a destination on the same host. Requires shared memory.
+    pub local: bool,
+    /// Maximum number of dirty memory passes while the VM runs.
+    pub max_dirty_passes: usize,
+    /// Dirty memory, in bytes, small enough for the VM to be paused while
+    /// sending it. The pre-copy stops as soon as a pass sends no more.
+    pub downtime_threshold: u64,
+}
+
+impl Default for MigrationPolicy {
+    fn default() -> Self {
+        MigrationPolicy {
+            local: false,
+            max_dirty_passes: 5,
+            downtime_threshold: 0,
+        }
+    }
+}
+
+/// Phase of a migration, as reported by `Vm::migrate_to()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MigrationPhase {
+    /// The destination accepted the migration.
+    Started,
+    /// The destination created the VM from the configuration.
+    ConfigSent,
+    /// Memory pre-copy pass, pass 0 sending the whole guest memory.
+    MemoryPass { pass: usize, bytes: u64 },
+    /// The VM was paused, and the memory dirtied since the last pass sent.
+    StopAndCopy { bytes: u64 },
+    /// The destination restored the VM state.
+    StateSent,
+    /// The destination resumed the VM.
+    Completed,
+    /// The migration failed, and the VM was resumed on the source.
+    Failed(String),
+}
+
+/// Options set on the migration socket before any transfer, on both ends.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MigrationSocketOptions {
+    /// Size of the socket send buffer (SO_SNDBUF), in bytes.
+    pub send_buffer_size: Option<u32>,
+    /// Size of the socket receive buffer (SO_RCVBUF), in bytes.
+    pub recv_buffer_size: Option<u32>,
+}
+
+fn set_socket_option(fd: RawFd, level: i32, name: i32, value: &[u8]) -> io::Result<()> {
+    // SAFETY: the value is valid for value.len() bytes, which is all the
+    // kernel reads.
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            value.as_ptr() as *const libc::c_void,
+            value.len() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+fn get_socket_option(fd: RawFd, level: i32, name: i32, value: &mut [u8]) -> io::Result<usize> {
+    let mut len = value.len() as libc::socklen_t;
+    // SAFETY: the value is valid for len bytes, which is all the kernel
+    // writes, and len is valid.
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            level,
+            name,
+            value.as_mut_ptr() as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(len as usize)
+}
+
+fn set_socket_option_int(fd: RawFd, level: i32, name: i32, value: i32) -> io::Result<()> {
+    set_socket_option(fd, level, name, &value.to_ne_bytes())
+}
+
+fn get_socket_option_int(fd: RawFd, level: i32, name: i32) -> io::Result<i32> {
+    let mut value = [0u8; 4];
+    get_socket_option(fd, level, name, &mut value)?;
+    Ok(i32::from_ne_bytes(value))
+}
+
+impl MigrationSocketOptions {
+    fn apply_to_fd(&self, fd: RawFd) -> io::Result<()> {
+        for (size, name, what) in [
+            (self.send_buffer_size, libc::SO_SNDBUF, "send"),
+            (self.recv_buffer_size, libc::SO_RCVBUF, "receive"),
+        ] {
+            if let Some(size) = size {
+                set_socket_option_int(fd, libc::SOL_SOCKET, name, size as i32)?;
+                // The kernel doubles the requested size for its bookkeeping,
+                // after capping it to net.core.wmem_max or rmem_max.
+                let effective = get_socket_option_int(fd, libc::SOL_SOCKET, name)? as u32;
+                if effective / 2 < size {
+                    warn!(
+                        "Migration socket {} buffer capped to {} bytes, {} requested",
+                        what,
+                        effective / 2,
+                        size
+                    );
+                }
+                info!("Migration socket {} buffer: {} bytes", what, effective);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Set the options on `socket`, checking they were applied and logging
+    /// the values in effect.
+    pub fn apply<T: AsRawFd>(&self, socket: &T) -> std::result::Result<(), MigratableError> {
+        if *self == Self::default() {
+            return Ok(());
+        }
+
+        self.apply_to_fd(socket.as_raw_fd())
+            .map_err(MigratableError::MigrateSocket)
+    }
+}
+
+pub fn socket_url_to_path(url: &str) -> std::result::Result<PathBuf, MigratableError> {
+    url.strip_prefix("unix:")
         .ok_or_else(|| {
             MigratableError::MigrateSend(anyhow!("Could not extract path from URL: {}", url))
         })
-        .map(|s| s.into())?;
+        .map(|s| s.into())
+}
+
+pub fn url_to_path(url: &str) -> std::result::Result<PathBuf, MigratableError> {
+    let path = match parse_snapshot_url(url)? {
+        SnapshotUrl::Path(path) => path,
+        SnapshotUrl::Fd(_) => {
+            return Err(MigratableError::MigrateSend(anyhow!(
+                "URL {} does not refer to a directory",
+                url
+            )))
+        }
+    };
 
     if !path.is_dir() {
         return Err(MigratableError::MigrateSend(anyhow!(
@@ -34,16 +326,46 @@ pub fn url_to_path(url: &str) -> std::result::Result<PathBuf, MigratableError> {
     Ok(path)
 }
 
+// Returns a stream to write the snapshot to if the URL refers to a file
+// descriptor. The file descriptor is duplicated so that it stays open for
+// the next parts of the snapshot.
+pub fn url_to_stream(url: &str) -> std::result::Result<Option<File>, MigratableError> {
+    match parse_snapshot_url(url)? {
+        SnapshotUrl::Path(_) => Ok(None),
+        SnapshotUrl::Fd(fd) => {
+            // SAFETY: FFI call, the kernel checks the file descriptor.
+            let fd = unsafe { libc::dup(fd) };
+            if fd < 0 {
+                return Err(MigratableError::MigrateSend(
+                    io::Error::last_os_error().into(),
+                ));
+            }
+            // SAFETY: fd is a valid file descriptor we own.
+            Ok(Some(unsafe { File::from_raw_fd(fd) }))
+        }
+    }
+}
+
+pub fn write_snapshot_section(
+    stream: &mut File,
+    data: &[u8],
+) -> std::result::Result<(), MigratableError> {
+    stream
+        .write_all(&(data.len() as u64).to_le_bytes())
+        .and_then(|_| stream.write_all(data))
+        .map_err(|e| MigratableError::MigrateSend(e.into()))
+}
+
 #[cfg(feature = "guest_debug")]
 pub fn url_to_file(url: &str) -> std::result::Result<PathBuf, GuestDebuggableError> {
-    let file: PathBuf = url
-        .strip_prefix("file://")
-        .ok_or_else(|| {
-            GuestDebuggableError::Coredump(anyhow!("Could not extract file from URL: {}", url))
-        })
-        .map(|s| s.into())?;
-
-    Ok(file)
+    match parse_snapshot_url(url) {
+        Ok(SnapshotUrl::Path(file)) => Ok(file),
+        Ok(SnapshotUrl::Fd(_)) => Err(GuestDebuggableError::Coredump(anyhow!(
+            "Coredump can't be written to a file descriptor: {}",
+            url
+        ))),
+        Err(e) => Err(GuestDebuggableError::Coredump(e.into())),
+    }
 }
 
 pub fn recv_vm_config(source_url: &str) -> std::result::Result<VmConfig, MigratableError> {
@@ -84,3 +406,188 @@ pub fn get_vm_snapshot(snapshot: &Snapshot) -> std::result::Result<VmSnapshot, M
         "Could not find VM config snapshot section"
     )))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::VmParams;
+    use std::io::{Read, Seek, SeekFrom};
+    use std::os::unix::net::UnixStream;
+    use vmm_sys_util::tempdir::TempDir;
+
+    fn vm_config() -> VmConfig {
+        VmConfig::parse(VmParams {
+            cpus: "boot=2,max=4",
+            memory: "size=1G",
+            memory_zones: None,
+            kernel: Some("/path/to/kernel"),
+            initramfs: None,
+            cmdline: None,
+            disks: None,
+            net: None,
+            rng: "src=/dev/urandom",
+            balloon: None,
+            fs: None,
+            pmem: None,
+            serial: "null",
+            console: "tty",
+            extra_serial: None,
+            devices: None,
+            user_devices: None,
+            vdpa: None,
+            vsock: None,
+            #[cfg(target_arch = "x86_64")]
+            sgx_epc: None,
+            numa: None,
+            watchdog: false,
+            #[cfg(feature = "tdx")]
+            tdx: None,
+            #[cfg(feature = "gdb")]
+            gdb: false,
+            platform: None,
+            #[cfg(target_arch = "x86_64")]
+            guest_panic: None,
+            stop_on_boot: false,
+            break_on_triple_fault: false,
+            on_reset: None,
+            no_cmdline_console: false,
+        })
+        .unwrap()
+    }
+
+    fn write_snapshot(dir: &Path, config: Option<&[u8]>, state: Option<&[u8]>) {
+        fs::create_dir(dir).unwrap();
+        if let Some(config) = config {
+            fs::write(dir.join(SNAPSHOT_CONFIG_FILE), config).unwrap();
+        }
+        if let Some(state) = state {
+            fs::write(dir.join(SNAPSHOT_STATE_FILE), state).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_list_snapshots() {
+        let tmp_dir = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let dir = tmp_dir.as_path();
+        let config = serde_json::to_vec(&vm_config()).unwrap();
+        let state = serde_json::to_vec(&Snapshot::new(VM_SNAPSHOT_ID)).unwrap();
+
+        write_snapshot(&dir.join("b-valid"), Some(&config[..]), Some(&state[..]));
+        write_snapshot(&dir.join("a-no-state"), Some(&config[..]), None);
+        write_snapshot(&dir.join("c-bad-config"), Some(&b"{"[..]), Some(&state[..]));
+        // Neither a snapshot directory nor a directory at all.
+        write_snapshot(&dir.join("empty"), None, None);
+        fs::write(dir.join(SNAPSHOT_CONFIG_FILE), &config[..]).unwrap();
+
+        let snapshots = list_snapshots(dir).unwrap();
+        let ids: Vec<&str> = snapshots.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, ["a-no-state", "b-valid", "c-bad-config"]);
+
+        let no_state = &snapshots[0];
+        assert!(!no_state.valid);
+        assert!(no_state
+            .error
+            .as_ref()
+            .unwrap()
+            .contains(SNAPSHOT_STATE_FILE));
+        assert!(no_state.config.is_some());
+        assert_eq!(no_state.size, config.len() as u64);
+
+        let valid = &snapshots[1];
+        assert!(valid.valid);
+        assert_eq!(valid.error, None);
+        assert_eq!(valid.path, dir.join("b-valid"));
+        assert!(valid.timestamp.is_some());
+        assert_eq!(valid.size, (config.len() + state.len()) as u64);
+        assert_eq!(
+            valid.config,
+            Some(SnapshotConfigSummary {
+                boot_vcpus: 2,
+                max_vcpus: 4,
+                memory_size: 1 << 30,
+                kernel: Some(PathBuf::from("/path/to/kernel")),
+            })
+        );
+
+        let bad_config = &snapshots[2];
+        assert!(!bad_config.valid);
+        assert!(bad_config
+            .error
+            .as_ref()
+            .unwrap()
+            .contains(SNAPSHOT_CONFIG_FILE));
+        assert_eq!(bad_config.config, None);
+
+        assert!(list_snapshots(&dir.join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_parse_snapshot_url() {
+        assert!(matches!(
+            parse_snapshot_url("file:///tmp/snapshot").unwrap(),
+            SnapshotUrl::Path(path) if path == Path::new("/tmp/snapshot")
+        ));
+        assert!(matches!(
+            parse_snapshot_url("fd://3").unwrap(),
+            SnapshotUrl::Fd(3)
+        ));
+        assert!(parse_snapshot_url("fd://three").is_err());
+        assert!(parse_snapshot_url("fd://").is_err());
+        assert!(parse_snapshot_url("/tmp/snapshot").is_err());
+        assert!(parse_snapshot_url("tcp:127.0.0.1:1234").is_err());
+
+        // A file descriptor can't be used where a directory is needed.
+        assert!(url_to_path("fd://3").is_err());
+    }
+
+    #[test]
+    fn test_snapshot_stream() {
+        let tmp_dir = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let url = format!("file://{}", tmp_dir.as_path().display());
+        assert!(url_to_stream(&url).unwrap().is_none());
+
+        let mut file = vmm_sys_util::tempfile::TempFile::new().unwrap().into_file();
+        let url = format!("fd://{}", file.as_raw_fd());
+
+        // Each part is written through its own stream, the file descriptor
+        // from the URL staying open in between.
+        let mut stream = url_to_stream(&url).unwrap().unwrap();
+        write_snapshot_section(&mut stream, b"config").unwrap();
+        drop(stream);
+        let mut stream = url_to_stream(&url).unwrap().unwrap();
+        write_snapshot_section(&mut stream, b"").unwrap();
+        write_snapshot_section(&mut stream, b"state").unwrap();
+        drop(stream);
+
+        let mut expected = Vec::new();
+        for section in [&b"config"[..], b"", b"state"] {
+            expected.extend_from_slice(&(section.len() as u64).to_le_bytes());
+            expected.extend_from_slice(section);
+        }
+        let mut content = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut content).unwrap();
+        assert_eq!(content, expected);
+
+        assert!(url_to_stream("fd://-1").is_err());
+    }
+
+    #[test]
+    fn test_migration_socket_options() {
+        let (socket, _peer) = UnixStream::pair().unwrap();
+
+        // Nothing to apply.
+        MigrationSocketOptions::default().apply(&socket).unwrap();
+
+        let options = MigrationSocketOptions {
+            send_buffer_size: Some(64 << 10),
+            recv_buffer_size: Some(64 << 10),
+        };
+        options.apply(&socket).unwrap();
+        let fd = socket.as_raw_fd();
+        for name in [libc::SO_SNDBUF, libc::SO_RCVBUF] {
+            let effective = get_socket_option_int(fd, libc::SOL_SOCKET, name).unwrap();
+            assert_eq!(effective, 128 << 10);
+        }
+    }
+}
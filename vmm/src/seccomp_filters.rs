@@ -9,6 +9,10 @@ use seccompiler::{
     SeccompCondition as Cond, SeccompFilter, SeccompRule,
 };
 use std::convert::TryInto;
+#[cfg(feature = "seccomp_trace")]
+use std::io;
+#[cfg(feature = "seccomp_trace")]
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
 pub enum Thread {
     Api,
@@ -402,6 +406,46 @@ fn create_vmm_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, BackendError> {
     rules
 }
 
+// Socket options set by the VMM thread: the guest agent timeouts and the
+// migration socket buffer sizes.
+fn create_vmm_setsockopt_seccomp_rule() -> Result<Vec<SeccompRule>, BackendError> {
+    let sol_socket = libc::SOL_SOCKET as u64;
+    Ok(or![
+        and![
+            Cond::new(1, ArgLen::Dword, Eq, sol_socket)?,
+            Cond::new(2, ArgLen::Dword, Eq, libc::SO_RCVTIMEO as u64)?
+        ],
+        and![
+            Cond::new(1, ArgLen::Dword, Eq, sol_socket)?,
+            Cond::new(2, ArgLen::Dword, Eq, libc::SO_SNDTIMEO as u64)?
+        ],
+        and![
+            Cond::new(1, ArgLen::Dword, Eq, sol_socket)?,
+            Cond::new(2, ArgLen::Dword, Eq, libc::SO_SNDBUF as u64)?
+        ],
+        and![
+            Cond::new(1, ArgLen::Dword, Eq, sol_socket)?,
+            Cond::new(2, ArgLen::Dword, Eq, libc::SO_RCVBUF as u64)?
+        ],
+    ])
+}
+
+// Socket options read back by the VMM thread: the migration socket buffer
+// sizes.
+fn create_vmm_getsockopt_seccomp_rule() -> Result<Vec<SeccompRule>, BackendError> {
+    let sol_socket = libc::SOL_SOCKET as u64;
+    Ok(or![
+        and![
+            Cond::new(1, ArgLen::Dword, Eq, sol_socket)?,
+            Cond::new(2, ArgLen::Dword, Eq, libc::SO_SNDBUF as u64)?
+        ],
+        and![
+            Cond::new(1, ArgLen::Dword, Eq, sol_socket)?,
+            Cond::new(2, ArgLen::Dword, Eq, libc::SO_RCVBUF as u64)?
+        ],
+    ])
+}
+
 fn create_api_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, BackendError> {
     Ok(or![and![Cond::new(1, ArgLen::Dword, Eq, FIONBIO)?]])
 }
@@ -503,6 +547,7 @@ fn vmm_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
         (libc::SYS_getpgrp, vec![]),
         (libc::SYS_getpid, vec![]),
         (libc::SYS_getrandom, vec![]),
+        (libc::SYS_getsockopt, create_vmm_getsockopt_seccomp_rule()?),
         (libc::SYS_gettid, vec![]),
         (libc::SYS_gettimeofday, vec![]),
         (libc::SYS_getuid, vec![]),
@@ -560,6 +605,7 @@ fn vmm_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
         (libc::SYS_sendto, vec![]),
         (libc::SYS_set_robust_list, vec![]),
         (libc::SYS_setsid, vec![]),
+        (libc::SYS_setsockopt, create_vmm_setsockopt_seccomp_rule()?),
         (libc::SYS_shutdown, vec![]),
         (libc::SYS_sigaltstack, vec![]),
         (
@@ -728,13 +774,18 @@ fn api_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
 }
 
 fn get_seccomp_rules(thread_type: Thread) -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
-    match thread_type {
-        Thread::Api => Ok(api_thread_rules()?),
-        Thread::SignalHandler => Ok(signal_handler_thread_rules()?),
-        Thread::Vcpu => Ok(vcpu_thread_rules()?),
-        Thread::Vmm => Ok(vmm_thread_rules()?),
-        Thread::PtyForeground => Ok(pty_foreground_thread_rules()?),
-    }
+    #[allow(unused_mut)]
+    let mut rules = match thread_type {
+        Thread::Api => api_thread_rules()?,
+        Thread::SignalHandler => signal_handler_thread_rules()?,
+        Thread::Vcpu => vcpu_thread_rules()?,
+        Thread::Vmm => vmm_thread_rules()?,
+        Thread::PtyForeground => pty_foreground_thread_rules()?,
+    };
+    // Needed by the SIGSYS handler recording the violations.
+    #[cfg(feature = "seccomp_trace")]
+    rules.push((libc::SYS_gettid, vec![]));
+    Ok(rules)
 }
 
 /// Generate a BPF program based on the seccomp_action value
@@ -768,3 +819,118 @@ pub fn get_seccomp_filter(
         .map_err(Error::Backend),
     }
 }
+
+/// System call blocked by a seccomp filter.
+#[cfg(feature = "seccomp_trace")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SeccompViolation {
+    pub syscall: i64,
+    pub tid: i32,
+    /// Name of the thread, unknown if it exited since then.
+    pub thread: Option<String>,
+}
+
+#[cfg(feature = "seccomp_trace")]
+const MAX_SECCOMP_VIOLATIONS: usize = 64;
+
+// Violations recorded from the SIGSYS handler, which can't allocate nor
+// lock. Each entry packs the system call number in the upper 32 bits and
+// the thread id in the lower ones, zero marking an unused entry.
+#[cfg(feature = "seccomp_trace")]
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_VIOLATION: AtomicU64 = AtomicU64::new(0);
+#[cfg(feature = "seccomp_trace")]
+static SECCOMP_VIOLATIONS: [AtomicU64; MAX_SECCOMP_VIOLATIONS] =
+    [NO_VIOLATION; MAX_SECCOMP_VIOLATIONS];
+#[cfg(feature = "seccomp_trace")]
+static SECCOMP_VIOLATIONS_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// Layout of the siginfo_t fields filled for SIGSYS, which the libc crate
+// doesn't expose.
+#[cfg(feature = "seccomp_trace")]
+#[repr(C)]
+#[allow(dead_code)]
+struct SigSysInfo {
+    si_signo: libc::c_int,
+    si_errno: libc::c_int,
+    si_code: libc::c_int,
+    call_addr: *mut libc::c_void,
+    syscall: libc::c_int,
+    arch: libc::c_uint,
+}
+
+#[cfg(feature = "seccomp_trace")]
+extern "C" fn sigsys_handler(
+    _signum: libc::c_int,
+    info: *mut libc::siginfo_t,
+    context: *mut libc::c_void,
+) {
+    // SAFETY: the kernel provides a valid siginfo_t for SIGSYS.
+    let syscall = unsafe { (*(info as *const SigSysInfo)).syscall };
+    // SAFETY: gettid is allowed by all the filters when recording violations.
+    let tid = unsafe { libc::syscall(libc::SYS_gettid) };
+
+    let index = SECCOMP_VIOLATIONS_COUNT.fetch_add(1, Ordering::SeqCst);
+    if index < MAX_SECCOMP_VIOLATIONS {
+        SECCOMP_VIOLATIONS[index].store(
+            (syscall as u32 as u64) << 32 | tid as u32 as u64,
+            Ordering::SeqCst,
+        );
+    }
+
+    // The system call wasn't executed, make it fail with ENOSYS rather than
+    // returning whatever the register held.
+    let context = context as *mut libc::ucontext_t;
+    // SAFETY: the kernel provides a valid ucontext_t to the handler.
+    unsafe {
+        #[cfg(target_arch = "x86_64")]
+        {
+            (*context).uc_mcontext.gregs[libc::REG_RAX as usize] = -libc::ENOSYS as i64;
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            (*context).uc_mcontext.regs[0] = -libc::ENOSYS as i64 as u64;
+        }
+    }
+}
+
+/// Install a SIGSYS handler recording the system calls blocked by the
+/// filters in trap mode, instead of letting the thread die. The blocked
+/// system calls fail with ENOSYS.
+#[cfg(feature = "seccomp_trace")]
+pub fn record_seccomp_violations() -> io::Result<()> {
+    // SAFETY: the action is fully initialized before being installed, and the
+    // handler only uses async signal safe operations.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = sigsys_handler as usize;
+        action.sa_flags = libc::SA_SIGINFO;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(libc::SIGSYS, &action, std::ptr::null_mut()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Violations recorded since `record_seccomp_violations()` was called, only
+/// the first `MAX_SECCOMP_VIOLATIONS` ones are kept.
+#[cfg(feature = "seccomp_trace")]
+pub fn seccomp_violations() -> Vec<SeccompViolation> {
+    SECCOMP_VIOLATIONS
+        .iter()
+        .map(|entry| entry.load(Ordering::SeqCst))
+        .filter(|entry| *entry != 0)
+        .map(|entry| {
+            let tid = entry as u32 as i32;
+            SeccompViolation {
+                syscall: (entry >> 32) as u32 as i64,
+                tid,
+                thread: std::fs::read_to_string(format!("/proc/self/task/{}/comm", tid))
+                    .ok()
+                    .map(|name| name.trim_end().to_string()),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,51 @@
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Terminal the VM console is attached to. By default this is the terminal
+//! the VMM runs in, if any, switched to raw mode while the VM runs. A VMM
+//! embedding the `vmm` crate can provide its own, for instance to display the
+//! console in a GUI or forward it over the network.
+
+use std::io;
+use vmm_sys_util::errno;
+use vmm_sys_util::terminal::Terminal;
+
+pub trait TerminalBackend: Send + Sync {
+    /// Pass the input through as is, called when the VM boots.
+    fn set_raw_mode(&self) -> errno::Result<()>;
+
+    /// Go back to line based input, called when the VM exits.
+    fn set_canon_mode(&self) -> errno::Result<()>;
+
+    /// Size of the terminal, in columns and rows, forwarded to the guest
+    /// console on resize. When unknown, the console size follows the one of
+    /// the console tty.
+    fn size(&self) -> Option<(u16, u16)> {
+        None
+    }
+}
+
+/// The VMM standard input, when it is a terminal.
+pub struct StdinTerminal;
+
+impl StdinTerminal {
+    pub fn new() -> Option<Self> {
+        // SAFETY: isatty() only checks the file descriptor, and its
+        // return value is checked.
+        if unsafe { libc::isatty(libc::STDIN_FILENO as i32) } != 0 {
+            Some(StdinTerminal)
+        } else {
+            None
+        }
+    }
+}
+
+impl TerminalBackend for StdinTerminal {
+    fn set_raw_mode(&self) -> errno::Result<()> {
+        io::stdin().lock().set_raw_mode()
+    }
+
+    fn set_canon_mode(&self) -> errno::Result<()> {
+        io::stdin().lock().set_canon_mode()
+    }
+}
@@ -11,30 +11,52 @@
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 //
 
+use crate::balloon_policy::{self, BalloonAdjuster, BalloonPolicy};
+#[cfg(target_arch = "x86_64")]
+use crate::config::GuestPanicAction;
 use crate::config::NumaConfig;
 use crate::config::{
-    add_to_config, DeviceConfig, DiskConfig, FsConfig, HotplugMethod, NetConfig, PmemConfig,
-    UserDeviceConfig, ValidationError, VdpaConfig, VmConfig, VsockConfig,
+    add_to_config, DeviceConfig, DiskConfig, FsConfig, HotplugMethod, MemoryConfig,
+    MemoryZoneConfig, NetConfig, PmemConfig, ResetAction, SerialBackend, SerialDeviceConfig,
+    UnregisteredIoPolicy, UserDeviceConfig, ValidationError, VdpaConfig, VmConfig, VsockConfig,
+    DEFAULT_MAX_PHYS_BITS,
 };
 #[cfg(feature = "guest_debug")]
 use crate::coredump::{
     CpuElf64Writable, DumpState, Elf64Writable, GuestDebuggable, GuestDebuggableError, NoteDescType,
 };
 use crate::cpu;
-use crate::device_manager::{Console, DeviceManager, DeviceManagerError, PtyPair};
+use crate::device_manager::{
+    ActivationPolicy, BusRangeInfo, Console, DeviceManager, DeviceManagerError, DeviceSummary,
+    PtyPair,
+};
 use crate::device_tree::DeviceTree;
+#[cfg(feature = "fault_injection")]
+use crate::fault_injection::MemoryFaultInjector;
 #[cfg(feature = "gdb")]
 use crate::gdb::{Debuggable, DebuggableError, GdbRequestPayload, GdbResponsePayload};
+use crate::guest_agent::{
+    self, GuestAgentCommand, GuestAgentResponse, GUEST_AGENT_TIMEOUT, GUEST_AGENT_VSOCK_PORT,
+};
+use crate::interrupt::IrqRoutingInfo;
+use crate::io_trace::{IoDirection, IoSpace, IoTraceEntry, IoTracer};
 use crate::memory_manager::{
-    Error as MemoryManagerError, MemoryManager, MemoryManagerSnapshotData,
+    Error as MemoryManagerError, MemoryManager, MemoryManagerSnapshotData, MemoryReclaimAdvice,
+    RegionUsage, ZoneMigration, ZonePrefault, SNAPSHOT_FILENAME,
 };
 #[cfg(feature = "guest_debug")]
 use crate::migration::url_to_file;
-use crate::migration::{get_vm_snapshot, url_to_path, SNAPSHOT_CONFIG_FILE, SNAPSHOT_STATE_FILE};
+use crate::migration::{
+    get_vm_snapshot, socket_url_to_path, url_to_path, url_to_stream, write_snapshot_section,
+    MigrationPhase, MigrationPolicy, MigrationSocketOptions, SNAPSHOT_CONFIG_FILE,
+    SNAPSHOT_STATE_FILE,
+};
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
+use crate::terminal::{StdinTerminal, TerminalBackend};
 use crate::GuestMemoryMmap;
 use crate::{
-    PciDeviceInfo, CPU_MANAGER_SNAPSHOT_ID, DEVICE_MANAGER_SNAPSHOT_ID, MEMORY_MANAGER_SNAPSHOT_ID,
+    PciDeviceInfo, VmMigrationConfig, CPU_MANAGER_SNAPSHOT_ID, DEVICE_MANAGER_SNAPSHOT_ID,
+    MEMORY_MANAGER_SNAPSHOT_ID,
 };
 use anyhow::anyhow;
 use arch::get_host_cpu_phys_bits;
@@ -62,6 +84,7 @@ use linux_loader::loader::elf::PvhBootCapability::PvhEntryPresent;
 #[cfg(target_arch = "aarch64")]
 use linux_loader::loader::pe::Error::InvalidImageMagicNumber;
 use linux_loader::loader::KernelLoader;
+use pci::PciBdf;
 use seccompiler::{apply_filter, SeccompAction};
 use serde::{Deserialize, Serialize};
 use signal_hook::{
@@ -73,6 +96,7 @@ use std::cmp;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::convert::TryInto;
+use std::fmt;
 use std::fs::{File, OpenOptions};
 use std::io::{self, Read, Write};
 use std::io::{Seek, SeekFrom};
@@ -81,11 +105,14 @@ use std::mem;
 #[cfg(feature = "guest_debug")]
 use std::mem::size_of;
 use std::num::Wrapping;
-use std::ops::Deref;
-use std::os::unix::net::UnixStream;
+use std::ops::{Deref, Range};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
 use std::panic::AssertUnwindSafe;
-use std::sync::{Arc, Mutex, RwLock};
-use std::time::Instant;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 use std::{result, str, thread};
 use thiserror::Error;
 use vm_device::Bus;
@@ -94,9 +121,10 @@ use vm_device::BusDevice;
 #[cfg(target_arch = "x86_64")]
 use vm_memory::Address;
 #[cfg(feature = "tdx")]
-use vm_memory::{ByteValued, GuestMemory, GuestMemoryRegion};
+use vm_memory::ByteValued;
 use vm_memory::{Bytes, GuestAddress, GuestAddressSpace, GuestMemoryAtomic};
-use vm_migration::protocol::{Request, Response, Status};
+use vm_memory::{GuestMemory, GuestMemoryRegion};
+use vm_migration::protocol::{Command, Request, Response, Status};
 use vm_migration::{
     protocol::MemoryRangeTable, Migratable, MigratableError, Pausable, Snapshot,
     SnapshotDataSection, Snapshottable, Transportable,
@@ -104,7 +132,6 @@ use vm_migration::{
 use vmm_sys_util::eventfd::EventFd;
 use vmm_sys_util::signal::unblock_signal;
 use vmm_sys_util::sock_ctrl_msg::ScmSocket;
-use vmm_sys_util::terminal::Terminal;
 
 /// Errors associated with VM management
 #[derive(Debug, Error)]
@@ -125,12 +152,34 @@ pub enum Error {
     #[error("Cannot load the initramfs into memory")]
     InitramfsLoad,
 
+    #[error("Initramfs at {initramfs:#x?} overlaps the {name} at {range:#x?}")]
+    InitramfsCollision {
+        initramfs: Range<u64>,
+        name: &'static str,
+        range: Range<u64>,
+    },
+
     #[error("Cannot load the kernel command line in memory: {0}")]
     LoadCmdLine(#[source] linux_loader::loader::Error),
 
     #[error("Cannot modify the kernel command line: {0}")]
     CmdLineInsertStr(#[source] linux_loader::cmdline::Error),
 
+    #[error("Cannot modify the kernel command line after the VM has booted")]
+    CmdLineAfterBoot,
+
+    #[error("Timed out waiting for the guest to signal boot completion")]
+    BootCompleteTimeout,
+
+    #[error("Cannot allocate memory to check the kernel: {0}")]
+    KernelCheckMemory(#[source] vm_memory::Error),
+
+    #[error("Legacy ISA serial ports can't be hot-plugged")]
+    LegacySerialHotplug,
+
+    #[error("Unknown serial device: {0}")]
+    UnknownSerialDevice(String),
+
     #[error("Cannot configure system: {0}")]
     ConfigureSystem(#[source] arch::Error),
 
@@ -150,6 +199,9 @@ pub enum Error {
     #[error("Cannot setup terminal in canonical mode.: {0}")]
     SetTerminalCanon(#[source] vmm_sys_util::errno::Error),
 
+    #[error("The terminal backend can only be changed before booting the VM")]
+    TerminalBackendAfterBoot,
+
     #[error("Cannot spawn a signal handler thread: {0}")]
     SignalHandlerSpawn(#[source] io::Error),
 
@@ -168,9 +220,18 @@ pub enum Error {
     #[error("VM is not running")]
     VmNotRunning,
 
+    #[error("VM is not paused")]
+    VmNotPaused,
+
+    #[error("Snapshot doesn't match the {0} of the VM")]
+    IncompatibleSnapshot(&'static str),
+
     #[error("Cannot clone EventFd: {0}")]
     EventFdClone(#[source] io::Error),
 
+    #[error("Cannot signal the VM exit: {0}")]
+    ExitEventWrite(#[source] io::Error),
+
     #[error("invalid VM state transition: {0:?} to {1:?}")]
     InvalidStateTransition(VmState, VmState),
 
@@ -210,6 +271,43 @@ pub enum Error {
     #[error("Cannot send VM snapshot: {0}")]
     SnapshotSend(#[source] MigratableError),
 
+    #[error("Snapshot hook failed: {0}")]
+    SnapshotHook(#[source] anyhow::Error),
+
+    #[error("Guest agent request failed: {0}")]
+    GuestAgent(#[source] guest_agent::Error),
+
+    #[error("Cannot change the memory hotplug method: {0}")]
+    HotplugMethodChange(&'static str),
+
+    #[error("Cannot migrate the memory zone: {0}")]
+    MemoryZoneMigration(&'static str),
+
+    #[error("Cannot toggle the dirty log: {0}")]
+    DirtyLog(#[source] MigratableError),
+
+    #[error("Invalid halt polling interval: {0} ns")]
+    InvalidHaltPollNs(u64),
+
+    #[error("Cannot set the halt polling interval: {0}")]
+    HaltPoll(#[source] hypervisor::HypervisorVmError),
+
+    #[error("Cannot enable the KVM dirty ring: {0}")]
+    DirtyRing(#[source] hypervisor::HypervisorVmError),
+
+    #[error("The KVM dirty ring must be enabled before booting the VM")]
+    DirtyRingAfterBoot,
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("The TSC-deadline timer can only be toggled before booting the VM")]
+    TscDeadlineTimerAfterBoot,
+
+    #[error("Timed out waiting for the guest to drive device {0}")]
+    DeviceReadyTimeout(String),
+
+    #[error("Guest did not release the vCPUs: {present} present, {desired} requested")]
+    CpuUnplugRefused { desired: u8, present: u8 },
+
     #[error("Invalid restore source URL")]
     InvalidRestoreSourceUrl,
 
@@ -225,15 +323,39 @@ pub enum Error {
     #[error("Invalid NUMA configuration")]
     InvalidNumaConfig,
 
+    #[error("Invalid NUMA configuration for vCPU {cpu}: {reason}")]
+    InvalidNumaCpu {
+        cpu: u8,
+        reason: InvalidNumaCpuReason,
+    },
+
     #[error("Cannot create seccomp filter: {0}")]
     CreateSeccompFilter(#[source] seccompiler::Error),
 
     #[error("Cannot apply seccomp filter: {0}")]
     ApplySeccompFilter(#[source] seccompiler::Error),
 
+    #[error("Cannot duplicate a guest memory file: {0}")]
+    DupMemoryFile(#[source] io::Error),
+
+    #[error("Cannot loosen the seccomp filtering from {0:?} to {1:?}")]
+    LoosenSeccompAction(SeccompAction, SeccompAction),
+
     #[error("Failed resizing a memory zone")]
     ResizeZone,
 
+    #[error("Error setting the balloon policy: {0}")]
+    BalloonPolicy(#[source] balloon_policy::Error),
+
+    #[error("Unknown NUMA node {0}")]
+    UnknownNumaNode(u32),
+
+    #[error("NUMA node {0} has no memory hotplug region")]
+    NumaNodeWithoutHotplugRegion(u32),
+
+    #[error("Not enough hotpluggable memory left on NUMA node {0}")]
+    NumaNodeHotplugExhausted(u32),
+
     #[error("Cannot activate virtio devices: {0:?}")]
     ActivateVirtioDevices(DeviceManagerError),
 
@@ -252,6 +374,9 @@ pub enum Error {
     #[error("Firmware too big")]
     FirmwareTooLarge,
 
+    #[error("Kernel needs {needed} bytes of guest memory but only {available} are available")]
+    KernelTooLargeForMemory { needed: u64, available: u64 },
+
     #[error("Failed to copy firmware to memory: {0}")]
     FirmwareLoad(#[source] vm_memory::GuestMemoryError),
 
@@ -295,20 +420,43 @@ pub enum Error {
     #[error("Error debugging VM: {0:?}")]
     Debug(DebuggableError),
 
-    #[cfg(target_arch = "x86_64")]
     #[error("Error spawning kernel loading thread")]
     KernelLoadThreadSpawn(std::io::Error),
 
-    #[cfg(target_arch = "x86_64")]
     #[error("Error joining kernel loading thread")]
     KernelLoadThreadJoin(std::boxed::Box<dyn std::any::Any + std::marker::Send>),
 
     #[cfg(feature = "guest_debug")]
     #[error("Error coredumping VM: {0:?}")]
     Coredump(GuestDebuggableError),
+
+    #[error("VM was not restored")]
+    VmNotRestored,
+
+    #[error("Restored VM is inconsistent: {0}")]
+    RestoredInconsistent(String),
+
+    #[cfg(feature = "fault_injection")]
+    #[error("Invalid fault probability {0}, expected between 0 and 1")]
+    InvalidFaultProbability(f64),
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("Error generating common CPUID: {0}")]
+    CommonCpuId(#[source] arch::Error),
 }
 pub type Result<T> = result::Result<T, Error>;
 
+/// Why a vCPU doesn't belong to exactly one NUMA node.
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum InvalidNumaCpuReason {
+    #[error("not assigned to any NUMA node")]
+    Unassigned,
+    #[error("assigned to both NUMA nodes {0} and {1}")]
+    AssignedTwice(u32, u32),
+    #[error("listed by NUMA node {0} but beyond the {1} vCPUs of the VM")]
+    OutOfRange(u32, u8),
+}
+
 #[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
 pub enum VmState {
     Created,
@@ -356,6 +504,56 @@ impl VmState {
     }
 }
 
+/// I/O port the guest writes BOOT_COMPLETE_CODE to once it has finished
+/// booting. This is the debug port, so the write is still logged as a regular
+/// debug port event.
+#[cfg(target_arch = "x86_64")]
+const BOOT_COMPLETE_IOPORT: u64 = 0x80;
+#[cfg(target_arch = "x86_64")]
+const BOOT_COMPLETE_CODE: u8 = 0x7f;
+
+/// Upper bound for the vCPU halt polling interval. Polling longer than this
+/// would only burn host CPU time.
+pub const MAX_HALT_POLL_NS: u64 = 1_000_000_000;
+
+/// Callback run right before or right after the paused window of a snapshot.
+pub type SnapshotHook<'a> = Box<dyn FnOnce() -> anyhow::Result<()> + 'a>;
+
+/// How long the guest is given to offline and eject the vCPUs being removed.
+const CPU_UNPLUG_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How far the actual balloon size can be from the requested one for the
+/// resize to be considered done by resize_balloon_sync().
+const BALLOON_RESIZE_TOLERANCE: u64 = 1 << 20;
+
+/// Tracks whether the guest has signaled the end of its boot process.
+#[derive(Default)]
+struct BootCompleteNotifier {
+    complete: Mutex<bool>,
+    cond: Condvar,
+}
+
+impl BootCompleteNotifier {
+    fn notify(&self) {
+        let mut complete = self.complete.lock().unwrap();
+        if !*complete {
+            *complete = true;
+            event!("vm", "boot-complete");
+            self.cond.notify_all();
+        }
+    }
+
+    /// Returns true if the guest signaled before the timeout expired.
+    fn wait(&self, timeout: Duration) -> bool {
+        let complete = self.complete.lock().unwrap();
+        let (complete, _) = self
+            .cond
+            .wait_timeout_while(complete, timeout, |complete| !*complete)
+            .unwrap();
+        *complete
+    }
+}
+
 struct VmOpsHandler {
     memory: GuestMemoryAtomic<GuestMemoryMmap>,
     #[cfg(target_arch = "x86_64")]
@@ -363,10 +561,27 @@ struct VmOpsHandler {
     mmio_bus: Arc<Bus>,
     #[cfg(target_arch = "x86_64")]
     pci_config_io: Arc<Mutex<dyn BusDevice>>,
+    boot_complete: Arc<BootCompleteNotifier>,
+    io_tracer: Arc<IoTracer>,
+    unregistered_io: UnregisteredIoPolicy,
+    unregistered_read_fill: u8,
+    // Faulting accesses to unregistered addresses, for the vCPU which made
+    // each of them to stop rather than exit.
+    unregistered_io_faults: Arc<AtomicUsize>,
+    #[cfg(feature = "fault_injection")]
+    memory_faults: Arc<MemoryFaultInjector>,
 }
 
 impl VmOps for VmOpsHandler {
     fn guest_mem_write(&self, gpa: u64, buf: &[u8]) -> result::Result<usize, HypervisorVmError> {
+        #[cfg(feature = "fault_injection")]
+        if self.memory_faults.enabled() && self.memory_faults.should_fail(gpa, buf.len()) {
+            return Err(HypervisorVmError::GuestMemWrite(anyhow!(
+                "Injected fault writing guest memory at 0x{:x}",
+                gpa
+            )));
+        }
+
         self.memory
             .memory()
             .write(buf, GuestAddress(gpa))
@@ -374,6 +589,14 @@ impl VmOps for VmOpsHandler {
     }
 
     fn guest_mem_read(&self, gpa: u64, buf: &mut [u8]) -> result::Result<usize, HypervisorVmError> {
+        #[cfg(feature = "fault_injection")]
+        if self.memory_faults.enabled() && self.memory_faults.should_fail(gpa, buf.len()) {
+            return Err(HypervisorVmError::GuestMemRead(anyhow!(
+                "Injected fault reading guest memory at 0x{:x}",
+                gpa
+            )));
+        }
+
         self.memory
             .memory()
             .read(buf, GuestAddress(gpa))
@@ -382,14 +605,44 @@ impl VmOps for VmOpsHandler {
 
     fn mmio_read(&self, gpa: u64, data: &mut [u8]) -> result::Result<(), HypervisorVmError> {
         if let Err(vm_device::BusError::MissingAddressRange) = self.mmio_bus.read(gpa, data) {
+            if self.unregistered_io == UnregisteredIoPolicy::Fault {
+                self.unregistered_io_faults.fetch_add(1, Ordering::SeqCst);
+                return Err(HypervisorVmError::MmioBusRead(anyhow!(
+                    "Guest MMIO read to unregistered address 0x{:x}",
+                    gpa
+                )));
+            }
             warn!("Guest MMIO read to unregistered address 0x{:x}", gpa);
+            data.fill(self.unregistered_read_fill);
+        }
+        if self.io_tracer.enabled() {
+            self.io_tracer
+                .record(IoSpace::Mmio, IoDirection::Read, gpa, data);
         }
         Ok(())
     }
 
     fn mmio_write(&self, gpa: u64, data: &[u8]) -> result::Result<(), HypervisorVmError> {
+        if self.io_tracer.enabled() {
+            self.io_tracer
+                .record(IoSpace::Mmio, IoDirection::Write, gpa, data);
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        if gpa == arch::layout::BOOT_COMPLETE_MAPPED_IO_START.0 {
+            self.boot_complete.notify();
+            return Ok(());
+        }
+
         match self.mmio_bus.write(gpa, data) {
             Err(vm_device::BusError::MissingAddressRange) => {
+                if self.unregistered_io == UnregisteredIoPolicy::Fault {
+                    self.unregistered_io_faults.fetch_add(1, Ordering::SeqCst);
+                    return Err(HypervisorVmError::MmioBusWrite(anyhow!(
+                        "Guest MMIO write to unregistered address 0x{:x}",
+                        gpa
+                    )));
+                }
                 warn!("Guest MMIO write to unregistered address 0x{:x}", gpa);
             }
             Ok(Some(barrier)) => {
@@ -412,11 +665,21 @@ impl VmOps for VmOpsHandler {
                 port - PCI_CONFIG_IO_PORT,
                 data,
             );
-            return Ok(());
+        } else if let Err(vm_device::BusError::MissingAddressRange) = self.io_bus.read(port, data) {
+            if self.unregistered_io == UnregisteredIoPolicy::Fault {
+                self.unregistered_io_faults.fetch_add(1, Ordering::SeqCst);
+                return Err(HypervisorVmError::IoBusRead(anyhow!(
+                    "Guest PIO read to unregistered address 0x{:x}",
+                    port
+                )));
+            }
+            warn!("Guest PIO read to unregistered address 0x{:x}", port);
+            data.fill(self.unregistered_read_fill);
         }
 
-        if let Err(vm_device::BusError::MissingAddressRange) = self.io_bus.read(port, data) {
-            warn!("Guest PIO read to unregistered address 0x{:x}", port);
+        if self.io_tracer.enabled() {
+            self.io_tracer
+                .record(IoSpace::Pio, IoDirection::Read, port, data);
         }
         Ok(())
     }
@@ -425,6 +688,15 @@ impl VmOps for VmOpsHandler {
     fn pio_write(&self, port: u64, data: &[u8]) -> result::Result<(), HypervisorVmError> {
         use pci::{PCI_CONFIG_IO_PORT, PCI_CONFIG_IO_PORT_SIZE};
 
+        if self.io_tracer.enabled() {
+            self.io_tracer
+                .record(IoSpace::Pio, IoDirection::Write, port, data);
+        }
+
+        if port == BOOT_COMPLETE_IOPORT && data.first() == Some(&BOOT_COMPLETE_CODE) {
+            self.boot_complete.notify();
+        }
+
         if (PCI_CONFIG_IO_PORT..(PCI_CONFIG_IO_PORT + PCI_CONFIG_IO_PORT_SIZE)).contains(&port) {
             self.pci_config_io.lock().unwrap().write(
                 PCI_CONFIG_IO_PORT,
@@ -436,6 +708,13 @@ impl VmOps for VmOpsHandler {
 
         match self.io_bus.write(port, data) {
             Err(vm_device::BusError::MissingAddressRange) => {
+                if self.unregistered_io == UnregisteredIoPolicy::Fault {
+                    self.unregistered_io_faults.fetch_add(1, Ordering::SeqCst);
+                    return Err(HypervisorVmError::IoBusWrite(anyhow!(
+                        "Guest PIO write to unregistered address 0x{:x}",
+                        port
+                    )));
+                }
                 warn!("Guest PIO write to unregistered address 0x{:x}", port);
             }
             Ok(Some(barrier)) => {
@@ -449,14 +728,304 @@ impl VmOps for VmOpsHandler {
     }
 }
 
+/// Guest physical address width as configured, as supported by the host, and
+/// as actually used by the guest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PhysBits {
+    pub configured: u8,
+    pub host: u8,
+    pub effective: u8,
+}
+
+impl PhysBits {
+    fn new(configured: u8, host: u8) -> Self {
+        PhysBits {
+            configured,
+            host,
+            effective: cmp::min(host, configured),
+        }
+    }
+
+    /// The guest got fewer bits than configured, which may prevent it from
+    /// addressing all its memory and devices.
+    pub fn clamped(&self) -> bool {
+        self.effective < self.configured
+    }
+}
+
+pub fn phys_bits(max_phys_bits: u8) -> PhysBits {
+    PhysBits::new(max_phys_bits, get_host_cpu_phys_bits())
+}
+
 pub fn physical_bits(max_phys_bits: u8) -> u8 {
-    let host_phys_bits = get_host_cpu_phys_bits();
+    phys_bits(max_phys_bits).effective
+}
+
+// Check the CPUID of the source VM is compatible with the one the VM gets on
+// this host. This is mostly about features, the "topology/sgx" leaves being
+// irrelevant.
+#[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+pub(crate) fn check_cpuid_compatibility(
+    hypervisor: Arc<dyn hypervisor::Hypervisor>,
+    src_vm_config: &Arc<Mutex<VmConfig>>,
+    src_vm_cpuid: &hypervisor::x86_64::CpuId,
+) -> std::result::Result<(), MigratableError> {
+    let dest_cpuid = &{
+        let vm_config = &src_vm_config.lock().unwrap();
+
+        #[cfg(feature = "tdx")]
+        let tdx_enabled = vm_config.tdx.is_some();
+        let phys_bits = physical_bits(vm_config.cpus.max_phys_bits);
+        arch::generate_common_cpuid(
+            hypervisor,
+            None,
+            None,
+            phys_bits,
+            vm_config.cpus.kvm_hyperv,
+            #[cfg(feature = "tdx")]
+            tdx_enabled,
+        )
+        .map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error generating common cpuid: {:?}", e))
+        })?
+    };
+    arch::CpuidFeatureEntry::check_cpuid_compatibility(src_vm_cpuid, dest_cpuid).map_err(|e| {
+        MigratableError::MigrateReceive(anyhow!(
+            "Error checking cpu feature compatibility': {:?}",
+            e
+        ))
+    })
+}
+
+// Emit the event reporting the outcome of a device hotplug, forwarding the
+// result of the operation.
+fn device_added_event(device_type: &str, result: Result<PciDeviceInfo>) -> Result<PciDeviceInfo> {
+    match &result {
+        Ok(info) => event!(
+            "vm",
+            "device-added",
+            "id",
+            &info.id,
+            "type",
+            device_type,
+            "bdf",
+            info.bdf.to_string()
+        ),
+        Err(e) => event!(
+            "vm",
+            "device-add-failed",
+            "type",
+            device_type,
+            "error",
+            e.to_string()
+        ),
+    }
 
-    cmp::min(host_phys_bits, max_phys_bits)
+    result
+}
+
+fn merge_cmdline(cmdline: &str, extra: &str) -> String {
+    let mut merged = cmdline.trim().to_string();
+    for arg in extra.split_whitespace() {
+        if merged.split_whitespace().any(|a| a == arg) {
+            continue;
+        }
+        if !merged.is_empty() {
+            merged.push(' ');
+        }
+        merged.push_str(arg);
+    }
+    merged
 }
 
 pub const HANDLED_SIGNALS: [i32; 3] = [SIGWINCH, SIGTERM, SIGINT];
 
+// Guest memory ranges used by the boot payloads, consulted when loading a
+// payload so that it can't silently overwrite another one.
+struct LoadedRanges {
+    ranges: Vec<(&'static str, Range<u64>)>,
+}
+
+impl LoadedRanges {
+    fn new() -> Self {
+        #[cfg(target_arch = "x86_64")]
+        let ranges = vec![
+            (
+                "command line",
+                arch::layout::CMDLINE_START.raw_value()
+                    ..arch::layout::CMDLINE_START.raw_value() + arch::CMDLINE_MAX_SIZE as u64,
+            ),
+            (
+                "ACPI tables",
+                arch::layout::RSDP_POINTER.raw_value()..arch::layout::HIGH_RAM_START.raw_value(),
+            ),
+        ];
+        #[cfg(target_arch = "aarch64")]
+        let ranges = vec![
+            (
+                "device tree",
+                arch::layout::FDT_START.raw_value()..arch::layout::ACPI_START.raw_value(),
+            ),
+            (
+                "ACPI tables",
+                arch::layout::ACPI_START.raw_value()
+                    ..arch::layout::ACPI_START.raw_value() + arch::layout::ACPI_MAX_SIZE,
+            ),
+        ];
+
+        LoadedRanges { ranges }
+    }
+
+    fn insert(&mut self, name: &'static str, range: Range<u64>) {
+        self.ranges.push((name, range));
+    }
+
+    fn find_overlap(&self, range: &Range<u64>) -> Option<&(&'static str, Range<u64>)> {
+        self.ranges
+            .iter()
+            .find(|(_, r)| r.start < range.end && range.start < r.end)
+    }
+}
+
+/// Guest RAM added by the latest memory resize.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HotplugRegionInfo {
+    pub start: u64,
+    pub size: u64,
+    pub numa_node: Option<u32>,
+}
+
+/// Capabilities of the VM and of the host it runs on.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PlatformInfo {
+    pub arch: String,
+    pub hypervisor: String,
+    /// The IOAPIC and PIC are emulated by the VMM, only the local APICs are
+    /// handled by the hypervisor. Always the case on x86_64.
+    pub split_irq: bool,
+    pub tdx: bool,
+    pub sgx: bool,
+    pub num_pci_segments: u16,
+    /// Guest physical address width, clamped to the host one.
+    pub max_phys_bits: u8,
+    pub phys_bits: PhysBits,
+}
+
+/// SGX EPC section exposed to the guest.
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SgxEpcSectionInfo {
+    pub id: String,
+    pub start: u64,
+    pub size: u64,
+    pub numa_node: Option<u32>,
+}
+
+/// Panic notification received from the guest through the pvpanic device.
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct GuestPanicInfo {
+    /// Raw pvpanic event flags.
+    pub flags: u8,
+    pub panicked: bool,
+    /// The guest loaded a crash kernel to collect a dump of its own memory.
+    pub crash_loaded: bool,
+    /// Time elapsed between the VMM start and the notification.
+    pub timestamp_ms: u64,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl From<devices::legacy::PvPanicEvent> for GuestPanicInfo {
+    fn from(event: devices::legacy::PvPanicEvent) -> Self {
+        GuestPanicInfo {
+            flags: event.flags,
+            panicked: event.flags & devices::legacy::PVPANIC_PANICKED != 0,
+            crash_loaded: event.flags & devices::legacy::PVPANIC_CRASH_LOADED != 0,
+            timestamp_ms: event.timestamp.as_millis() as u64,
+        }
+    }
+}
+
+/// Rate at which a guest RAM region is being written to.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct RegionDirtyRate {
+    pub memory_zone: String,
+    pub start: u64,
+    pub size: u64,
+    pub dirty_bytes: u64,
+    pub mib_per_sec: f64,
+    /// The region is dirtied faster than the guest RAM as a whole, relative
+    /// to its size.
+    pub hot: bool,
+}
+
+/// Lifecycle operation whose duration is recorded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum VmOperation {
+    Boot,
+    Pause,
+    Resume,
+    Snapshot,
+    Restore,
+    Shutdown,
+}
+
+/// Duration of the successful runs of a lifecycle operation.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct OperationTiming {
+    pub operation: VmOperation,
+    pub count: u64,
+    pub last_ms: u64,
+    pub max_ms: u64,
+}
+
+/// Lifecycle statistics of the VM, accounting for the guest reboots.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct VmStats {
+    /// Guest reboots since the VMM started.
+    pub reboots: u64,
+    /// Time elapsed since the current boot.
+    pub uptime_ms: u64,
+    /// Time elapsed since the first boot, summing the uptime of all boots.
+    pub total_uptime_ms: u64,
+}
+
+/// Breakdown of the RAM the guest currently has, in bytes.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MemorySizes {
+    /// RAM the guest was booted with.
+    pub boot: u64,
+    /// RAM hot-plugged since the boot, as far as the guest accepted it.
+    pub hotplugged: u64,
+    /// RAM the guest handed back to the host through the balloon.
+    pub balloon_inflated: u64,
+    /// RAM the guest is left with, before the kernel overhead.
+    pub effective_available: u64,
+}
+
+/// Phase of a memory zone migration, as reported by
+/// `Vm::migrate_memory_zone()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ZoneMigrationPhase {
+    /// The whole zone was copied to the new backing.
+    Copy { bytes: u64 },
+    /// The VM was paused, and the memory written during the copy copied
+    /// again.
+    StopAndCopy { bytes: u64 },
+    /// The guest was switched over to the new backing.
+    Completed,
+}
+
+/// Rate at which the guest RAM is being written to, measured over a
+/// sampling window.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct DirtyRateStats {
+    pub window_ms: u64,
+    pub dirty_bytes: u64,
+    pub mib_per_sec: f64,
+    pub regions: Vec<RegionDirtyRate>,
+}
+
 pub struct Vm {
     #[cfg(any(target_arch = "aarch64", feature = "tdx"))]
     kernel: Option<File>,
@@ -464,7 +1033,9 @@ pub struct Vm {
     threads: Vec<thread::JoinHandle<()>>,
     device_manager: Arc<Mutex<DeviceManager>>,
     config: Arc<Mutex<VmConfig>>,
-    on_tty: bool,
+    // Terminal the console is attached to, switched to raw mode while the VM
+    // runs.
+    terminal: Option<Arc<dyn TerminalBackend>>,
     signals: Option<Handle>,
     state: RwLock<VmState>,
     cpu_manager: Arc<Mutex<cpu::CpuManager>>,
@@ -474,14 +1045,57 @@ pub struct Vm {
     vm: Arc<dyn hypervisor::Vm>,
     #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
     saved_clock: Option<hypervisor::ClockData>,
+    // CPUID exposed to the guest, without the topology and SGX leaves, as
+    // recorded in the snapshots and checked on migration.
+    #[cfg(target_arch = "x86_64")]
+    common_cpuid: hypervisor::x86_64::CpuId,
     numa_nodes: NumaNodes,
     seccomp_action: SeccompAction,
     exit_evt: EventFd,
-    #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-    hypervisor: Arc<dyn hypervisor::Hypervisor>,
+    // Reason given to request_exit(), if the exit was requested that way.
+    exit_reason: Mutex<Option<String>>,
+    operation_timings: Mutex<BTreeMap<VmOperation, OperationTiming>>,
+    // Creation time of the VM, marking the start of the current boot.
+    timestamp: Instant,
+    // Guest reboots so far, and the uptime accumulated by the previous
+    // boots, carried over from the VM this one replaced.
+    reboots: u64,
+    previous_uptime: Duration,
     stop_on_boot: bool,
-    #[cfg(target_arch = "x86_64")]
+    // Set when restoring a VM whose vCPU threads should only be started by
+    // the first resume.
+    defer_vcpus_start: bool,
+    restore_report: Option<RestoreReport>,
     load_kernel_handle: Option<thread::JoinHandle<Result<EntryPoint>>>,
+    boot_complete: Arc<BootCompleteNotifier>,
+    io_tracer: Arc<IoTracer>,
+    #[cfg(feature = "fault_injection")]
+    memory_faults: Arc<MemoryFaultInjector>,
+    memory_reclaim: Option<MemoryReclaimAdvice>,
+    loaded_ranges: Arc<Mutex<LoadedRanges>>,
+    kernel_entry_point: Option<GuestAddress>,
+    // Device tree generated when configuring the system.
+    #[cfg(target_arch = "aarch64")]
+    device_tree_blob: Option<Vec<u8>>,
+    dirty_log_continuous: bool,
+    dirty_log_migration: bool,
+    // Pages read from the dirty log by dirty_rate() while it was in use,
+    // handed over to the next dirty_log() call.
+    pending_dirty_log: MemoryRangeTable,
+    // Pages read from the dirty log since the last reset, by any of its
+    // users, to be copied back by the next reset.
+    reset_dirty_log: MemoryRangeTable,
+    // Snapshot the VM was last reset to, identified by its location and the
+    // modification time of its memory file, and whether the dirty log has
+    // been armed since then to only restore the pages written to.
+    reset_base: Option<(String, Option<SystemTime>)>,
+    dirty_log_reset: bool,
+    memory_hotplug_region: Option<HotplugRegionInfo>,
+    #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+    guest_panic_dumped: bool,
+    halt_poll_ns: Option<u64>,
+    balloon_adjuster: Option<BalloonAdjuster>,
+    migration_socket_options: MigrationSocketOptions,
 }
 
 impl Vm {
@@ -490,15 +1104,21 @@ impl Vm {
         config: Arc<Mutex<VmConfig>>,
         memory_manager: Arc<Mutex<MemoryManager>>,
         vm: Arc<dyn hypervisor::Vm>,
-        exit_evt: EventFd,
-        reset_evt: EventFd,
-        #[cfg(feature = "gdb")] vm_debug_evt: EventFd,
+        event_fds: VmEventFds,
         seccomp_action: &SeccompAction,
         hypervisor: Arc<dyn hypervisor::Hypervisor>,
-        activate_evt: EventFd,
         restoring: bool,
         timestamp: Instant,
     ) -> Result<Self> {
+        let VmEventFds {
+            exit_evt,
+            reset_evt,
+            #[cfg(target_arch = "x86_64")]
+            guest_panic_evt,
+            #[cfg(feature = "gdb")]
+            vm_debug_evt,
+            activate_evt,
+        } = event_fds;
         let kernel = config
             .lock()
             .unwrap()
@@ -508,12 +1128,18 @@ impl Vm {
             .transpose()
             .map_err(Error::KernelFile)?;
 
+        let loaded_ranges = Arc::new(Mutex::new(LoadedRanges::new()));
+
         #[cfg(target_arch = "x86_64")]
         let load_kernel_handle = if !restoring {
-            Self::load_kernel_async(&kernel, &memory_manager, &config)?
+            Self::load_kernel_async(&kernel, &memory_manager, &loaded_ranges, &config)?
         } else {
             None
         };
+        // The UEFI flash the kernel loader falls back to is created along
+        // with the devices, the loader is spawned from Vm::new() instead.
+        #[cfg(target_arch = "aarch64")]
+        let load_kernel_handle = None;
 
         let boot_id_list = config
             .lock()
@@ -524,8 +1150,11 @@ impl Vm {
         info!("Booting VM from config: {:?}", &config);
 
         // Create NUMA nodes based on NumaConfig.
-        let numa_nodes =
-            Self::create_numa_nodes(config.lock().unwrap().numa.clone(), &memory_manager)?;
+        let numa_nodes = Self::create_numa_nodes(
+            config.lock().unwrap().numa.clone(),
+            config.lock().unwrap().cpus.max_vcpus,
+            &memory_manager,
+        )?;
 
         #[cfg(feature = "tdx")]
         let force_iommu = config.lock().unwrap().tdx.is_some();
@@ -533,9 +1162,9 @@ impl Vm {
         let force_iommu = false;
 
         #[cfg(feature = "gdb")]
-        let stop_on_boot = config.lock().unwrap().gdb;
+        let stop_on_boot = config.lock().unwrap().gdb || config.lock().unwrap().stop_on_boot;
         #[cfg(not(feature = "gdb"))]
-        let stop_on_boot = false;
+        let stop_on_boot = config.lock().unwrap().stop_on_boot;
 
         let device_manager = DeviceManager::new(
             vm.clone(),
@@ -543,6 +1172,8 @@ impl Vm {
             memory_manager.clone(),
             &exit_evt,
             &reset_evt,
+            #[cfg(target_arch = "x86_64")]
+            &guest_panic_evt,
             seccomp_action.clone(),
             numa_nodes.clone(),
             &activate_evt,
@@ -561,6 +1192,15 @@ impl Vm {
         #[cfg(target_arch = "x86_64")]
         let pci_config_io =
             device_manager.lock().unwrap().pci_config_io() as Arc<Mutex<dyn BusDevice>>;
+        let boot_complete = Arc::new(BootCompleteNotifier::default());
+        let io_tracer = Arc::new(IoTracer::default());
+        #[cfg(feature = "fault_injection")]
+        let memory_faults = Arc::new(MemoryFaultInjector::default());
+        let (unregistered_io, unregistered_read_fill) = {
+            let platform = config.lock().unwrap().platform.clone().unwrap_or_default();
+            (platform.unregistered_io, platform.unregistered_read_fill)
+        };
+        let unregistered_io_faults = Arc::new(AtomicUsize::new(0));
         let vm_ops: Arc<dyn VmOps> = Arc::new(VmOpsHandler {
             memory,
             #[cfg(target_arch = "x86_64")]
@@ -568,6 +1208,13 @@ impl Vm {
             mmio_bus,
             #[cfg(target_arch = "x86_64")]
             pci_config_io,
+            boot_complete: boot_complete.clone(),
+            io_tracer: io_tracer.clone(),
+            unregistered_io,
+            unregistered_read_fill,
+            unregistered_io_faults: unregistered_io_faults.clone(),
+            #[cfg(feature = "fault_injection")]
+            memory_faults: memory_faults.clone(),
         });
 
         let exit_evt_clone = exit_evt.try_clone().map_err(Error::EventFdClone)?;
@@ -591,8 +1238,41 @@ impl Vm {
             &numa_nodes,
         )
         .map_err(Error::CpuManager)?;
+        cpu_manager
+            .lock()
+            .unwrap()
+            .set_unregistered_io_faults(unregistered_io_faults);
+        cpu_manager
+            .lock()
+            .unwrap()
+            .set_pause_on_reset(config.lock().unwrap().on_reset == ResetAction::Pause);
+        #[cfg(target_arch = "x86_64")]
+        cpu_manager
+            .lock()
+            .unwrap()
+            .set_break_on_triple_fault(config.lock().unwrap().break_on_triple_fault);
+
+        #[cfg(target_arch = "x86_64")]
+        let common_cpuid = {
+            let phys_bits = physical_bits(config.lock().unwrap().cpus.max_phys_bits);
+            let mut cpuid = arch::generate_common_cpuid(
+                hypervisor,
+                None,
+                None,
+                phys_bits,
+                config.lock().unwrap().cpus.kvm_hyperv,
+                #[cfg(feature = "tdx")]
+                tdx_enabled,
+            )
+            .map_err(Error::CommonCpuId)?;
+            arch::update_cpuid_tsc_deadline_timer(
+                &mut cpuid,
+                config.lock().unwrap().cpus.tsc_deadline_timer,
+            );
+            cpuid
+        };
 
-        let on_tty = unsafe { libc::isatty(libc::STDIN_FILENO as i32) } != 0;
+        let terminal = StdinTerminal::new().map(|t| Arc::new(t) as Arc<dyn TerminalBackend>);
 
         let initramfs = config
             .lock()
@@ -609,7 +1289,7 @@ impl Vm {
             initramfs,
             device_manager,
             config,
-            on_tty,
+            terminal,
             threads: Vec::with_capacity(1),
             signals: None,
             state: RwLock::new(VmState::Created),
@@ -618,19 +1298,92 @@ impl Vm {
             vm,
             #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
             saved_clock: None,
+            #[cfg(target_arch = "x86_64")]
+            common_cpuid,
             numa_nodes,
             seccomp_action: seccomp_action.clone(),
             exit_evt,
-            #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-            hypervisor,
+            exit_reason: Mutex::new(None),
+            operation_timings: Mutex::new(BTreeMap::new()),
+            timestamp,
+            reboots: 0,
+            previous_uptime: Duration::ZERO,
             stop_on_boot,
-            #[cfg(target_arch = "x86_64")]
+            defer_vcpus_start: false,
+            restore_report: None,
             load_kernel_handle,
+            boot_complete,
+            io_tracer,
+            #[cfg(feature = "fault_injection")]
+            memory_faults,
+            memory_reclaim: None,
+            loaded_ranges,
+            kernel_entry_point: None,
+            #[cfg(target_arch = "aarch64")]
+            device_tree_blob: None,
+            dirty_log_continuous: false,
+            dirty_log_migration: false,
+            pending_dirty_log: MemoryRangeTable::default(),
+            reset_dirty_log: MemoryRangeTable::default(),
+            reset_base: None,
+            dirty_log_reset: false,
+            memory_hotplug_region: None,
+            #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+            guest_panic_dumped: false,
+            halt_poll_ns: None,
+            balloon_adjuster: None,
+            migration_socket_options: MigrationSocketOptions::default(),
         })
     }
 
+    /// Check that each vCPU belongs to exactly one NUMA node, unless none
+    /// of the nodes lists any vCPU. The error names the offending vCPU.
+    pub fn validate_numa_cpu_coverage(configs: &[NumaConfig], max_vcpus: u8) -> Result<()> {
+        // The NUMA nodes may only describe the memory.
+        if configs.iter().all(|config| config.cpus.is_none()) {
+            return Ok(());
+        }
+
+        let mut cpu_nodes: Vec<Option<u32>> = vec![None; max_vcpus as usize];
+        for config in configs.iter() {
+            for cpu in config.cpus.iter().flatten() {
+                match cpu_nodes.get_mut(*cpu as usize) {
+                    None => {
+                        return Err(Error::InvalidNumaCpu {
+                            cpu: *cpu,
+                            reason: InvalidNumaCpuReason::OutOfRange(
+                                config.guest_numa_id,
+                                max_vcpus,
+                            ),
+                        });
+                    }
+                    Some(Some(node)) => {
+                        return Err(Error::InvalidNumaCpu {
+                            cpu: *cpu,
+                            reason: InvalidNumaCpuReason::AssignedTwice(
+                                *node,
+                                config.guest_numa_id,
+                            ),
+                        });
+                    }
+                    Some(node) => *node = Some(config.guest_numa_id),
+                }
+            }
+        }
+
+        if let Some(cpu) = cpu_nodes.iter().position(|node| node.is_none()) {
+            return Err(Error::InvalidNumaCpu {
+                cpu: cpu as u8,
+                reason: InvalidNumaCpuReason::Unassigned,
+            });
+        }
+
+        Ok(())
+    }
+
     fn create_numa_nodes(
         configs: Option<Vec<NumaConfig>>,
+        max_vcpus: u8,
         memory_manager: &Arc<Mutex<MemoryManager>>,
     ) -> Result<NumaNodes> {
         let mm = memory_manager.lock().unwrap();
@@ -638,6 +1391,8 @@ impl Vm {
         let mut numa_nodes = BTreeMap::new();
 
         if let Some(configs) = &configs {
+            Self::validate_numa_cpu_coverage(configs, max_vcpus)?;
+
             for config in configs.iter() {
                 if numa_nodes.contains_key(&config.guest_numa_id) {
                     error!("Can't define twice the same NUMA node");
@@ -709,18 +1464,28 @@ impl Vm {
         Ok(numa_nodes)
     }
 
+    /// Validate the configuration along with the host resources it relies
+    /// on, such as hugepages, without creating the VM.
+    pub fn validate_config(config: &VmConfig) -> Result<()> {
+        config.clone().validate().map_err(Error::ConfigValidation)?;
+        if let Some(numa) = &config.numa {
+            Self::validate_numa_cpu_coverage(numa, config.cpus.max_vcpus)?;
+        }
+
+        MemoryManager::validate_hugepages(&config.memory).map_err(Error::MemoryManager)
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         config: Arc<Mutex<VmConfig>>,
-        exit_evt: EventFd,
-        reset_evt: EventFd,
-        #[cfg(feature = "gdb")] vm_debug_evt: EventFd,
+        event_fds: VmEventFds,
         seccomp_action: &SeccompAction,
         hypervisor: Arc<dyn hypervisor::Hypervisor>,
-        activate_evt: EventFd,
         serial_pty: Option<PtyPair>,
+        extra_serial_ptys: Vec<Option<PtyPair>>,
         console_pty: Option<PtyPair>,
         console_resize_pipe: Option<File>,
+        warm_restart: Option<WarmRestartData>,
     ) -> Result<Self> {
         let timestamp = Instant::now();
 
@@ -746,11 +1511,27 @@ impl Vm {
             vm.enable_split_irq().unwrap();
         }
 
-        let phys_bits = physical_bits(config.lock().unwrap().cpus.max_phys_bits);
+        let max_phys_bits = config.lock().unwrap().cpus.max_phys_bits;
+        let clamp = phys_bits(max_phys_bits);
+        // The default width is larger than what most hosts support, only
+        // report explicitly configured widths.
+        if clamp.clamped() && max_phys_bits != DEFAULT_MAX_PHYS_BITS {
+            warn!(
+                "Guest physical address width clamped from {} to {} bits, as supported by the host",
+                clamp.configured, clamp.effective
+            );
+        }
+        let phys_bits = clamp.effective;
 
         #[cfg(target_arch = "x86_64")]
         let sgx_epc_config = config.lock().unwrap().sgx_epc.clone();
 
+        // Reusing the guest memory left by a warm shutdown means laying it
+        // out exactly like it was.
+        let (memory_manager_data, memory_files) = match warm_restart {
+            Some(data) => (Some(data.memory_manager_data), Some(data.memory_files)),
+            None => (None, None),
+        };
         let memory_manager = MemoryManager::new(
             vm.clone(),
             &config.lock().unwrap().memory.clone(),
@@ -758,24 +1539,21 @@ impl Vm {
             phys_bits,
             #[cfg(feature = "tdx")]
             tdx_enabled,
-            None,
-            None,
+            memory_manager_data.as_ref(),
+            memory_files,
             #[cfg(target_arch = "x86_64")]
             sgx_epc_config,
         )
         .map_err(Error::MemoryManager)?;
 
-        let new_vm = Vm::new_from_memory_manager(
+        #[cfg_attr(not(target_arch = "aarch64"), allow(unused_mut))]
+        let mut new_vm = Vm::new_from_memory_manager(
             config,
             memory_manager,
             vm,
-            exit_evt,
-            reset_evt,
-            #[cfg(feature = "gdb")]
-            vm_debug_evt,
+            event_fds,
             seccomp_action,
             hypervisor,
-            activate_evt,
             false,
             timestamp,
         )?;
@@ -786,8 +1564,24 @@ impl Vm {
             .device_manager
             .lock()
             .unwrap()
-            .create_devices(serial_pty, console_pty, console_resize_pipe)
+            .create_devices(
+                serial_pty,
+                extra_serial_ptys,
+                console_pty,
+                console_resize_pipe,
+            )
             .map_err(Error::DeviceManager)?;
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            new_vm.load_kernel_handle = Self::load_kernel_async(
+                &new_vm.kernel,
+                &new_vm.memory_manager,
+                &new_vm.loaded_ranges,
+                &new_vm.device_manager,
+            )?;
+        }
+
         Ok(new_vm)
     }
 
@@ -795,14 +1589,12 @@ impl Vm {
     pub fn new_from_snapshot(
         snapshot: &Snapshot,
         vm_config: Arc<Mutex<VmConfig>>,
-        exit_evt: EventFd,
-        reset_evt: EventFd,
-        #[cfg(feature = "gdb")] vm_debug_evt: EventFd,
+        event_fds: VmEventFds,
         source_url: Option<&str>,
         prefault: bool,
+        paused: bool,
         seccomp_action: &SeccompAction,
         hypervisor: Arc<dyn hypervisor::Hypervisor>,
-        activate_evt: EventFd,
     ) -> Result<Self> {
         let timestamp = Instant::now();
 
@@ -842,31 +1634,26 @@ impl Vm {
             ))));
         };
 
-        Vm::new_from_memory_manager(
+        let mut vm = Vm::new_from_memory_manager(
             vm_config,
             memory_manager,
             vm,
-            exit_evt,
-            reset_evt,
-            #[cfg(feature = "gdb")]
-            vm_debug_evt,
+            event_fds,
             seccomp_action,
             hypervisor,
-            activate_evt,
             true,
             timestamp,
-        )
-    }
+        )?;
+        vm.defer_vcpus_start = paused;
+
+        Ok(vm)
+    }
 
-    #[allow(clippy::too_many_arguments)]
     pub fn new_from_migration(
         config: Arc<Mutex<VmConfig>>,
-        exit_evt: EventFd,
-        reset_evt: EventFd,
-        #[cfg(feature = "gdb")] vm_debug_evt: EventFd,
+        event_fds: VmEventFds,
         seccomp_action: &SeccompAction,
         hypervisor: Arc<dyn hypervisor::Hypervisor>,
-        activate_evt: EventFd,
         memory_manager_data: &MemoryManagerSnapshotData,
         existing_memory_files: Option<HashMap<u32, File>>,
     ) -> Result<Self> {
@@ -903,13 +1690,9 @@ impl Vm {
             config,
             memory_manager,
             vm,
-            exit_evt,
-            reset_evt,
-            #[cfg(feature = "gdb")]
-            vm_debug_evt,
+            event_fds,
             seccomp_action,
             hypervisor,
-            activate_evt,
             true,
             timestamp,
         )
@@ -930,9 +1713,22 @@ impl Vm {
             arch::initramfs_load_addr(guest_mem, size).map_err(|_| Error::InitramfsLoad)?;
         let address = GuestAddress(address);
 
+        let range = address.raw_value()..address.raw_value() + size as u64;
+        if let Some((name, conflict)) = self.loaded_ranges.lock().unwrap().find_overlap(&range) {
+            return Err(Error::InitramfsCollision {
+                initramfs: range,
+                name: *name,
+                range: conflict.clone(),
+            });
+        }
+
         guest_mem
             .read_from(address, &mut initramfs, size)
             .map_err(|_| Error::InitramfsLoad)?;
+        self.loaded_ranges
+            .lock()
+            .unwrap()
+            .insert("initramfs", range);
 
         info!("Initramfs loaded: address = 0x{:x}", address.0);
         Ok(arch::InitramfsConfig { address, size })
@@ -947,18 +1743,40 @@ impl Vm {
             .insert_str(&config.lock().unwrap().cmdline.args)
             .map_err(Error::CmdLineInsertStr)?;
 
+        // The additions set up the console, which may conflict with the one
+        // from the user provided arguments.
         #[cfg(target_arch = "aarch64")]
-        for entry in device_manager.lock().unwrap().cmdline_additions() {
-            cmdline.insert_str(entry).map_err(Error::CmdLineInsertStr)?;
+        {
+            let config = config.lock().unwrap();
+            if config.cmdline.console_additions {
+                if config.cmdline.has_console() {
+                    warn!(
+                        "The kernel command line already sets up the console, \
+                        which may conflict with the one appended by the VMM. \
+                        Use --no-cmdline-console to only keep the former."
+                    );
+                }
+                for entry in device_manager.lock().unwrap().cmdline_additions() {
+                    cmdline.insert_str(entry).map_err(Error::CmdLineInsertStr)?;
+                }
+            }
         }
         Ok(cmdline)
     }
 
     #[cfg(target_arch = "aarch64")]
-    fn load_kernel(&mut self) -> Result<EntryPoint> {
-        let guest_memory = self.memory_manager.lock().as_ref().unwrap().guest_memory();
-        let mem = guest_memory.memory();
-        let mut kernel = self.kernel.as_ref().unwrap();
+    fn load_kernel(
+        mut kernel: File,
+        memory_manager: Arc<Mutex<MemoryManager>>,
+        loaded_ranges: Arc<Mutex<LoadedRanges>>,
+        uefi_flash: GuestMemoryAtomic<GuestMemoryMmap>,
+    ) -> Result<EntryPoint> {
+        info!("Loading kernel");
+
+        let mem = {
+            let guest_memory = memory_manager.lock().as_ref().unwrap().guest_memory();
+            guest_memory.memory()
+        };
         let entry_addr = match linux_loader::loader::pe::PE::load(
             mem.deref(),
             Some(arch::layout::KERNEL_START),
@@ -970,7 +1788,6 @@ impl Vm {
             // If failed, retry to load it as UEFI binary.
             // As the UEFI binary is formatless, it must be the last option to try.
             Err(linux_loader::loader::Error::Pe(InvalidImageMagicNumber)) => {
-                let uefi_flash = self.device_manager.lock().as_ref().unwrap().uefi_flash();
                 let mem = uefi_flash.memory();
                 arch::aarch64::uefi::load_uefi(mem.deref(), arch::layout::UEFI_START, &mut kernel)
                     .map_err(Error::UefiLoad)?;
@@ -986,17 +1803,206 @@ impl Vm {
         };
 
         let entry_point_addr: GuestAddress = entry_addr.kernel_load;
+        loaded_ranges.lock().unwrap().insert(
+            "kernel",
+            entry_point_addr.raw_value()..entry_addr.kernel_end,
+        );
 
         Ok(EntryPoint {
             entry_addr: entry_point_addr,
         })
     }
 
+    /// Make sure `kernel` can be loaded in a guest with the same memory
+    /// layout. The kernel is loaded into scratch memory so that the running
+    /// guest is left untouched. The scratch memory only covers the range the
+    /// kernel headers say it is loaded to, rather than the whole guest RAM.
+    pub fn check_kernel(&self, kernel: &Path) -> Result<()> {
+        let mut kernel = File::open(kernel).map_err(Error::KernelFile)?;
+        let boot_memory = self.memory_manager.lock().unwrap().boot_guest_memory();
+
+        #[cfg(target_arch = "x86_64")]
+        let footprint = Self::check_kernel_fits(&mut kernel, &boot_memory)?;
+        #[cfg(target_arch = "aarch64")]
+        let footprint = {
+            let footprint = Self::kernel_footprint(&mut kernel)?;
+            if let Some(footprint) = footprint.as_ref() {
+                let available = boot_memory
+                    .find_region(GuestAddress(footprint.start))
+                    .map(|region| region.start_addr().raw_value() + region.len() - footprint.start)
+                    .unwrap_or(0);
+                let needed = footprint.end - footprint.start;
+                if needed > available {
+                    return Err(Error::KernelTooLargeForMemory { needed, available });
+                }
+            }
+            footprint
+        };
+        // Without a footprint, the loader rejects the kernel, or finds
+        // nothing to load, before writing anything.
+        let mem = match footprint.filter(|footprint| !footprint.is_empty()) {
+            Some(footprint) => GuestMemoryMmap::from_ranges(&[(
+                GuestAddress(footprint.start),
+                (footprint.end - footprint.start) as usize,
+            )])
+            .map_err(Error::KernelCheckMemory)?,
+            None => GuestMemoryMmap::new(),
+        };
+        kernel.seek(SeekFrom::Start(0)).map_err(Error::KernelFile)?;
+
+        #[cfg(target_arch = "x86_64")]
+        match linux_loader::loader::elf::Elf::load(
+            &mem,
+            None,
+            &mut kernel,
+            Some(Self::kernel_highmem_start(&self.config.lock().unwrap())),
+        ) {
+            Ok(_) => {}
+            Err(linux_loader::loader::Error::Elf(
+                linux_loader::loader::elf::Error::InvalidElfMagicNumber,
+            )) => {
+                // Not an ELF header, this is a raw firmware
+                let size = kernel.seek(SeekFrom::End(0)).map_err(Error::FirmwareFile)?;
+                if size > 4 << 20 {
+                    return Err(Error::FirmwareTooLarge);
+                }
+            }
+            Err(e) => return Err(Error::KernelLoad(e)),
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        match linux_loader::loader::pe::PE::load(
+            &mem,
+            Some(arch::layout::KERNEL_START),
+            &mut kernel,
+            None,
+        ) {
+            // Anything which is not a kernel PE image is loaded as UEFI binary
+            Ok(_) | Err(linux_loader::loader::Error::Pe(InvalidImageMagicNumber)) => {}
+            Err(e) => return Err(Error::KernelLoad(e)),
+        }
+
+        Ok(())
+    }
+
+    // Guest memory range an arm64 Image kernel is loaded to, the way the PE
+    // loader does. Returns None if the kernel is not an arm64 Image, which
+    // is then loaded as a UEFI binary.
+    #[cfg(target_arch = "aarch64")]
+    fn kernel_footprint(kernel: &mut File) -> Result<Option<Range<u64>>> {
+        const ARM64_IMAGE_MAGIC: &[u8; 4] = b"ARM\x64";
+        const DEFAULT_TEXT_OFFSET: u64 = 0x80000;
+
+        let mut header = [0u8; 64];
+        kernel.seek(SeekFrom::Start(0)).map_err(Error::KernelFile)?;
+        if kernel.read_exact(&mut header).is_err() || &header[56..60] != ARM64_IMAGE_MAGIC {
+            return Ok(None);
+        }
+
+        // Kernels older than 3.17 have an image_size of 0 and expect the
+        // default text_offset.
+        let image_size = u64::from_le_bytes(header[16..24].try_into().unwrap());
+        let text_offset = if image_size == 0 {
+            DEFAULT_TEXT_OFFSET
+        } else {
+            u64::from_le_bytes(header[8..16].try_into().unwrap())
+        };
+        let size = kernel.seek(SeekFrom::End(0)).map_err(Error::KernelFile)?;
+        let start = arch::layout::KERNEL_START
+            .raw_value()
+            .saturating_add(text_offset);
+
+        Ok(Some(start..start.saturating_add(size)))
+    }
+
+    // Guest memory range covered by the loadable segments of an ELF kernel.
+    // Returns None if the kernel is not a valid ELF64 binary, leaving the
+    // loader report the actual error.
+    #[cfg(target_arch = "x86_64")]
+    fn kernel_footprint(kernel: &mut File) -> Result<Option<Range<u64>>> {
+        const PT_LOAD: u32 = 1;
+        const ELF64_PHDR_SIZE: usize = 56;
+        // Same bound on the program headers as the Linux ELF loader.
+        const MAX_PHDRS_SIZE: usize = 64 << 10;
+
+        let mut ehdr = [0u8; 64];
+        kernel.seek(SeekFrom::Start(0)).map_err(Error::KernelFile)?;
+        if kernel.read_exact(&mut ehdr).is_err() || &ehdr[..4] != b"\x7fELF" || ehdr[4] != 2 {
+            return Ok(None);
+        }
+
+        let phoff = u64::from_le_bytes(ehdr[32..40].try_into().unwrap());
+        let phentsize = u16::from_le_bytes(ehdr[54..56].try_into().unwrap()) as usize;
+        let phnum = u16::from_le_bytes(ehdr[56..58].try_into().unwrap()) as usize;
+        if phentsize != ELF64_PHDR_SIZE || phnum == 0 || phentsize * phnum > MAX_PHDRS_SIZE {
+            return Ok(None);
+        }
+
+        let mut phdrs = vec![0u8; phentsize * phnum];
+        kernel
+            .seek(SeekFrom::Start(phoff))
+            .map_err(Error::KernelFile)?;
+        if kernel.read_exact(&mut phdrs).is_err() {
+            return Ok(None);
+        }
+
+        let mut footprint: Option<Range<u64>> = None;
+        for phdr in phdrs.chunks_exact(phentsize) {
+            if u32::from_le_bytes(phdr[0..4].try_into().unwrap()) != PT_LOAD {
+                continue;
+            }
+            let paddr = u64::from_le_bytes(phdr[24..32].try_into().unwrap());
+            let memsz = u64::from_le_bytes(phdr[40..48].try_into().unwrap());
+            let end = paddr.saturating_add(memsz);
+            footprint = Some(match footprint {
+                Some(f) => f.start.min(paddr)..f.end.max(end),
+                None => paddr..end,
+            });
+        }
+
+        Ok(footprint)
+    }
+
+    // Fail early with a meaningful error if the guest RAM can't hold the
+    // kernel, rather than with a generic error from the loader.
+    #[cfg(target_arch = "x86_64")]
+    fn check_kernel_fits(kernel: &mut File, mem: &GuestMemoryMmap) -> Result<Option<Range<u64>>> {
+        let footprint = Self::kernel_footprint(kernel)?;
+        kernel.seek(SeekFrom::Start(0)).map_err(Error::KernelFile)?;
+
+        if let Some(needed) = footprint.as_ref().map(|f| f.end) {
+            // The kernel segments are loaded at their physical address, in
+            // the RAM region starting at 0.
+            let available = mem
+                .find_region(GuestAddress(0))
+                .map(|region| region.len())
+                .unwrap_or(0);
+            if needed > available {
+                return Err(Error::KernelTooLargeForMemory { needed, available });
+            }
+        }
+
+        Ok(footprint)
+    }
+
+    // Lowest address the kernel can be loaded at, which can be overridden
+    // from the kernel configuration.
+    #[cfg(target_arch = "x86_64")]
+    fn kernel_highmem_start(config: &VmConfig) -> GuestAddress {
+        config
+            .kernel
+            .as_ref()
+            .and_then(|k| k.highmem_start)
+            .map(GuestAddress)
+            .unwrap_or(arch::layout::HIGH_RAM_START)
+    }
+
     #[cfg(target_arch = "x86_64")]
     fn load_kernel(
         mut kernel: File,
-        cmdline: Cmdline,
         memory_manager: Arc<Mutex<MemoryManager>>,
+        loaded_ranges: Arc<Mutex<LoadedRanges>>,
+        highmem_start: GuestAddress,
     ) -> Result<EntryPoint> {
         use linux_loader::loader::{elf::Error::InvalidElfMagicNumber, Error::Elf};
         info!("Loading kernel");
@@ -1005,11 +2011,12 @@ impl Vm {
             let guest_memory = memory_manager.lock().as_ref().unwrap().guest_memory();
             guest_memory.memory()
         };
+        let footprint = Self::check_kernel_fits(&mut kernel, mem.deref())?;
         let entry_addr = match linux_loader::loader::elf::Elf::load(
             mem.deref(),
             None,
             &mut kernel,
-            Some(arch::layout::HIGH_RAM_START),
+            Some(highmem_start),
         ) {
             Ok(entry_addr) => entry_addr,
             Err(e) => match e {
@@ -1049,6 +2056,10 @@ impl Vm {
                         .memory()
                         .read_exact_from(load_address, &mut kernel, size as usize)
                         .map_err(Error::FirmwareLoad)?;
+                    loaded_ranges
+                        .lock()
+                        .unwrap()
+                        .insert("firmware", load_address.raw_value()..(4 << 30));
 
                     return Ok(EntryPoint { entry_addr: None });
                 }
@@ -1058,8 +2069,9 @@ impl Vm {
             },
         };
 
-        linux_loader::loader::load_cmdline(mem.deref(), arch::layout::CMDLINE_START, &cmdline)
-            .map_err(Error::LoadCmdLine)?;
+        if let Some(footprint) = footprint {
+            loaded_ranges.lock().unwrap().insert("kernel", footprint);
+        }
 
         if let PvhEntryPresent(entry_addr) = entry_addr.pvh_boot_cap {
             // Use the PVH kernel entry point to boot the guest
@@ -1072,11 +2084,12 @@ impl Vm {
         }
     }
 
-    #[cfg(target_arch = "x86_64")]
     fn load_kernel_async(
         kernel: &Option<File>,
         memory_manager: &Arc<Mutex<MemoryManager>>,
-        config: &Arc<Mutex<VmConfig>>,
+        loaded_ranges: &Arc<Mutex<LoadedRanges>>,
+        #[cfg(target_arch = "aarch64")] device_manager: &Arc<Mutex<DeviceManager>>,
+        #[cfg(target_arch = "x86_64")] config: &Arc<Mutex<VmConfig>>,
     ) -> Result<Option<thread::JoinHandle<Result<EntryPoint>>>> {
         // Kernel with TDX is loaded in a different manner
         #[cfg(feature = "tdx")]
@@ -1088,14 +2101,25 @@ impl Vm {
             .as_ref()
             .map(|kernel| {
                 let kernel = kernel.try_clone().unwrap();
-                let config = config.clone();
                 let memory_manager = memory_manager.clone();
+                let loaded_ranges = loaded_ranges.clone();
+                #[cfg(target_arch = "x86_64")]
+                let highmem_start = Self::kernel_highmem_start(&config.lock().unwrap());
+                #[cfg(target_arch = "aarch64")]
+                let uefi_flash = device_manager.lock().unwrap().uefi_flash();
 
                 std::thread::Builder::new()
                     .name("kernel_loader".into())
                     .spawn(move || {
-                        let cmdline = Self::generate_cmdline(&config)?;
-                        Self::load_kernel(kernel, cmdline, memory_manager)
+                        Self::load_kernel(
+                            kernel,
+                            memory_manager,
+                            loaded_ranges,
+                            #[cfg(target_arch = "x86_64")]
+                            highmem_start,
+                            #[cfg(target_arch = "aarch64")]
+                            uefi_flash,
+                        )
                     })
                     .map_err(Error::KernelLoadThreadSpawn)
             })
@@ -1107,6 +2131,12 @@ impl Vm {
         info!("Configuring system");
         let mem = self.memory_manager.lock().unwrap().boot_guest_memory();
 
+        // The command line is generated here rather than when the kernel is
+        // loaded so that arguments appended before boot are taken into account.
+        let cmdline = Self::generate_cmdline(&self.config)?;
+        linux_loader::loader::load_cmdline(&mem, arch::layout::CMDLINE_START, &cmdline)
+            .map_err(Error::LoadCmdLine)?;
+
         let initramfs_config = match self.initramfs {
             Some(_) => Some(self.load_initramfs(&mem)?),
             None => None,
@@ -1122,13 +2152,19 @@ impl Vm {
             .as_ref()
             .cloned();
 
-        let serial_number = self
-            .config
-            .lock()
-            .unwrap()
-            .platform
+        let platform = self.config.lock().unwrap().platform.clone();
+        let serial_number = platform.as_ref().and_then(|p| p.serial_number.clone());
+        // The UUID has already been validated along with the configuration.
+        // SMBIOS expects its first three fields to be little endian.
+        let uuid = platform
             .as_ref()
-            .and_then(|p| p.serial_number.clone());
+            .and_then(|p| p.uuid.as_ref())
+            .and_then(|uuid| uuid::Uuid::parse_str(uuid).ok())
+            .map(|uuid| uuid.to_bytes_le());
+        let oem_strings = platform
+            .as_ref()
+            .and_then(|p| p.oem_strings.as_ref())
+            .map(|strings| strings.iter().map(|s| s.as_str()).collect::<Vec<&str>>());
 
         arch::configure_system(
             &mem,
@@ -1138,6 +2174,9 @@ impl Vm {
             rsdp_addr,
             sgx_epc_region,
             serial_number.as_deref(),
+            uuid,
+            oem_strings.as_deref(),
+            &self.reserved_memory(),
         )
         .map_err(Error::ConfigureSystem)?;
         Ok(())
@@ -1194,7 +2233,8 @@ impl Vm {
                 &self.memory_manager.lock().as_ref().unwrap().vm,
                 self.cpu_manager.lock().unwrap().boot_vcpus() as u64,
             )
-            .map_err(|_| {
+            .map_err(|e| {
+                error!("Failed creating the GICv3 and its ITS: {:?}", e);
                 Error::ConfigureSystem(arch::Error::PlatformSpecific(
                     arch::aarch64::Error::SetupGic,
                 ))
@@ -1212,20 +2252,23 @@ impl Vm {
                 ))
             })?;
 
-        arch::configure_system(
-            &mem,
-            cmdline.as_str(),
-            vcpu_mpidrs,
-            vcpu_topology,
-            device_info,
-            &initramfs_config,
-            &pci_space_info,
-            virtio_iommu_bdf.map(|bdf| bdf.into()),
-            &vgic,
-            &self.numa_nodes,
-            pmu_supported,
-        )
-        .map_err(Error::ConfigureSystem)?;
+        self.device_tree_blob = Some(
+            arch::configure_system(
+                &mem,
+                cmdline.as_str(),
+                vcpu_mpidrs,
+                vcpu_topology,
+                device_info,
+                &initramfs_config,
+                &pci_space_info,
+                virtio_iommu_bdf.map(|bdf| bdf.into()),
+                &vgic,
+                &self.numa_nodes,
+                pmu_supported,
+                &self.reserved_memory(),
+            )
+            .map_err(Error::ConfigureSystem)?,
+        );
 
         // Activate gic device
         self.device_manager
@@ -1245,6 +2288,23 @@ impl Vm {
         self.device_manager.lock().unwrap().serial_pty()
     }
 
+    /// PTY of the serial port at `index`, index 0 being the main serial
+    /// port and the following ones the additional serial ports.
+    pub fn serial_pty_n(&self, index: usize) -> Option<PtyPair> {
+        if index == 0 {
+            self.serial_pty()
+        } else {
+            self.device_manager
+                .lock()
+                .unwrap()
+                .extra_serial_pty(index - 1)
+        }
+    }
+
+    pub fn extra_serial_ptys(&self) -> Vec<Option<PtyPair>> {
+        self.device_manager.lock().unwrap().extra_serial_ptys()
+    }
+
     pub fn console_pty(&self) -> Option<PtyPair> {
         self.device_manager.lock().unwrap().console_pty()
     }
@@ -1254,18 +2314,16 @@ impl Vm {
     }
 
     pub fn shutdown(&mut self) -> Result<()> {
+        let start = Instant::now();
         let mut state = self.state.try_write().map_err(|_| Error::PoisonedState)?;
         let new_state = VmState::Shutdown;
 
         state.valid_transition(new_state)?;
 
-        if self.on_tty {
+        if let Some(terminal) = &self.terminal {
             // Don't forget to set the terminal in canonical mode
             // before to exit.
-            io::stdin()
-                .lock()
-                .set_canon_mode()
-                .map_err(Error::SetTerminalCanon)?;
+            terminal.set_canon_mode().map_err(Error::SetTerminalCanon)?;
         }
 
         // Trigger the termination of the signal_handler thread
@@ -1273,6 +2331,8 @@ impl Vm {
             signals.close();
         }
 
+        self.balloon_adjuster = None;
+
         // Wake up the DeviceManager threads so they will get terminated cleanly
         self.device_manager
             .lock()
@@ -1290,13 +2350,285 @@ impl Vm {
         for thread in self.threads.drain(..) {
             thread.join().map_err(Error::ThreadCleanup)?
         }
+
+        if self.config.lock().unwrap().memory.wipe_on_shutdown {
+            let duration = self
+                .memory_manager
+                .lock()
+                .unwrap()
+                .wipe_guest_memory()
+                .map_err(Error::MemoryManager)?;
+            info!("Guest memory wiped in {} ms", duration.as_millis());
+        }
+
         *state = new_state;
 
-        event!("vm", "shutdown");
+        let duration_ms = self.record_timing(VmOperation::Shutdown, start);
+        event!("vm", "shutdown", "duration_ms", duration_ms);
+
+        Ok(())
+    }
+
+    /// Pause the VM and let the host reclaim its memory. The guest pages
+    /// are faulted back in after the VM is resumed, at the cost of a slower
+    /// resume, hence `Cold` should be preferred for short pauses.
+    pub fn pause_and_reclaim(&mut self, advice: MemoryReclaimAdvice) -> Result<()> {
+        self.pause().map_err(Error::Pause)?;
+
+        self.memory_manager
+            .lock()
+            .unwrap()
+            .reclaim_guest_memory(advice)
+            .map_err(Error::MemoryManager)?;
+        self.memory_reclaim = Some(advice);
+
+        Ok(())
+    }
+
+    /// Set how long idle vCPUs poll for a wakeup before halting, overriding
+    /// the host wide KVM default for this VM. Low values save host CPU time
+    /// for idle guests, high values reduce the wakeup latency of bursty ones.
+    /// The interval can be changed at any time, but isn't kept across a
+    /// reboot.
+    pub fn set_halt_poll_ns(&mut self, ns: u64) -> Result<()> {
+        if ns > MAX_HALT_POLL_NS {
+            return Err(Error::InvalidHaltPollNs(ns));
+        }
+
+        self.vm
+            .set_halt_poll_ns(ns as u32)
+            .map_err(Error::HaltPoll)?;
+        self.halt_poll_ns = Some(ns);
+
+        Ok(())
+    }
+
+    /// Halt polling interval set through `set_halt_poll_ns()`, `None` when
+    /// the host default applies.
+    pub fn halt_poll_ns(&self) -> Option<u64> {
+        self.halt_poll_ns
+    }
+
+    /// Track the pages the guest writes to through KVM per vCPU dirty rings
+    /// of `entries` entries, rather than by scanning the dirty bitmaps. This
+    /// is cheaper for guests dirtying their memory at a high rate. The dirty
+    /// log is reported the same way, whichever tracking is used.
+    ///
+    /// The dirty ring must be enabled before the VM is booted, and isn't
+    /// kept across a reboot.
+    pub fn enable_kvm_dirty_ring(&mut self, entries: u32) -> Result<()> {
+        if self.get_state()? != VmState::Created {
+            return Err(Error::DirtyRingAfterBoot);
+        }
+
+        self.vm.enable_dirty_ring(entries).map_err(Error::DirtyRing)
+    }
+
+    /// Present or hide the TSC-deadline mode of the APIC timer in the guest
+    /// CPUID, for guests misbehaving with one of the timer modes. The CPUID
+    /// is set when the vCPUs are created, so this must be done before the
+    /// VM is booted. The setting is kept across a reboot.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_tsc_deadline_timer(&mut self, enabled: bool) -> Result<()> {
+        if self.get_state()? != VmState::Created {
+            return Err(Error::TscDeadlineTimerAfterBoot);
+        }
+
+        self.config.lock().unwrap().cpus.tsc_deadline_timer = enabled;
+        self.cpu_manager
+            .lock()
+            .unwrap()
+            .set_tsc_deadline_timer(enabled);
+        arch::update_cpuid_tsc_deadline_timer(&mut self.common_cpuid, enabled);
+
+        Ok(())
+    }
+
+    /// Lock the guest RAM in host memory so that it can't be swapped out,
+    /// returning the number of bytes locked. This fails if the process
+    /// RLIMIT_MEMLOCK is too low, the limit being part of the error.
+    pub fn lock_guest_memory(&mut self) -> Result<u64> {
+        let locked = self
+            .memory_manager
+            .lock()
+            .unwrap()
+            .lock_guest_memory()
+            .map_err(Error::MemoryManager)?;
+        info!("Locked {} bytes of guest memory", locked);
+
+        Ok(locked)
+    }
+
+    /// Let the host swap the guest RAM out again, returning the number of
+    /// bytes unlocked.
+    pub fn unlock_guest_memory(&mut self) -> Result<u64> {
+        self.memory_manager
+            .lock()
+            .unwrap()
+            .unlock_guest_memory()
+            .map_err(Error::MemoryManager)
+    }
+
+    /// Change the method used to hotplug memory. The guest is told about
+    /// the hotplug method when it boots, so the new method only applies once
+    /// the VM is rebooted, memory resizes are still done with the previous
+    /// method until then. Once memory has been hotplugged, the guest and the
+    /// host would disagree on the state of the hotplugged memory and the
+    /// method can't be changed anymore. This isn't supported with user
+    /// defined memory zones, which always rely on virtio-mem.
+    pub fn set_hotplug_method(&mut self, method: HotplugMethod) -> Result<()> {
+        let mut config = self.config.lock().unwrap();
+        if config.memory.hotplug_method == method {
+            return Ok(());
+        }
+
+        if config.memory.size == 0 {
+            return Err(Error::HotplugMethodChange(
+                "not supported with user defined memory zones",
+            ));
+        }
+
+        if self.memory_manager.lock().unwrap().hotplugged_ram() > 0
+            || config.memory.hotplugged_size.is_some()
+        {
+            return Err(Error::HotplugMethodChange(
+                "memory has already been hotplugged",
+            ));
+        }
+
+        config.memory.hotplug_method = method;
+
+        Ok(())
+    }
+
+    /// Keep the dirty log armed outside of any migration, so that the guest
+    /// memory written since the previous call can be polled with
+    /// `dirty_log()`, e.g. to replicate it. Each read clears the log.
+    ///
+    /// Dirty logging write-protects the guest memory, so the first write to
+    /// a page after each read traps into the hypervisor, which can slow down
+    /// write intensive workloads. Disabling it stops the dirty logging.
+    pub fn enable_dirty_log_continuous(&mut self, enable: bool) -> Result<()> {
+        if enable == self.dirty_log_continuous {
+            return Ok(());
+        }
+
+        // A migration in progress, or the tracking of the pages written
+        // since the last reset, keeps the dirty log armed on its own.
+        if !self.dirty_log_migration && !self.dirty_log_reset {
+            if enable {
+                self.arm_dirty_log().map_err(Error::DirtyLog)?;
+            } else {
+                self.disarm_dirty_log().map_err(Error::DirtyLog)?;
+            }
+        }
+        self.dirty_log_continuous = enable;
 
         Ok(())
     }
 
+    fn arm_dirty_log(&mut self) -> std::result::Result<(), MigratableError> {
+        self.memory_manager.lock().unwrap().start_dirty_log()?;
+        self.device_manager.lock().unwrap().start_dirty_log()
+    }
+
+    fn disarm_dirty_log(&mut self) -> std::result::Result<(), MigratableError> {
+        self.memory_manager.lock().unwrap().stop_dirty_log()?;
+        self.device_manager.lock().unwrap().stop_dirty_log()
+    }
+
+    // Every read of the dirty log goes through here, so that the pages
+    // written to since the last reset are tracked whoever reads the log.
+    fn read_dirty_log(&mut self) -> std::result::Result<MemoryRangeTable, MigratableError> {
+        let table = MemoryRangeTable::new_from_tables(vec![
+            self.memory_manager.lock().unwrap().dirty_log()?,
+            self.device_manager.lock().unwrap().dirty_log()?,
+        ]);
+        if self.dirty_log_reset {
+            self.reset_dirty_log.extend(table.clone());
+        }
+
+        Ok(table)
+    }
+
+    /// Measure how fast the guest writes to its memory over `window`,
+    /// overall and for each guest RAM region. If the dirty log is already
+    /// in use, by a migration or continuous dirty logging, the pages read
+    /// while sampling are handed back through the next `dirty_log()` call
+    /// so that they aren't missed.
+    pub fn dirty_rate(&mut self, window: Duration) -> Result<DirtyRateStats> {
+        let armed = self.dirty_log_migration || self.dirty_log_continuous || self.dirty_log_reset;
+        if armed {
+            // Start sampling from a clean log.
+            let table = self.read_dirty_log().map_err(Error::DirtyLog)?;
+            self.pending_dirty_log.extend(table);
+        } else {
+            self.arm_dirty_log().map_err(Error::DirtyLog)?;
+        }
+
+        let start = Instant::now();
+        thread::sleep(window);
+        let table = self.read_dirty_log();
+        let elapsed = start.elapsed();
+
+        let table = if armed {
+            table.map_err(Error::DirtyLog)?
+        } else {
+            let disarmed = self.disarm_dirty_log();
+            let table = table.map_err(Error::DirtyLog)?;
+            disarmed.map_err(Error::DirtyLog)?;
+            table
+        };
+
+        let regions = self.memory_usage_by_region(false)?;
+        let secs = elapsed.as_secs_f64();
+        let to_mib_per_sec = |bytes: u64| bytes as f64 / (1 << 20) as f64 / secs;
+
+        let mut regions: Vec<RegionDirtyRate> = regions
+            .into_iter()
+            .map(|region| {
+                let end = region.start + region.size;
+                let dirty_bytes = table
+                    .regions()
+                    .iter()
+                    .map(|r| {
+                        let r_end = r.gpa + r.length;
+                        r_end.min(end).saturating_sub(r.gpa.max(region.start))
+                    })
+                    .sum();
+                RegionDirtyRate {
+                    memory_zone: region.memory_zone,
+                    start: region.start,
+                    size: region.size,
+                    dirty_bytes,
+                    mib_per_sec: to_mib_per_sec(dirty_bytes),
+                    hot: false,
+                }
+            })
+            .collect();
+
+        let dirty_bytes: u64 = regions.iter().map(|r| r.dirty_bytes).sum();
+        let total_size: u64 = regions.iter().map(|r| r.size).sum();
+        for region in regions.iter_mut() {
+            // Compare dirty_bytes / size against the overall ratio.
+            region.hot = region.dirty_bytes as u128 * total_size as u128
+                > dirty_bytes as u128 * region.size as u128;
+        }
+
+        let stats = DirtyRateStats {
+            window_ms: elapsed.as_millis() as u64,
+            dirty_bytes,
+            mib_per_sec: to_mib_per_sec(dirty_bytes),
+            regions,
+        };
+
+        if armed {
+            self.pending_dirty_log.extend(table);
+        }
+
+        Ok(stats)
+    }
+
     pub fn resize(
         &mut self,
         desired_vcpus: Option<u8>,
@@ -1306,6 +2638,7 @@ impl Vm {
         event!("vm", "resizing");
 
         if let Some(desired_vcpus) = desired_vcpus {
+            let present_vcpus = self.cpu_manager.lock().unwrap().vcpu_counts().present;
             if self
                 .cpu_manager
                 .lock()
@@ -1318,11 +2651,18 @@ impl Vm {
                     .unwrap()
                     .notify_hotplug(AcpiNotificationFlags::CPU_DEVICES_CHANGED)
                     .map_err(Error::DeviceManager)?;
+
+                if desired_vcpus < present_vcpus {
+                    self.wait_for_vcpus_removal(desired_vcpus)?;
+                }
             }
             self.config.lock().unwrap().cpus.boot_vcpus = desired_vcpus;
         }
 
         if let Some(desired_memory) = desired_memory {
+            self.memory_hotplug_region = None;
+            let plugged = self.memory_manager.lock().unwrap().virtio_mem_plugged(None);
+
             let new_region = self
                 .memory_manager
                 .lock()
@@ -1330,6 +2670,15 @@ impl Vm {
                 .resize(desired_memory)
                 .map_err(Error::MemoryManager)?;
 
+            self.memory_hotplug_region = match &new_region {
+                Some(region) => Some(HotplugRegionInfo {
+                    start: region.start_addr().raw_value(),
+                    size: region.len(),
+                    numa_node: None,
+                }),
+                None => self.virtio_mem_hotplug_info(None, plugged),
+            };
+
             let mut memory_config = &mut self.config.lock().unwrap().memory;
 
             if let Some(new_region) = &new_region {
@@ -1339,7 +2688,9 @@ impl Vm {
                     .update_memory(new_region)
                     .map_err(Error::DeviceManager)?;
 
-                match memory_config.hotplug_method {
+                // The configured method may only apply after a reboot, see
+                // set_hotplug_method().
+                match self.memory_manager.lock().unwrap().hotplug_method() {
                     HotplugMethod::Acpi => {
                         self.device_manager
                             .lock()
@@ -1385,19 +2736,111 @@ impl Vm {
         Ok(())
     }
 
-    pub fn resize_zone(&mut self, id: String, desired_memory: u64) -> Result<()> {
-        let memory_config = &mut self.config.lock().unwrap().memory;
+    /// Hot-plug `size` bytes of RAM into the guest NUMA node `numa_node`,
+    /// through the virtio-mem device of one of the memory zones of the node.
+    /// Unlike `resize`, this lets the caller decide where the memory lands.
+    pub fn add_memory(&mut self, size: u64, numa_node: u32) -> Result<()> {
+        let node = self
+            .numa_nodes
+            .get(&numa_node)
+            .ok_or(Error::UnknownNumaNode(numa_node))?;
+        if node.hotplug_regions.is_empty() {
+            return Err(Error::NumaNodeWithoutHotplugRegion(numa_node));
+        }
 
-        if let Some(zones) = &mut memory_config.zones {
-            for zone in zones.iter_mut() {
-                if zone.id == id {
-                    if desired_memory >= zone.size {
-                        let hotplugged_size = desired_memory - zone.size;
-                        self.memory_manager
-                            .lock()
-                            .unwrap()
-                            .resize_zone(&id, desired_memory - zone.size)
-                            .map_err(Error::MemoryManager)?;
+        let (id, desired_memory) = zone_to_grow(
+            self.config.lock().unwrap().memory.zones.as_deref(),
+            &node.memory_zones,
+            size,
+        )
+        .ok_or(Error::NumaNodeHotplugExhausted(numa_node))?;
+
+        self.resize_zone(id, desired_memory)
+    }
+
+    // Memory plugged into a virtio-mem region since `previous` was taken.
+    fn virtio_mem_hotplug_info(
+        &self,
+        id: Option<&str>,
+        previous: Option<(GuestAddress, u64)>,
+    ) -> Option<HotplugRegionInfo> {
+        let (start, plugged) = self.memory_manager.lock().unwrap().virtio_mem_plugged(id)?;
+        let previous = previous.map(|(_, plugged)| plugged).unwrap_or(0);
+        if plugged <= previous {
+            return None;
+        }
+
+        Some(HotplugRegionInfo {
+            start: start.raw_value(),
+            size: plugged - previous,
+            numa_node: id.and_then(|id| {
+                self.numa_nodes
+                    .iter()
+                    .find(|(_, node)| node.memory_zones.iter().any(|zone| zone == id))
+                    .map(|(node_id, _)| *node_id)
+            }),
+        })
+    }
+
+    /// Guest RAM added by the latest memory resize, `None` if it didn't add
+    /// any memory. With virtio-mem, the guest picks which blocks of the
+    /// virtio-mem region it plugs, `start` is the base of that region and
+    /// `size` the amount of memory requested on top of what was plugged.
+    pub fn memory_hotplug_region_info(&self) -> Option<HotplugRegionInfo> {
+        self.memory_hotplug_region.clone()
+    }
+
+    // Wait for the guest to eject the vCPUs marked for removal. A guest that
+    // can't offline one of them, because some work is bound to it for
+    // instance, never ejects it. In that case the removal is cancelled and
+    // the configuration reflects the vCPUs actually present.
+    fn wait_for_vcpus_removal(&self, desired_vcpus: u8) -> Result<()> {
+        let start = Instant::now();
+        loop {
+            // The CPU manager lock can't be held while waiting since the
+            // guest needs it to eject the vCPUs.
+            let present = self.cpu_manager.lock().unwrap().vcpu_counts().present;
+            if present <= desired_vcpus {
+                return Ok(());
+            }
+
+            if start.elapsed() >= CPU_UNPLUG_TIMEOUT {
+                let mut cpu_manager = self.cpu_manager.lock().unwrap();
+                cpu_manager.cancel_vcpus_removal();
+                let present = cpu_manager.vcpu_counts().present;
+                self.config.lock().unwrap().cpus.boot_vcpus = present;
+
+                return Err(Error::CpuUnplugRefused {
+                    desired: desired_vcpus,
+                    present,
+                });
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    pub fn resize_zone(&mut self, id: String, desired_memory: u64) -> Result<()> {
+        let memory_config = &mut self.config.lock().unwrap().memory;
+
+        if let Some(zones) = &mut memory_config.zones {
+            for zone in zones.iter_mut() {
+                if zone.id == id {
+                    if desired_memory >= zone.size {
+                        let hotplugged_size = desired_memory - zone.size;
+                        self.memory_hotplug_region = None;
+                        let plugged = self
+                            .memory_manager
+                            .lock()
+                            .unwrap()
+                            .virtio_mem_plugged(Some(&id));
+                        self.memory_manager
+                            .lock()
+                            .unwrap()
+                            .resize_zone(&id, desired_memory - zone.size)
+                            .map_err(Error::MemoryManager)?;
+                        self.memory_hotplug_region =
+                            self.virtio_mem_hotplug_info(Some(&id), plugged);
                         // We update the memory zone config regardless of the
                         // actual 'resize-zone' operation result (happened or
                         // not), so that if the VM reboots it will be running
@@ -1422,12 +2865,14 @@ impl Vm {
     }
 
     pub fn add_device(&mut self, mut device_cfg: DeviceConfig) -> Result<PciDeviceInfo> {
-        let pci_device_info = self
-            .device_manager
-            .lock()
-            .unwrap()
-            .add_device(&mut device_cfg)
-            .map_err(Error::DeviceManager)?;
+        let pci_device_info = device_added_event(
+            "vfio",
+            self.device_manager
+                .lock()
+                .unwrap()
+                .add_device(&mut device_cfg)
+                .map_err(Error::DeviceManager),
+        )?;
 
         // Update VmConfig by adding the new device. This is important to
         // ensure the device would be created in case of a reboot.
@@ -1446,12 +2891,14 @@ impl Vm {
     }
 
     pub fn add_user_device(&mut self, mut device_cfg: UserDeviceConfig) -> Result<PciDeviceInfo> {
-        let pci_device_info = self
-            .device_manager
-            .lock()
-            .unwrap()
-            .add_user_device(&mut device_cfg)
-            .map_err(Error::DeviceManager)?;
+        let pci_device_info = device_added_event(
+            "vfio-user",
+            self.device_manager
+                .lock()
+                .unwrap()
+                .add_user_device(&mut device_cfg)
+                .map_err(Error::DeviceManager),
+        )?;
 
         // Update VmConfig by adding the new device. This is important to
         // ensure the device would be created in case of a reboot.
@@ -1469,12 +2916,85 @@ impl Vm {
         Ok(pci_device_info)
     }
 
-    pub fn remove_device(&mut self, id: String) -> Result<()> {
+    /// Run `command` through the guest agent, reached over the vsock device.
+    pub fn guest_agent_request(&self, command: GuestAgentCommand) -> Result<GuestAgentResponse> {
+        let socket = self
+            .config
+            .lock()
+            .unwrap()
+            .vsock
+            .as_ref()
+            .map(|vsock| vsock.socket.clone())
+            .ok_or(Error::GuestAgent(guest_agent::Error::NoVsock))?;
+
+        guest_agent::request(
+            &socket,
+            GUEST_AGENT_VSOCK_PORT,
+            command,
+            GUEST_AGENT_TIMEOUT,
+        )
+        .map_err(Error::GuestAgent)
+    }
+
+    /// Wait for the guest driver to bring up the hot-plugged virtio device
+    /// `id`, that is to set DRIVER_OK in the device status. This tells the
+    /// device is actually usable from the guest.
+    pub fn wait_device_ready(&self, id: &str, timeout: Duration) -> Result<()> {
+        let start = Instant::now();
+        loop {
+            // Poll without holding the device manager lock while sleeping.
+            if self
+                .device_manager
+                .lock()
+                .unwrap()
+                .virtio_device_ready(id)
+                .map_err(Error::DeviceManager)?
+            {
+                return Ok(());
+            }
+
+            if start.elapsed() >= timeout {
+                return Err(Error::DeviceReadyTimeout(id.to_string()));
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Reconnect the vhost-user device `id` to its backend. The devices
+    /// already reconnect on their own when the backend closes the
+    /// connection, this is meant for the backends restarted after the
+    /// automatic reconnection gave up.
+    pub fn reconnect_vhost_user(&self, id: &str) -> Result<()> {
         self.device_manager
             .lock()
             .unwrap()
-            .remove_device(id.clone())
+            .reconnect_vhost_user(id)
             .map_err(Error::DeviceManager)?;
+        event!("vm", "vhost-user-reconnect-requested", "id", id);
+        Ok(())
+    }
+
+    pub fn remove_device(&mut self, id: String) -> Result<()> {
+        // The "device-removed" event is emitted once the guest has ejected
+        // the device.
+        if let Err(e) = self
+            .device_manager
+            .lock()
+            .unwrap()
+            .remove_device(id.clone())
+        {
+            event!(
+                "vm",
+                "device-remove-failed",
+                "id",
+                &id,
+                "error",
+                format!("{:?}", e)
+            );
+            return Err(Error::DeviceManager(e));
+        }
+        event!("vm", "device-removing", "id", &id);
 
         // Update VmConfig by removing the device. This is important to
         // ensure the device would not be created in case of a reboot.
@@ -1522,6 +3042,11 @@ impl Vm {
             }
         }
 
+        // Remove if serial device
+        if let Some(serial_devices) = config.serial_devices.as_mut() {
+            serial_devices.retain(|dev| dev.id.as_ref() != Some(&id));
+        }
+
         self.device_manager
             .lock()
             .unwrap()
@@ -1531,12 +3056,14 @@ impl Vm {
     }
 
     pub fn add_disk(&mut self, mut disk_cfg: DiskConfig) -> Result<PciDeviceInfo> {
-        let pci_device_info = self
-            .device_manager
-            .lock()
-            .unwrap()
-            .add_disk(&mut disk_cfg)
-            .map_err(Error::DeviceManager)?;
+        let pci_device_info = device_added_event(
+            "disk",
+            self.device_manager
+                .lock()
+                .unwrap()
+                .add_disk(&mut disk_cfg)
+                .map_err(Error::DeviceManager),
+        )?;
 
         // Update VmConfig by adding the new device. This is important to
         // ensure the device would be created in case of a reboot.
@@ -1555,12 +3082,14 @@ impl Vm {
     }
 
     pub fn add_fs(&mut self, mut fs_cfg: FsConfig) -> Result<PciDeviceInfo> {
-        let pci_device_info = self
-            .device_manager
-            .lock()
-            .unwrap()
-            .add_fs(&mut fs_cfg)
-            .map_err(Error::DeviceManager)?;
+        let pci_device_info = device_added_event(
+            "fs",
+            self.device_manager
+                .lock()
+                .unwrap()
+                .add_fs(&mut fs_cfg)
+                .map_err(Error::DeviceManager),
+        )?;
 
         // Update VmConfig by adding the new device. This is important to
         // ensure the device would be created in case of a reboot.
@@ -1578,14 +3107,46 @@ impl Vm {
         Ok(pci_device_info)
     }
 
-    pub fn add_pmem(&mut self, mut pmem_cfg: PmemConfig) -> Result<PciDeviceInfo> {
-        let pci_device_info = self
-            .device_manager
+    /// Hotplug a virtio-fs device driven by the backend reached through
+    /// `socket`, already connected by the caller, for instance a supervisor
+    /// spawning virtiofsd. The `socket` path from `fs_cfg` is ignored.
+    ///
+    /// Since the connection can't be established again, the device isn't
+    /// added to the VM configuration and won't be recreated on reboot, nor
+    /// can it reconnect to the backend.
+    pub fn add_fs_with_socket(
+        &mut self,
+        mut fs_cfg: FsConfig,
+        socket: UnixStream,
+    ) -> Result<PciDeviceInfo> {
+        let pci_device_info = device_added_event(
+            "fs",
+            self.device_manager
+                .lock()
+                .unwrap()
+                .add_fs_with_socket(&mut fs_cfg, socket)
+                .map_err(Error::DeviceManager),
+        )?;
+
+        self.device_manager
             .lock()
             .unwrap()
-            .add_pmem(&mut pmem_cfg)
+            .notify_hotplug(AcpiNotificationFlags::PCI_DEVICES_CHANGED)
             .map_err(Error::DeviceManager)?;
 
+        Ok(pci_device_info)
+    }
+
+    pub fn add_pmem(&mut self, mut pmem_cfg: PmemConfig) -> Result<PciDeviceInfo> {
+        let pci_device_info = device_added_event(
+            "pmem",
+            self.device_manager
+                .lock()
+                .unwrap()
+                .add_pmem(&mut pmem_cfg)
+                .map_err(Error::DeviceManager),
+        )?;
+
         // Update VmConfig by adding the new device. This is important to
         // ensure the device would be created in case of a reboot.
         {
@@ -1603,12 +3164,14 @@ impl Vm {
     }
 
     pub fn add_net(&mut self, mut net_cfg: NetConfig) -> Result<PciDeviceInfo> {
-        let pci_device_info = self
-            .device_manager
-            .lock()
-            .unwrap()
-            .add_net(&mut net_cfg)
-            .map_err(Error::DeviceManager)?;
+        let pci_device_info = device_added_event(
+            "net",
+            self.device_manager
+                .lock()
+                .unwrap()
+                .add_net(&mut net_cfg)
+                .map_err(Error::DeviceManager),
+        )?;
 
         // Update VmConfig by adding the new device. This is important to
         // ensure the device would be created in case of a reboot.
@@ -1627,12 +3190,14 @@ impl Vm {
     }
 
     pub fn add_vdpa(&mut self, mut vdpa_cfg: VdpaConfig) -> Result<PciDeviceInfo> {
-        let pci_device_info = self
-            .device_manager
-            .lock()
-            .unwrap()
-            .add_vdpa(&mut vdpa_cfg)
-            .map_err(Error::DeviceManager)?;
+        let pci_device_info = device_added_event(
+            "vdpa",
+            self.device_manager
+                .lock()
+                .unwrap()
+                .add_vdpa(&mut vdpa_cfg)
+                .map_err(Error::DeviceManager),
+        )?;
 
         // Update VmConfig by adding the new device. This is important to
         // ensure the device would be created in case of a reboot.
@@ -1651,18 +3216,60 @@ impl Vm {
     }
 
     pub fn add_vsock(&mut self, mut vsock_cfg: VsockConfig) -> Result<PciDeviceInfo> {
-        let pci_device_info = self
-            .device_manager
+        let pci_device_info = device_added_event(
+            "vsock",
+            self.device_manager
+                .lock()
+                .unwrap()
+                .add_vsock(&mut vsock_cfg)
+                .map_err(Error::DeviceManager),
+        )?;
+
+        // Update VmConfig by adding the new device. This is important to
+        // ensure the device would be created in case of a reboot.
+        {
+            let mut config = self.config.lock().unwrap();
+            config.vsock = Some(vsock_cfg);
+        }
+
+        self.device_manager
             .lock()
             .unwrap()
-            .add_vsock(&mut vsock_cfg)
+            .notify_hotplug(AcpiNotificationFlags::PCI_DEVICES_CHANGED)
             .map_err(Error::DeviceManager)?;
 
+        Ok(pci_device_info)
+    }
+
+    /// Hot-plug a serial device, backed by a virtio-console PCI device.
+    /// Legacy serial ports sit on the ISA bus and can't be added at runtime,
+    /// they must be configured through `extra_serial` before booting.
+    pub fn add_serial(&mut self, mut serial_cfg: SerialDeviceConfig) -> Result<PciDeviceInfo> {
+        if serial_cfg.backend == SerialBackend::Legacy {
+            return Err(Error::LegacySerialHotplug);
+        }
+
+        {
+            // Validate the configuration change in a cloned configuration
+            let mut config = self.config.lock().unwrap().clone();
+            add_to_config(&mut config.serial_devices, serial_cfg.clone());
+            config.validate().map_err(Error::ConfigValidation)?;
+        }
+
+        let pci_device_info = device_added_event(
+            "serial",
+            self.device_manager
+                .lock()
+                .unwrap()
+                .add_serial(&mut serial_cfg)
+                .map_err(Error::DeviceManager),
+        )?;
+
         // Update VmConfig by adding the new device. This is important to
         // ensure the device would be created in case of a reboot.
         {
             let mut config = self.config.lock().unwrap();
-            config.vsock = Some(vsock_cfg);
+            add_to_config(&mut config.serial_devices, serial_cfg);
         }
 
         self.device_manager
@@ -1674,6 +3281,23 @@ impl Vm {
         Ok(pci_device_info)
     }
 
+    /// Remove a serial device previously added through `add_serial()`.
+    pub fn remove_serial(&mut self, id: String) -> Result<()> {
+        let known = self
+            .config
+            .lock()
+            .unwrap()
+            .serial_devices
+            .as_ref()
+            .map(|devices| devices.iter().any(|dev| dev.id.as_ref() == Some(&id)))
+            .unwrap_or(false);
+        if !known {
+            return Err(Error::UnknownSerialDevice(id));
+        }
+
+        self.remove_device(id)
+    }
+
     pub fn counters(&self) -> Result<HashMap<String, HashMap<&'static str, Wrapping<u64>>>> {
         Ok(self.device_manager.lock().unwrap().counters())
     }
@@ -1681,7 +3305,7 @@ impl Vm {
     fn os_signal_handler(
         mut signals: Signals,
         console_input_clone: Arc<Console>,
-        on_tty: bool,
+        terminal: Option<Arc<dyn TerminalBackend>>,
         exit_evt: &EventFd,
     ) {
         for sig in &HANDLED_SIGNALS {
@@ -1690,13 +3314,13 @@ impl Vm {
 
         for signal in signals.forever() {
             match signal {
-                SIGWINCH => {
-                    console_input_clone.update_console_size();
-                }
+                SIGWINCH => match terminal.as_ref().and_then(|t| t.size()) {
+                    Some((cols, rows)) => console_input_clone.set_console_size(cols, rows),
+                    None => console_input_clone.update_console_size(),
+                },
                 SIGTERM | SIGINT => {
-                    if on_tty {
-                        io::stdin()
-                            .lock()
+                    if let Some(terminal) = &terminal {
+                        terminal
                             .set_canon_mode()
                             .expect("failed to restore terminal mode");
                     }
@@ -1998,7 +3622,7 @@ impl Vm {
             Ok(signals) => {
                 self.signals = Some(signals.handle());
                 let exit_evt = self.exit_evt.try_clone().map_err(Error::EventFdClone)?;
-                let on_tty = self.on_tty;
+                let terminal = self.terminal.clone();
                 let signal_handler_seccomp_filter =
                     get_seccomp_filter(&self.seccomp_action, Thread::SignalHandler)
                         .map_err(Error::CreateSeccompFilter)?;
@@ -2016,7 +3640,7 @@ impl Vm {
                                 }
                             }
                             std::panic::catch_unwind(AssertUnwindSafe(|| {
-                                Vm::os_signal_handler(signals, console, on_tty, &exit_evt);
+                                Vm::os_signal_handler(signals, console, terminal, &exit_evt);
                             }))
                             .map_err(|_| {
                                 error!("signal_handler thead panicked");
@@ -2033,16 +3657,49 @@ impl Vm {
     }
 
     fn setup_tty(&self) -> Result<()> {
-        if self.on_tty {
-            io::stdin()
-                .lock()
-                .set_raw_mode()
-                .map_err(Error::SetTerminalRaw)?;
+        if let Some(terminal) = &self.terminal {
+            terminal.set_raw_mode().map_err(Error::SetTerminalRaw)?;
+            if let Some((cols, rows)) = terminal.size() {
+                self.device_manager
+                    .lock()
+                    .unwrap()
+                    .console()
+                    .set_console_size(cols, rows);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Attach the console to `terminal` rather than to the VMM standard
+    /// input, or to no terminal at all with `None`. This must be done before
+    /// the VM is booted.
+    pub fn set_terminal_backend(
+        &mut self,
+        terminal: Option<Arc<dyn TerminalBackend>>,
+    ) -> Result<()> {
+        if self.get_state()? != VmState::Created {
+            return Err(Error::TerminalBackendAfterBoot);
         }
 
+        self.terminal = terminal;
         Ok(())
     }
 
+    pub fn terminal_backend(&self) -> Option<Arc<dyn TerminalBackend>> {
+        self.terminal.clone()
+    }
+
+    /// Report the size of the terminal backend to the guest console, for
+    /// terminals resized without the VMM receiving SIGWINCH.
+    pub fn update_console_size(&self) {
+        let console = self.device_manager.lock().unwrap().console().clone();
+        match self.terminal.as_ref().and_then(|t| t.size()) {
+            Some((cols, rows)) => console.set_console_size(cols, rows),
+            None => console.update_console_size(),
+        }
+    }
+
     // Creates ACPI tables
     // In case of TDX being used, this is a no-op since the tables will be
     // created and passed when populating the HOB.
@@ -2067,7 +3724,6 @@ impl Vm {
         Some(rsdp_addr)
     }
 
-    #[cfg(target_arch = "x86_64")]
     fn entry_point(&mut self) -> Result<Option<EntryPoint>> {
         self.load_kernel_handle
             .take()
@@ -2075,22 +3731,41 @@ impl Vm {
             .transpose()
     }
 
-    #[cfg(target_arch = "aarch64")]
-    fn entry_point(&mut self) -> Result<Option<EntryPoint>> {
-        Ok(if self.kernel.as_ref().is_some() {
-            Some(self.load_kernel()?)
-        } else {
-            None
-        })
+    /// Append extra arguments to the kernel command line. Arguments already
+    /// present on the command line are skipped. This can only be done before
+    /// the VM is booted.
+    pub fn append_cmdline(&mut self, args: &str) -> Result<()> {
+        if self.get_state()? != VmState::Created {
+            return Err(Error::CmdLineAfterBoot);
+        }
+
+        let previous = self.config.lock().unwrap().cmdline.args.clone();
+        self.config.lock().unwrap().cmdline.args = merge_cmdline(&previous, args);
+
+        // Make sure the resulting command line still fits
+        if let Err(e) = Self::generate_cmdline(
+            &self.config,
+            #[cfg(target_arch = "aarch64")]
+            &self.device_manager,
+        ) {
+            self.config.lock().unwrap().cmdline.args = previous;
+            return Err(e);
+        }
+
+        Ok(())
     }
 
     pub fn boot(&mut self) -> Result<()> {
         info!("Booting VM");
         event!("vm", "booting");
+        let start = Instant::now();
         let current_state = self.get_state()?;
         if current_state == VmState::Paused {
             return self.resume().map_err(Error::Resume);
         }
+        if current_state == VmState::BreakPoint {
+            return self.resume_from_breakpoint();
+        }
 
         let new_state = if self.stop_on_boot {
             VmState::BreakPoint
@@ -2109,6 +3784,14 @@ impl Vm {
         // Load kernel synchronously or if asynchronous then wait for load to
         // finish.
         let entry_point = self.entry_point()?;
+        #[cfg(target_arch = "x86_64")]
+        {
+            self.kernel_entry_point = entry_point.and_then(|e| e.entry_addr);
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            self.kernel_entry_point = entry_point.map(|e| e.entry_addr);
+        }
 
         // The initial TDX configuration must be done before the vCPUs are
         // created
@@ -2181,10 +3864,94 @@ impl Vm {
 
         let mut state = self.state.try_write().map_err(|_| Error::PoisonedState)?;
         *state = new_state;
+        let duration_ms = self.record_timing(VmOperation::Boot, start);
+        event!("vm", "booted", "duration_ms", duration_ms);
+        Ok(())
+    }
+
+    /// Let the VM run after it has been stopped in the BreakPoint state.
+    /// When the vCPUs were created but never started (stop on boot), they
+    /// are started now, otherwise they are simply resumed.
+    fn resume_from_breakpoint(&mut self) -> Result<()> {
+        if self.cpu_manager.lock().unwrap().vcpus_paused() {
+            self.resume().map_err(Error::Resume)?;
+        } else {
+            self.cpu_manager
+                .lock()
+                .unwrap()
+                .start_boot_vcpus()
+                .map_err(Error::CpuManager)?;
+
+            let mut state = self.state.try_write().map_err(|_| Error::PoisonedState)?;
+            *state = VmState::Running;
+        }
+
         event!("vm", "booted");
         Ok(())
     }
 
+    /// Block until the guest signals it has finished booting, or until the
+    /// timeout expires. See docs/debug-port.md for the guest side contract.
+    pub fn wait_for_boot_complete(&self, timeout: Duration) -> Result<()> {
+        if self.boot_complete.wait(timeout) {
+            Ok(())
+        } else {
+            Err(Error::BootCompleteTimeout)
+        }
+    }
+
+    /// Start recording guest MMIO and PIO accesses, keeping the last
+    /// `capacity` of them. Restrict the trace to a single device by passing
+    /// its address range as `filter`.
+    pub fn enable_io_trace(&self, capacity: usize, filter: Option<Range<u64>>) {
+        self.io_tracer.enable(capacity, filter);
+    }
+
+    pub fn disable_io_trace(&self) {
+        self.io_tracer.disable();
+    }
+
+    /// Accesses recorded since tracing was enabled, oldest first.
+    pub fn io_trace(&self) -> Vec<IoTraceEntry> {
+        self.io_tracer.entries()
+    }
+
+    /// Port ranges registered on the PIO bus and the device owning each of
+    /// them. Always empty on AArch64, which has no PIO bus.
+    pub fn io_address_map(&self) -> Vec<BusRangeInfo> {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .bus_address_map(IoSpace::Pio)
+    }
+
+    /// Address ranges registered on the MMIO bus and the device owning each
+    /// of them.
+    pub fn mmio_address_map(&self) -> Vec<BusRangeInfo> {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .bus_address_map(IoSpace::Mmio)
+    }
+
+    /// Make the guest memory accesses performed on behalf of the vCPUs, such
+    /// as while emulating an instruction, fail with `probability` when they
+    /// touch `range`. A probability of 0 removes the fault for that range.
+    #[cfg(feature = "fault_injection")]
+    pub fn inject_memory_fault(&self, range: Range<u64>, probability: f64) -> Result<()> {
+        if !(0.0..=1.0).contains(&probability) {
+            return Err(Error::InvalidFaultProbability(probability));
+        }
+        self.memory_faults.inject(range, probability);
+        Ok(())
+    }
+
+    /// Remove all the faults injected with `inject_memory_fault()`.
+    #[cfg(feature = "fault_injection")]
+    pub fn clear_memory_faults(&self) {
+        self.memory_faults.clear();
+    }
+
     /// Gets a thread-safe reference counted pointer to the VM configuration.
     pub fn get_config(&self) -> Arc<Mutex<VmConfig>> {
         Arc::clone(&self.config)
@@ -2209,8 +3976,155 @@ impl Vm {
         Ok(self.saved_clock)
     }
 
-    #[cfg(target_arch = "aarch64")]
-    /// Add the vGIC section to the VM snapshot.
+    /// Roll the paused VM back to `snapshot`, saved at `source_url`, in
+    /// place: the guest memory, the vCPUs and the devices are restored
+    /// without recreating the VM. The snapshot must have been taken from
+    /// the same VM configuration, with the same memory layout, vCPUs and
+    /// devices.
+    ///
+    /// The dirty log is armed after a reset, so that resetting again to the
+    /// same snapshot only copies back the pages written to in the meantime.
+    pub fn reset_to_snapshot(&mut self, snapshot: &Snapshot, source_url: &str) -> Result<()> {
+        if self.get_state()? != VmState::Paused {
+            return Err(Error::VmNotPaused);
+        }
+
+        let missing =
+            |id: &str| Error::Restore(MigratableError::Restore(anyhow!("Missing {} snapshot", id)));
+        let memory_manager_snapshot = snapshot
+            .snapshots
+            .get(MEMORY_MANAGER_SNAPSHOT_ID)
+            .ok_or_else(|| missing(MEMORY_MANAGER_SNAPSHOT_ID))?;
+        let cpu_manager_snapshot = snapshot
+            .snapshots
+            .get(CPU_MANAGER_SNAPSHOT_ID)
+            .ok_or_else(|| missing(CPU_MANAGER_SNAPSHOT_ID))?;
+        let device_manager_snapshot = snapshot
+            .snapshots
+            .get(DEVICE_MANAGER_SNAPSHOT_ID)
+            .ok_or_else(|| missing(DEVICE_MANAGER_SNAPSHOT_ID))?;
+        let memory_manager_data: MemoryManagerSnapshotData = memory_manager_snapshot
+            .to_versioned_state(MEMORY_MANAGER_SNAPSHOT_ID)
+            .map_err(Error::Restore)?;
+
+        // Check everything up front, not to leave the VM half restored.
+        if !self
+            .memory_manager
+            .lock()
+            .unwrap()
+            .snapshot_compatible(&memory_manager_data)
+        {
+            return Err(Error::IncompatibleSnapshot("memory layout"));
+        }
+        if !self
+            .cpu_manager
+            .lock()
+            .unwrap()
+            .snapshot_compatible(cpu_manager_snapshot)
+        {
+            return Err(Error::IncompatibleSnapshot("vCPUs"));
+        }
+        if !self
+            .device_manager
+            .lock()
+            .unwrap()
+            .snapshot_compatible(device_manager_snapshot)
+        {
+            return Err(Error::IncompatibleSnapshot("devices"));
+        }
+
+        event!("vm", "resetting-to-snapshot");
+
+        let mut memory_file_path = url_to_path(source_url).map_err(Error::Restore)?;
+        memory_file_path.push(SNAPSHOT_FILENAME);
+        let base = (
+            source_url.to_string(),
+            std::fs::metadata(memory_file_path)
+                .and_then(|m| m.modified())
+                .ok(),
+        );
+
+        // Only the pages written to since the last reset need to be copied
+        // back, as long as it was to the same snapshot.
+        let dirty = if self.dirty_log_reset && self.reset_base.as_ref() == Some(&base) {
+            Some(self.read_reset_dirty_log()?)
+        } else {
+            None
+        };
+        self.reset_base = None;
+
+        let restored = self
+            .memory_manager
+            .lock()
+            .unwrap()
+            .restore_in_place(&memory_manager_data, source_url, dirty.as_ref())
+            .map_err(Error::MemoryManager)?;
+
+        self.cpu_manager
+            .lock()
+            .unwrap()
+            .restore_vcpus_in_place(cpu_manager_snapshot)
+            .map_err(Error::CpuManager)?;
+
+        #[cfg(target_arch = "aarch64")]
+        if let Some(gicv3_its_snapshot) = snapshot.snapshots.get(GIC_V3_ITS_SNAPSHOT_ID) {
+            self.device_manager
+                .lock()
+                .unwrap()
+                .get_interrupt_controller()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .restore(*gicv3_its_snapshot.clone())
+                .map_err(Error::Restore)?;
+        }
+
+        // The virtio devices are reset before being restored, their queues
+        // being reloaded from the restored guest memory.
+        self.device_manager
+            .lock()
+            .unwrap()
+            .restore_devices(*device_manager_snapshot.clone())
+            .map_err(Error::Restore)?;
+
+        #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+        self.load_clock_from_snapshot(snapshot)?;
+
+        // Copying the snapshot back marked the pages as dirty, start over
+        // from a clean log.
+        if self.dirty_log_reset || self.dirty_log_migration || self.dirty_log_continuous {
+            self.read_reset_dirty_log()?;
+        } else {
+            self.arm_dirty_log().map_err(Error::DirtyLog)?;
+        }
+        self.dirty_log_reset = true;
+        self.reset_base = Some(base);
+
+        event!(
+            "vm",
+            "reset-to-snapshot",
+            "restored_bytes",
+            restored.to_string()
+        );
+
+        Ok(())
+    }
+
+    // Read the pages written to since the last reset, handing the ones not
+    // read from the dirty log yet over to its other users, if any.
+    fn read_reset_dirty_log(&mut self) -> Result<MemoryRangeTable> {
+        let table = self.read_dirty_log().map_err(Error::DirtyLog)?;
+        if self.dirty_log_migration || self.dirty_log_continuous {
+            self.pending_dirty_log.extend(table);
+        } else {
+            self.pending_dirty_log = MemoryRangeTable::default();
+        }
+
+        Ok(std::mem::take(&mut self.reset_dirty_log))
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    /// Add the vGIC section to the VM snapshot.
     fn add_vgic_snapshot_section(
         &self,
         vm_snapshot: &mut Snapshot,
@@ -2250,8 +4164,9 @@ impl Vm {
         let vcpu_numbers = saved_vcpu_states.len();
 
         // Creating a GIC device here, as the GIC will not be created when
-        // restoring the device manager. Note that currently only the bare GICv3
-        // without ITS is supported.
+        // restoring the device manager. The GICv3 is always created along
+        // with its ITS, whose tables are reloaded from the guest RAM when the
+        // GIC state is restored, meaning the memory must be restored first.
         self.device_manager
             .lock()
             .unwrap()
@@ -2319,6 +4234,133 @@ impl Vm {
         self.device_manager.lock().unwrap().balloon_size()
     }
 
+    /// RAM the guest has right now, accounting for the memory hot-plugged
+    /// and for the memory taken back through the balloon. This is what the
+    /// guest reports as its total memory, minus what the kernel reserves.
+    pub fn total_guest_memory(&self) -> MemorySizes {
+        let (boot, hotplugged) = {
+            let memory_manager = self.memory_manager.lock().unwrap();
+            (memory_manager.boot_ram(), memory_manager.plugged_ram())
+        };
+        let balloon_inflated = self.balloon_size();
+
+        MemorySizes {
+            boot,
+            hotplugged,
+            balloon_inflated,
+            effective_available: (boot + hotplugged).saturating_sub(balloon_inflated),
+        }
+    }
+
+    /// Resize the balloon to `size` bytes, waiting for the guest to inflate
+    /// or deflate it accordingly. Returns the size the balloon actually
+    /// reached, which is off the requested one if the guest only partially
+    /// honored the request before `timeout` expired.
+    pub fn resize_balloon_sync(&mut self, size: u64, timeout: Duration) -> Result<u64> {
+        // The guest can't act on the request while paused.
+        if self.get_state()? != VmState::Running {
+            return Err(Error::VmNotRunning);
+        }
+
+        self.resize(None, None, Some(size))?;
+
+        let start = Instant::now();
+        let actual = loop {
+            let actual = self.balloon_size();
+            if actual.abs_diff(size) <= BALLOON_RESIZE_TOLERANCE || start.elapsed() >= timeout {
+                break actual;
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        };
+
+        event!(
+            "vm",
+            "balloon-resized",
+            "size",
+            size.to_string(),
+            "actual",
+            actual.to_string()
+        );
+
+        Ok(actual)
+    }
+
+    /// Let the VMM size the balloon based on the host memory pressure,
+    /// according to `policy`, or go back to manual sizing with `None`.
+    pub fn set_balloon_policy(&mut self, policy: Option<BalloonPolicy>) -> Result<()> {
+        let state = self.get_state()?;
+        if state != VmState::Running && state != VmState::Paused {
+            return Err(Error::VmNotRunning);
+        }
+
+        // Dropping the adjuster stops it.
+        self.balloon_adjuster = None;
+        if let Some(policy) = policy {
+            self.balloon_adjuster = Some(
+                BalloonAdjuster::new(
+                    policy,
+                    state == VmState::Running,
+                    self.config.clone(),
+                    self.device_manager.clone(),
+                )
+                .map_err(Error::BalloonPolicy)?,
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn balloon_policy(&self) -> Option<BalloonPolicy> {
+        self.balloon_adjuster.as_ref().map(|a| a.policy())
+    }
+
+    /// Flattened device tree describing the VM to the guest, as generated
+    /// when booting, which can be decompiled with `dtc -I dtb`. This is
+    /// `None` until the VM is booted, and for a restored VM.
+    #[cfg(target_arch = "aarch64")]
+    pub fn device_tree_blob(&self) -> Option<Vec<u8>> {
+        self.device_tree_blob.clone()
+    }
+
+    /// Read the guest PMU counters of vCPU `cpu_id`. The VM must be paused,
+    /// as a running vCPU thread holds its vCPU until it exits to the VMM.
+    /// Fails if the PMU could not be enabled when configuring the VM.
+    #[cfg(target_arch = "aarch64")]
+    pub fn pmu_counters(&self, cpu_id: u8) -> Result<cpu::PmuCounters> {
+        if self.get_state()? != VmState::Paused {
+            return Err(Error::VmNotPaused);
+        }
+
+        self.cpu_manager
+            .lock()
+            .unwrap()
+            .pmu_counters(cpu_id)
+            .map_err(Error::CpuManager)
+    }
+
+    /// Kill the thread of vCPU `cpu_id` when it doesn't stop running, for
+    /// instance after pause_with_timeout() failed because of it, so that the
+    /// rest of the VM can be paused, snapshotted or shut down.
+    ///
+    /// This is a last resort: the guest is left in an inconsistent state and
+    /// the VM must not be expected to run correctly afterwards.
+    pub fn force_kill_vcpu(&self, cpu_id: u8) -> Result<()> {
+        self.cpu_manager
+            .lock()
+            .unwrap()
+            .force_kill_vcpu(cpu_id)
+            .map_err(Error::CpuManager)?;
+        event!("vm", "vcpu-killed", "id", cpu_id.to_string());
+        Ok(())
+    }
+
+    /// Index of each vCPU along with the identifier the guest knows it by,
+    /// that is the APIC id on x86_64 and the MPIDR on AArch64.
+    pub fn vcpu_ids(&self) -> Vec<cpu::VcpuIdentity> {
+        self.cpu_manager.lock().unwrap().vcpu_ids()
+    }
+
     pub fn receive_memory_regions<F>(
         &mut self,
         ranges: &MemoryRangeTable,
@@ -2446,139 +4488,1428 @@ impl Vm {
             .memory_range_table(false)
     }
 
-    pub fn device_tree(&self) -> Arc<Mutex<DeviceTree>> {
-        self.device_manager.lock().unwrap().device_tree()
-    }
-
-    pub fn activate_virtio_devices(&self) -> Result<()> {
-        self.device_manager
-            .lock()
-            .unwrap()
-            .activate_virtio_devices()
-            .map_err(Error::ActivateVirtioDevices)
-    }
-
-    #[cfg(target_arch = "x86_64")]
-    pub fn power_button(&self) -> Result<()> {
-        return self
-            .device_manager
-            .lock()
-            .unwrap()
-            .notify_power_button()
-            .map_err(Error::PowerButton);
+    // Send a request along with its payload, abandoning the migration if the
+    // destination fails handling it.
+    fn send_migration_request<T>(
+        socket: &mut T,
+        request: Request,
+        payload: &[u8],
+        what: &str,
+    ) -> std::result::Result<(), MigratableError>
+    where
+        T: Read + Write,
+    {
+        request.write_to(socket)?;
+        socket
+            .write_all(payload)
+            .map_err(MigratableError::MigrateSocket)?;
+        Self::check_migration_response(socket, what)
     }
 
-    #[cfg(target_arch = "aarch64")]
-    pub fn power_button(&self) -> Result<()> {
-        self.device_manager
-            .lock()
-            .unwrap()
-            .notify_power_button()
-            .map_err(Error::PowerButton)
-    }
+    fn check_migration_response<T>(
+        socket: &mut T,
+        what: &str,
+    ) -> std::result::Result<(), MigratableError>
+    where
+        T: Read + Write,
+    {
+        let res = Response::read_from(socket)?;
+        if res.status() != Status::Ok {
+            warn!("Error during {}", what);
+            Request::abandon().write_to(socket)?;
+            Response::read_from(socket).ok();
+            return Err(MigratableError::MigrateSend(anyhow!(
+                "Error during {}",
+                what
+            )));
+        }
 
-    pub fn memory_manager_data(&self) -> MemoryManagerSnapshotData {
-        self.memory_manager.lock().unwrap().snapshot_data()
+        Ok(())
     }
 
-    #[cfg(all(target_arch = "x86_64", feature = "gdb"))]
-    pub fn debug_request(
+    // Send the memory described by `table`, returning the number of bytes
+    // sent. Nothing is sent if the table is empty.
+    fn send_memory_table<T>(
         &mut self,
-        gdb_request: &GdbRequestPayload,
-        cpu_id: usize,
-    ) -> Result<GdbResponsePayload> {
-        use GdbRequestPayload::*;
-        match gdb_request {
-            SetSingleStep(single_step) => {
-                self.set_guest_debug(cpu_id, &[], *single_step)
-                    .map_err(Error::Debug)?;
-            }
-            SetHwBreakPoint(addrs) => {
-                self.set_guest_debug(cpu_id, addrs, false)
-                    .map_err(Error::Debug)?;
-            }
-            Pause => {
-                self.debug_pause().map_err(Error::Debug)?;
-            }
-            Resume => {
-                self.debug_resume().map_err(Error::Debug)?;
-            }
-            ReadRegs => {
-                let regs = self.read_regs(cpu_id).map_err(Error::Debug)?;
-                return Ok(GdbResponsePayload::RegValues(Box::new(regs)));
-            }
-            WriteRegs(regs) => {
-                self.write_regs(cpu_id, regs).map_err(Error::Debug)?;
-            }
-            ReadMem(vaddr, len) => {
-                let mem = self.read_mem(cpu_id, *vaddr, *len).map_err(Error::Debug)?;
-                return Ok(GdbResponsePayload::MemoryRegion(mem));
-            }
-            WriteMem(vaddr, data) => {
-                self.write_mem(cpu_id, vaddr, data).map_err(Error::Debug)?;
-            }
-            ActiveVcpus => {
-                let active_vcpus = self.active_vcpus();
-                return Ok(GdbResponsePayload::ActiveVcpus(active_vcpus));
-            }
+        table: &MemoryRangeTable,
+        socket: &mut T,
+    ) -> std::result::Result<u64, MigratableError>
+    where
+        T: Read + Write,
+    {
+        if table.regions().is_empty() {
+            return Ok(0);
         }
-        Ok(GdbResponsePayload::CommandComplete)
+
+        Request::memory(table.length()).write_to(socket)?;
+        table.write_to(socket)?;
+        // And then the memory itself
+        self.send_memory_regions(table, socket)?;
+        Self::check_migration_response(socket, "memory migration")?;
+
+        Ok(table.regions().iter().map(|r| r.length).sum())
     }
 
-    #[cfg(feature = "guest_debug")]
-    fn get_dump_state(
+    fn send_migration(
         &mut self,
         destination_url: &str,
-    ) -> std::result::Result<DumpState, GuestDebuggableError> {
-        let nr_cpus = self.config.lock().unwrap().cpus.boot_vcpus as u32;
-        let elf_note_size = self.get_note_size(NoteDescType::ElfAndVmmDesc, nr_cpus) as isize;
-        let mut elf_phdr_num = 1 as u16;
-        let elf_sh_info = 0;
-        let coredump_file_path = url_to_file(destination_url)?;
-        let mapping_num = self.memory_manager.lock().unwrap().num_guest_ram_mappings();
+        policy: &MigrationPolicy,
+        progress: &mut dyn FnMut(MigrationPhase),
+    ) -> std::result::Result<(), MigratableError> {
+        let path = socket_url_to_path(destination_url)?;
+        let mut socket = UnixStream::connect(&path).map_err(|e| {
+            MigratableError::MigrateSend(anyhow!("Error connecting to UNIX socket: {}", e))
+        })?;
+        self.migration_socket_options.apply(&socket)?;
 
-        if mapping_num < UINT16_MAX - 2 {
-            elf_phdr_num += mapping_num as u16;
+        // Start the migration
+        Request::start().write_to(&mut socket)?;
+        Self::check_migration_response(&mut socket, "migration start")?;
+        progress(MigrationPhase::Started);
+
+        // Send config
+        let vm_config = self.get_config();
+        if policy.local {
+            self.send_memory_fds(&mut socket)?;
+        }
+
+        let vm_migration_config = VmMigrationConfig {
+            vm_config,
+            #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+            common_cpuid: self.common_cpuid(),
+            memory_manager_data: self.memory_manager_data(),
+        };
+        let config_data = serde_json::to_vec(&vm_migration_config).unwrap();
+        Self::send_migration_request(
+            &mut socket,
+            Request::config(config_data.len() as u64),
+            &config_data,
+            "config migration",
+        )?;
+        progress(MigrationPhase::ConfigSent);
+
+        // Let every Migratable object know about the migration being started.
+        self.start_migration()?;
+
+        if policy.local {
+            self.pause()?;
+            progress(MigrationPhase::StopAndCopy { bytes: 0 });
         } else {
-            panic!("mapping num beyond 65535 not supported");
+            // Start logging dirty pages, and send the whole memory
+            self.start_dirty_log()?;
+            let table = self.memory_range_table()?;
+            let bytes = self.send_memory_table(&table, &mut socket)?;
+            progress(MigrationPhase::MemoryPass { pass: 0, bytes });
+
+            // Send the dirty memory until it converges
+            for pass in 1..=policy.max_dirty_passes {
+                let table = self.dirty_log()?;
+                let bytes = self.send_memory_table(&table, &mut socket)?;
+                progress(MigrationPhase::MemoryPass { pass, bytes });
+                if bytes <= policy.downtime_threshold {
+                    break;
+                }
+            }
+
+            // Now pause VM, and send the last batch of dirty pages
+            self.pause()?;
+            let table = self.dirty_log()?;
+            let bytes = self.send_memory_table(&table, &mut socket)?;
+            self.stop_dirty_log()?;
+            progress(MigrationPhase::StopAndCopy { bytes });
         }
-        let coredump_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create_new(true)
-            .open(coredump_file_path)
-            .map_err(|e| GuestDebuggableError::Coredump(e.into()))?;
 
-        let mem_offset = self.coredump_get_mem_offset(elf_phdr_num, elf_note_size);
-        let mem_data = self
-            .memory_manager
-            .lock()
-            .unwrap()
-            .coredump_memory_regions(mem_offset);
+        // Capture snapshot and send it
+        let vm_snapshot = self.snapshot()?;
+        let snapshot_data = serde_json::to_vec(&vm_snapshot).unwrap();
+        Self::send_migration_request(
+            &mut socket,
+            Request::state(snapshot_data.len() as u64),
+            &snapshot_data,
+            "state migration",
+        )?;
+        progress(MigrationPhase::StateSent);
 
-        Ok(DumpState {
-            elf_note_size,
-            elf_phdr_num,
-            elf_sh_info,
-            mem_offset,
-            mem_info: Some(mem_data),
-            file: Some(coredump_file),
-        })
-    }
+        // Complete the migration
+        Request::complete().write_to(&mut socket)?;
+        Self::check_migration_response(&mut socket, "migration completion")?;
 
-    #[cfg(feature = "guest_debug")]
-    fn coredump_get_mem_offset(&self, phdr_num: u16, note_size: isize) -> u64 {
-        size_of::<elf::Elf64_Ehdr>() as u64
-            + note_size as u64
-            + size_of::<elf::Elf64_Phdr>() as u64 * phdr_num as u64
+        // Let every Migratable object know about the migration being complete
+        self.complete_migration()?;
+        progress(MigrationPhase::Completed);
+
+        Ok(())
     }
-}
 
-impl Pausable for Vm {
-    fn pause(&mut self) -> std::result::Result<(), MigratableError> {
-        event!("vm", "pausing");
-        let mut state = self
-            .state
+    fn receive_migration_config<T>(
+        req: &Request,
+        socket: &mut T,
+        config: MigrationReceiveConfig,
+        existing_memory_files: Option<HashMap<u32, File>>,
+    ) -> std::result::Result<Self, MigratableError>
+    where
+        T: Read + Write,
+    {
+        // Read in config data along with memory manager data
+        let mut data: Vec<u8> = Vec::new();
+        data.resize_with(req.length() as usize, Default::default);
+        socket
+            .read_exact(&mut data)
+            .map_err(MigratableError::MigrateSocket)?;
+
+        let vm_migration_config: VmMigrationConfig =
+            serde_json::from_slice(&data).map_err(|e| {
+                MigratableError::MigrateReceive(anyhow!("Error deserialising config: {}", e))
+            })?;
+
+        #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+        check_cpuid_compatibility(
+            config.hypervisor.clone(),
+            &vm_migration_config.vm_config,
+            &vm_migration_config.common_cpuid,
+        )?;
+
+        let vm = Vm::new_from_migration(
+            vm_migration_config.vm_config,
+            config.event_fds,
+            &config.seccomp_action,
+            config.hypervisor,
+            &vm_migration_config.memory_manager_data,
+            existing_memory_files,
+        )
+        .map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error creating VM from snapshot: {:?}", e))
+        })?;
+
+        Response::ok().write_to(socket)?;
+
+        Ok(vm)
+    }
+
+    fn receive_migration_state<T>(
+        &mut self,
+        req: &Request,
+        socket: &mut T,
+    ) -> std::result::Result<(), MigratableError>
+    where
+        T: Read + Write,
+    {
+        // Read in state data
+        let mut data: Vec<u8> = Vec::new();
+        data.resize_with(req.length() as usize, Default::default);
+        socket
+            .read_exact(&mut data)
+            .map_err(MigratableError::MigrateSocket)?;
+        let snapshot: Snapshot = serde_json::from_slice(&data).map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error deserialising snapshot: {}", e))
+        })?;
+
+        self.restore(snapshot).map_err(|e| {
+            Response::error().write_to(socket).ok();
+            e
+        })?;
+
+        Response::ok().write_to(socket)?;
+
+        Ok(())
+    }
+
+    fn receive_migration_memory<T>(
+        &mut self,
+        req: &Request,
+        socket: &mut T,
+    ) -> std::result::Result<(), MigratableError>
+    where
+        T: Read + Write,
+    {
+        // Read table
+        let table = MemoryRangeTable::read_from(socket, req.length())?;
+
+        // And then read the memory itself
+        self.receive_memory_regions(&table, socket).map_err(|e| {
+            Response::error().write_to(socket).ok();
+            e
+        })?;
+        Response::ok().write_to(socket)?;
+        Ok(())
+    }
+
+    // Run the receiving side of the migration protocol until the source
+    // completes the migration, returning true, or abandons it, returning
+    // false. The VM is stored in `vm` once created.
+    fn receive_migration_loop(
+        socket: &mut UnixStream,
+        config: MigrationReceiveConfig,
+        vm: &mut Option<Vm>,
+    ) -> std::result::Result<bool, MigratableError> {
+        let mut config = Some(config);
+        let mut started = false;
+        let mut restored = false;
+        let mut existing_memory_files = None;
+        loop {
+            let req = Request::read_from(socket)?;
+            match req.command() {
+                Command::Invalid => info!("Invalid Command Received"),
+                Command::Start => {
+                    info!("Start Command Received");
+                    started = true;
+
+                    Response::ok().write_to(socket)?;
+                }
+                Command::Config => {
+                    info!("Config Command Received");
+
+                    if !started {
+                        warn!("Migration not started yet");
+                        Response::error().write_to(socket)?;
+                        continue;
+                    }
+                    if let Some(config) = config.take() {
+                        *vm = Some(Self::receive_migration_config(
+                            &req,
+                            socket,
+                            config,
+                            existing_memory_files.take(),
+                        )?);
+                    } else {
+                        warn!("Configuration already received");
+                        Response::error().write_to(socket)?;
+                    }
+                }
+                Command::State => {
+                    info!("State Command Received");
+
+                    if !started {
+                        warn!("Migration not started yet");
+                        Response::error().write_to(socket)?;
+                        continue;
+                    }
+                    match vm.as_mut() {
+                        Some(vm) if !restored => {
+                            vm.receive_migration_state(&req, socket)?;
+                            restored = true;
+                        }
+                        _ => {
+                            warn!("Configuration not sent yet, or state already received");
+                            Response::error().write_to(socket)?;
+                        }
+                    }
+                }
+                Command::Memory => {
+                    info!("Memory Command Received");
+
+                    if !started {
+                        warn!("Migration not started yet");
+                        Response::error().write_to(socket)?;
+                        continue;
+                    }
+                    match vm.as_mut() {
+                        Some(vm) if !restored => vm.receive_migration_memory(&req, socket)?,
+                        _ => {
+                            warn!("Configuration not sent yet, or state already received");
+                            Response::error().write_to(socket)?;
+                        }
+                    }
+                }
+                Command::MemoryFd => {
+                    info!("MemoryFd Command Received");
+
+                    if !started {
+                        warn!("Migration not started yet");
+                        Response::error().write_to(socket)?;
+                        continue;
+                    }
+
+                    let mut buf = [0u8; 4];
+                    let (_, file) = socket.recv_with_fd(&mut buf).map_err(|e| {
+                        MigratableError::MigrateReceive(anyhow!(
+                            "Error receiving slot from socket: {}",
+                            e
+                        ))
+                    })?;
+
+                    let slot = u32::from_le_bytes(buf);
+                    existing_memory_files
+                        .get_or_insert_with(HashMap::new)
+                        .insert(slot, file.unwrap());
+
+                    Response::ok().write_to(socket)?;
+                }
+                Command::Complete => {
+                    info!("Complete Command Received");
+                    match vm.as_mut() {
+                        Some(vm) if restored => {
+                            vm.resume()?;
+                            Response::ok().write_to(socket)?;
+                            return Ok(true);
+                        }
+                        _ => {
+                            warn!("VM not restored yet");
+                            Response::error().write_to(socket)?;
+                            return Err(MigratableError::MigrateReceive(anyhow!(
+                                "Migration completed before the VM was restored"
+                            )));
+                        }
+                    }
+                }
+                Command::Abandon => {
+                    info!("Abandon Command Received");
+                    Response::ok().write_to(socket).ok();
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    /// Receive a VM sent with `migrate_to()`, listening on the `unix:`
+    /// socket `listen_url`. The VM is created from the configuration sent by
+    /// the source along with `config`, its memory and state are received,
+    /// and it is resumed once the source completes the migration.
+    ///
+    /// If the source abandons the migration, the partially received VM is
+    /// shut down and `None` returned. If the connection is lost before the
+    /// migration completes, it is shut down as well and an error returned.
+    pub fn receive_migration(
+        listen_url: &str,
+        config: MigrationReceiveConfig,
+    ) -> std::result::Result<Option<Self>, MigratableError> {
+        let path = socket_url_to_path(listen_url)?;
+        let listener = UnixListener::bind(&path).map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error binding to UNIX socket: {}", e))
+        })?;
+        let (mut socket, _addr) = listener.accept().map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error accepting on UNIX socket: {}", e))
+        })?;
+        std::fs::remove_file(&path).map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error unlinking UNIX socket: {}", e))
+        })?;
+        config.socket_options.apply(&socket)?;
+
+        let mut vm = None;
+        match Self::receive_migration_loop(&mut socket, config, &mut vm) {
+            Ok(true) => Ok(vm),
+            Ok(false) => {
+                info!("Migration abandoned by the source");
+                Self::discard_received_vm(vm);
+                Ok(None)
+            }
+            Err(e) => {
+                error!("Receiving migration failed: {:?}", e);
+                Self::discard_received_vm(vm);
+                Err(e)
+            }
+        }
+    }
+
+    // Shut down a VM whose migration didn't complete, if it got far enough
+    // to need it.
+    fn discard_received_vm(vm: Option<Vm>) {
+        if let Some(mut vm) = vm {
+            if vm.get_state().map_or(false, |s| s != VmState::Created) {
+                if let Err(e) = vm.shutdown() {
+                    warn!("Error shutting down the partially received VM: {:?}", e);
+                }
+            }
+        }
+    }
+
+    /// Options set on the socket of the next migrations sent with
+    /// `migrate_to()`.
+    pub fn set_migration_socket_options(&mut self, options: MigrationSocketOptions) {
+        self.migration_socket_options = options;
+    }
+
+    /// Live migrate the VM to `destination_url`, the `unix:` socket the
+    /// destination VMM receives the migration on. The memory is copied while
+    /// the VM runs until the dirty memory converges as defined by `policy`,
+    /// then the VM is paused to send the remaining memory and its state.
+    /// Each phase is reported to `progress`.
+    ///
+    /// On success the VM is left paused and should be shut down, as it now
+    /// runs on the destination. On failure it is resumed on the source.
+    pub fn migrate_to(
+        &mut self,
+        destination_url: &str,
+        policy: &MigrationPolicy,
+        progress: &mut dyn FnMut(MigrationPhase),
+    ) -> std::result::Result<(), MigratableError> {
+        if policy.local && !self.config.lock().unwrap().memory.shared {
+            return Err(MigratableError::MigrateSend(anyhow!(
+                "Local migration requires shared memory enabled"
+            )));
+        }
+
+        self.send_migration(destination_url, policy, progress)
+            .map_err(|migration_err| {
+                error!("Migration failed: {:?}", migration_err);
+                progress(MigrationPhase::Failed(migration_err.to_string()));
+
+                // Stop logging dirty pages
+                if let Err(e) = self.stop_dirty_log() {
+                    return e;
+                }
+
+                if self.get_state().unwrap() == VmState::Paused {
+                    if let Err(e) = self.resume() {
+                        return e;
+                    }
+                }
+
+                migration_err
+            })
+    }
+
+    pub fn device_tree(&self) -> Arc<Mutex<DeviceTree>> {
+        self.device_manager.lock().unwrap().device_tree()
+    }
+
+    /// Interrupt routing currently configured for the VM, mapping legacy
+    /// interrupt lines and GSI routes to the devices using them. This is
+    /// meant to help debugging interrupt delivery issues.
+    pub fn interrupt_routing(&self) -> IrqRoutingInfo {
+        self.device_manager.lock().unwrap().interrupt_routing()
+    }
+
+    /// Last panic reported by the guest since the VM was booted, or since
+    /// the last call to `clear_last_panic()`.
+    #[cfg(target_arch = "x86_64")]
+    pub fn last_panic(&self) -> Option<GuestPanicInfo> {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .pvpanic_device()
+            .and_then(|pvpanic| pvpanic.lock().unwrap().last_event())
+            .map(GuestPanicInfo::from)
+    }
+
+    /// Apply the configured policy after the guest reported a panic, and
+    /// return the follow-up action left to the caller. The guest is dumped
+    /// at most once per boot so that a panic reported while the dump is
+    /// being written, or after it, doesn't try to overwrite it.
+    #[cfg(target_arch = "x86_64")]
+    pub fn handle_guest_panic(&mut self) -> Result<GuestPanicAction> {
+        let guest_panic = match self.config.lock().unwrap().guest_panic.clone() {
+            Some(guest_panic) => guest_panic,
+            None => return Ok(GuestPanicAction::None),
+        };
+
+        if let Some(panic_info) = self.last_panic() {
+            info!("Handling guest panic: {:?}", panic_info);
+        }
+
+        #[cfg(feature = "guest_debug")]
+        if let Some(path) = guest_panic.coredump.as_ref() {
+            if self.guest_panic_dumped {
+                warn!("Guest already dumped since boot, not dumping it again");
+            } else {
+                self.guest_panic_dumped = true;
+
+                // Dumping the guest requires its vCPUs to be paused
+                let running = self.get_state()? == VmState::Running;
+                if running {
+                    self.pause().map_err(Error::Pause)?;
+                }
+
+                match self.coredump(&format!("file://{}", path.display())) {
+                    Ok(()) => info!("Guest dumped to {}", path.display()),
+                    Err(e) => error!("Error dumping guest on panic: {:?}", e),
+                }
+
+                if running && guest_panic.action == GuestPanicAction::None {
+                    self.resume().map_err(Error::Resume)?;
+                }
+            }
+        }
+
+        if guest_panic.action == GuestPanicAction::Pause && self.get_state()? == VmState::Running {
+            self.pause().map_err(Error::Pause)?;
+        }
+
+        Ok(guest_panic.action)
+    }
+
+    /// Forget about the last panic reported by the guest, returning it.
+    #[cfg(target_arch = "x86_64")]
+    pub fn clear_last_panic(&self) -> Option<GuestPanicInfo> {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .pvpanic_device()
+            .and_then(|pvpanic| pvpanic.lock().unwrap().clear_last_event())
+            .map(GuestPanicInfo::from)
+    }
+
+    /// Layout of the SGX EPC sections exposed to the guest, empty if SGX EPC
+    /// isn't configured.
+    #[cfg(target_arch = "x86_64")]
+    pub fn sgx_epc_sections(&self) -> Vec<SgxEpcSectionInfo> {
+        let mm = self.memory_manager.lock().unwrap();
+        let sgx_epc_region = match mm.sgx_epc_region() {
+            Some(sgx_epc_region) => sgx_epc_region,
+            None => return Vec::new(),
+        };
+
+        sgx_epc_region
+            .epc_sections()
+            .iter()
+            .map(|(id, section)| SgxEpcSectionInfo {
+                id: id.clone(),
+                start: section.start().raw_value(),
+                size: section.size(),
+                numa_node: self
+                    .numa_nodes
+                    .iter()
+                    .find(|(_, node)| {
+                        node.sgx_epc_sections
+                            .iter()
+                            .any(|s| s.start() == section.start())
+                    })
+                    .map(|(node_id, _)| *node_id),
+            })
+            .collect()
+    }
+
+    pub fn platform_info(&self) -> PlatformInfo {
+        #[cfg(feature = "kvm")]
+        let hypervisor = "kvm";
+        #[cfg(feature = "mshv")]
+        let hypervisor = "mshv";
+
+        let config = self.config.lock().unwrap();
+
+        #[cfg(feature = "tdx")]
+        let tdx = config.tdx.is_some();
+        #[cfg(not(feature = "tdx"))]
+        let tdx = false;
+
+        #[cfg(target_arch = "x86_64")]
+        let sgx = config.sgx_epc.is_some();
+        #[cfg(target_arch = "aarch64")]
+        let sgx = false;
+
+        let phys_bits = phys_bits(config.cpus.max_phys_bits);
+
+        PlatformInfo {
+            arch: std::env::consts::ARCH.to_string(),
+            hypervisor: hypervisor.to_string(),
+            split_irq: cfg!(target_arch = "x86_64"),
+            tdx,
+            sgx,
+            num_pci_segments: self.device_manager.lock().unwrap().pci_segments().len() as u16,
+            max_phys_bits: phys_bits.effective,
+            phys_bits,
+        }
+    }
+
+    /// Number of boot, present, maximum and running vCPUs.
+    pub fn vcpu_counts(&self) -> cpu::VcpuCounts {
+        self.cpu_manager.lock().unwrap().vcpu_counts()
+    }
+
+    /// Per vCPU busy fraction over the window elapsed since the previous
+    /// call. It is derived from the CPU time consumed by the vCPU threads,
+    /// so a vCPU halted by the guest counts as idle without the guest
+    /// having to cooperate.
+    pub fn cpu_utilization(&self) -> Vec<f64> {
+        self.cpu_manager.lock().unwrap().cpu_utilization()
+    }
+
+    /// Called when a reset has been requested, to find out whether it comes
+    /// from a vCPU stopped by an access to an unregistered address, with the
+    /// `unregistered_io` platform policy set to `fault`. In that case the VM
+    /// is paused into the `BreakPoint` state and `true` is returned so that
+    /// the caller doesn't reset the VM.
+    pub fn handle_unregistered_io_fault(&mut self) -> Result<bool> {
+        if !self
+            .cpu_manager
+            .lock()
+            .unwrap()
+            .take_unregistered_io_fault()
+        {
+            return Ok(false);
+        }
+
+        warn!("Guest accessed an unregistered address, stopping the VM");
+        if self.get_state()? == VmState::Running {
+            self.pause().map_err(Error::Pause)?;
+        }
+        let mut state = self.state.try_write().map_err(|_| Error::PoisonedState)?;
+        *state = VmState::BreakPoint;
+        Ok(true)
+    }
+
+    /// Called when a reset has been requested, to find out whether it comes
+    /// from a guest triple fault that the VM has been configured to stop on.
+    /// In that case the VM is paused into the `BreakPoint` state, keeping the
+    /// guest state around for inspection, and `true` is returned so that the
+    /// caller doesn't reset the VM.
+    #[cfg(target_arch = "x86_64")]
+    pub fn handle_triple_fault(&mut self) -> Result<bool> {
+        if !self.cpu_manager.lock().unwrap().take_triple_fault()
+            || !self.config.lock().unwrap().break_on_triple_fault
+        {
+            return Ok(false);
+        }
+
+        warn!("Guest triple faulted, stopping the VM");
+        if self.get_state()? == VmState::Running {
+            self.pause().map_err(Error::Pause)?;
+        }
+        let mut state = self.state.try_write().map_err(|_| Error::PoisonedState)?;
+        *state = VmState::BreakPoint;
+        Ok(true)
+    }
+
+    /// Called when the guest requested a reset, to find out how the VMM should
+    /// handle it according to the `on_reset` policy. The VM is paused here
+    /// when the policy asks for it, the other actions are left to the caller.
+    pub fn handle_reset(&mut self) -> Result<ResetAction> {
+        let action = self.config.lock().unwrap().on_reset;
+        match action {
+            ResetAction::Restart => {}
+            ResetAction::Exit => info!("Guest requested a reset, exiting"),
+            ResetAction::Pause => {
+                info!("Guest requested a reset, pausing the VM");
+                if self.get_state()? == VmState::Running {
+                    self.pause().map_err(Error::Pause)?;
+                }
+            }
+        }
+        event!("vm", "reset-requested", "action", format!("{:?}", action));
+        Ok(action)
+    }
+
+    /// Address the guest started executing at, as computed when loading the
+    /// kernel. This is `None` until the VM is booted, when booting a raw
+    /// firmware, or when the VM was restored from a snapshot.
+    pub fn kernel_entry_point(&self) -> Option<GuestAddress> {
+        self.kernel_entry_point
+    }
+
+    /// Identifiers explicitly given to devices, memory zones and other
+    /// resources by the configuration the VM was booted with, sorted
+    /// alphabetically. Names generated by the VMM for devices created
+    /// without an identifier (e.g. `_disk0`) are never part of the list.
+    /// These identifiers are host side names only and aren't exposed to the
+    /// guest, the device tree can be used to map them to guest visible PCI
+    /// addresses.
+    pub fn boot_id_list(&self) -> Vec<String> {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .boot_id_list()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Breakdown of the guest RAM regions and their host backing. Set
+    /// `resident` to also count the resident pages of each region, which
+    /// is expensive on large guests.
+    pub fn memory_usage_by_region(&self, resident: bool) -> Result<Vec<RegionUsage>> {
+        self.memory_manager
+            .lock()
+            .unwrap()
+            .memory_usage_by_region(resident)
+            .map_err(Error::MemoryManager)
+    }
+
+    /// Dump the guest RAM into a new qcow2 image at `path`, where it only
+    /// takes as much space as the memory that isn't zeroed. The guest
+    /// physical addresses are used as offsets into the image, which can be
+    /// converted back to a raw dump with `qemu-img convert`. The VM must be
+    /// paused for the dump to be consistent. Returns the number of bytes of
+    /// guest RAM written.
+    pub fn dump_memory_qcow(&self, path: &Path) -> Result<u64> {
+        if self.get_state()? != VmState::Paused {
+            return Err(Error::VmNotPaused);
+        }
+
+        let start = Instant::now();
+        let written = self
+            .memory_manager
+            .lock()
+            .unwrap()
+            .dump_memory_qcow(path)
+            .map_err(Error::MemoryManager)?;
+        info!(
+            "Dumped {} bytes of guest RAM to {} in {} ms",
+            written,
+            path.display(),
+            start.elapsed().as_millis()
+        );
+
+        Ok(written)
+    }
+
+    /// Fault in the whole RAM of memory zone `id`, for instance to warm up
+    /// the memory used by a latency sensitive workload without prefaulting
+    /// the rest of the guest RAM.
+    pub fn prefault_zone(&self, id: &str) -> Result<ZonePrefault> {
+        let prefault = self
+            .memory_manager
+            .lock()
+            .unwrap()
+            .prefault_zone(id)
+            .map_err(Error::MemoryManager)?;
+        info!(
+            "Prefaulted {} pages of memory zone {} in {} ms",
+            prefault.faulted_pages, id, prefault.duration_ms
+        );
+        Ok(prefault)
+    }
+
+    /// Move the RAM of memory zone `id` to the file at `backing`, created
+    /// if needed, without stopping the guest for longer than it takes to
+    /// copy the memory written to during the copy. The guest is then
+    /// switched over to the new backing, mapped as shared, and the previous
+    /// one released. Zones backed by virtio-mem, and VMs with devices
+    /// accessing the guest memory from outside of the VMM, aren't supported.
+    pub fn migrate_memory_zone(
+        &mut self,
+        id: &str,
+        backing: &Path,
+        progress: &mut dyn FnMut(ZoneMigrationPhase),
+    ) -> Result<()> {
+        let state = self.get_state()?;
+        if state != VmState::Running && state != VmState::Paused {
+            return Err(Error::VmNotRunning);
+        }
+
+        let sharers = self.device_manager.lock().unwrap().guest_memory_sharers();
+        if !sharers.is_empty() {
+            error!(
+                "Memory zone {} can't be migrated, guest memory mapped by {:?}",
+                id, sharers
+            );
+            return Err(Error::MemoryZoneMigration(
+                "guest memory mapped by a device outside of the VMM",
+            ));
+        }
+        if self.dirty_log_migration {
+            return Err(Error::MemoryZoneMigration("VM migration in progress"));
+        }
+
+        let migration = self
+            .memory_manager
+            .lock()
+            .unwrap()
+            .prepare_zone_migration(id, backing)
+            .map_err(Error::MemoryManager)?;
+
+        // The pages the guest writes to during the copy are tracked through
+        // the dirty log, to be copied again once the VM is paused.
+        let running = state == VmState::Running;
+        let armed = self.dirty_log_continuous || self.dirty_log_reset;
+        if running {
+            if armed {
+                let table = self.read_dirty_log().map_err(Error::DirtyLog)?;
+                self.pending_dirty_log.extend(table);
+            } else {
+                self.arm_dirty_log().map_err(Error::DirtyLog)?;
+            }
+        }
+
+        let result = self.copy_memory_zone(migration, running, armed, progress);
+
+        if running {
+            let disarmed = if armed {
+                Ok(())
+            } else {
+                self.disarm_dirty_log()
+            };
+            let resumed = if self.get_state()? == VmState::Paused {
+                self.resume()
+            } else {
+                Ok(())
+            };
+            result?;
+            disarmed.map_err(Error::DirtyLog)?;
+            resumed.map_err(Error::Resume)?;
+        } else {
+            result?;
+        }
+
+        // Keep the new backing across reboots.
+        if let Some(zone) = self
+            .config
+            .lock()
+            .unwrap()
+            .memory
+            .zones
+            .iter_mut()
+            .flatten()
+            .find(|z| z.id == id)
+        {
+            zone.file = Some(backing.to_path_buf());
+            zone.shared = true;
+        }
+
+        event!("vm", "memory-zone-migrated", "id", id);
+        progress(ZoneMigrationPhase::Completed);
+
+        Ok(())
+    }
+
+    // Copy the zone to its new backing and switch the guest over to it,
+    // pausing the VM if running. When dirty logging is armed by others, the
+    // dirty log of the previous backing is handed over to them.
+    fn copy_memory_zone(
+        &mut self,
+        migration: ZoneMigration,
+        running: bool,
+        armed: bool,
+        progress: &mut dyn FnMut(ZoneMigrationPhase),
+    ) -> Result<()> {
+        let bytes = self
+            .memory_manager
+            .lock()
+            .unwrap()
+            .copy_zone_ranges(&migration, None)
+            .map_err(Error::MemoryManager)?;
+        progress(ZoneMigrationPhase::Copy { bytes });
+
+        if running {
+            self.pause().map_err(Error::Pause)?;
+        }
+        if running || armed {
+            let table = self.read_dirty_log().map_err(Error::DirtyLog)?;
+            if running {
+                let bytes = self
+                    .memory_manager
+                    .lock()
+                    .unwrap()
+                    .copy_zone_ranges(&migration, Some(&table))
+                    .map_err(Error::MemoryManager)?;
+                progress(ZoneMigrationPhase::StopAndCopy { bytes });
+            }
+            if armed {
+                self.pending_dirty_log.extend(table);
+            }
+        }
+
+        let mut memory_manager = self.memory_manager.lock().unwrap();
+        memory_manager
+            .complete_zone_migration(migration)
+            .map_err(Error::MemoryManager)?;
+        // Memory slots only log dirty pages once the dirty log is started
+        // again after their creation.
+        if armed {
+            memory_manager.start_dirty_log().map_err(Error::DirtyLog)?;
+        }
+
+        Ok(())
+    }
+
+    /// Memory map presented to the guest when it booted, the e820 table on
+    /// x86_64 or the device tree memory nodes on aarch64. Memory hotplugged
+    /// afterwards isn't part of it.
+    pub fn guest_memory_map(&self) -> Vec<arch::MemoryMapEntry> {
+        let reserved_memory = self.reserved_memory();
+        let memory_manager = self.memory_manager.lock().unwrap();
+        let mem = memory_manager.boot_guest_memory();
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            arch::guest_memory_map(
+                &mem,
+                memory_manager.sgx_epc_region().as_ref(),
+                &reserved_memory,
+            )
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            arch::guest_memory_map(&mem, &self.numa_nodes, &reserved_memory)
+        }
+    }
+
+    // RAM ranges reserved for the firmware or the OEM through the memory
+    // configuration, as (guest address, size) pairs.
+    fn reserved_memory(&self) -> Vec<(u64, u64)> {
+        self.config
+            .lock()
+            .unwrap()
+            .memory
+            .reserved
+            .iter()
+            .flatten()
+            .map(|r| (r.guest_address(), r.size))
+            .collect()
+    }
+
+    /// Host files backing the memory zones, as (zone, path) pairs. Zones
+    /// backed by anonymous memory aren't listed. This lets external tools
+    /// back the guest memory up out of band.
+    pub fn memory_backing_files(&self) -> Vec<(String, PathBuf)> {
+        self.memory_manager.lock().unwrap().memory_backing_files()
+    }
+
+    /// Time spent creating the guest RAM, including prefaulting it when
+    /// requested. Guest memory is otherwise populated lazily on access.
+    pub fn memory_init_duration(&self) -> Duration {
+        self.memory_manager.lock().unwrap().init_duration()
+    }
+
+    /// Approximate size of a snapshot taken now, in bytes. The guest memory
+    /// is written in full except for regions backed by a shared file, which
+    /// already hold their content on the host. The CPU and device states are
+    /// estimated from the number of vCPUs and devices. This doesn't need the
+    /// VM to be paused, so the result can be off if the VM is resized
+    /// before the snapshot is taken.
+    pub fn snapshot_size_estimate(&self) -> u64 {
+        const VCPU_STATE_ESTIMATE: u64 = 16 << 10;
+        const DEVICE_STATE_ESTIMATE: u64 = 4 << 10;
+
+        let memory_size = self.memory_manager.lock().unwrap().snapshot_memory_size();
+
+        let config = self.config.lock().unwrap();
+        let config_size = serde_json::to_vec(config.deref())
+            .map(|c| c.len() as u64)
+            .unwrap_or(0);
+        let vcpus = config.cpus.boot_vcpus as u64;
+        drop(config);
+
+        let devices = self
+            .device_manager
+            .lock()
+            .unwrap()
+            .device_tree()
+            .lock()
+            .unwrap()
+            .iter()
+            .count() as u64;
+
+        memory_size + config_size + vcpus * VCPU_STATE_ESTIMATE + devices * DEVICE_STATE_ESTIMATE
+    }
+
+    /// Route virtio device activation requests through `policy` instead of
+    /// activating devices as soon as the guest driver is ready. A deferred
+    /// request keeps the vCPU which completed the driver initialization
+    /// waiting, until `activate_virtio_devices()` is called again and the
+    /// policy approves or denies it. Passing `None` restores the default
+    /// behavior of activating every device.
+    pub fn set_activation_policy(&self, policy: Option<ActivationPolicy>) {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .set_activation_policy(policy);
+    }
+
+    pub fn activate_virtio_devices(&self) -> Result<()> {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .activate_virtio_devices()
+            .map_err(Error::ActivateVirtioDevices)
+    }
+
+    /// Make the VMM shut the VM down and exit, the same way it does when
+    /// receiving SIGTERM. `reason` is logged and reported by exit_reason().
+    pub fn request_exit(&self, reason: &str) -> Result<()> {
+        info!("VM exit requested: {}", reason);
+        event!("vm", "exit-requested", "reason", reason);
+        *self.exit_reason.lock().unwrap() = Some(reason.to_string());
+
+        if let Some(terminal) = &self.terminal {
+            terminal.set_canon_mode().map_err(Error::SetTerminalCanon)?;
+        }
+
+        self.exit_evt.write(1).map_err(Error::ExitEventWrite)
+    }
+
+    /// CPUID the guest sees, the one a migration destination must be
+    /// compatible with. Computed once when the VM is created, its inputs
+    /// being fixed for the lifetime of the VM.
+    #[cfg(target_arch = "x86_64")]
+    pub fn common_cpuid(&self) -> hypervisor::x86_64::CpuId {
+        self.common_cpuid.clone()
+    }
+
+    /// Reason of the exit requested through request_exit(), if any.
+    pub fn exit_reason(&self) -> Option<String> {
+        self.exit_reason.lock().unwrap().clone()
+    }
+
+    /// Time taken by the lifecycle operations run so far, only accounting
+    /// for the ones that succeeded.
+    pub fn operation_timings(&self) -> Vec<OperationTiming> {
+        self.operation_timings
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Number of guest reboots and uptime of the VM.
+    pub fn stats(&self) -> VmStats {
+        let uptime = self.timestamp.elapsed();
+        VmStats {
+            reboots: self.reboots,
+            uptime_ms: uptime.as_millis() as u64,
+            total_uptime_ms: (self.previous_uptime + uptime).as_millis() as u64,
+        }
+    }
+
+    // Number of reboots and uptime the VM created when rebooting this one
+    // starts from.
+    pub(crate) fn reboot_stats(&self) -> (u64, Duration) {
+        (
+            self.reboots + 1,
+            self.previous_uptime + self.timestamp.elapsed(),
+        )
+    }
+
+    pub(crate) fn set_reboot_stats(&mut self, (reboots, previous_uptime): (u64, Duration)) {
+        self.reboots = reboots;
+        self.previous_uptime = previous_uptime;
+    }
+
+    // Record the duration of `operation`, started at `start`, returning it
+    // in milliseconds for the completion event.
+    fn record_timing(&self, operation: VmOperation, start: Instant) -> String {
+        let elapsed_ms = start.elapsed().as_millis() as u64;
+        info!("VM {:?} took {} ms", operation, elapsed_ms);
+
+        let mut timings = self.operation_timings.lock().unwrap();
+        let timing = timings.entry(operation).or_insert(OperationTiming {
+            operation,
+            count: 0,
+            last_ms: 0,
+            max_ms: 0,
+        });
+        timing.count += 1;
+        timing.last_ms = elapsed_ms;
+        timing.max_ms = std::cmp::max(timing.max_ms, elapsed_ms);
+
+        elapsed_ms.to_string()
+    }
+
+    /// Change the time the guest can go without pinging its watchdog before
+    /// the VM is reset. It must be longer than the 15 seconds between two
+    /// pings of the guest driver.
+    pub fn set_watchdog_timeout(&self, timeout: Duration) -> Result<()> {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .set_watchdog_timeout(timeout)
+            .map_err(Error::DeviceManager)
+    }
+
+    /// Ping the watchdog on behalf of the guest, postponing the reset. This
+    /// has no effect until the guest has started using its watchdog, which
+    /// is reported by returning false.
+    pub fn pet_watchdog(&self) -> Result<bool> {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .pet_watchdog()
+            .map_err(Error::DeviceManager)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    pub fn power_button(&self) -> Result<()> {
+        return self
+            .device_manager
+            .lock()
+            .unwrap()
+            .notify_power_button()
+            .map_err(Error::PowerButton);
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    pub fn power_button(&self) -> Result<()> {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .notify_power_button()
+            .map_err(Error::PowerButton)
+    }
+
+    /// Find which device the guest sees at the PCI address
+    /// `segment:bus:device.function`, as reported by `lspci` for instance.
+    pub fn device_by_bdf(
+        &self,
+        segment: u16,
+        bus: u8,
+        device: u8,
+        function: u8,
+    ) -> Option<DeviceSummary> {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .device_by_bdf(PciBdf::new(segment, bus, device, function))
+    }
+
+    /// Number of PCI slots still available for hotplug on each PCI segment,
+    /// indexed by segment id. Every segment is made of a single bus, so
+    /// more slots are obtained by booting with more segments through
+    /// `--platform num_pci_segments`.
+    pub fn pci_free_slots(&self) -> Vec<usize> {
+        self.device_manager.lock().unwrap().pci_free_slots()
+    }
+
+    /// Switch the vCPUs to a stricter seccomp filtering, for instance once
+    /// the guest booted. The running vCPU threads stack the new filter on
+    /// top of the one they already apply, since a seccomp filter can't be
+    /// removed, and the vCPUs and devices added afterwards only get the new
+    /// one. The VMM, API, signal handler and running device threads keep
+    /// their filter. Asking for a more permissive action than the current
+    /// one is an error.
+    ///
+    /// This returns once every running vCPU thread applied the new filter,
+    /// failing if any of them couldn't.
+    pub fn set_seccomp_action(&mut self, seccomp_action: SeccompAction) -> Result<()> {
+        fn strictness(action: &SeccompAction) -> u8 {
+            match action {
+                SeccompAction::Allow => 0,
+                SeccompAction::Log => 1,
+                _ => 2,
+            }
+        }
+
+        let current = strictness(&self.seccomp_action);
+        let requested = strictness(&seccomp_action);
+        if requested < current {
+            return Err(Error::LoosenSeccompAction(
+                self.seccomp_action.clone(),
+                seccomp_action,
+            ));
+        }
+        if requested == current {
+            return Ok(());
+        }
+
+        let results = self
+            .cpu_manager
+            .lock()
+            .unwrap()
+            .set_seccomp_action(seccomp_action.clone())
+            .map_err(Error::CpuManager)?;
+        // The CpuManager lock is released by now, a vCPU may need it before
+        // getting to the filter.
+        for result in results.iter() {
+            result.map_err(Error::ApplySeccompFilter)?;
+        }
+        self.device_manager
+            .lock()
+            .unwrap()
+            .set_seccomp_action(seccomp_action.clone());
+        info!("Seccomp action set to {:?}", seccomp_action);
+        self.seccomp_action = seccomp_action;
+
+        Ok(())
+    }
+
+    /// System calls blocked by the seccomp filters, provided the VMM
+    /// records them through `seccomp_filters::record_seccomp_violations()`.
+    #[cfg(feature = "seccomp_trace")]
+    pub fn seccomp_violations(&self) -> Vec<crate::seccomp_filters::SeccompViolation> {
+        crate::seccomp_filters::seccomp_violations()
+    }
+
+    /// Guest address, size, file descriptor and file offset of every guest
+    /// RAM region, for another process to map the guest memory directly.
+    /// This hands out full access to the guest memory, which must have been
+    /// created with `shared=on`. The file descriptors remain owned by the VM
+    /// and must be duplicated to outlive it.
+    pub fn export_memory_fds(&self) -> Result<Vec<(GuestAddress, u64, RawFd, u64)>> {
+        self.memory_manager
+            .lock()
+            .unwrap()
+            .export_memory_fds()
+            .map_err(Error::MemoryManager)
+    }
+
+    /// Shut the VM down like `shutdown()`, but hand back the guest memory
+    /// files along with their layout, so that the next VM can skip creating
+    /// and sizing them by passing the result to `Vm::new()`. This is meant
+    /// for restarting a VM with the same memory configuration. The memory
+    /// content is only preserved with `shared=on`, and unless
+    /// `wipe_on_shutdown` is set, as the guest writes to a private mapping
+    /// never reach the files.
+    pub fn shutdown_warm(&mut self) -> Result<WarmRestartData> {
+        let mut memory_files = HashMap::new();
+        for (slot, fd) in self.memory_manager.lock().unwrap().memory_slot_fds() {
+            // SAFETY: the file descriptor is valid as long as the memory
+            // manager is alive.
+            let fd = unsafe { libc::dup(fd) };
+            if fd < 0 {
+                return Err(Error::DupMemoryFile(io::Error::last_os_error()));
+            }
+            // SAFETY: fd was just returned by dup() and nothing else owns it.
+            memory_files.insert(slot, unsafe { File::from_raw_fd(fd) });
+        }
+        let memory_manager_data = self.memory_manager_data();
+
+        self.shutdown()?;
+        event!("vm", "warm-shutdown");
+
+        Ok(WarmRestartData {
+            memory_manager_data,
+            memory_files,
+        })
+    }
+
+    pub fn memory_manager_data(&self) -> MemoryManagerSnapshotData {
+        self.memory_manager.lock().unwrap().snapshot_data()
+    }
+
+    /// Read guest memory by physical address. Unlike `read_mem()`, this
+    /// doesn't depend on the vCPU page tables, which makes it usable before
+    /// the guest has set them up.
+    #[cfg(feature = "gdb")]
+    pub fn read_phys_mem(
+        &self,
+        gpa: GuestAddress,
+        len: usize,
+    ) -> std::result::Result<Vec<u8>, DebuggableError> {
+        let mut buf = vec![0; len];
+        self.memory_manager
+            .lock()
+            .unwrap()
+            .guest_memory()
+            .memory()
+            .read_slice(&mut buf, gpa)
+            .map_err(DebuggableError::ReadMem)?;
+        Ok(buf)
+    }
+
+    /// Write guest memory by physical address, bypassing the vCPU page tables.
+    #[cfg(feature = "gdb")]
+    pub fn write_phys_mem(
+        &self,
+        gpa: GuestAddress,
+        data: &[u8],
+    ) -> std::result::Result<(), DebuggableError> {
+        self.memory_manager
+            .lock()
+            .unwrap()
+            .guest_memory()
+            .memory()
+            .write_slice(data, gpa)
+            .map_err(DebuggableError::WriteMem)
+    }
+
+    #[cfg(all(target_arch = "x86_64", feature = "gdb"))]
+    pub fn debug_request(
+        &mut self,
+        gdb_request: &GdbRequestPayload,
+        cpu_id: usize,
+    ) -> Result<GdbResponsePayload> {
+        use GdbRequestPayload::*;
+        match gdb_request {
+            SetSingleStep(single_step) => {
+                self.set_guest_debug(cpu_id, &[], *single_step)
+                    .map_err(Error::Debug)?;
+            }
+            SetHwBreakPoint(addrs) => {
+                self.set_guest_debug(cpu_id, addrs, false)
+                    .map_err(Error::Debug)?;
+            }
+            Pause => {
+                self.debug_pause().map_err(Error::Debug)?;
+            }
+            Resume => {
+                self.debug_resume().map_err(Error::Debug)?;
+            }
+            ReadRegs => {
+                let regs = self.read_regs(cpu_id).map_err(Error::Debug)?;
+                return Ok(GdbResponsePayload::RegValues(Box::new(regs)));
+            }
+            WriteRegs(regs) => {
+                self.write_regs(cpu_id, regs).map_err(Error::Debug)?;
+            }
+            ReadMem(vaddr, len) => {
+                let mem = self.read_mem(cpu_id, *vaddr, *len).map_err(Error::Debug)?;
+                return Ok(GdbResponsePayload::MemoryRegion(mem));
+            }
+            WriteMem(vaddr, data) => {
+                self.write_mem(cpu_id, vaddr, data).map_err(Error::Debug)?;
+            }
+            ReadPhysMem(gpa, len) => {
+                let mem = self.read_phys_mem(*gpa, *len).map_err(Error::Debug)?;
+                return Ok(GdbResponsePayload::MemoryRegion(mem));
+            }
+            WritePhysMem(gpa, data) => {
+                self.write_phys_mem(*gpa, data).map_err(Error::Debug)?;
+            }
+            ActiveVcpus => {
+                let active_vcpus = self.active_vcpus();
+                return Ok(GdbResponsePayload::ActiveVcpus(active_vcpus));
+            }
+        }
+        Ok(GdbResponsePayload::CommandComplete)
+    }
+
+    #[cfg(feature = "guest_debug")]
+    fn get_dump_state(
+        &mut self,
+        destination_url: &str,
+    ) -> std::result::Result<DumpState, GuestDebuggableError> {
+        let nr_cpus = self.config.lock().unwrap().cpus.boot_vcpus as u32;
+        let elf_note_size = self.get_note_size(NoteDescType::ElfAndVmmDesc, nr_cpus) as isize;
+        let mut elf_phdr_num = 1 as u16;
+        let elf_sh_info = 0;
+        let coredump_file_path = url_to_file(destination_url)?;
+        let mapping_num = self.memory_manager.lock().unwrap().num_guest_ram_mappings();
+
+        if mapping_num < UINT16_MAX - 2 {
+            elf_phdr_num += mapping_num as u16;
+        } else {
+            panic!("mapping num beyond 65535 not supported");
+        }
+        let coredump_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(coredump_file_path)
+            .map_err(|e| GuestDebuggableError::Coredump(e.into()))?;
+
+        let mem_offset = self.coredump_get_mem_offset(elf_phdr_num, elf_note_size);
+        let mem_data = self
+            .memory_manager
+            .lock()
+            .unwrap()
+            .coredump_memory_regions(mem_offset);
+
+        Ok(DumpState {
+            elf_note_size,
+            elf_phdr_num,
+            elf_sh_info,
+            mem_offset,
+            mem_info: Some(mem_data),
+            file: Some(coredump_file),
+        })
+    }
+
+    #[cfg(feature = "guest_debug")]
+    fn coredump_get_mem_offset(&self, phdr_num: u16, note_size: isize) -> u64 {
+        size_of::<elf::Elf64_Ehdr>() as u64
+            + note_size as u64
+            + size_of::<elf::Elf64_Phdr>() as u64 * phdr_num as u64
+    }
+}
+
+impl Vm {
+    /// Pause the VM, failing if the vCPUs don't stop running within
+    /// `timeout`. The VM is left running in that case, so that the caller
+    /// can decide what to do with it, such as shutting it down.
+    pub fn pause_with_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> std::result::Result<(), MigratableError> {
+        self.pause_until(Some(Instant::now() + timeout))
+    }
+
+    fn pause_until(
+        &mut self,
+        deadline: Option<Instant>,
+    ) -> std::result::Result<(), MigratableError> {
+        event!("vm", "pausing");
+        let start = Instant::now();
+        let mut state = self
+            .state
             .try_write()
             .map_err(|e| MigratableError::Pause(anyhow!("Could not get VM state: {}", e)))?;
         let new_state = VmState::Paused;
@@ -2604,17 +5935,43 @@ impl Pausable for Vm {
             MigratableError::Pause(anyhow!("Error activating pending virtio devices: {:?}", e))
         })?;
 
-        self.cpu_manager.lock().unwrap().pause()?;
+        // Resizing the balloon of a paused VM would block.
+        if let Some(balloon_adjuster) = &self.balloon_adjuster {
+            balloon_adjuster.suspend();
+        }
+
+        let paused = match deadline {
+            Some(deadline) => self
+                .cpu_manager
+                .lock()
+                .unwrap()
+                .pause_with_deadline(deadline),
+            None => self.cpu_manager.lock().unwrap().pause(),
+        };
+        if let Err(e) = paused {
+            if let Some(balloon_adjuster) = &self.balloon_adjuster {
+                balloon_adjuster.resume();
+            }
+            return Err(e);
+        }
         self.device_manager.lock().unwrap().pause()?;
 
         *state = new_state;
 
-        event!("vm", "paused");
+        let duration_ms = self.record_timing(VmOperation::Pause, start);
+        event!("vm", "paused", "duration_ms", duration_ms);
         Ok(())
     }
+}
+
+impl Pausable for Vm {
+    fn pause(&mut self) -> std::result::Result<(), MigratableError> {
+        self.pause_until(None)
+    }
 
     fn resume(&mut self) -> std::result::Result<(), MigratableError> {
         event!("vm", "resuming");
+        let start = Instant::now();
         let mut state = self
             .state
             .try_write()
@@ -2625,6 +5982,16 @@ impl Pausable for Vm {
             .valid_transition(new_state)
             .map_err(|e| MigratableError::Resume(anyhow!("Invalid transition: {:?}", e)))?;
 
+        if self.defer_vcpus_start {
+            self.cpu_manager
+                .lock()
+                .unwrap()
+                .start_restored_vcpus()
+                .map_err(|e| {
+                    MigratableError::Resume(anyhow!("Cannot start restored vCPUs: {:#?}", e))
+                })?;
+            self.defer_vcpus_start = false;
+        }
         self.cpu_manager.lock().unwrap().resume()?;
         #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
         {
@@ -2636,9 +6003,22 @@ impl Pausable for Vm {
         }
         self.device_manager.lock().unwrap().resume()?;
 
+        if let Some(balloon_adjuster) = &self.balloon_adjuster {
+            balloon_adjuster.resume();
+        }
+
+        if let Some(advice) = self.memory_reclaim.take() {
+            info!(
+                "Resumed VM with reclaimed memory ({:?}) in {} ms",
+                advice,
+                start.elapsed().as_millis()
+            );
+        }
+
         // And we're back to the Running state.
         *state = new_state;
-        event!("vm", "resumed");
+        let duration_ms = self.record_timing(VmOperation::Resume, start);
+        event!("vm", "resumed", "duration_ms", duration_ms);
         Ok(())
     }
 }
@@ -2653,13 +6033,166 @@ pub struct VmSnapshot {
 }
 
 pub const VM_SNAPSHOT_ID: &str = "vm";
-impl Snapshottable for Vm {
-    fn id(&self) -> String {
-        VM_SNAPSHOT_ID.to_string()
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum RestoreStatus {
+    /// The section isn't part of the snapshot.
+    Missing,
+    /// The section is part of the snapshot, but it hasn't been restored.
+    Found,
+    Restored,
+}
+
+/// Event fds through which a VM notifies the VMM. Grouping them rather
+/// than passing each one separately prevents swapping two of them.
+pub struct VmEventFds {
+    pub exit_evt: EventFd,
+    pub reset_evt: EventFd,
+    #[cfg(target_arch = "x86_64")]
+    pub guest_panic_evt: EventFd,
+    #[cfg(feature = "gdb")]
+    pub vm_debug_evt: EventFd,
+    pub activate_evt: EventFd,
+}
+
+/// What `Vm::receive_migration()` creates the destination VM with, the
+/// configuration itself coming from the source.
+pub struct MigrationReceiveConfig {
+    pub event_fds: VmEventFds,
+    pub seccomp_action: SeccompAction,
+    pub hypervisor: Arc<dyn hypervisor::Hypervisor>,
+    pub socket_options: MigrationSocketOptions,
+}
+
+/// Guest memory kept open by `Vm::shutdown_warm()`, for the next VM to
+/// reuse instead of allocating it again.
+pub struct WarmRestartData {
+    pub memory_manager_data: MemoryManagerSnapshotData,
+    /// Guest memory files, indexed by memory slot.
+    pub memory_files: HashMap<u32, File>,
+}
+
+/// Which sections of a VM snapshot were found and restored.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct RestoreReport {
+    pub memory: RestoreStatus,
+    /// Missing from memory only snapshots, in which case the devices are
+    /// created from the configuration.
+    pub devices: RestoreStatus,
+    pub cpus: RestoreStatus,
+    #[cfg(target_arch = "aarch64")]
+    pub vgic: RestoreStatus,
+}
+
+impl RestoreReport {
+    fn new(snapshot: &Snapshot) -> Self {
+        let status = |id: &str| {
+            if snapshot.snapshots.contains_key(id) {
+                RestoreStatus::Found
+            } else {
+                RestoreStatus::Missing
+            }
+        };
+
+        RestoreReport {
+            memory: status(MEMORY_MANAGER_SNAPSHOT_ID),
+            devices: status(DEVICE_MANAGER_SNAPSHOT_ID),
+            cpus: status(CPU_MANAGER_SNAPSHOT_ID),
+            #[cfg(target_arch = "aarch64")]
+            vgic: status(GIC_V3_ITS_SNAPSHOT_ID),
+        }
     }
 
-    fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+    /// Whether a section the VM can't be restored without is missing.
+    pub fn missing_required(&self) -> bool {
+        #[cfg(target_arch = "aarch64")]
+        if self.vgic == RestoreStatus::Missing {
+            return true;
+        }
+
+        self.memory == RestoreStatus::Missing || self.cpus == RestoreStatus::Missing
+    }
+}
+
+impl fmt::Display for RestoreReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "memory: {:?}, devices: {:?}, cpus: {:?}",
+            self.memory, self.devices, self.cpus
+        )?;
+        #[cfg(target_arch = "aarch64")]
+        write!(f, ", vgic: {:?}", self.vgic)?;
+        Ok(())
+    }
+}
+
+impl Vm {
+    /// Snapshot the guest memory and the vCPUs only, skipping the devices.
+    /// This is cheaper for VMs with a lot of devices, but restoring such a
+    /// snapshot resets every device to the state it had when the VM booted,
+    /// which the guest drivers must be able to cope with.
+    pub fn snapshot_memory_only(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        self.snapshot_with_devices(false)
+    }
+
+    /// Snapshot a running VM, pausing it for the duration of the snapshot.
+    /// `pre_snapshot` runs right before the VM is paused, and `post_snapshot`
+    /// right after it is resumed, since the guest must be running for an
+    /// agent to freeze and thaw its filesystems. If the pre-snapshot hook
+    /// fails nothing else happens, otherwise the VM is always resumed and the
+    /// post-snapshot hook always run, even if the snapshot itself failed.
+    /// A VM which is already paused is snapshotted and left paused.
+    pub fn snapshot_with_hooks(
+        &mut self,
+        memory_only: bool,
+        pre_snapshot: Option<SnapshotHook>,
+        post_snapshot: Option<SnapshotHook>,
+    ) -> Result<Snapshot> {
+        let was_running = self.get_state()? == VmState::Running;
+
+        if let Some(pre_snapshot) = pre_snapshot {
+            pre_snapshot().map_err(Error::SnapshotHook)?;
+        }
+
+        let snapshot = if was_running {
+            Pausable::pause(self).map_err(Error::Pause)
+        } else {
+            Ok(())
+        }
+        .and_then(|_| {
+            let snapshot = if memory_only {
+                self.snapshot_memory_only()
+            } else {
+                Snapshottable::snapshot(self)
+            }
+            .map_err(Error::Snapshot);
+
+            let resumed = if was_running {
+                Pausable::resume(self).map_err(Error::Resume)
+            } else {
+                Ok(())
+            };
+
+            // Report the snapshot failure first, it happened before.
+            let snapshot = snapshot?;
+            resumed.map(|_| snapshot)
+        });
+
+        let post_snapshot = post_snapshot
+            .map(|post_snapshot| post_snapshot().map_err(Error::SnapshotHook))
+            .unwrap_or(Ok(()));
+
+        let snapshot = snapshot?;
+        post_snapshot.map(|_| snapshot)
+    }
+
+    fn snapshot_with_devices(
+        &mut self,
+        devices: bool,
+    ) -> std::result::Result<Snapshot, MigratableError> {
         event!("vm", "snapshotting");
+        let start = Instant::now();
 
         #[cfg(feature = "tdx")]
         {
@@ -2677,25 +6210,6 @@ impl Snapshottable for Vm {
             )));
         }
 
-        #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-        let common_cpuid = {
-            #[cfg(feature = "tdx")]
-            let tdx_enabled = self.config.lock().unwrap().tdx.is_some();
-            let phys_bits = physical_bits(self.config.lock().unwrap().cpus.max_phys_bits);
-            arch::generate_common_cpuid(
-                self.hypervisor.clone(),
-                None,
-                None,
-                phys_bits,
-                self.config.lock().unwrap().cpus.kvm_hyperv,
-                #[cfg(feature = "tdx")]
-                tdx_enabled,
-            )
-            .map_err(|e| {
-                MigratableError::MigrateReceive(anyhow!("Error generating common cpuid: {:?}", e))
-            })?
-        };
-
         let mut vm_snapshot = Snapshot::new(VM_SNAPSHOT_ID);
         let vm_state = self
             .vm
@@ -2706,7 +6220,7 @@ impl Snapshottable for Vm {
             clock: self.saved_clock,
             state: Some(vm_state),
             #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-            common_cpuid,
+            common_cpuid: self.common_cpuid(),
         })
         .map_err(|e| MigratableError::Snapshot(e.into()))?;
 
@@ -2717,18 +6231,57 @@ impl Snapshottable for Vm {
         self.add_vgic_snapshot_section(&mut vm_snapshot)
             .map_err(|e| MigratableError::Snapshot(e.into()))?;
 
-        vm_snapshot.add_snapshot(self.device_manager.lock().unwrap().snapshot()?);
+        if devices {
+            vm_snapshot.add_snapshot(self.device_manager.lock().unwrap().snapshot()?);
+        }
         vm_snapshot.add_data_section(SnapshotDataSection {
             id: format!("{}-section", VM_SNAPSHOT_ID),
             snapshot: vm_snapshot_data,
         });
 
-        event!("vm", "snapshotted");
+        let duration_ms = self.record_timing(VmOperation::Snapshot, start);
+        event!("vm", "snapshotted", "duration_ms", duration_ms);
         Ok(vm_snapshot)
     }
+}
+
+impl Snapshottable for Vm {
+    fn id(&self) -> String {
+        VM_SNAPSHOT_ID.to_string()
+    }
+
+    fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        self.snapshot_with_devices(true)
+    }
 
     fn restore(&mut self, snapshot: Snapshot) -> std::result::Result<(), MigratableError> {
         event!("vm", "restoring");
+        let start = Instant::now();
+
+        let mut report = RestoreReport::new(&snapshot);
+        let result = self.restore_sections(snapshot, &mut report);
+        self.restore_report = Some(report.clone());
+        result.map_err(|e| MigratableError::Restore(anyhow!("{} ({})", e, report)))?;
+
+        let duration_ms = self.record_timing(VmOperation::Restore, start);
+        event!("vm", "restored", "duration_ms", duration_ms);
+        Ok(())
+    }
+}
+
+impl Vm {
+    // Restore the VM from its snapshot, keeping track in `report` of the
+    // sections that have been restored.
+    fn restore_sections(
+        &mut self,
+        snapshot: Snapshot,
+        report: &mut RestoreReport,
+    ) -> std::result::Result<(), MigratableError> {
+        if report.missing_required() {
+            return Err(MigratableError::Restore(anyhow!(
+                "Missing required snapshot sections"
+            )));
+        }
 
         let current_state = self
             .get_state()
@@ -2747,21 +6300,30 @@ impl Snapshottable for Vm {
                 .lock()
                 .unwrap()
                 .restore(*memory_manager_snapshot.clone())?;
+            report.memory = RestoreStatus::Restored;
         } else {
             return Err(MigratableError::Restore(anyhow!(
                 "Missing memory manager snapshot"
             )));
         }
 
-        if let Some(device_manager_snapshot) = snapshot.snapshots.get(DEVICE_MANAGER_SNAPSHOT_ID) {
+        // A memory only snapshot doesn't contain any device state, in which
+        // case the devices are created from the configuration.
+        let device_manager_snapshot = snapshot.snapshots.get(DEVICE_MANAGER_SNAPSHOT_ID);
+        if let Some(device_manager_snapshot) = device_manager_snapshot {
             self.device_manager
                 .lock()
                 .unwrap()
                 .restore(*device_manager_snapshot.clone())?;
         } else {
-            return Err(MigratableError::Restore(anyhow!(
-                "Missing device manager snapshot"
-            )));
+            info!("No device state in snapshot, creating devices from the configuration");
+            self.device_manager
+                .lock()
+                .unwrap()
+                .create_devices_for_restore()
+                .map_err(|e| {
+                    MigratableError::Restore(anyhow!("Could not create devices {:?}", e))
+                })?;
         }
 
         if let Some(cpu_manager_snapshot) = snapshot.snapshots.get(CPU_MANAGER_SNAPSHOT_ID) {
@@ -2769,6 +6331,7 @@ impl Snapshottable for Vm {
                 .lock()
                 .unwrap()
                 .restore(*cpu_manager_snapshot.clone())?;
+            report.cpus = RestoreStatus::Restored;
         } else {
             return Err(MigratableError::Restore(anyhow!(
                 "Missing CPU manager snapshot"
@@ -2776,27 +6339,30 @@ impl Snapshottable for Vm {
         }
 
         #[cfg(target_arch = "aarch64")]
-        self.restore_vgic_and_enable_interrupt(&snapshot)?;
+        {
+            self.restore_vgic_and_enable_interrupt(&snapshot)?;
+            report.vgic = RestoreStatus::Restored;
+        }
 
-        if let Some(device_manager_snapshot) = snapshot.snapshots.get(DEVICE_MANAGER_SNAPSHOT_ID) {
+        if let Some(device_manager_snapshot) = device_manager_snapshot {
             self.device_manager
                 .lock()
                 .unwrap()
                 .restore_devices(*device_manager_snapshot.clone())?;
-        } else {
-            return Err(MigratableError::Restore(anyhow!(
-                "Missing device manager snapshot"
-            )));
+            report.devices = RestoreStatus::Restored;
         }
 
-        // Now we can start all vCPUs from here.
-        self.cpu_manager
-            .lock()
-            .unwrap()
-            .start_restored_vcpus()
-            .map_err(|e| {
-                MigratableError::Restore(anyhow!("Cannot start restored vCPUs: {:#?}", e))
-            })?;
+        // Now we can start all vCPUs from here, unless this is left to the
+        // first resume.
+        if !self.defer_vcpus_start {
+            self.cpu_manager
+                .lock()
+                .unwrap()
+                .start_restored_vcpus()
+                .map_err(|e| {
+                    MigratableError::Restore(anyhow!("Cannot start restored vCPUs: {:#?}", e))
+                })?;
+        }
 
         self.setup_signal_handler().map_err(|e| {
             MigratableError::Restore(anyhow!("Could not setup signal handler: {:#?}", e))
@@ -2810,7 +6376,80 @@ impl Snapshottable for Vm {
             .map_err(|e| MigratableError::Restore(anyhow!("Could not set VM state: {:#?}", e)))?;
         *state = new_state;
 
-        event!("vm", "restored");
+        Ok(())
+    }
+
+    /// Sections of the snapshot found and restored by the last restore,
+    /// whether it succeeded or not.
+    pub fn restore_report(&self) -> Option<RestoreReport> {
+        self.restore_report.clone()
+    }
+
+    /// Check the restored VM matches its configuration, before letting it
+    /// run: the vCPUs, the guest RAM and the devices must all be there.
+    /// This catches snapshots which were restored without error, but don't
+    /// describe the VM they come with, such as a truncated device state.
+    /// The error names the first inconsistency found.
+    pub fn verify_restored(&self) -> Result<()> {
+        if self.restore_report.is_none() {
+            return Err(Error::VmNotRestored);
+        }
+        let config = self.config.lock().unwrap();
+
+        let present_vcpus = self.cpu_manager.lock().unwrap().present_vcpus();
+        if present_vcpus != config.cpus.boot_vcpus {
+            return Err(Error::RestoredInconsistent(format!(
+                "{} vCPUs restored, {} expected",
+                present_vcpus, config.cpus.boot_vcpus
+            )));
+        }
+
+        let memory_manager = self.memory_manager.lock().unwrap();
+        let boot_ram = memory_manager.boot_ram();
+        check_restored_ram(
+            &config.memory,
+            boot_ram,
+            boot_ram + memory_manager.hotplugged_ram(),
+        )
+        .map_err(Error::RestoredInconsistent)?;
+        let mapped: u64 = memory_manager
+            .guest_memory()
+            .memory()
+            .iter()
+            .map(|region| region.len())
+            .sum();
+        if mapped < boot_ram {
+            return Err(Error::RestoredInconsistent(format!(
+                "{} bytes of guest memory mapped, less than the {} bytes of boot RAM",
+                mapped, boot_ram
+            )));
+        }
+        drop(memory_manager);
+
+        let device_ids = config
+            .disks
+            .iter()
+            .flatten()
+            .map(|d| &d.id)
+            .chain(config.net.iter().flatten().map(|n| &n.id))
+            .chain(config.fs.iter().flatten().map(|f| &f.id))
+            .chain(config.pmem.iter().flatten().map(|p| &p.id))
+            .chain(config.devices.iter().flatten().map(|d| &d.id))
+            .chain(config.user_devices.iter().flatten().map(|d| &d.id))
+            .chain(config.vdpa.iter().flatten().map(|d| &d.id))
+            .chain(config.vsock.iter().map(|v| &v.id))
+            .flatten();
+        let device_tree = self.device_tree();
+        let device_tree = device_tree.lock().unwrap();
+        for id in device_ids {
+            if !device_tree.contains_key(id) {
+                return Err(Error::RestoredInconsistent(format!(
+                    "device {} not restored",
+                    id
+                )));
+            }
+        }
+
         Ok(())
     }
 }
@@ -2821,43 +6460,46 @@ impl Transportable for Vm {
         snapshot: &Snapshot,
         destination_url: &str,
     ) -> std::result::Result<(), MigratableError> {
-        let mut snapshot_config_path = url_to_path(destination_url)?;
-        snapshot_config_path.push(SNAPSHOT_CONFIG_FILE);
-
-        // Create the snapshot config file
-        let mut snapshot_config_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create_new(true)
-            .open(snapshot_config_path)
-            .map_err(|e| MigratableError::MigrateSend(e.into()))?;
-
-        // Serialize and write the snapshot config
+        // Serialize the snapshot config and state
         let vm_config = serde_json::to_string(self.config.lock().unwrap().deref())
             .map_err(|e| MigratableError::MigrateSend(e.into()))?;
-
-        snapshot_config_file
-            .write(vm_config.as_bytes())
-            .map_err(|e| MigratableError::MigrateSend(e.into()))?;
-
-        let mut snapshot_state_path = url_to_path(destination_url)?;
-        snapshot_state_path.push(SNAPSHOT_STATE_FILE);
-
-        // Create the snapshot state file
-        let mut snapshot_state_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create_new(true)
-            .open(snapshot_state_path)
-            .map_err(|e| MigratableError::MigrateSend(e.into()))?;
-
-        // Serialize and write the snapshot state
         let vm_state =
             serde_json::to_vec(snapshot).map_err(|e| MigratableError::MigrateSend(e.into()))?;
 
-        snapshot_state_file
-            .write(&vm_state)
-            .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+        if let Some(mut stream) = url_to_stream(destination_url)? {
+            write_snapshot_section(&mut stream, vm_config.as_bytes())?;
+            write_snapshot_section(&mut stream, &vm_state)?;
+        } else {
+            let mut snapshot_config_path = url_to_path(destination_url)?;
+            snapshot_config_path.push(SNAPSHOT_CONFIG_FILE);
+
+            // Create the snapshot config file
+            let mut snapshot_config_file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(snapshot_config_path)
+                .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+
+            snapshot_config_file
+                .write(vm_config.as_bytes())
+                .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+
+            let mut snapshot_state_path = url_to_path(destination_url)?;
+            snapshot_state_path.push(SNAPSHOT_STATE_FILE);
+
+            // Create the snapshot state file
+            let mut snapshot_state_file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(snapshot_state_path)
+                .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+
+            snapshot_state_file
+                .write(&vm_state)
+                .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+        }
 
         // Tell the memory manager to also send/write its own snapshot.
         if let Some(memory_manager_snapshot) = snapshot.snapshots.get(MEMORY_MANAGER_SNAPSHOT_ID) {
@@ -2877,20 +6519,30 @@ impl Transportable for Vm {
 
 impl Migratable for Vm {
     fn start_dirty_log(&mut self) -> std::result::Result<(), MigratableError> {
-        self.memory_manager.lock().unwrap().start_dirty_log()?;
-        self.device_manager.lock().unwrap().start_dirty_log()
+        self.dirty_log_migration = true;
+        // Already armed for continuous dirty logging or since the last reset.
+        if self.dirty_log_continuous || self.dirty_log_reset {
+            return Ok(());
+        }
+
+        self.arm_dirty_log()
     }
 
     fn stop_dirty_log(&mut self) -> std::result::Result<(), MigratableError> {
-        self.memory_manager.lock().unwrap().stop_dirty_log()?;
-        self.device_manager.lock().unwrap().stop_dirty_log()
+        self.dirty_log_migration = false;
+        // Don't disarm the dirty log from under its other users.
+        if self.dirty_log_continuous || self.dirty_log_reset {
+            return Ok(());
+        }
+
+        self.disarm_dirty_log()
     }
 
     fn dirty_log(&mut self) -> std::result::Result<MemoryRangeTable, MigratableError> {
-        Ok(MemoryRangeTable::new_from_tables(vec![
-            self.memory_manager.lock().unwrap().dirty_log()?,
-            self.device_manager.lock().unwrap().dirty_log()?,
-        ]))
+        let mut table = std::mem::take(&mut self.pending_dirty_log);
+        table.extend(self.read_dirty_log()?);
+
+        Ok(table)
     }
 
     fn start_migration(&mut self) -> std::result::Result<(), MigratableError> {
@@ -2918,6 +6570,27 @@ impl Debuggable for Vm {
             .set_guest_debug(cpu_id, addrs, singlestep)
     }
 
+    fn set_guest_debug_all(
+        &mut self,
+        addrs: &[GuestAddress],
+        singlestep: bool,
+    ) -> std::result::Result<(), DebuggableError> {
+        // Stop the vCPUs so that all of them see the new settings at once.
+        let running = self.get_state().ok() == Some(VmState::Running);
+        if running {
+            self.pause().map_err(DebuggableError::Pause)?;
+        }
+        let result = self
+            .cpu_manager
+            .lock()
+            .unwrap()
+            .set_guest_debug_all(addrs, singlestep);
+        if running {
+            self.resume().map_err(DebuggableError::Resume)?;
+        }
+        result
+    }
+
     fn debug_pause(&mut self) -> std::result::Result<(), DebuggableError> {
         if !self.cpu_manager.lock().unwrap().vcpus_paused() {
             self.pause().map_err(DebuggableError::Pause)?;
@@ -3049,6 +6722,61 @@ impl GuestDebuggable for Vm {
     }
 }
 
+/// Check the RAM of a restored VM against its configuration. Resizing the VM
+/// updates the configuration, so the total RAM is what has to match: ACPI
+/// hotplug grows `size` while virtio-mem records the extra RAM in
+/// `hotplugged_size`. Memory zones are resized through virtio-mem without
+/// touching the configured zone sizes, so only the boot RAM is checked then.
+fn check_restored_ram(
+    memory: &MemoryConfig,
+    boot_ram: u64,
+    current_ram: u64,
+) -> std::result::Result<(), String> {
+    match &memory.zones {
+        Some(zones) if memory.size == 0 => {
+            let expected: u64 = zones.iter().map(|z| z.size).sum();
+            if boot_ram != expected {
+                return Err(format!(
+                    "{} bytes of boot RAM restored, {} expected",
+                    boot_ram, expected
+                ));
+            }
+        }
+        _ => {
+            let expected = memory.size + memory.hotplugged_size.unwrap_or(0);
+            if current_ram != expected {
+                return Err(format!(
+                    "{} bytes of RAM restored, {} expected",
+                    current_ram, expected
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Memory zone among `node_zones` to grow by `size` bytes, and its new size.
+// This is the first one with enough hotpluggable memory left.
+fn zone_to_grow(
+    zones: Option<&[MemoryZoneConfig]>,
+    node_zones: &[String],
+    size: u64,
+) -> Option<(String, u64)> {
+    zones
+        .into_iter()
+        .flatten()
+        .filter(|zone| node_zones.contains(&zone.id))
+        .find_map(|zone| {
+            let hotplugged_size = zone.hotplugged_size.unwrap_or(0);
+            let available = zone
+                .hotplug_size
+                .unwrap_or(0)
+                .saturating_sub(hotplugged_size);
+            (available >= size).then(|| (zone.id.clone(), zone.size + hotplugged_size + size))
+        })
+}
+
 #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
 #[cfg(test)]
 mod tests {
@@ -3119,6 +6847,233 @@ mod tests {
         test_vm_state_transitions(VmState::Paused);
     }
 
+    #[test]
+    fn test_restore_report() {
+        let mut snapshot = Snapshot::new(VM_SNAPSHOT_ID);
+        snapshot.add_snapshot(Snapshot::new(MEMORY_MANAGER_SNAPSHOT_ID));
+        let report = RestoreReport::new(&snapshot);
+        assert_eq!(report.memory, RestoreStatus::Found);
+        assert_eq!(report.devices, RestoreStatus::Missing);
+        assert_eq!(report.cpus, RestoreStatus::Missing);
+        assert!(report.missing_required());
+        assert!(report.to_string().contains("cpus: Missing"));
+
+        snapshot.add_snapshot(Snapshot::new(CPU_MANAGER_SNAPSHOT_ID));
+        #[cfg(target_arch = "aarch64")]
+        snapshot.add_snapshot(Snapshot::new(GIC_V3_ITS_SNAPSHOT_ID));
+        assert!(!RestoreReport::new(&snapshot).missing_required());
+    }
+
+    #[test]
+    fn test_check_restored_ram() {
+        let mut memory = MemoryConfig {
+            size: 1 << 30,
+            ..Default::default()
+        };
+        assert!(check_restored_ram(&memory, 1 << 30, 1 << 30).is_ok());
+        assert!(check_restored_ram(&memory, 1 << 30, 2 << 30).is_err());
+
+        // ACPI hotplug updates the configured size, not the boot RAM.
+        memory.size = 2 << 30;
+        assert!(check_restored_ram(&memory, 1 << 30, 2 << 30).is_ok());
+        assert!(check_restored_ram(&memory, 1 << 30, 1 << 30).is_err());
+
+        // virtio-mem records the hotplugged RAM separately.
+        memory.size = 1 << 30;
+        memory.hotplug_method = HotplugMethod::VirtioMem;
+        memory.hotplugged_size = Some(1 << 30);
+        assert!(check_restored_ram(&memory, 1 << 30, 2 << 30).is_ok());
+        assert!(check_restored_ram(&memory, 2 << 30, 3 << 30).is_err());
+
+        memory.size = 0;
+        memory.hotplugged_size = None;
+        let zone = MemoryZoneConfig {
+            id: "mem0".to_owned(),
+            size: 1 << 30,
+            file: None,
+            shared: false,
+            hugepages: false,
+            hugepage_size: None,
+            host_numa_node: None,
+            hotplug_size: Some(1 << 30),
+            hotplugged_size: Some(1 << 30),
+            prefault: false,
+        };
+        memory.zones = Some(vec![
+            zone.clone(),
+            MemoryZoneConfig {
+                id: "mem1".to_owned(),
+                ..zone
+            },
+        ]);
+        assert!(check_restored_ram(&memory, 2 << 30, 3 << 30).is_ok());
+        assert!(check_restored_ram(&memory, 1 << 30, 1 << 30).is_err());
+    }
+
+    // ELF64 header followed by `phnum` program headers of `phentsize` bytes,
+    // the first one describing a loadable segment.
+    fn elf_kernel(phentsize: u16, phnum: u16) -> File {
+        let mut elf = vec![0u8; 64];
+        elf[..5].copy_from_slice(b"\x7fELF\x02");
+        elf[32..40].copy_from_slice(&64u64.to_le_bytes());
+        elf[54..56].copy_from_slice(&phentsize.to_le_bytes());
+        elf[56..58].copy_from_slice(&phnum.to_le_bytes());
+        let mut phdr = vec![0u8; usize::from(phentsize).max(56)];
+        phdr[0..4].copy_from_slice(&1u32.to_le_bytes());
+        phdr[24..32].copy_from_slice(&0x100_0000u64.to_le_bytes());
+        phdr[40..48].copy_from_slice(&0x20_0000u64.to_le_bytes());
+        elf.extend_from_slice(&phdr[..usize::from(phentsize)]);
+
+        let mut file = vmm_sys_util::tempfile::TempFile::new().unwrap().into_file();
+        file.write_all(&elf).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_kernel_footprint() {
+        assert_eq!(
+            Vm::kernel_footprint(&mut elf_kernel(56, 1)).unwrap(),
+            Some(0x100_0000..0x120_0000)
+        );
+        // Not the size of an ELF64 program header.
+        assert_eq!(Vm::kernel_footprint(&mut elf_kernel(64, 1)).unwrap(), None);
+        assert_eq!(Vm::kernel_footprint(&mut elf_kernel(0, 1)).unwrap(), None);
+        // Too many program headers, rejected before reading them.
+        assert_eq!(
+            Vm::kernel_footprint(&mut elf_kernel(56, u16::MAX)).unwrap(),
+            None
+        );
+        // Truncated program headers.
+        assert_eq!(Vm::kernel_footprint(&mut elf_kernel(56, 2)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_zone_to_grow() {
+        let zone = |id: &str, hotplug_size: u64, hotplugged_size: Option<u64>| MemoryZoneConfig {
+            id: id.to_owned(),
+            size: 1 << 30,
+            file: None,
+            shared: false,
+            hugepages: false,
+            hugepage_size: None,
+            host_numa_node: None,
+            hotplug_size: Some(hotplug_size),
+            hotplugged_size,
+            prefault: false,
+        };
+        let zones = vec![
+            zone("mem0", 1 << 30, None),
+            zone("mem1", 1 << 30, Some(512 << 20)),
+            zone("mem2", 1 << 30, None),
+        ];
+        let node_zones = vec!["mem1".to_owned(), "mem2".to_owned()];
+
+        // Zones of other nodes are left alone.
+        assert_eq!(
+            zone_to_grow(Some(&zones), &node_zones, 256 << 20),
+            Some(("mem1".to_owned(), (1 << 30) + (768 << 20)))
+        );
+        // The first zone of the node doesn't have enough room left.
+        assert_eq!(
+            zone_to_grow(Some(&zones), &node_zones, 1 << 30),
+            Some(("mem2".to_owned(), 2 << 30))
+        );
+        assert_eq!(zone_to_grow(Some(&zones), &node_zones, 2 << 30), None);
+        assert_eq!(zone_to_grow(None, &node_zones, 256 << 20), None);
+    }
+
+    #[test]
+    fn test_merge_cmdline() {
+        assert_eq!(merge_cmdline("", "console=ttyS0"), "console=ttyS0");
+        assert_eq!(
+            merge_cmdline("console=ttyS0 quiet", "quiet  panic=1"),
+            "console=ttyS0 quiet panic=1"
+        );
+        assert_eq!(merge_cmdline("console=hvc0 ", ""), "console=hvc0");
+    }
+
+    fn numa_config(guest_numa_id: u32, cpus: Option<Vec<u8>>) -> NumaConfig {
+        NumaConfig {
+            guest_numa_id,
+            cpus,
+            distances: None,
+            memory_zones: None,
+            sgx_epc_sections: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_numa_cpu_coverage() {
+        // Each vCPU on exactly one node.
+        let configs = vec![
+            numa_config(0, Some(vec![0, 1, 4])),
+            numa_config(1, Some(vec![2, 3, 5])),
+        ];
+        assert!(Vm::validate_numa_cpu_coverage(&configs, 6).is_ok());
+
+        // Nodes only describing the memory.
+        let configs = vec![numa_config(0, None), numa_config(1, None)];
+        assert!(Vm::validate_numa_cpu_coverage(&configs, 6).is_ok());
+
+        // vCPU 5 left out.
+        let configs = vec![
+            numa_config(0, Some(vec![0, 1, 4])),
+            numa_config(1, Some(vec![2, 3])),
+        ];
+        assert!(matches!(
+            Vm::validate_numa_cpu_coverage(&configs, 6),
+            Err(Error::InvalidNumaCpu {
+                cpu: 5,
+                reason: InvalidNumaCpuReason::Unassigned
+            })
+        ));
+        let configs = vec![numa_config(0, Some(vec![0, 1, 2])), numa_config(1, None)];
+        assert!(matches!(
+            Vm::validate_numa_cpu_coverage(&configs, 4),
+            Err(Error::InvalidNumaCpu {
+                cpu: 3,
+                reason: InvalidNumaCpuReason::Unassigned
+            })
+        ));
+
+        // vCPU 2 on both nodes.
+        let configs = vec![
+            numa_config(0, Some(vec![0, 1, 2])),
+            numa_config(1, Some(vec![2, 3])),
+        ];
+        assert!(matches!(
+            Vm::validate_numa_cpu_coverage(&configs, 4),
+            Err(Error::InvalidNumaCpu {
+                cpu: 2,
+                reason: InvalidNumaCpuReason::AssignedTwice(0, 1)
+            })
+        ));
+
+        // vCPU 4 beyond the maximum.
+        let configs = vec![
+            numa_config(0, Some(vec![0, 1])),
+            numa_config(1, Some(vec![2, 3, 4])),
+        ];
+        assert!(matches!(
+            Vm::validate_numa_cpu_coverage(&configs, 4),
+            Err(Error::InvalidNumaCpu {
+                cpu: 4,
+                reason: InvalidNumaCpuReason::OutOfRange(1, 4)
+            })
+        ));
+    }
+
+    #[test]
+    fn test_phys_bits_clamp() {
+        let phys_bits = PhysBits::new(46, 39);
+        assert_eq!(phys_bits.effective, 39);
+        assert!(phys_bits.clamped());
+
+        let phys_bits = PhysBits::new(39, 46);
+        assert_eq!(phys_bits.effective, 39);
+        assert!(!phys_bits.clamped());
+    }
+
     #[cfg(feature = "tdx")]
     #[test]
     fn test_hob_memory_resources() {
@@ -3329,6 +7284,7 @@ mod tests {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::device_manager::RTC_DEVICE_NAME;
     use crate::GuestMemoryMmap;
     use arch::aarch64::fdt::create_fdt;
     use arch::aarch64::layout;
@@ -3359,7 +7315,7 @@ mod tests {
                 },
             ),
             (
-                (DeviceType::Rtc, "rtc".to_string()),
+                (DeviceType::Rtc, RTC_DEVICE_NAME.to_string()),
                 MmioDeviceInfo {
                     addr: 2 * LEN,
                     len: LEN,
@@ -3395,6 +7351,7 @@ mod tests {
             &BTreeMap::new(),
             None,
             true,
+            &[],
         )
         .is_ok())
     }